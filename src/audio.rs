@@ -0,0 +1,1074 @@
+use crate::class_prelude::*;
+use crate::control::{Recipient, RequestType};
+use crate::endpoint::{Endpoint, EndpointDirection};
+use crate::Result;
+
+/// Interface class code of audio interfaces.
+pub const USB_CLASS_AUDIO: u8 = 0x01;
+
+/// Descriptor type of class-specific interface descriptors.
+pub const CS_INTERFACE: u8 = 0x24;
+
+/// Descriptor type of class-specific endpoint descriptors.
+pub const CS_ENDPOINT: u8 = 0x25;
+
+/// Audio interface subclass codes
+#[allow(missing_docs)]
+pub mod subclass {
+    pub const AUDIOCONTROL: u8 = 0x01;
+    pub const AUDIOSTREAMING: u8 = 0x02;
+}
+
+/// Audio interface protocol code of Audio Device Class 2.0 interfaces.
+pub const PROTOCOL_IP_VERSION_02_00: u8 = 0x20;
+
+/// Terminal types, for the terminal on the device side of a function
+#[allow(missing_docs)]
+pub mod terminal_type {
+    pub const USB_STREAMING: u16 = 0x0101;
+    pub const MICROPHONE: u16 = 0x0201;
+    pub const SPEAKER: u16 = 0x0301;
+    pub const HEADPHONES: u16 = 0x0302;
+    pub const ANALOG_CONNECTOR: u16 = 0x0601;
+    pub const DIGITAL_AUDIO_INTERFACE: u16 = 0x0602;
+    pub const LINE_CONNECTOR: u16 = 0x0603;
+    pub const SPDIF_INTERFACE: u16 = 0x0605;
+}
+
+/// IDs of the entities in the topology of an [`AudioClass`], which is a USB streaming or device
+/// side input terminal, a feature unit with mute and volume controls, and an output terminal.
+/// Audio Device Class 2.0 functions also have a clock source.
+#[allow(missing_docs)]
+pub mod entity {
+    pub const INPUT_TERMINAL: u8 = 1;
+    pub const FEATURE_UNIT: u8 = 2;
+    pub const OUTPUT_TERMINAL: u8 = 3;
+    pub const CLOCK_SOURCE: u8 = 4;
+}
+
+/// Audio class request codes. Audio Device Class 2.0 uses CUR and RANGE in both directions.
+#[allow(missing_docs)]
+pub mod audio_request {
+    pub const SET_CUR: u8 = 0x01;
+    pub const GET_CUR: u8 = 0x81;
+    pub const GET_MIN: u8 = 0x82;
+    pub const GET_MAX: u8 = 0x83;
+    pub const GET_RES: u8 = 0x84;
+    pub const CUR: u8 = 0x01;
+    pub const RANGE: u8 = 0x02;
+}
+
+/// Control selectors of feature units
+#[allow(missing_docs)]
+pub mod feature_unit_control {
+    pub const MUTE: u8 = 0x01;
+    pub const VOLUME: u8 = 0x02;
+}
+
+/// Control selectors of clock sources, in Audio Device Class 2.0
+#[allow(missing_docs)]
+pub mod clock_source_control {
+    pub const SAM_FREQ: u8 = 0x01;
+    pub const CLOCK_VALID: u8 = 0x02;
+}
+
+/// Control selector of the sampling frequency of an endpoint, in Audio Device Class 1.0.
+pub const SAMPLING_FREQ_CONTROL: u8 = 0x01;
+
+/// Largest number of sample rates of a stream.
+pub const MAX_SAMPLE_RATES: usize = 8;
+
+// Class-specific descriptor subtypes
+const HEADER: u8 = 0x01;
+const INPUT_TERMINAL: u8 = 0x02;
+const OUTPUT_TERMINAL: u8 = 0x03;
+const FEATURE_UNIT: u8 = 0x06;
+const CLOCK_SOURCE: u8 = 0x0a;
+const AS_GENERAL: u8 = 0x01;
+const FORMAT_TYPE: u8 = 0x02;
+const EP_GENERAL: u8 = 0x01;
+
+const FORMAT_TYPE_I: u8 = 0x01;
+const FORMAT_PCM: u16 = 0x0001;
+const FUNCTION_CATEGORY_OTHER: u8 = 0xff;
+
+// Alternate settings of the streaming interface.
+const STREAM_ALT_IDLE: u8 = 0;
+const STREAM_ALT_ACTIVE: u8 = 1;
+
+// Feedback is measured over 2^FEEDBACK_REFRESH frames.
+const FEEDBACK_REFRESH: u8 = 5;
+const FEEDBACK_SIZE: u16 = 3;
+
+/// Version of the Audio Device Class specification that a function conforms to.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AudioVersion {
+    /// Audio Device Class 1.0, supported by all hosts.
+    Uac1,
+    /// Audio Device Class 2.0, which requires an interface association descriptor and Windows 10
+    /// or later.
+    Uac2,
+}
+
+/// The format and controls of the audio stream of an [`AudioClass`].
+#[derive(Copy, Clone, Debug)]
+pub struct StreamConfig<'a> {
+    terminal_type: u16,
+    channels: u8,
+    subframe_size: u8,
+    bit_resolution: u8,
+    sample_rates: &'a [u32],
+    volume_range: (i16, i16, i16),
+}
+
+impl<'a> StreamConfig<'a> {
+    /// Creates a PCM stream configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `terminal_type` - The [`terminal_type`] on the device side, for example a speaker.
+    /// * `channels` - Number of interleaved channels. Two channels are left and right.
+    /// * `subframe_size` - Bytes per sample: 2, 3 or 4.
+    /// * `bit_resolution` - Significant bits per sample.
+    /// * `sample_rates` - Sample rates in Hz that the host can choose from. The first one is used
+    ///   until the host chooses another one.
+    pub fn new(
+        terminal_type: u16,
+        channels: u8,
+        subframe_size: u8,
+        bit_resolution: u8,
+        sample_rates: &'a [u32],
+    ) -> Self {
+        StreamConfig {
+            terminal_type,
+            channels,
+            subframe_size,
+            bit_resolution,
+            sample_rates,
+            volume_range: (-100 * 256, 0, 256),
+        }
+    }
+
+    /// Sets the range of the volume control as minimum, maximum and resolution in 1/256 dB. The
+    /// default is -100 dB to 0 dB in steps of 1 dB.
+    pub fn volume_range(mut self, min: i16, max: i16, resolution: i16) -> Self {
+        self.volume_range = (min, max, resolution);
+        self
+    }
+
+    fn channel_config(&self) -> u16 {
+        // Stereo streams are left and right front, others have no spatial location.
+        if self.channels == 2 {
+            0x0003
+        } else {
+            0x0000
+        }
+    }
+
+    fn frame_size(&self) -> usize {
+        usize::from(self.channels) * usize::from(self.subframe_size)
+    }
+}
+
+enum Stream<'a, B: UsbBus> {
+    Playback {
+        data: EndpointOut<'a, B>,
+        feedback: EndpointIn<'a, B>,
+    },
+    Capture {
+        data: EndpointIn<'a, B>,
+    },
+}
+
+/// An audio function with one isochronous PCM stream, for either playback from the host to a
+/// speaker or capture from a microphone to the host. A headset uses one function of each kind.
+///
+/// The function's topology is an input terminal, a feature unit with master mute and volume
+/// controls and an output terminal. Its streaming interface has a zero-bandwidth alternate
+/// setting 0 and an active alternate setting 1 with asynchronous endpoints, and the host can
+/// choose between the configured sample rates.
+///
+/// The data endpoints carry one packet per frame, so the function is meant for full speed.
+/// Playback functions report the rate of the device's audio clock through an explicit feedback
+/// endpoint. The rate is measured from the samples counted with
+/// [`count_samples`](AudioClass::count_samples) between start-of-frame events, which the
+/// peripheral driver must report.
+///
+/// Audio Device Class 2.0 functions need a device built with
+/// [`composite_with_iads`](crate::device::UsbDeviceBuilder::composite_with_iads).
+///
+/// ```no_run
+/// use usb_device::audio::{terminal_type, AudioClass, AudioVersion, StreamConfig};
+/// use usb_device::class_prelude::*;
+/// use usb_device::dummy::DummyUsbBus;
+/// use usb_device::prelude::*;
+///
+/// let usb_bus = UsbBusAllocator::new(DummyUsbBus::new());
+///
+/// let config = StreamConfig::new(terminal_type::SPEAKER, 2, 2, 16, &[48000, 44100]);
+/// let mut speaker = AudioClass::playback(&usb_bus, AudioVersion::Uac1, config).unwrap();
+///
+/// let mut control_buffer = [0u8; 256];
+/// let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+///     .composite_with_iads()
+///     .build()
+///     .unwrap();
+///
+/// loop {
+///     usb_dev.poll(&mut [&mut speaker]);
+///
+///     let mut packet = [0u8; 200];
+///     if let Ok(len) = speaker.read(&mut packet) {
+///         // Queue the samples for the codec, and count the ones it has played with
+///         // speaker.count_samples()
+///     }
+/// }
+/// ```
+pub struct AudioClass<'a, B: UsbBus> {
+    version: AudioVersion,
+    control_if: InterfaceNumber,
+    stream_if: InterfaceNumber,
+    stream: Stream<'a, B>,
+    config: StreamConfig<'a>,
+    stream_alt: u8,
+    sample_rate: u32,
+    mute: bool,
+    volume: i16,
+    samples: u32,
+    frames: u32,
+    feedback: u32,
+}
+
+impl<'a, B: UsbBus> AudioClass<'a, B> {
+    /// Creates a new playback function, which streams audio from the host to the device.
+    ///
+    /// # Errors
+    ///
+    /// * [`Unsupported`](crate::UsbError::Unsupported) - The stream has no or more than
+    ///   [`MAX_SAMPLE_RATES`] sample rates, or its packets don't fit in 1023 bytes.
+    pub fn playback(
+        alloc: &'a UsbBusAllocator<B>,
+        version: AudioVersion,
+        config: StreamConfig<'a>,
+    ) -> Result<Self> {
+        let max_packet_size = data_packet_size(&config)?;
+
+        let control_if = alloc.interface();
+        let stream_if = alloc.interface();
+        let data = alloc.isochronous(
+            IsochronousSynchronizationType::Asynchronous,
+            IsochronousUsageType::Data,
+            max_packet_size,
+            1,
+        );
+        let feedback = alloc.isochronous(
+            IsochronousSynchronizationType::NoSynchronization,
+            IsochronousUsageType::Feedback,
+            FEEDBACK_SIZE,
+            1,
+        );
+
+        Ok(Self::with_stream(
+            version,
+            control_if,
+            stream_if,
+            Stream::Playback { data, feedback },
+            config,
+        ))
+    }
+
+    /// Creates a new capture function, which streams audio from the device to the host.
+    ///
+    /// # Errors
+    ///
+    /// * [`Unsupported`](crate::UsbError::Unsupported) - The stream has no or more than
+    ///   [`MAX_SAMPLE_RATES`] sample rates, or its packets don't fit in 1023 bytes.
+    pub fn capture(
+        alloc: &'a UsbBusAllocator<B>,
+        version: AudioVersion,
+        config: StreamConfig<'a>,
+    ) -> Result<Self> {
+        let max_packet_size = data_packet_size(&config)?;
+
+        let control_if = alloc.interface();
+        let stream_if = alloc.interface();
+        let data = alloc.isochronous(
+            IsochronousSynchronizationType::Asynchronous,
+            IsochronousUsageType::Data,
+            max_packet_size,
+            1,
+        );
+
+        Ok(Self::with_stream(
+            version,
+            control_if,
+            stream_if,
+            Stream::Capture { data },
+            config,
+        ))
+    }
+
+    fn with_stream(
+        version: AudioVersion,
+        control_if: InterfaceNumber,
+        stream_if: InterfaceNumber,
+        stream: Stream<'a, B>,
+        config: StreamConfig<'a>,
+    ) -> Self {
+        let sample_rate = config.sample_rates[0];
+
+        AudioClass {
+            version,
+            control_if,
+            stream_if,
+            stream,
+            config,
+            stream_alt: STREAM_ALT_IDLE,
+            sample_rate,
+            mute: false,
+            volume: config.volume_range.1,
+            samples: 0,
+            frames: 0,
+            feedback: nominal_feedback(sample_rate),
+        }
+    }
+
+    /// Gets the number of the AudioControl interface.
+    pub fn control_interface(&self) -> InterfaceNumber {
+        self.control_if
+    }
+
+    /// Gets the number of the AudioStreaming interface.
+    pub fn streaming_interface(&self) -> InterfaceNumber {
+        self.stream_if
+    }
+
+    /// Gets whether the host has selected the active alternate setting and is streaming audio.
+    pub fn is_streaming(&self) -> bool {
+        self.stream_alt == STREAM_ALT_ACTIVE
+    }
+
+    /// Gets the sample rate chosen by the host, in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Gets whether the host has muted the function.
+    pub fn is_muted(&self) -> bool {
+        self.mute
+    }
+
+    /// Gets the volume set by the host, in 1/256 dB.
+    pub fn volume(&self) -> i16 {
+        self.volume
+    }
+
+    /// Gets the feedback value reported to the host, in samples per frame in 10.14 fixed point
+    /// format.
+    pub fn feedback(&self) -> u32 {
+        self.feedback
+    }
+
+    /// Reads one packet of interleaved samples received from the host.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - The function is not a playback
+    ///   function or is not streaming.
+    /// * [`WouldBlock`](crate::UsbError::WouldBlock) - No packet has been received.
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - `data` is shorter than the packet.
+    pub fn read(&self, data: &mut [u8]) -> Result<usize> {
+        match &self.stream {
+            Stream::Playback { data: ep, .. } if self.is_streaming() => ep.read(data),
+            _ => Err(UsbError::InvalidState),
+        }
+    }
+
+    /// Writes one packet of interleaved samples to send to the host in the next frame.
+    ///
+    /// Capture functions are asynchronous, so the number of samples in a packet should follow
+    /// the device's audio clock.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - The function is not a capture function
+    ///   or is not streaming.
+    /// * [`WouldBlock`](crate::UsbError::WouldBlock) - The previous packet has not been sent yet.
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - `data` is longer than a packet.
+    pub fn write(&self, data: &[u8]) -> Result<usize> {
+        match &self.stream {
+            Stream::Capture { data: ep } if self.is_streaming() => ep.write(data),
+            _ => Err(UsbError::InvalidState),
+        }
+    }
+
+    /// Counts samples played by the device's audio clock, to measure its rate for the feedback
+    /// endpoint. Call it as the codec consumes samples, for example from a DMA interrupt.
+    pub fn count_samples(&mut self, samples: u32) {
+        self.samples = self.samples.wrapping_add(samples);
+    }
+
+    fn reset_stream(&mut self) {
+        self.samples = 0;
+        self.frames = 0;
+        self.feedback = nominal_feedback(self.sample_rate);
+    }
+
+    fn write_feedback(&self) {
+        if let Stream::Playback { feedback, .. } = &self.stream {
+            if self.is_streaming() {
+                feedback.write(&self.feedback.to_le_bytes()[..3]).ok();
+            }
+        }
+    }
+
+    fn data_endpoint_address(&self) -> EndpointAddress {
+        match &self.stream {
+            Stream::Playback { data, .. } => data.address(),
+            Stream::Capture { data } => data.address(),
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) -> bool {
+        if !self.config.sample_rates.contains(&sample_rate) {
+            return false;
+        }
+
+        usb_debug!("Audio sample rate {}", sample_rate);
+        self.sample_rate = sample_rate;
+        self.reset_stream();
+        true
+    }
+
+    fn set_volume(&mut self, volume: i16) {
+        let (min, max, _) = self.config.volume_range;
+        self.volume = volume.clamp(min, max);
+    }
+
+    // Gets the value of an audio control for a request, returning its length.
+    fn get_control(&self, req: &control::Request, buf: &mut [u8]) -> Option<usize> {
+        let entity = (req.index >> 8) as u8;
+        let selector = (req.value >> 8) as u8;
+        let channel = req.value as u8;
+
+        let (min, max, resolution) = self.config.volume_range;
+
+        let value: &[u8] = match (self.version, req.recipient, entity, selector) {
+            (AudioVersion::Uac1, Recipient::Endpoint, _, SAMPLING_FREQ_CONTROL)
+                if req.request == audio_request::GET_CUR =>
+            {
+                &self.sample_rate.to_le_bytes()[..3]
+            }
+            (AudioVersion::Uac1, Recipient::Interface, entity::FEATURE_UNIT, _) if channel == 0 => {
+                match (selector, req.request) {
+                    (feature_unit_control::MUTE, audio_request::GET_CUR) => &[self.mute as u8],
+                    (feature_unit_control::VOLUME, audio_request::GET_CUR) => {
+                        &self.volume.to_le_bytes()
+                    }
+                    (feature_unit_control::VOLUME, audio_request::GET_MIN) => &min.to_le_bytes(),
+                    (feature_unit_control::VOLUME, audio_request::GET_MAX) => &max.to_le_bytes(),
+                    (feature_unit_control::VOLUME, audio_request::GET_RES) => {
+                        &resolution.to_le_bytes()
+                    }
+                    _ => return None,
+                }
+            }
+            (AudioVersion::Uac2, Recipient::Interface, entity::FEATURE_UNIT, _) if channel == 0 => {
+                match (selector, req.request) {
+                    (feature_unit_control::MUTE, audio_request::CUR) => &[self.mute as u8],
+                    (feature_unit_control::VOLUME, audio_request::CUR) => {
+                        &self.volume.to_le_bytes()
+                    }
+                    (feature_unit_control::VOLUME, audio_request::RANGE) => {
+                        let mut range = [0u8; 8];
+                        range[..2].copy_from_slice(&1u16.to_le_bytes());
+                        range[2..4].copy_from_slice(&min.to_le_bytes());
+                        range[4..6].copy_from_slice(&max.to_le_bytes());
+                        range[6..8].copy_from_slice(&resolution.to_le_bytes());
+                        return copy_response(&range, buf);
+                    }
+                    _ => return None,
+                }
+            }
+            (AudioVersion::Uac2, Recipient::Interface, entity::CLOCK_SOURCE, _) => {
+                match (selector, req.request) {
+                    (clock_source_control::SAM_FREQ, audio_request::CUR) => {
+                        &self.sample_rate.to_le_bytes()
+                    }
+                    (clock_source_control::SAM_FREQ, audio_request::RANGE) => {
+                        return sample_rate_ranges(self.config.sample_rates, buf);
+                    }
+                    (clock_source_control::CLOCK_VALID, audio_request::CUR) => &[1],
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+
+        copy_response(value, buf)
+    }
+
+    // Sets an audio control from a request, returning whether it was accepted.
+    fn set_control(&mut self, req: &control::Request, data: &[u8]) -> bool {
+        let entity = (req.index >> 8) as u8;
+        let selector = (req.value >> 8) as u8;
+        let channel = req.value as u8;
+
+        // The value of SET_CUR in Audio Device Class 1.0 and CUR in 2.0 is the same.
+        if req.request != audio_request::CUR {
+            return false;
+        }
+
+        match (self.version, req.recipient, entity, selector, data) {
+            (AudioVersion::Uac1, Recipient::Endpoint, _, SAMPLING_FREQ_CONTROL, &[b0, b1, b2]) => {
+                self.set_sample_rate(u32::from_le_bytes([b0, b1, b2, 0]))
+            }
+            (
+                AudioVersion::Uac2,
+                Recipient::Interface,
+                entity::CLOCK_SOURCE,
+                clock_source_control::SAM_FREQ,
+                &[b0, b1, b2, b3],
+            ) => self.set_sample_rate(u32::from_le_bytes([b0, b1, b2, b3])),
+            (
+                _,
+                Recipient::Interface,
+                entity::FEATURE_UNIT,
+                feature_unit_control::MUTE,
+                &[mute],
+            ) if channel == 0 => {
+                self.mute = mute != 0;
+                true
+            }
+            (
+                _,
+                Recipient::Interface,
+                entity::FEATURE_UNIT,
+                feature_unit_control::VOLUME,
+                &[b0, b1],
+            ) if channel == 0 => {
+                self.set_volume(i16::from_le_bytes([b0, b1]));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn is_for_function(&self, req: &control::Request) -> bool {
+        if req.request_type != RequestType::Class {
+            return false;
+        }
+
+        match req.recipient {
+            Recipient::Interface => req.index as u8 == u8::from(self.control_if),
+            Recipient::Endpoint => {
+                self.version == AudioVersion::Uac1
+                    && req.index == u8::from(self.data_endpoint_address()).into()
+            }
+            _ => false,
+        }
+    }
+
+    fn write_control_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        let config = &self.config;
+        let channel_config = config.channel_config();
+
+        let (input_type, output_type) = match self.stream {
+            Stream::Playback { .. } => (terminal_type::USB_STREAMING, config.terminal_type),
+            Stream::Capture { .. } => (config.terminal_type, terminal_type::USB_STREAMING),
+        };
+        let input_type = input_type.to_le_bytes();
+        let output_type = output_type.to_le_bytes();
+
+        // Master channel and logical channels
+        let controls = usize::from(config.channels) + 1;
+
+        match self.version {
+            AudioVersion::Uac1 => {
+                let total_length = (9 + 12 + (7 + controls) + 9) as u16;
+                let total_length = total_length.to_le_bytes();
+                let channel_config = channel_config.to_le_bytes();
+
+                writer.write(
+                    CS_INTERFACE,
+                    &[
+                        HEADER,
+                        0x00, // bcdADC
+                        0x01,
+                        total_length[0], // wTotalLength
+                        total_length[1],
+                        1,                     // bInCollection
+                        self.stream_if.into(), // baInterfaceNr
+                    ],
+                )?;
+                writer.write(
+                    CS_INTERFACE,
+                    &[
+                        INPUT_TERMINAL,
+                        entity::INPUT_TERMINAL, // bTerminalID
+                        input_type[0],          // wTerminalType
+                        input_type[1],
+                        0,                 // bAssocTerminal
+                        config.channels,   // bNrChannels
+                        channel_config[0], // wChannelConfig
+                        channel_config[1],
+                        0, // iChannelNames
+                        0, // iTerminal
+                    ],
+                )?;
+                writer.write_with(CS_INTERFACE, |buf| {
+                    let len = 5 + controls;
+                    if buf.len() < len {
+                        return Err(UsbError::BufferOverflow);
+                    }
+
+                    buf[0] = FEATURE_UNIT;
+                    buf[1] = entity::FEATURE_UNIT; // bUnitID
+                    buf[2] = entity::INPUT_TERMINAL; // bSourceID
+                    buf[3] = 1; // bControlSize
+
+                    // bmaControls: mute and volume on the master channel only
+                    buf[4..4 + controls].fill(0);
+                    buf[4] = 0x03;
+
+                    buf[4 + controls] = 0; // iFeature
+
+                    Ok(len)
+                })?;
+                writer.write(
+                    CS_INTERFACE,
+                    &[
+                        OUTPUT_TERMINAL,
+                        entity::OUTPUT_TERMINAL, // bTerminalID
+                        output_type[0],          // wTerminalType
+                        output_type[1],
+                        0,                    // bAssocTerminal
+                        entity::FEATURE_UNIT, // bSourceID
+                        0,                    // iTerminal
+                    ],
+                )
+            }
+            AudioVersion::Uac2 => {
+                let total_length = (9 + 8 + 17 + (6 + 4 * controls) + 12) as u16;
+                let total_length = total_length.to_le_bytes();
+                let channel_config = u32::from(channel_config).to_le_bytes();
+
+                // The sample rate can only be chosen if there are several.
+                let (clock_attributes, clock_controls) = if config.sample_rates.len() > 1 {
+                    (0x03, 0x07)
+                } else {
+                    (0x01, 0x05)
+                };
+
+                writer.write(
+                    CS_INTERFACE,
+                    &[
+                        HEADER,
+                        0x00, // bcdADC
+                        0x02,
+                        FUNCTION_CATEGORY_OTHER, // bCategory
+                        total_length[0],         // wTotalLength
+                        total_length[1],
+                        0x00, // bmControls
+                    ],
+                )?;
+                writer.write(
+                    CS_INTERFACE,
+                    &[
+                        CLOCK_SOURCE,
+                        entity::CLOCK_SOURCE, // bClockID
+                        clock_attributes,     // bmAttributes
+                        clock_controls,       // bmControls
+                        0,                    // bAssocTerminal
+                        0,                    // iClockSource
+                    ],
+                )?;
+                writer.write(
+                    CS_INTERFACE,
+                    &[
+                        INPUT_TERMINAL,
+                        entity::INPUT_TERMINAL, // bTerminalID
+                        input_type[0],          // wTerminalType
+                        input_type[1],
+                        0,                    // bAssocTerminal
+                        entity::CLOCK_SOURCE, // bCSourceID
+                        config.channels,      // bNrChannels
+                        channel_config[0],    // bmChannelConfig
+                        channel_config[1],
+                        channel_config[2],
+                        channel_config[3],
+                        0, // iChannelNames
+                        0, // bmControls
+                        0,
+                        0, // iTerminal
+                    ],
+                )?;
+                writer.write_with(CS_INTERFACE, |buf| {
+                    let len = 4 + 4 * controls;
+                    if buf.len() < len {
+                        return Err(UsbError::BufferOverflow);
+                    }
+
+                    buf[0] = FEATURE_UNIT;
+                    buf[1] = entity::FEATURE_UNIT; // bUnitID
+                    buf[2] = entity::INPUT_TERMINAL; // bSourceID
+
+                    // bmaControls: host programmable mute and volume on the master channel only
+                    buf[3..3 + 4 * controls].fill(0);
+                    buf[3] = 0x0f;
+
+                    buf[3 + 4 * controls] = 0; // iFeature
+
+                    Ok(len)
+                })?;
+                writer.write(
+                    CS_INTERFACE,
+                    &[
+                        OUTPUT_TERMINAL,
+                        entity::OUTPUT_TERMINAL, // bTerminalID
+                        output_type[0],          // wTerminalType
+                        output_type[1],
+                        0,                    // bAssocTerminal
+                        entity::FEATURE_UNIT, // bSourceID
+                        entity::CLOCK_SOURCE, // bCSourceID
+                        0,                    // bmControls
+                        0,
+                        0, // iTerminal
+                    ],
+                )
+            }
+        }
+    }
+
+    fn write_streaming_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        let config = &self.config;
+
+        // The terminal that connects the streaming interface to the topology.
+        let terminal_link = match self.stream {
+            Stream::Playback { .. } => entity::INPUT_TERMINAL,
+            Stream::Capture { .. } => entity::OUTPUT_TERMINAL,
+        };
+
+        match self.version {
+            AudioVersion::Uac1 => {
+                let format = FORMAT_PCM.to_le_bytes();
+
+                writer.write(
+                    CS_INTERFACE,
+                    &[
+                        AS_GENERAL,
+                        terminal_link, // bTerminalLink
+                        1,             // bDelay
+                        format[0],     // wFormatTag
+                        format[1],
+                    ],
+                )?;
+                writer.write_with(CS_INTERFACE, |buf| {
+                    let rates = config.sample_rates;
+                    let len = 6 + 3 * rates.len();
+                    if buf.len() < len {
+                        return Err(UsbError::BufferOverflow);
+                    }
+
+                    buf[0] = FORMAT_TYPE;
+                    buf[1] = FORMAT_TYPE_I;
+                    buf[2] = config.channels; // bNrChannels
+                    buf[3] = config.subframe_size; // bSubframeSize
+                    buf[4] = config.bit_resolution; // bBitResolution
+                    buf[5] = rates.len() as u8; // bSamFreqType
+
+                    // tSamFreq
+                    for (i, rate) in rates.iter().enumerate() {
+                        buf[6 + 3 * i..9 + 3 * i].copy_from_slice(&rate.to_le_bytes()[..3]);
+                    }
+
+                    Ok(len)
+                })?;
+            }
+            AudioVersion::Uac2 => {
+                let channel_config = u32::from(config.channel_config()).to_le_bytes();
+                let formats = u32::from(FORMAT_PCM).to_le_bytes();
+
+                writer.write(
+                    CS_INTERFACE,
+                    &[
+                        AS_GENERAL,
+                        terminal_link, // bTerminalLink
+                        0,             // bmControls
+                        FORMAT_TYPE_I, // bFormatType
+                        formats[0],    // bmFormats
+                        formats[1],
+                        formats[2],
+                        formats[3],
+                        config.channels,   // bNrChannels
+                        channel_config[0], // bmChannelConfig
+                        channel_config[1],
+                        channel_config[2],
+                        channel_config[3],
+                        0, // iChannelNames
+                    ],
+                )?;
+                writer.write(
+                    CS_INTERFACE,
+                    &[
+                        FORMAT_TYPE,
+                        FORMAT_TYPE_I,
+                        config.subframe_size,  // bSubslotSize
+                        config.bit_resolution, // bBitResolution
+                    ],
+                )?;
+            }
+        }
+
+        match &self.stream {
+            Stream::Playback { data, feedback } => {
+                self.write_data_endpoint(writer, data, feedback.address().into())?;
+                self.write_feedback_endpoint(writer, feedback)
+            }
+            Stream::Capture { data } => self.write_data_endpoint(writer, data, 0),
+        }
+    }
+
+    fn write_data_endpoint<D: EndpointDirection>(
+        &self,
+        writer: &mut DescriptorWriter,
+        endpoint: &Endpoint<'_, B, D>,
+        synch_address: u8,
+    ) -> Result<()> {
+        match self.version {
+            AudioVersion::Uac1 => {
+                // Audio Device Class 1.0 endpoint descriptors have bRefresh and bSynchAddress.
+                writer.endpoint_ex(endpoint, |buf| {
+                    if buf.len() < 2 {
+                        return Err(UsbError::BufferOverflow);
+                    }
+
+                    buf[0] = 0; // bRefresh
+                    buf[1] = synch_address; // bSynchAddress
+
+                    Ok(2)
+                })?;
+
+                let attributes = if self.config.sample_rates.len() > 1 {
+                    SAMPLING_FREQ_CONTROL
+                } else {
+                    0x00
+                };
+
+                writer.write(
+                    CS_ENDPOINT,
+                    &[
+                        EP_GENERAL, // bDescriptorSubtype
+                        attributes, // bmAttributes
+                        0,          // bLockDelayUnits
+                        0,          // wLockDelay
+                        0,
+                    ],
+                )
+            }
+            AudioVersion::Uac2 => {
+                writer.endpoint(endpoint)?;
+                writer.write(
+                    CS_ENDPOINT,
+                    &[
+                        EP_GENERAL, // bDescriptorSubtype
+                        0,          // bmAttributes
+                        0,          // bmControls
+                        0,          // bLockDelayUnits
+                        0,          // wLockDelay
+                        0,
+                    ],
+                )
+            }
+        }
+    }
+
+    fn write_feedback_endpoint(
+        &self,
+        writer: &mut DescriptorWriter,
+        endpoint: &EndpointIn<'a, B>,
+    ) -> Result<()> {
+        match self.version {
+            AudioVersion::Uac1 => writer.endpoint_ex(endpoint, |buf| {
+                if buf.len() < 2 {
+                    return Err(UsbError::BufferOverflow);
+                }
+
+                buf[0] = FEEDBACK_REFRESH; // bRefresh
+                buf[1] = 0; // bSynchAddress
+
+                Ok(2)
+            }),
+            AudioVersion::Uac2 => writer.endpoint(endpoint),
+        }
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for AudioClass<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        let protocol = match self.version {
+            AudioVersion::Uac1 => 0x00,
+            AudioVersion::Uac2 => PROTOCOL_IP_VERSION_02_00,
+        };
+
+        writer.iad(self.control_if, 2, USB_CLASS_AUDIO, 0x00, protocol, None)?;
+
+        writer.interface(
+            self.control_if,
+            USB_CLASS_AUDIO,
+            subclass::AUDIOCONTROL,
+            protocol,
+        )?;
+        self.write_control_descriptors(writer)?;
+
+        writer.interface(
+            self.stream_if,
+            USB_CLASS_AUDIO,
+            subclass::AUDIOSTREAMING,
+            protocol,
+        )?;
+        writer.interface_alt(
+            self.stream_if,
+            STREAM_ALT_ACTIVE,
+            USB_CLASS_AUDIO,
+            subclass::AUDIOSTREAMING,
+            protocol,
+            None,
+        )?;
+        self.write_streaming_descriptors(writer)
+    }
+
+    fn reset(&mut self) {
+        self.stream_alt = STREAM_ALT_IDLE;
+        self.reset_stream();
+    }
+
+    fn sof(&mut self, _frame_number: u16) {
+        if !self.is_streaming() {
+            return;
+        }
+
+        if let Stream::Playback { .. } = self.stream {
+            self.frames += 1;
+
+            if self.frames == 1 << FEEDBACK_REFRESH {
+                // Keep the nominal rate until the audio clock runs.
+                if self.samples != 0 {
+                    self.feedback = self.samples << (14 - FEEDBACK_REFRESH);
+                }
+
+                self.samples = 0;
+                self.frames = 0;
+            }
+        }
+    }
+
+    fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+        if let Stream::Playback { feedback, .. } = &self.stream {
+            if addr == feedback.address() {
+                self.write_feedback();
+            }
+        }
+    }
+
+    fn get_alt_setting(&mut self, interface: InterfaceNumber) -> Option<u8> {
+        if interface == self.stream_if {
+            Some(self.stream_alt)
+        } else {
+            None
+        }
+    }
+
+    fn set_alt_setting(&mut self, interface: InterfaceNumber, alternative: u8) -> bool {
+        if interface != self.stream_if || alternative > STREAM_ALT_ACTIVE {
+            return false;
+        }
+
+        self.stream_alt = alternative;
+        self.reset_stream();
+        self.write_feedback();
+
+        true
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+
+        if !self.is_for_function(&req) {
+            return;
+        }
+
+        let mut buf = [0u8; 2 + 12 * MAX_SAMPLE_RATES];
+        match self.get_control(&req, &mut buf) {
+            Some(len) => {
+                let len = len.min(req.length.into());
+                xfer.accept_with(&buf[..len]).ok();
+            }
+            None => {
+                xfer.reject().ok();
+            }
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+
+        if !self.is_for_function(&req) {
+            return;
+        }
+
+        if self.set_control(&req, xfer.data()) {
+            xfer.accept().ok();
+        } else {
+            xfer.reject().ok();
+        }
+    }
+}
+
+// Gets the packet size for one frame at the highest sample rate, with room for one more sample
+// as the rates of the host and device clocks differ.
+fn data_packet_size(config: &StreamConfig) -> Result<u16> {
+    let rates = config.sample_rates;
+    if rates.is_empty() || rates.len() > MAX_SAMPLE_RATES {
+        return Err(UsbError::Unsupported);
+    }
+
+    let max_rate = rates.iter().copied().max().unwrap_or(0) as usize;
+    let len = (max_rate.div_ceil(1000) + 1) * config.frame_size();
+
+    if len > 1023 {
+        return Err(UsbError::Unsupported);
+    }
+
+    Ok(len as u16)
+}
+
+// Gets the feedback value for a sample rate, in samples per frame in 10.14 format.
+fn nominal_feedback(sample_rate: u32) -> u32 {
+    ((u64::from(sample_rate) << 14) / 1000) as u32
+}
+
+// Writes the RANGE of the sampling frequency control, with one subrange per sample rate.
+fn sample_rate_ranges(rates: &[u32], buf: &mut [u8]) -> Option<usize> {
+    let len = 2 + 12 * rates.len();
+    if buf.len() < len {
+        return None;
+    }
+
+    buf[..2].copy_from_slice(&(rates.len() as u16).to_le_bytes()); // wNumSubRanges
+
+    for (range, rate) in buf[2..len].chunks_exact_mut(12).zip(rates) {
+        range[..4].copy_from_slice(&rate.to_le_bytes()); // dMIN
+        range[4..8].copy_from_slice(&rate.to_le_bytes()); // dMAX
+        range[8..].fill(0); // dRES
+    }
+
+    Some(len)
+}
+
+fn copy_response(value: &[u8], buf: &mut [u8]) -> Option<usize> {
+    let dest = buf.get_mut(..value.len())?;
+    dest.copy_from_slice(value);
+    Some(value.len())
+}
@@ -119,10 +119,96 @@ pub trait UsbBus: Sized {
     /// suspended.
     fn resume(&self);
 
+    /// Signals a remote wakeup to the host by driving resume signalling on the bus for 1 to 15
+    /// milliseconds, as required by the USB 2.0 spec section 7.1.7.7. This will be called by
+    /// [`UsbDevice::remote_wakeup`](crate::device::UsbDevice::remote_wakeup) after
+    /// [`resume`](UsbBus::resume), and only if the host has enabled remote wakeup.
+    ///
+    /// Implementations may either block until the signalling is complete, or start it and end it
+    /// from a timer or interrupt.
+    ///
+    /// The default implementation just returns `Unsupported`.
+    ///
+    /// # Errors
+    ///
+    /// * [`Unsupported`](crate::UsbError::Unsupported) - This UsbBus implementation doesn't support
+    ///   remote wakeup.
+    fn remote_wakeup(&self) -> Result<()> {
+        Err(UsbError::Unsupported)
+    }
+
+    /// Sets whether the peripheral accepts Link Power Management (LPM) transactions from the host
+    /// by answering them with ACK and entering the L1 sleep state, which must then be reported with
+    /// [`PollResult::Sleep`]. Rejected transactions are answered with NYET and the bus stays
+    /// active. This is only called for devices that enable LPM with
+    /// [`UsbDeviceBuilder::lpm`](crate::device::UsbDeviceBuilder::lpm), after every bus reset and
+    /// whenever the application changes the setting with
+    /// [`UsbDevice::set_lpm_accepted`](crate::device::UsbDevice::set_lpm_accepted).
+    ///
+    /// The default implementation just returns `Unsupported`.
+    ///
+    /// # Errors
+    ///
+    /// * [`Unsupported`](crate::UsbError::Unsupported) - This UsbBus implementation doesn't support
+    ///   LPM.
+    fn set_lpm_accepted(&self, accepted: bool) -> Result<()> {
+        let _ = accepted;
+        Err(UsbError::Unsupported)
+    }
+
     /// Gets information about events and incoming data. Usually called in a loop or from an
     /// interrupt handler. See the [`PollResult`] struct for more information.
     fn poll(&self) -> PollResult;
 
+    /// Gets the bus speed negotiated with the host during the last USB reset. This will be called
+    /// by [`UsbDevice`](crate::device::UsbDevice) after [`reset`](UsbBus::reset).
+    ///
+    /// The default implementation returns [`UsbSpeed::Full`].
+    fn speed(&self) -> UsbSpeed {
+        UsbSpeed::Full
+    }
+
+    /// Gets the highest bus speed the peripheral can operate at. Devices that can operate at
+    /// [`UsbSpeed::High`] answer the DEVICE_QUALIFIER and OTHER_SPEED_CONFIGURATION descriptor
+    /// requests, which describe the device at the speed it is not currently operating at.
+    ///
+    /// The default implementation returns [`UsbSpeed::Full`].
+    fn max_speed(&self) -> UsbSpeed {
+        UsbSpeed::Full
+    }
+
+    /// Gets the 11-bit frame number of the last start-of-frame packet received from the host. This
+    /// is used to answer SYNCH_FRAME requests and by classes that synchronize to the USB frame
+    /// clock.
+    ///
+    /// The default implementation just returns `Unsupported`.
+    ///
+    /// # Errors
+    ///
+    /// * [`Unsupported`](crate::UsbError::Unsupported) - This UsbBus implementation doesn't support
+    ///   reading the frame number.
+    fn frame_number(&self) -> Result<u16> {
+        Err(UsbError::Unsupported)
+    }
+
+    /// Puts the peripheral into one of the USB 2.0 electrical test modes. This will be called by
+    /// [`UsbDevice`](crate::device::UsbDevice) after the status stage of a SET_FEATURE(TEST_MODE)
+    /// request from the host has completed. Test modes are only defined for high speed, so this is
+    /// only called if [`max_speed`](UsbBus::max_speed) returns [`UsbSpeed::High`].
+    ///
+    /// The peripheral must stay in the test mode until it is power cycled.
+    ///
+    /// The default implementation just returns `Unsupported`.
+    ///
+    /// # Errors
+    ///
+    /// * [`Unsupported`](crate::UsbError::Unsupported) - This UsbBus implementation doesn't support
+    ///   the test mode.
+    fn set_test_mode(&self, mode: TestMode) -> Result<()> {
+        let _ = mode;
+        Err(UsbError::Unsupported)
+    }
+
     /// Simulates a disconnect from the USB bus, causing the host to reset and re-enumerate the
     /// device.
     ///
@@ -145,7 +231,18 @@ pub trait UsbBus: Sized {
 
 struct AllocatorState {
     next_interface_number: u8,
+    // The largest number of interfaces allocated for any configuration.
+    num_interfaces: u8,
     next_string_index: u8,
+    // One bit per allocated endpoint, with the IN endpoints in the upper half.
+    endpoints: u32,
+    // The same for isochronous endpoints only.
+    isochronous_endpoints: u32,
+}
+
+pub(crate) fn endpoint_bit(ep_addr: EndpointAddress) -> u32 {
+    let offset = if ep_addr.is_in() { 16 } else { 0 };
+    1 << (offset + ep_addr.index())
 }
 
 /// Helper type used for UsbBus resource allocation and initialization.
@@ -164,11 +261,33 @@ impl<B: UsbBus> UsbBusAllocator<B> {
             bus_ptr: AtomicPtr::new(ptr::null_mut()),
             state: RefCell::new(AllocatorState {
                 next_interface_number: 0,
+                num_interfaces: 0,
                 next_string_index: 4,
+                endpoints: 0,
+                isochronous_endpoints: 0,
             }),
         }
     }
 
+    // Gets whether a string index has been allocated with `string`.
+    pub(crate) fn string_allocated(&self, index: u8) -> bool {
+        (4..self.state.borrow().next_string_index).contains(&index)
+    }
+
+    pub(crate) fn num_interfaces(&self) -> u8 {
+        self.state.borrow().num_interfaces
+    }
+
+    // Gets the allocated endpoints, as bits from `endpoint_bit`.
+    pub(crate) fn endpoints(&self) -> u32 {
+        self.state.borrow().endpoints
+    }
+
+    // Gets the endpoints allocated as isochronous, as bits from `endpoint_bit`.
+    pub(crate) fn isochronous_endpoints(&self) -> u32 {
+        self.state.borrow().isochronous_endpoints
+    }
+
     pub(crate) fn freeze(&self) -> &B {
         // Prevent further allocation by borrowing the allocation state permanently.
         mem::forget(self.state.borrow_mut());
@@ -197,10 +316,22 @@ impl<B: UsbBus> UsbBusAllocator<B> {
         let mut state = self.state.borrow_mut();
         let number = state.next_interface_number;
         state.next_interface_number += 1;
+        state.num_interfaces = state.num_interfaces.max(state.next_interface_number);
 
         InterfaceNumber(number)
     }
 
+    /// Starts allocating interface numbers for another configuration of a device with multiple
+    /// [configurations](crate::device::UsbDeviceBuilder::configurations), so that the interfaces
+    /// of each configuration are numbered from zero. Endpoints and string indices stay unique
+    /// across the whole device.
+    ///
+    /// Classes that are created after calling this must be assigned to their configuration with
+    /// [`UsbConfiguration::classes`](crate::device::UsbConfiguration::classes).
+    pub fn next_configuration(&self) {
+        self.state.borrow_mut().next_interface_number = 0;
+    }
+
     /// Allocates a new string index.
     pub fn string(&self) -> StringIndex {
         let mut state = self.state.borrow_mut();
@@ -221,10 +352,28 @@ impl<B: UsbBus> UsbBusAllocator<B> {
         max_packet_size: u16,
         interval: u8,
     ) -> Result<Endpoint<'_, B, D>> {
-        self.bus
-            .borrow_mut()
-            .alloc_ep(D::DIRECTION, ep_addr, ep_type, max_packet_size, interval)
-            .map(|a| Endpoint::new(&self.bus_ptr, a, ep_type, max_packet_size, interval))
+        let ep_addr = self.bus.borrow_mut().alloc_ep(
+            D::DIRECTION,
+            ep_addr,
+            ep_type,
+            max_packet_size,
+            interval,
+        )?;
+
+        let mut state = self.state.borrow_mut();
+        state.endpoints |= endpoint_bit(ep_addr);
+
+        if let EndpointType::Isochronous { .. } = ep_type {
+            state.isochronous_endpoints |= endpoint_bit(ep_addr);
+        }
+
+        Ok(Endpoint::new(
+            &self.bus_ptr,
+            ep_addr,
+            ep_type,
+            max_packet_size,
+            interval,
+        ))
     }
 
     /// Allocates a control endpoint.
@@ -319,7 +468,7 @@ impl<B: UsbBus> UsbBusAllocator<B> {
 }
 
 /// A handle for a USB interface that contains its number.
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct InterfaceNumber(pub(crate) u8);
 
@@ -330,7 +479,7 @@ impl From<InterfaceNumber> for u8 {
 }
 
 /// A handle for a USB string descriptor that contains its index.
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct StringIndex(u8);
 
@@ -346,6 +495,49 @@ impl From<StringIndex> for u8 {
     }
 }
 
+/// USB bus speed.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UsbSpeed {
+    /// Low speed, 1.5 Mbit/s.
+    Low,
+    /// Full speed, 12 Mbit/s.
+    Full,
+    /// High speed, 480 Mbit/s.
+    High,
+}
+
+/// USB 2.0 electrical test modes selected with SET_FEATURE(TEST_MODE). See USB 2.0 spec section
+/// 7.1.20.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TestMode {
+    /// Test_J: drive a continuous J state.
+    J = 1,
+    /// Test_K: drive a continuous K state.
+    K = 2,
+    /// Test_SE0_NAK: stay in high speed receive mode and NAK every IN token.
+    Se0Nak = 3,
+    /// Test_Packet: repeatedly transmit the test packet defined by the specification.
+    Packet = 4,
+    /// Test_Force_Enable: enable the downstream facing port of a hub.
+    ForceEnable = 5,
+}
+
+impl TestMode {
+    pub(crate) fn from_selector(selector: u8) -> Option<TestMode> {
+        match selector {
+            1 => Some(TestMode::J),
+            2 => Some(TestMode::K),
+            3 => Some(TestMode::Se0Nak),
+            4 => Some(TestMode::Packet),
+            5 => Some(TestMode::ForceEnable),
+            _ => None,
+        }
+    }
+}
+
 /// Event and incoming packet information returned by [`UsbBus::poll`].
 pub enum PollResult {
     /// No events or packets to report.
@@ -354,7 +546,7 @@ pub enum PollResult {
     /// The USB reset condition has been detected.
     Reset,
 
-    /// USB packets have been received or sent. Each data field is a bit-field where the least
+    /// USB packets have been received or sent. Each endpoint field is a bit-field where the least
     /// significant bit represents endpoint 0 etc., and a set bit signifies the event has occurred
     /// for the corresponding endpoint.
     Data {
@@ -369,6 +561,13 @@ pub enum PollResult {
         /// A SETUP packet has been received. This event should continue to be reported until the
         /// packet is read. The corresponding bit in `ep_out` may also be set but is ignored.
         ep_setup: u16,
+
+        /// A start-of-frame packet with the contained 11-bit frame number has been received. This
+        /// may be reported with all endpoint fields zero. Reporting this is optional, and
+        /// implementations should only do so if SOF interrupts have been requested by the
+        /// application, because they occur every millisecond (or every 125 microseconds at high
+        /// speed).
+        sof: Option<u16>,
     },
 
     /// A USB suspend request has been detected or, in the case of self-powered devices, the device
@@ -378,4 +577,15 @@ pub enum PollResult {
     /// A USB resume request has been detected after being suspended or, in the case of self-powered
     /// devices, the device has been connected to the USB bus.
     Resume,
+
+    /// The host has put the bus into the L1 sleep state with an LPM transaction accepted by the
+    /// peripheral. The peripheral shall return a value other than `Sleep` from `poll` when the bus
+    /// resumes, such as [`PollResult::Resume`].
+    Sleep {
+        /// The Best Effort Service Latency (BESL) requested by the host, from 0 to 15.
+        besl: u8,
+
+        /// Whether the host allows the device to wake it up with remote wakeup signalling.
+        remote_wakeup: bool,
+    },
 }
@@ -0,0 +1,617 @@
+use crate::class_prelude::*;
+use crate::Result;
+
+/// Interface class code of CDC communication interfaces.
+pub const USB_CLASS_CDC: u8 = 0x02;
+
+/// Interface class code of CDC data interfaces.
+pub const USB_CLASS_CDC_DATA: u8 = 0x0a;
+
+/// Descriptor type of class-specific interface descriptors.
+pub const CS_INTERFACE: u8 = 0x24;
+
+/// Descriptor type of class-specific endpoint descriptors.
+pub const CS_ENDPOINT: u8 = 0x25;
+
+/// Release of the CDC specification that the classes conform to, in BCD.
+pub const BCD_CDC: u16 = 0x0110;
+
+/// Communication interface subclass codes
+#[allow(missing_docs)]
+pub mod subclass {
+    pub const ACM: u8 = 0x02;
+    pub const ECM: u8 = 0x06;
+    pub const NCM: u8 = 0x0d;
+}
+
+/// Functional descriptor subtypes
+#[allow(missing_docs)]
+pub mod functional_descriptor {
+    pub const HEADER: u8 = 0x00;
+    pub const CALL_MANAGEMENT: u8 = 0x01;
+    pub const ACM: u8 = 0x02;
+    pub const UNION: u8 = 0x06;
+    pub const ETHERNET_NETWORKING: u8 = 0x0f;
+    pub const NCM: u8 = 0x1a;
+}
+
+/// CDC class request codes
+#[allow(missing_docs)]
+pub mod cdc_request {
+    pub const SEND_ENCAPSULATED_COMMAND: u8 = 0x00;
+    pub const GET_ENCAPSULATED_RESPONSE: u8 = 0x01;
+    pub const SET_LINE_CODING: u8 = 0x20;
+    pub const GET_LINE_CODING: u8 = 0x21;
+    pub const SET_CONTROL_LINE_STATE: u8 = 0x22;
+    pub const SEND_BREAK: u8 = 0x23;
+    pub const SET_ETHERNET_MULTICAST_FILTERS: u8 = 0x40;
+    pub const SET_ETHERNET_PACKET_FILTER: u8 = 0x43;
+    pub const GET_ETHERNET_STATISTIC: u8 = 0x44;
+    pub const GET_NTB_PARAMETERS: u8 = 0x80;
+    pub const GET_NTB_FORMAT: u8 = 0x83;
+    pub const SET_NTB_FORMAT: u8 = 0x84;
+}
+
+/// CDC notification codes
+#[allow(missing_docs)]
+pub mod notification {
+    pub const NETWORK_CONNECTION: u8 = 0x00;
+    pub const RESPONSE_AVAILABLE: u8 = 0x01;
+    pub const SERIAL_STATE: u8 = 0x20;
+    pub const CONNECTION_SPEED_CHANGE: u8 = 0x2a;
+}
+
+/// CDC Abstract Control Model, for virtual serial ports.
+pub mod acm;
+
+/// CDC Network Control Model, for Ethernet-over-USB.
+pub mod ncm;
+
+/// CDC Ethernet Control Model, for Ethernet-over-USB with older Linux and macOS hosts.
+pub mod ecm;
+
+/// Remote NDIS, for Ethernet-over-USB with older Windows hosts.
+pub mod rndis;
+
+/// Maximum size of an Ethernet frame without the frame check sequence.
+pub const MAX_SEGMENT_SIZE: usize = 1514;
+
+/// The frame-level interface shared by the Ethernet functions [`CdcNcmClass`](ncm::CdcNcmClass),
+/// [`CdcEcmClass`](ecm::CdcEcmClass) and [`RndisClass`](rndis::RndisClass), so that the network
+/// stack can be connected to whichever function the host uses.
+///
+/// [`receive_frame`](EthernetFunction::receive_frame) and
+/// [`transmit_frame`](EthernetFunction::transmit_frame) give closures direct access to the frame
+/// in the buffers of the function, which matches the `RxToken` and `TxToken` traits of network
+/// stacks such as smoltcp.
+pub trait EthernetFunction {
+    /// Gets whether the host is ready to exchange frames, which it is once it has brought its
+    /// network interface up.
+    fn is_active(&self) -> bool;
+
+    /// Gets whether the link is reported as connected.
+    fn is_connected(&self) -> bool;
+
+    /// Reports the link as connected or disconnected to the host. The host doesn't exchange frames
+    /// while the link is disconnected.
+    fn set_connected(&mut self, connected: bool);
+
+    /// Reports the bit rates of the link in bits per second to the host. Downlink is the
+    /// direction to the host.
+    fn set_connection_speed(&mut self, downlink_bit_rate: u32, uplink_bit_rate: u32);
+
+    /// Gets whether a received frame is waiting to be read.
+    fn can_receive(&mut self) -> bool;
+
+    /// Gets whether a frame can be sent.
+    fn can_transmit(&self) -> bool;
+
+    /// Passes the next received frame to `f` and removes it.
+    ///
+    /// # Errors
+    ///
+    /// * [`WouldBlock`](crate::UsbError::WouldBlock) - No frame has been received.
+    fn receive_frame<R>(&mut self, f: impl FnOnce(&mut [u8]) -> R) -> Result<R>;
+
+    /// Sends a frame of `len` bytes, which `f` writes into the buffer passed to it.
+    ///
+    /// # Errors
+    ///
+    /// * [`WouldBlock`](crate::UsbError::WouldBlock) - The previous frame has not been sent yet.
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - The host is not ready to exchange
+    ///   frames.
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - The frame doesn't fit in the
+    ///   transmit buffer.
+    fn transmit_frame<R>(&mut self, len: usize, f: impl FnOnce(&mut [u8]) -> R) -> Result<R>;
+
+    /// Copies the next received frame into `buf` and returns its length.
+    ///
+    /// # Errors
+    ///
+    /// * [`WouldBlock`](crate::UsbError::WouldBlock) - No frame has been received.
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - `buf` is too short for the frame.
+    ///   The frame is dropped.
+    fn read_frame(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.receive_frame(|frame| {
+            let dest = buf.get_mut(..frame.len()).ok_or(UsbError::BufferOverflow)?;
+            dest.copy_from_slice(frame);
+            Ok(frame.len())
+        })?
+    }
+
+    /// Sends a copy of `frame`.
+    ///
+    /// # Errors
+    ///
+    /// See [`transmit_frame`](EthernetFunction::transmit_frame).
+    fn write_frame(&mut self, frame: &[u8]) -> Result<()> {
+        self.transmit_frame(frame.len(), |buf| buf.copy_from_slice(frame))
+    }
+}
+
+/// A FIFO of bytes in a buffer supplied by the class user.
+pub(crate) struct RingBuffer<'a> {
+    buf: &'a mut [u8],
+    start: usize,
+    len: usize,
+}
+
+impl<'a> RingBuffer<'a> {
+    pub(crate) fn new(buf: &'a mut [u8]) -> Self {
+        RingBuffer {
+            buf,
+            start: 0,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(crate) fn free(&self) -> usize {
+        self.buf.len() - self.len
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+
+    /// Appends as much of `data` as fits and returns the number of bytes appended.
+    pub(crate) fn push(&mut self, data: &[u8]) -> usize {
+        let count = data.len().min(self.free());
+
+        for (i, &byte) in data[..count].iter().enumerate() {
+            let index = (self.start + self.len + i) % self.buf.len();
+            self.buf[index] = byte;
+        }
+
+        self.len += count;
+        count
+    }
+
+    /// Removes up to `data.len()` bytes from the front and returns the number of bytes removed.
+    pub(crate) fn pop(&mut self, data: &mut [u8]) -> usize {
+        let count = data.len().min(self.len);
+
+        for (i, byte) in data[..count].iter_mut().enumerate() {
+            *byte = self.buf[(self.start + i) % self.buf.len()];
+        }
+
+        self.consume(count);
+        count
+    }
+
+    /// Gets the contiguous bytes at the front.
+    pub(crate) fn head(&self) -> &[u8] {
+        let end = (self.start + self.len).min(self.buf.len());
+        &self.buf[self.start..end]
+    }
+
+    /// Removes `count` bytes from the front.
+    pub(crate) fn consume(&mut self, count: usize) {
+        let count = count.min(self.len);
+
+        self.len -= count;
+        self.start = if self.len == 0 {
+            0
+        } else {
+            (self.start + count) % self.buf.len()
+        };
+    }
+
+    /// Gets `count` contiguous free bytes at the back, rotating the contents to the start of the
+    /// buffer if necessary, or `None` if there is not enough free space. Bytes written to them are
+    /// appended with [`commit`](RingBuffer::commit).
+    pub(crate) fn tail_mut(&mut self, count: usize) -> Option<&mut [u8]> {
+        if count > self.free() {
+            return None;
+        }
+
+        let capacity = self.buf.len();
+        let end = self.start + self.len;
+
+        let range = if end >= capacity {
+            // The contents wrap around, so the free space is between the end and the start.
+            end - capacity..end - capacity + count
+        } else if capacity - end >= count {
+            end..end + count
+        } else {
+            self.buf.rotate_left(self.start);
+            self.start = 0;
+            self.len..self.len + count
+        };
+
+        Some(&mut self.buf[range])
+    }
+
+    /// Appends `count` bytes written to the slice returned by [`tail_mut`](RingBuffer::tail_mut).
+    pub(crate) fn commit(&mut self, count: usize) {
+        self.len += count.min(self.free());
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum TransferState {
+    Receiving,
+    // Dropping the packets of a transfer that doesn't fit in the buffer.
+    Discarding,
+    Complete,
+}
+
+/// Collects the packets of bulk OUT transfers in a buffer supplied by the class user. A transfer
+/// ends with a short packet or when the buffer is full. While a complete transfer is waiting to be
+/// released, further packets are left in the endpoint, so that the host is held off with NAKs.
+pub(crate) struct TransferReceiver<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    state: TransferState,
+    pending: bool,
+}
+
+impl<'a> TransferReceiver<'a> {
+    pub(crate) fn new(buf: &'a mut [u8]) -> Self {
+        TransferReceiver {
+            buf,
+            len: 0,
+            state: TransferState::Receiving,
+            pending: false,
+        }
+    }
+
+    /// Gets the size of the largest transfer that can be received.
+    pub(crate) fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Gets the complete transfer, if there is one.
+    pub(crate) fn transfer(&self) -> Option<&[u8]> {
+        match self.state {
+            TransferState::Complete => Some(&self.buf[..self.len]),
+            _ => None,
+        }
+    }
+
+    /// Gets the complete transfer for modification, if there is one.
+    pub(crate) fn transfer_mut(&mut self) -> Option<&mut [u8]> {
+        match self.state {
+            TransferState::Complete => Some(&mut self.buf[..self.len]),
+            _ => None,
+        }
+    }
+
+    /// Releases the complete transfer and starts receiving the next one.
+    pub(crate) fn release<B: UsbBus>(&mut self, ep: &EndpointOut<B>) {
+        self.state = TransferState::Receiving;
+        self.len = 0;
+        self.fill(ep);
+    }
+
+    /// Handles a packet received on the endpoint.
+    pub(crate) fn endpoint_out<B: UsbBus>(&mut self, ep: &EndpointOut<B>) {
+        self.pending = true;
+        self.fill(ep);
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.len = 0;
+        self.state = TransferState::Receiving;
+        self.pending = false;
+    }
+
+    fn fill<B: UsbBus>(&mut self, ep: &EndpointOut<B>) {
+        if !self.pending {
+            return;
+        }
+
+        let max_packet_size = usize::from(ep.max_packet_size());
+
+        let result = match self.state {
+            TransferState::Complete => return,
+            TransferState::Discarding => {
+                let end = max_packet_size.min(self.buf.len());
+                ep.read(&mut self.buf[..end])
+            }
+            TransferState::Receiving => {
+                let end = self.buf.len().min(self.len + max_packet_size);
+                ep.read(&mut self.buf[self.len..end])
+            }
+        };
+
+        self.pending = false;
+
+        let count = match result {
+            Ok(count) => count,
+            Err(UsbError::WouldBlock) => return,
+            Err(_err) => {
+                usb_debug!("Dropping transfer: {:?}", _err);
+                self.state = TransferState::Discarding;
+                self.len = 0;
+                return;
+            }
+        };
+
+        if self.state == TransferState::Discarding {
+            if count < max_packet_size {
+                self.state = TransferState::Receiving;
+            }
+            return;
+        }
+
+        self.len += count;
+
+        if count < max_packet_size || self.len == self.buf.len() {
+            self.state = TransferState::Complete;
+        }
+    }
+}
+
+/// Sends bulk IN transfers from a buffer supplied by the class user. Transfers that end with a
+/// full packet are terminated with a zero-length packet, unless they are as long as the largest
+/// transfer the host expects.
+pub(crate) struct TransferSender<'a> {
+    buf: &'a mut [u8],
+    max_transfer_size: usize,
+    len: usize,
+    pos: usize,
+    in_flight: bool,
+    needs_zlp: bool,
+}
+
+impl<'a> TransferSender<'a> {
+    pub(crate) fn new(buf: &'a mut [u8], max_transfer_size: usize) -> Self {
+        TransferSender {
+            buf,
+            max_transfer_size,
+            len: 0,
+            pos: 0,
+            in_flight: false,
+            needs_zlp: false,
+        }
+    }
+
+    /// Gets the size of the largest transfer that can be sent.
+    pub(crate) fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Gets whether a transfer is being sent.
+    pub(crate) fn is_busy(&self) -> bool {
+        self.len != 0
+    }
+
+    /// Gets the buffer to write the next transfer into.
+    pub(crate) fn buf_mut(&mut self) -> &mut [u8] {
+        self.buf
+    }
+
+    /// Starts sending the first `len` bytes of the buffer.
+    pub(crate) fn send<B: UsbBus>(&mut self, len: usize, ep: &EndpointIn<B>) {
+        self.len = len;
+        self.pos = 0;
+        self.flush(ep);
+    }
+
+    /// Handles the completion of a packet on the endpoint.
+    pub(crate) fn endpoint_in_complete<B: UsbBus>(&mut self, ep: &EndpointIn<B>) {
+        self.in_flight = false;
+        self.flush(ep);
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.len = 0;
+        self.pos = 0;
+        self.in_flight = false;
+        self.needs_zlp = false;
+    }
+
+    fn flush<B: UsbBus>(&mut self, ep: &EndpointIn<B>) {
+        if self.in_flight || self.len == 0 {
+            return;
+        }
+
+        if self.pos == self.len {
+            if !self.needs_zlp {
+                self.len = 0;
+                self.pos = 0;
+            } else if ep.write(&[]).is_ok() {
+                self.needs_zlp = false;
+                self.in_flight = true;
+            }
+
+            return;
+        }
+
+        let max_packet_size = usize::from(ep.max_packet_size());
+        let end = self.len.min(self.pos + max_packet_size);
+
+        match ep.write(&self.buf[self.pos..end]) {
+            Ok(count) => {
+                self.pos += count;
+                self.in_flight = true;
+                self.needs_zlp = self.pos == self.len
+                    && count == max_packet_size
+                    && self.len < self.max_transfer_size;
+            }
+            Err(UsbError::WouldBlock) => {}
+            Err(_err) => {
+                usb_debug!("Failed to send transfer: {:?}", _err);
+            }
+        }
+    }
+}
+
+// Pending link notifications
+const NOTIFY_SPEED: u8 = 0x01;
+const NOTIFY_CONNECTION: u8 = 0x02;
+
+// Bit rate reported until the application sets one.
+const DEFAULT_BIT_RATE: u32 = 12_000_000;
+
+/// The link state of an Ethernet function, reported to the host with NETWORK_CONNECTION and
+/// CONNECTION_SPEED_CHANGE notifications.
+pub(crate) struct LinkState {
+    connected: bool,
+    downlink_bit_rate: u32,
+    uplink_bit_rate: u32,
+    pending: u8,
+    in_flight: bool,
+}
+
+impl LinkState {
+    pub(crate) fn new() -> Self {
+        LinkState {
+            connected: false,
+            downlink_bit_rate: DEFAULT_BIT_RATE,
+            uplink_bit_rate: DEFAULT_BIT_RATE,
+            pending: 0,
+            in_flight: false,
+        }
+    }
+
+    pub(crate) fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    pub(crate) fn downlink_bit_rate(&self) -> u32 {
+        self.downlink_bit_rate
+    }
+
+    pub(crate) fn set_connected(&mut self, connected: bool) {
+        self.connected = connected;
+        self.pending |= NOTIFY_CONNECTION;
+    }
+
+    pub(crate) fn set_speed(&mut self, downlink_bit_rate: u32, uplink_bit_rate: u32) {
+        self.downlink_bit_rate = downlink_bit_rate;
+        self.uplink_bit_rate = uplink_bit_rate;
+        self.pending |= NOTIFY_SPEED;
+    }
+
+    /// Queues both notifications, which the host expects when the data interface becomes active.
+    pub(crate) fn notify_all(&mut self) {
+        self.pending = NOTIFY_SPEED | NOTIFY_CONNECTION;
+    }
+
+    /// Handles the completion of a notification on the endpoint.
+    pub(crate) fn endpoint_in_complete<B: UsbBus>(
+        &mut self,
+        ep: &EndpointIn<B>,
+        interface: InterfaceNumber,
+    ) {
+        self.in_flight = false;
+        self.send(ep, interface);
+    }
+
+    /// Sends the next pending notification, unless one is still being sent.
+    pub(crate) fn send<B: UsbBus>(&mut self, ep: &EndpointIn<B>, interface: InterfaceNumber) {
+        if self.in_flight {
+            return;
+        }
+
+        let mut packet = [0u8; 16];
+        packet[0] = 0xa1; // bmRequestType
+        packet[4] = interface.into(); // wIndex
+
+        let (len, sent) = if self.pending & NOTIFY_SPEED != 0 {
+            packet[1] = notification::CONNECTION_SPEED_CHANGE; // bNotification
+            packet[6] = 8; // wLength
+            packet[8..12].copy_from_slice(&self.downlink_bit_rate.to_le_bytes()); // DLBitRate
+            packet[12..16].copy_from_slice(&self.uplink_bit_rate.to_le_bytes()); // ULBitRate
+            (16, NOTIFY_SPEED)
+        } else if self.pending & NOTIFY_CONNECTION != 0 {
+            packet[1] = notification::NETWORK_CONNECTION; // bNotification
+            packet[2] = self.connected.into(); // wValue
+            (8, NOTIFY_CONNECTION)
+        } else {
+            return;
+        };
+
+        if ep.write(&packet[..len]).is_ok() {
+            self.pending &= !sent;
+            self.in_flight = true;
+        }
+    }
+
+    /// Drops the pending notifications and forgets the one being sent.
+    pub(crate) fn reset(&mut self) {
+        self.pending = 0;
+        self.in_flight = false;
+    }
+}
+
+/// Formats a MAC address as the 12 uppercase hexadecimal digits of the iMACAddress string.
+pub(crate) fn mac_address_hex(mac_address: [u8; 6]) -> [u8; 12] {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+    let mut hex = [0u8; 12];
+    for (i, byte) in mac_address.iter().enumerate() {
+        hex[i * 2] = HEX[usize::from(byte >> 4)];
+        hex[i * 2 + 1] = HEX[usize::from(byte & 0x0f)];
+    }
+
+    hex
+}
+
+/// Writes the Ethernet Networking functional descriptor.
+pub(crate) fn write_ethernet_networking(
+    writer: &mut DescriptorWriter,
+    mac_address_string: StringIndex,
+) -> Result<()> {
+    let max_segment_size = (MAX_SEGMENT_SIZE as u16).to_le_bytes();
+
+    writer.write(
+        CS_INTERFACE,
+        &[
+            functional_descriptor::ETHERNET_NETWORKING,
+            mac_address_string.into(), // iMACAddress
+            0,
+            0,
+            0,
+            0, // bmEthernetStatistics
+            max_segment_size[0],
+            max_segment_size[1], // wMaxSegmentSize
+            0,
+            0, // wNumberMCFilters
+            0, // bNumberPowerFilters
+        ],
+    )
+}
+
+pub(crate) fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+pub(crate) fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
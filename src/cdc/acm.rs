@@ -0,0 +1,651 @@
+use crate::cdc::{
+    cdc_request, functional_descriptor, notification, subclass, RingBuffer, BCD_CDC, CS_INTERFACE,
+    USB_CLASS_CDC, USB_CLASS_CDC_DATA,
+};
+use crate::class_prelude::*;
+use crate::control::{Recipient, RequestType};
+use crate::Result;
+
+// Size of the SERIAL_STATE notification, the largest one sent.
+const NOTIFICATION_SIZE: usize = 10;
+
+// Maximum packet size of the notification endpoint.
+const NOTIFICATION_MAX_PACKET_SIZE: u16 = 16;
+
+// Polling interval of the notification endpoint in milliseconds.
+const NOTIFICATION_INTERVAL: u8 = 255;
+
+/// Bits of the UART state sent with [`CdcAcmClass::set_serial_state`]
+#[allow(missing_docs)]
+pub mod serial_state {
+    /// Data Carrier Detect
+    pub const DCD: u16 = 0x01;
+    /// Data Set Ready
+    pub const DSR: u16 = 0x02;
+    pub const BREAK: u16 = 0x04;
+    pub const RING: u16 = 0x08;
+    pub const FRAMING_ERROR: u16 = 0x10;
+    pub const PARITY_ERROR: u16 = 0x20;
+    pub const OVERRUN: u16 = 0x40;
+}
+
+/// Number of stop bits of the line coding.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StopBits {
+    /// 1 stop bit
+    One = 0,
+    /// 1.5 stop bits
+    OnePointFive = 1,
+    /// 2 stop bits
+    Two = 2,
+}
+
+/// Parity of the line coding.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ParityType {
+    /// No parity bit
+    None = 0,
+    /// Odd parity
+    Odd = 1,
+    /// Even parity
+    Even = 2,
+    /// Parity bit always 1
+    Mark = 3,
+    /// Parity bit always 0
+    Space = 4,
+}
+
+/// Serial line settings selected by the host with SET_LINE_CODING. A virtual serial port
+/// doesn't need to honor them, but they can be used to configure a real UART.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LineCoding {
+    /// Data rate in bits per second.
+    pub data_rate: u32,
+    /// Number of stop bits.
+    pub stop_bits: StopBits,
+    /// Parity.
+    pub parity_type: ParityType,
+    /// Number of data bits: 5, 6, 7, 8 or 16.
+    pub data_bits: u8,
+}
+
+impl Default for LineCoding {
+    /// 9600 bits per second, 8 data bits, no parity and 1 stop bit.
+    fn default() -> Self {
+        LineCoding {
+            data_rate: 9600,
+            stop_bits: StopBits::One,
+            parity_type: ParityType::None,
+            data_bits: 8,
+        }
+    }
+}
+
+impl LineCoding {
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 7 {
+            return None;
+        }
+
+        let stop_bits = match data[4] {
+            0 => StopBits::One,
+            1 => StopBits::OnePointFive,
+            2 => StopBits::Two,
+            _ => return None,
+        };
+
+        let parity_type = match data[5] {
+            0 => ParityType::None,
+            1 => ParityType::Odd,
+            2 => ParityType::Even,
+            3 => ParityType::Mark,
+            4 => ParityType::Space,
+            _ => return None,
+        };
+
+        Some(LineCoding {
+            data_rate: u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+            stop_bits,
+            parity_type,
+            data_bits: data[6],
+        })
+    }
+
+    fn to_bytes(self) -> [u8; 7] {
+        let rate = self.data_rate.to_le_bytes();
+
+        [
+            rate[0],
+            rate[1],
+            rate[2],
+            rate[3],                // dwDTERate
+            self.stop_bits as u8,   // bCharFormat
+            self.parity_type as u8, // bParityType
+            self.data_bits,         // bDataBits
+        ]
+    }
+}
+
+/// A CDC Abstract Control Model function, which appears as a serial port on the host.
+///
+/// The function consists of a communication interface with a notification endpoint and a data
+/// interface with a pair of bulk endpoints. When the device is built with
+/// [`composite_with_iads`](crate::device::UsbDeviceBuilder::composite_with_iads), the two
+/// interfaces are grouped with an interface association descriptor so that the function can be
+/// combined with others.
+///
+/// Received data is buffered in `rx_buf` and data to send in `tx_buf`. When `rx_buf` is full, the
+/// OUT endpoint is not read, so that the host is held off with NAKs until
+/// [`read`](CdcAcmClass::read) frees space. Transfers that end with a full packet are terminated
+/// with a zero-length packet, so that the host doesn't wait for more data.
+///
+/// With the `embedded-io` feature, the class implements the `embedded_io` `ReadReady` and
+/// `WriteReady` traits, and [`blocking`](CdcAcmClass::blocking) provides the blocking `Read` and
+/// `Write` traits. Data is only received and sent while
+/// [`UsbDevice::poll`](crate::device::UsbDevice::poll) is called, so the caller must keep polling
+/// while blocked, which the closure passed to `blocking` does.
+///
+/// ```no_run
+/// use usb_device::cdc::acm::CdcAcmClass;
+/// use usb_device::class_prelude::*;
+/// use usb_device::dummy::DummyUsbBus;
+/// use usb_device::prelude::*;
+///
+/// let usb_bus = UsbBusAllocator::new(DummyUsbBus::new());
+///
+/// let (mut rx_buf, mut tx_buf) = ([0u8; 128], [0u8; 128]);
+/// let mut serial = CdcAcmClass::new(&usb_bus, 64, &mut rx_buf, &mut tx_buf).unwrap();
+///
+/// let mut control_buffer = [0u8; 64];
+/// let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+///     .build()
+///     .unwrap();
+///
+/// loop {
+///     usb_dev.poll(&mut [&mut serial]);
+///
+///     // Echo everything back
+///     let mut buf = [0u8; 64];
+///     if let Ok(count) = serial.read(&mut buf) {
+///         serial.write(&buf[..count]).ok();
+///     }
+/// }
+/// ```
+pub struct CdcAcmClass<'a, B: UsbBus> {
+    comm_if: InterfaceNumber,
+    comm_ep: EndpointIn<'a, B>,
+    data_if: InterfaceNumber,
+    read_ep: EndpointOut<'a, B>,
+    write_ep: EndpointIn<'a, B>,
+    rx: RingBuffer<'a>,
+    tx: RingBuffer<'a>,
+    rx_pending: bool,
+    tx_in_flight: bool,
+    tx_needs_zlp: bool,
+    notification_in_flight: bool,
+    line_coding: LineCoding,
+    dtr: bool,
+    rts: bool,
+    break_duration: Option<u16>,
+}
+
+impl<'a, B: UsbBus> CdcAcmClass<'a, B> {
+    /// Creates a new CDC-ACM function.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_packet_size` - Maximum packet size of the bulk endpoints: 8, 16, 32 or 64 at full
+    ///   speed, 512 at high speed.
+    /// * `rx_buf` - Buffer for received data. It must hold at least one packet.
+    /// * `tx_buf` - Buffer for data waiting to be sent.
+    ///
+    /// # Errors
+    ///
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - `rx_buf` is shorter than
+    ///   `max_packet_size`, or `tx_buf` is empty.
+    pub fn new(
+        alloc: &'a UsbBusAllocator<B>,
+        max_packet_size: u16,
+        rx_buf: &'a mut [u8],
+        tx_buf: &'a mut [u8],
+    ) -> Result<Self> {
+        if rx_buf.len() < usize::from(max_packet_size) || tx_buf.is_empty() {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        Ok(CdcAcmClass {
+            comm_if: alloc.interface(),
+            comm_ep: alloc.interrupt(NOTIFICATION_MAX_PACKET_SIZE, NOTIFICATION_INTERVAL),
+            data_if: alloc.interface(),
+            read_ep: alloc.bulk(max_packet_size),
+            write_ep: alloc.bulk(max_packet_size),
+            rx: RingBuffer::new(rx_buf),
+            tx: RingBuffer::new(tx_buf),
+            rx_pending: false,
+            tx_in_flight: false,
+            tx_needs_zlp: false,
+            notification_in_flight: false,
+            line_coding: LineCoding::default(),
+            dtr: false,
+            rts: false,
+            break_duration: None,
+        })
+    }
+
+    /// Gets the line coding selected by the host.
+    pub fn line_coding(&self) -> &LineCoding {
+        &self.line_coding
+    }
+
+    /// Gets the state of the DTR signal. Terminal programs usually set it while the port is open.
+    pub fn dtr(&self) -> bool {
+        self.dtr
+    }
+
+    /// Gets the state of the RTS signal.
+    pub fn rts(&self) -> bool {
+        self.rts
+    }
+
+    /// Takes the duration of the last break requested by the host with SEND_BREAK, in
+    /// milliseconds. `0xffff` means that the break lasts until a break with a duration of 0 is
+    /// requested.
+    pub fn take_break(&mut self) -> Option<u16> {
+        self.break_duration.take()
+    }
+
+    /// Gets the number of received bytes waiting to be read.
+    pub fn available(&self) -> usize {
+        self.rx.len()
+    }
+
+    /// Gets the number of bytes that can be written without blocking.
+    pub fn write_capacity(&self) -> usize {
+        self.tx.free()
+    }
+
+    /// Reads received data into `data` and returns the number of bytes read.
+    ///
+    /// # Errors
+    ///
+    /// * [`WouldBlock`](crate::UsbError::WouldBlock) - No data has been received.
+    pub fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        let count = self.rx.pop(data);
+        self.fill_rx();
+
+        match count {
+            0 => Err(UsbError::WouldBlock),
+            count => Ok(count),
+        }
+    }
+
+    /// Queues as much of `data` as fits for sending and returns the number of bytes queued.
+    ///
+    /// # Errors
+    ///
+    /// * [`WouldBlock`](crate::UsbError::WouldBlock) - The send buffer is full.
+    pub fn write(&mut self, data: &[u8]) -> Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        let count = self.tx.push(data);
+        self.flush_tx();
+
+        match count {
+            0 => Err(UsbError::WouldBlock),
+            count => Ok(count),
+        }
+    }
+
+    /// Checks whether all queued data has been sent.
+    ///
+    /// # Errors
+    ///
+    /// * [`WouldBlock`](crate::UsbError::WouldBlock) - Data is still waiting to be sent.
+    pub fn flush(&mut self) -> Result<()> {
+        self.flush_tx();
+
+        if self.tx.is_empty() && !self.tx_in_flight && !self.tx_needs_zlp {
+            Ok(())
+        } else {
+            Err(UsbError::WouldBlock)
+        }
+    }
+
+    /// Wraps the class in [`Blocking`], which implements the blocking `embedded_io` `Read` and
+    /// `Write` traits. `poll` is called whenever a read or write has to wait, and must poll the
+    /// [`UsbDevice`](crate::device::UsbDevice) with the class.
+    ///
+    /// ```no_run
+    /// use embedded_io::{Read, Write};
+    /// use usb_device::cdc::acm::CdcAcmClass;
+    /// use usb_device::class_prelude::*;
+    /// use usb_device::dummy::DummyUsbBus;
+    /// use usb_device::prelude::*;
+    ///
+    /// let usb_bus = UsbBusAllocator::new(DummyUsbBus::new());
+    ///
+    /// let (mut rx_buf, mut tx_buf) = ([0u8; 128], [0u8; 128]);
+    /// let mut serial = CdcAcmClass::new(&usb_bus, 64, &mut rx_buf, &mut tx_buf).unwrap();
+    ///
+    /// let mut control_buffer = [0u8; 64];
+    /// let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let mut serial = serial.blocking(|serial| {
+    ///     usb_dev.poll(&mut [serial]);
+    /// });
+    ///
+    /// serial.write_all(b"Hello\r\n").unwrap();
+    /// serial.flush().unwrap();
+    ///
+    /// let mut buf = [0u8; 64];
+    /// let count = serial.read(&mut buf).unwrap();
+    /// ```
+    #[cfg(feature = "embedded-io")]
+    pub fn blocking<P: FnMut(&mut Self)>(&mut self, poll: P) -> Blocking<'_, 'a, B, P> {
+        Blocking { class: self, poll }
+    }
+
+    /// Sends a SERIAL_STATE notification with the given [`serial_state`] bits.
+    ///
+    /// # Errors
+    ///
+    /// * [`WouldBlock`](crate::UsbError::WouldBlock) - The previous notification has not been
+    ///   sent yet.
+    pub fn set_serial_state(&mut self, state: u16) -> Result<()> {
+        if self.notification_in_flight {
+            return Err(UsbError::WouldBlock);
+        }
+
+        let interface = u16::from(u8::from(self.comm_if)).to_le_bytes();
+        let state = state.to_le_bytes();
+
+        let packet: [u8; NOTIFICATION_SIZE] = [
+            0xa1,                       // bmRequestType
+            notification::SERIAL_STATE, // bNotification
+            0,
+            0, // wValue
+            interface[0],
+            interface[1], // wIndex
+            2,
+            0, // wLength
+            state[0],
+            state[1], // UART state bitmap
+        ];
+
+        self.comm_ep.write(&packet)?;
+        self.notification_in_flight = true;
+
+        Ok(())
+    }
+
+    // Moves a packet that was left in the OUT endpoint into the receive buffer once there is room.
+    fn fill_rx(&mut self) {
+        if !self.rx_pending {
+            return;
+        }
+
+        let max_packet_size = usize::from(self.read_ep.max_packet_size());
+        let buf = match self.rx.tail_mut(max_packet_size) {
+            Some(buf) => buf,
+            None => return,
+        };
+
+        match self.read_ep.read(buf) {
+            Ok(count) => {
+                self.rx.commit(count);
+                self.rx_pending = false;
+            }
+            Err(UsbError::WouldBlock) => self.rx_pending = false,
+            Err(_err) => {
+                usb_debug!("CDC-ACM read failed: {:?}", _err);
+                self.rx_pending = false;
+            }
+        }
+    }
+
+    fn flush_tx(&mut self) {
+        if self.tx_in_flight {
+            return;
+        }
+
+        let max_packet_size = usize::from(self.write_ep.max_packet_size());
+
+        if self.tx.is_empty() {
+            if self.tx_needs_zlp && self.write_ep.write(&[]).is_ok() {
+                self.tx_needs_zlp = false;
+                self.tx_in_flight = true;
+            }
+
+            return;
+        }
+
+        let head = self.tx.head();
+        let packet = &head[..head.len().min(max_packet_size)];
+
+        match self.write_ep.write(packet) {
+            Ok(count) => {
+                self.tx.consume(count);
+                self.tx_in_flight = true;
+                self.tx_needs_zlp = count == max_packet_size;
+            }
+            Err(UsbError::WouldBlock) => {}
+            Err(_err) => {
+                usb_debug!("CDC-ACM write failed: {:?}", _err);
+            }
+        }
+    }
+
+    fn reset_state(&mut self) {
+        self.rx.clear();
+        self.tx.clear();
+        self.rx_pending = false;
+        self.tx_in_flight = false;
+        self.tx_needs_zlp = false;
+        self.notification_in_flight = false;
+        self.line_coding = LineCoding::default();
+        self.dtr = false;
+        self.rts = false;
+        self.break_duration = None;
+    }
+
+    fn is_for_function(&self, req: &control::Request) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == u8::from(self.comm_if).into()
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for CdcAcmClass<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.iad(self.comm_if, 2, USB_CLASS_CDC, subclass::ACM, 0x00, None)?;
+
+        writer.interface(self.comm_if, USB_CLASS_CDC, subclass::ACM, 0x00)?;
+
+        let bcd_cdc = BCD_CDC.to_le_bytes();
+        writer.write(
+            CS_INTERFACE,
+            &[functional_descriptor::HEADER, bcd_cdc[0], bcd_cdc[1]],
+        )?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                functional_descriptor::CALL_MANAGEMENT,
+                0x00,                // bmCapabilities
+                self.data_if.into(), // bDataInterface
+            ],
+        )?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                functional_descriptor::ACM,
+                0x06, // bmCapabilities: line coding, control line state and break
+            ],
+        )?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                functional_descriptor::UNION,
+                self.comm_if.into(), // bControlInterface
+                self.data_if.into(), // bSubordinateInterface
+            ],
+        )?;
+        writer.endpoint(&self.comm_ep)?;
+
+        writer.interface(self.data_if, USB_CLASS_CDC_DATA, 0x00, 0x00)?;
+        writer.endpoint(&self.write_ep)?;
+        writer.endpoint(&self.read_ep)?;
+
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.reset_state();
+    }
+
+    fn endpoint_out(&mut self, addr: EndpointAddress) {
+        if addr == self.read_ep.address() {
+            self.rx_pending = true;
+            self.fill_rx();
+        }
+    }
+
+    fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+        if addr == self.write_ep.address() {
+            self.tx_in_flight = false;
+            self.flush_tx();
+        } else if addr == self.comm_ep.address() {
+            self.notification_in_flight = false;
+        }
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+
+        if !self.is_for_function(&req) {
+            return;
+        }
+
+        match req.request {
+            cdc_request::GET_LINE_CODING if req.length >= 7 => {
+                xfer.accept_with(&self.line_coding.to_bytes()).ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+
+        if !self.is_for_function(&req) {
+            return;
+        }
+
+        match req.request {
+            cdc_request::SET_LINE_CODING => match LineCoding::parse(xfer.data()) {
+                Some(line_coding) => {
+                    self.line_coding = line_coding;
+                    xfer.accept().ok();
+                }
+                None => {
+                    xfer.reject().ok();
+                }
+            },
+            cdc_request::SET_CONTROL_LINE_STATE => {
+                self.dtr = req.value & 0x0001 != 0;
+                self.rts = req.value & 0x0002 != 0;
+                xfer.accept().ok();
+            }
+            cdc_request::SEND_BREAK => {
+                self.break_duration = Some(req.value);
+                xfer.accept().ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<B: UsbBus> embedded_io::ErrorType for CdcAcmClass<'_, B> {
+    type Error = UsbError;
+}
+
+#[cfg(feature = "embedded-io")]
+impl<B: UsbBus> embedded_io::ReadReady for CdcAcmClass<'_, B> {
+    fn read_ready(&mut self) -> Result<bool> {
+        Ok(!self.rx.is_empty())
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<B: UsbBus> embedded_io::WriteReady for CdcAcmClass<'_, B> {
+    fn write_ready(&mut self) -> Result<bool> {
+        Ok(self.tx.free() > 0)
+    }
+}
+
+/// A [`CdcAcmClass`] that blocks in the `embedded_io` `Read` and `Write` traits, created with
+/// [`CdcAcmClass::blocking`].
+#[cfg(feature = "embedded-io")]
+pub struct Blocking<'c, 'a, B: UsbBus, P: FnMut(&mut CdcAcmClass<'a, B>)> {
+    class: &'c mut CdcAcmClass<'a, B>,
+    poll: P,
+}
+
+#[cfg(feature = "embedded-io")]
+impl<'a, B: UsbBus, P: FnMut(&mut CdcAcmClass<'a, B>)> Blocking<'_, 'a, B, P> {
+    // Calls `f` until it no longer returns `WouldBlock`, polling in between.
+    fn block<T>(&mut self, mut f: impl FnMut(&mut CdcAcmClass<'a, B>) -> Result<T>) -> Result<T> {
+        loop {
+            match f(self.class) {
+                Err(UsbError::WouldBlock) => (self.poll)(self.class),
+                res => return res,
+            }
+        }
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<'a, B: UsbBus, P: FnMut(&mut CdcAcmClass<'a, B>)> embedded_io::ErrorType
+    for Blocking<'_, 'a, B, P>
+{
+    type Error = UsbError;
+}
+
+#[cfg(feature = "embedded-io")]
+impl<'a, B: UsbBus, P: FnMut(&mut CdcAcmClass<'a, B>)> embedded_io::Read
+    for Blocking<'_, 'a, B, P>
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.block(|class| class.read(buf))
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<'a, B: UsbBus, P: FnMut(&mut CdcAcmClass<'a, B>)> embedded_io::Write
+    for Blocking<'_, 'a, B, P>
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.block(|class| class.write(buf))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.block(|class| class.flush())
+    }
+}
@@ -0,0 +1,333 @@
+use crate::cdc::{
+    cdc_request, functional_descriptor, mac_address_hex, subclass, write_ethernet_networking,
+    EthernetFunction, LinkState, TransferReceiver, TransferSender, BCD_CDC, CS_INTERFACE,
+    MAX_SEGMENT_SIZE, USB_CLASS_CDC, USB_CLASS_CDC_DATA,
+};
+use crate::class_prelude::*;
+use crate::control::{Recipient, RequestType};
+use crate::Result;
+
+// Alternate settings of the data interface.
+const DATA_ALT_IDLE: u8 = 0;
+const DATA_ALT_ACTIVE: u8 = 1;
+
+const NOTIFICATION_MAX_PACKET_SIZE: u16 = 16;
+const NOTIFICATION_INTERVAL: u8 = 32;
+
+/// A CDC Ethernet Control Model function, which appears as an Ethernet adapter on the host. Linux
+/// and macOS support it without additional drivers, including releases that predate NCM.
+///
+/// Every bulk transfer carries one Ethernet frame. A received frame is kept in `rx_buf` until it
+/// has been read, and a sent frame is copied into `tx_buf`. The host only exchanges frames after
+/// selecting alternate setting 1 of the data interface.
+///
+/// Frames are exchanged through the [`EthernetFunction`] trait.
+///
+/// ```no_run
+/// use usb_device::cdc::ecm::CdcEcmClass;
+/// use usb_device::cdc::EthernetFunction;
+/// use usb_device::class_prelude::*;
+/// use usb_device::dummy::DummyUsbBus;
+/// use usb_device::prelude::*;
+///
+/// let usb_bus = UsbBusAllocator::new(DummyUsbBus::new());
+///
+/// let (mut rx_buf, mut tx_buf) = ([0u8; 1514], [0u8; 1514]);
+/// let mac_address = [0x02, 0x00, 0x00, 0x12, 0x34, 0x56];
+/// let mut ecm = CdcEcmClass::new(&usb_bus, mac_address, 64, &mut rx_buf, &mut tx_buf).unwrap();
+///
+/// let mut control_buffer = [0u8; 64];
+/// let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+///     .composite_with_iads()
+///     .build()
+///     .unwrap();
+///
+/// ecm.set_connected(true);
+///
+/// loop {
+///     usb_dev.poll(&mut [&mut ecm]);
+///
+///     let mut frame = [0u8; 1514];
+///     if let Ok(len) = ecm.read_frame(&mut frame) {
+///         // Hand the frame to the network stack
+///     }
+/// }
+/// ```
+pub struct CdcEcmClass<'a, B: UsbBus> {
+    comm_if: InterfaceNumber,
+    comm_ep: EndpointIn<'a, B>,
+    data_if: InterfaceNumber,
+    read_ep: EndpointOut<'a, B>,
+    write_ep: EndpointIn<'a, B>,
+    mac_address_string: StringIndex,
+    mac_address: [u8; 12],
+    data_alt: u8,
+    rx: TransferReceiver<'a>,
+    tx: TransferSender<'a>,
+    link: LinkState,
+    packet_filter: u16,
+}
+
+impl<'a, B: UsbBus> CdcEcmClass<'a, B> {
+    /// Creates a new CDC-ECM function.
+    ///
+    /// # Arguments
+    ///
+    /// * `mac_address` - The MAC address of the host side of the link, reported in the
+    ///   `iMACAddress` string. Use a locally administered address that differs from the one used
+    ///   by the device's network stack.
+    /// * `max_packet_size` - Maximum packet size of the bulk endpoints: 64 at full speed, 512 at
+    ///   high speed.
+    /// * `rx_buf` - Buffer for frames received from the host, at least [`MAX_SEGMENT_SIZE`]
+    ///   bytes.
+    /// * `tx_buf` - Buffer for frames sent to the host, at least [`MAX_SEGMENT_SIZE`] bytes.
+    ///
+    /// # Errors
+    ///
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - A buffer is too small.
+    pub fn new(
+        alloc: &'a UsbBusAllocator<B>,
+        mac_address: [u8; 6],
+        max_packet_size: u16,
+        rx_buf: &'a mut [u8],
+        tx_buf: &'a mut [u8],
+    ) -> Result<Self> {
+        if rx_buf.len() < MAX_SEGMENT_SIZE || tx_buf.len() < MAX_SEGMENT_SIZE {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        // The host doesn't know the length of a frame, so every frame that ends with a full
+        // packet is terminated with a zero-length packet.
+        Ok(CdcEcmClass {
+            comm_if: alloc.interface(),
+            comm_ep: alloc.interrupt(NOTIFICATION_MAX_PACKET_SIZE, NOTIFICATION_INTERVAL),
+            data_if: alloc.interface(),
+            read_ep: alloc.bulk(max_packet_size),
+            write_ep: alloc.bulk(max_packet_size),
+            mac_address_string: alloc.string(),
+            mac_address: mac_address_hex(mac_address),
+            data_alt: DATA_ALT_IDLE,
+            rx: TransferReceiver::new(rx_buf),
+            tx: TransferSender::new(tx_buf, usize::MAX),
+            link: LinkState::new(),
+            packet_filter: 0,
+        })
+    }
+
+    /// Gets the Ethernet packet filter bitmap set by the host with SET_ETHERNET_PACKET_FILTER.
+    pub fn packet_filter(&self) -> u16 {
+        self.packet_filter
+    }
+
+    // Gets the length of the received frame, dropping transfers that are too long to be one.
+    fn current_frame(&mut self) -> Option<usize> {
+        let len = self.rx.transfer()?.len();
+
+        if len == 0 || len > MAX_SEGMENT_SIZE {
+            usb_debug!("Dropping invalid frame of {} bytes", len);
+            self.rx.release(&self.read_ep);
+            return None;
+        }
+
+        Some(len)
+    }
+
+    fn send_notification(&mut self) {
+        if self.is_active() {
+            self.link.send(&self.comm_ep, self.comm_if);
+        }
+    }
+
+    fn reset_data(&mut self) {
+        self.rx.reset();
+        self.tx.reset();
+        self.link.reset();
+    }
+
+    fn is_for_function(&self, req: &control::Request) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == u8::from(self.comm_if).into()
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for CdcEcmClass<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.iad(self.comm_if, 2, USB_CLASS_CDC, subclass::ECM, 0x00, None)?;
+
+        writer.interface(self.comm_if, USB_CLASS_CDC, subclass::ECM, 0x00)?;
+
+        let bcd_cdc = BCD_CDC.to_le_bytes();
+        writer.write(
+            CS_INTERFACE,
+            &[functional_descriptor::HEADER, bcd_cdc[0], bcd_cdc[1]],
+        )?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                functional_descriptor::UNION,
+                self.comm_if.into(), // bControlInterface
+                self.data_if.into(), // bSubordinateInterface
+            ],
+        )?;
+        write_ethernet_networking(writer, self.mac_address_string)?;
+        writer.endpoint(&self.comm_ep)?;
+
+        writer.interface(self.data_if, USB_CLASS_CDC_DATA, 0x00, 0x00)?;
+        writer.interface_alt(
+            self.data_if,
+            DATA_ALT_ACTIVE,
+            USB_CLASS_CDC_DATA,
+            0x00,
+            0x00,
+            None,
+        )?;
+        writer.endpoint(&self.write_ep)?;
+        writer.endpoint(&self.read_ep)?;
+
+        Ok(())
+    }
+
+    fn get_string(&self, index: StringIndex, _lang_id: LangID) -> Option<&str> {
+        if index == self.mac_address_string {
+            core::str::from_utf8(&self.mac_address).ok()
+        } else {
+            None
+        }
+    }
+
+    fn reset(&mut self) {
+        self.data_alt = DATA_ALT_IDLE;
+        self.packet_filter = 0;
+        self.reset_data();
+    }
+
+    fn endpoint_out(&mut self, addr: EndpointAddress) {
+        if addr == self.read_ep.address() && self.is_active() {
+            self.rx.endpoint_out(&self.read_ep);
+            self.current_frame();
+        }
+    }
+
+    fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+        if addr == self.write_ep.address() {
+            self.tx.endpoint_in_complete(&self.write_ep);
+        } else if addr == self.comm_ep.address() {
+            self.link.endpoint_in_complete(&self.comm_ep, self.comm_if);
+        }
+    }
+
+    fn get_alt_setting(&mut self, interface: InterfaceNumber) -> Option<u8> {
+        if interface == self.data_if {
+            Some(self.data_alt)
+        } else {
+            None
+        }
+    }
+
+    fn set_alt_setting(&mut self, interface: InterfaceNumber, alternative: u8) -> bool {
+        if interface != self.data_if || alternative > DATA_ALT_ACTIVE {
+            return false;
+        }
+
+        self.data_alt = alternative;
+        self.reset_data();
+
+        if alternative == DATA_ALT_ACTIVE {
+            self.link.notify_all();
+            self.send_notification();
+        }
+
+        true
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+
+        if !self.is_for_function(&req) {
+            return;
+        }
+
+        match req.request {
+            cdc_request::SET_ETHERNET_PACKET_FILTER => {
+                self.packet_filter = req.value;
+                xfer.accept().ok();
+            }
+            // No multicast filters are reported in the Ethernet Networking descriptor, so all
+            // multicast frames are passed on anyway.
+            cdc_request::SET_ETHERNET_MULTICAST_FILTERS => {
+                xfer.accept().ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+
+        if self.is_for_function(&req) {
+            xfer.reject().ok();
+        }
+    }
+}
+
+impl<B: UsbBus> EthernetFunction for CdcEcmClass<'_, B> {
+    fn is_active(&self) -> bool {
+        self.data_alt == DATA_ALT_ACTIVE
+    }
+
+    fn is_connected(&self) -> bool {
+        self.link.is_connected()
+    }
+
+    fn set_connected(&mut self, connected: bool) {
+        self.link.set_connected(connected);
+        self.send_notification();
+    }
+
+    fn set_connection_speed(&mut self, downlink_bit_rate: u32, uplink_bit_rate: u32) {
+        self.link.set_speed(downlink_bit_rate, uplink_bit_rate);
+        self.send_notification();
+    }
+
+    fn can_receive(&mut self) -> bool {
+        self.current_frame().is_some()
+    }
+
+    fn can_transmit(&self) -> bool {
+        self.is_active() && !self.tx.is_busy()
+    }
+
+    fn receive_frame<R>(&mut self, f: impl FnOnce(&mut [u8]) -> R) -> Result<R> {
+        self.current_frame().ok_or(UsbError::WouldBlock)?;
+
+        let frame = self.rx.transfer_mut().ok_or(UsbError::WouldBlock)?;
+        let result = f(frame);
+
+        self.rx.release(&self.read_ep);
+
+        Ok(result)
+    }
+
+    fn transmit_frame<R>(&mut self, len: usize, f: impl FnOnce(&mut [u8]) -> R) -> Result<R> {
+        if !self.is_active() {
+            return Err(UsbError::InvalidState);
+        }
+
+        if self.tx.is_busy() {
+            return Err(UsbError::WouldBlock);
+        }
+
+        if len > MAX_SEGMENT_SIZE {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        let result = f(&mut self.tx.buf_mut()[..len]);
+
+        self.tx.send(len, &self.write_ep);
+
+        Ok(result)
+    }
+}
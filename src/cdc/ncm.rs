@@ -0,0 +1,511 @@
+use crate::cdc::{
+    cdc_request, functional_descriptor, mac_address_hex, read_u16, read_u32, subclass,
+    write_ethernet_networking, EthernetFunction, LinkState, TransferReceiver, TransferSender,
+    BCD_CDC, CS_INTERFACE, USB_CLASS_CDC, USB_CLASS_CDC_DATA,
+};
+use crate::class_prelude::*;
+use crate::control::{Recipient, RequestType};
+use crate::Result;
+use core::ops::Range;
+
+/// Protocol code of the NCM data interface.
+pub const NCM_DATA_PROTOCOL: u8 = 0x01;
+
+/// Signature of the NTB16 transfer header, "NCMH".
+pub const NTH16_SIGNATURE: u32 = 0x484d_434e;
+
+/// Signature of an NTB16 datagram pointer table without CRCs, "NCM0".
+pub const NDP16_SIGNATURE: u32 = 0x304d_434e;
+
+/// Smallest NTB size allowed by the specification.
+pub const MIN_NTB_SIZE: usize = 2048;
+
+const BCD_NCM: u16 = 0x0100;
+
+// Alternate settings of the data interface.
+const DATA_ALT_IDLE: u8 = 0;
+const DATA_ALT_ACTIVE: u8 = 1;
+
+// The only NTB format supported.
+const NTB16_FORMAT: u16 = 0;
+
+const NTH16_LENGTH: usize = 12;
+
+// An NDP16 with one datagram pointer and the terminating null pointer.
+const NDP16_LENGTH: usize = 16;
+
+// Offset of the datagram in the NTBs sent to the host.
+const DATAGRAM_OFFSET: usize = NTH16_LENGTH + NDP16_LENGTH;
+
+// Divisor and alignment of datagrams and NDPs in both directions.
+const NTB_ALIGNMENT: u16 = 4;
+
+const NOTIFICATION_MAX_PACKET_SIZE: u16 = 16;
+const NOTIFICATION_INTERVAL: u8 = 32;
+
+/// A CDC Network Control Model function, which appears as an Ethernet adapter on the host. Linux,
+/// macOS and Windows 11 support it without additional drivers.
+///
+/// Ethernet frames are exchanged with the host in NCM Transfer Blocks (NTBs) with the 16-bit
+/// format. Received NTBs are kept in `rx_buf` until all their frames have been read, and every sent
+/// frame is wrapped in its own NTB in `tx_buf`. The host only exchanges frames after selecting
+/// alternate setting 1 of the data interface.
+///
+/// Frames are exchanged through the [`EthernetFunction`] trait.
+///
+/// ```no_run
+/// use usb_device::cdc::ncm::CdcNcmClass;
+/// use usb_device::cdc::EthernetFunction;
+/// use usb_device::class_prelude::*;
+/// use usb_device::dummy::DummyUsbBus;
+/// use usb_device::prelude::*;
+///
+/// let usb_bus = UsbBusAllocator::new(DummyUsbBus::new());
+///
+/// let (mut rx_buf, mut tx_buf) = ([0u8; 2048], [0u8; 2048]);
+/// let mac_address = [0x02, 0x00, 0x00, 0x12, 0x34, 0x56];
+/// let mut ncm = CdcNcmClass::new(&usb_bus, mac_address, 64, &mut rx_buf, &mut tx_buf).unwrap();
+///
+/// let mut control_buffer = [0u8; 64];
+/// let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+///     .composite_with_iads()
+///     .build()
+///     .unwrap();
+///
+/// ncm.set_connected(true);
+///
+/// loop {
+///     usb_dev.poll(&mut [&mut ncm]);
+///
+///     let mut frame = [0u8; 1514];
+///     if let Ok(len) = ncm.read_frame(&mut frame) {
+///         // Hand the frame to the network stack
+///     }
+/// }
+/// ```
+pub struct CdcNcmClass<'a, B: UsbBus> {
+    comm_if: InterfaceNumber,
+    comm_ep: EndpointIn<'a, B>,
+    data_if: InterfaceNumber,
+    read_ep: EndpointOut<'a, B>,
+    write_ep: EndpointIn<'a, B>,
+    mac_address_string: StringIndex,
+    mac_address: [u8; 12],
+    data_alt: u8,
+    rx: TransferReceiver<'a>,
+    // The NDP and datagram that are read next from the received NTB.
+    rx_cursor: Option<(usize, usize)>,
+    tx: TransferSender<'a>,
+    tx_sequence: u16,
+    link: LinkState,
+    packet_filter: u16,
+}
+
+impl<'a, B: UsbBus> CdcNcmClass<'a, B> {
+    /// Creates a new CDC-NCM function.
+    ///
+    /// # Arguments
+    ///
+    /// * `mac_address` - The MAC address of the host side of the link, reported in the
+    ///   `iMACAddress` string. Use a locally administered address that differs from the one used
+    ///   by the device's network stack.
+    /// * `max_packet_size` - Maximum packet size of the bulk endpoints: 64 at full speed, 512 at
+    ///   high speed.
+    /// * `rx_buf` - Buffer for NTBs received from the host, at least [`MIN_NTB_SIZE`] bytes.
+    /// * `tx_buf` - Buffer for NTBs sent to the host, at least [`MIN_NTB_SIZE`] bytes.
+    ///
+    /// # Errors
+    ///
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - A buffer is too small.
+    pub fn new(
+        alloc: &'a UsbBusAllocator<B>,
+        mac_address: [u8; 6],
+        max_packet_size: u16,
+        rx_buf: &'a mut [u8],
+        tx_buf: &'a mut [u8],
+    ) -> Result<Self> {
+        if rx_buf.len() < MIN_NTB_SIZE || tx_buf.len() < MIN_NTB_SIZE {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        // NTB16 lengths and offsets are 16-bit.
+        let rx_len = rx_buf.len().min(u16::MAX.into());
+        let tx_len = tx_buf.len().min(u16::MAX.into());
+
+        Ok(CdcNcmClass {
+            comm_if: alloc.interface(),
+            comm_ep: alloc.interrupt(NOTIFICATION_MAX_PACKET_SIZE, NOTIFICATION_INTERVAL),
+            data_if: alloc.interface(),
+            read_ep: alloc.bulk(max_packet_size),
+            write_ep: alloc.bulk(max_packet_size),
+            mac_address_string: alloc.string(),
+            mac_address: mac_address_hex(mac_address),
+            data_alt: DATA_ALT_IDLE,
+            rx: TransferReceiver::new(&mut rx_buf[..rx_len]),
+            rx_cursor: None,
+            tx: TransferSender::new(&mut tx_buf[..tx_len], tx_len),
+            tx_sequence: 0,
+            link: LinkState::new(),
+            packet_filter: 0,
+        })
+    }
+
+    /// Gets the Ethernet packet filter bitmap set by the host with SET_ETHERNET_PACKET_FILTER.
+    pub fn packet_filter(&self) -> u16 {
+        self.packet_filter
+    }
+
+    // Gets the datagram that is read next, skipping to the next NDP as needed. Releases the NTB
+    // and starts receiving the next one when all datagrams have been read.
+    fn current_datagram(&mut self) -> Option<Range<usize>> {
+        loop {
+            let ntb = self.rx.transfer()?;
+
+            let (ndp, datagram) = match self.rx_cursor {
+                Some(cursor) => cursor,
+                None => match parse_nth(ntb) {
+                    Some(ndp) => (ndp, 0),
+                    None => {
+                        usb_debug!("Dropping NTB with an invalid header");
+                        self.rx.release(&self.read_ep);
+                        return None;
+                    }
+                },
+            };
+
+            match parse_datagram(ntb, ndp, datagram) {
+                Ok(Some(range)) => {
+                    self.rx_cursor = Some((ndp, datagram));
+                    return Some(range);
+                }
+                Ok(None) => {
+                    let next_ndp = usize::from(read_u16(ntb, ndp + 6));
+
+                    // NDPs are only followed forward, so that a malformed chain can't loop.
+                    if next_ndp > ndp {
+                        self.rx_cursor = Some((next_ndp, 0));
+                        continue;
+                    }
+                }
+                Err(_err) => {
+                    usb_debug!("Dropping invalid NTB: {:?}", _err);
+                }
+            }
+
+            self.rx_cursor = None;
+            self.rx.release(&self.read_ep);
+
+            return None;
+        }
+    }
+
+    fn send_notification(&mut self) {
+        if self.is_active() {
+            self.link.send(&self.comm_ep, self.comm_if);
+        }
+    }
+
+    fn reset_data(&mut self) {
+        self.rx.reset();
+        self.rx_cursor = None;
+        self.tx.reset();
+        self.tx_sequence = 0;
+        self.link.reset();
+    }
+
+    fn ntb_parameters(&self) -> [u8; 28] {
+        let mut params = [0u8; 28];
+
+        params[0..2].copy_from_slice(&28u16.to_le_bytes()); // wLength
+        params[2..4].copy_from_slice(&0x0001u16.to_le_bytes()); // bmNtbFormatsSupported: NTB16
+        params[4..8].copy_from_slice(&(self.tx.capacity() as u32).to_le_bytes()); // dwNtbInMaxSize
+        params[8..10].copy_from_slice(&NTB_ALIGNMENT.to_le_bytes()); // wNdpInDivisor
+        params[10..12].copy_from_slice(&0u16.to_le_bytes()); // wNdpInPayloadRemainder
+        params[12..14].copy_from_slice(&NTB_ALIGNMENT.to_le_bytes()); // wNdpInAlignment
+        params[16..20].copy_from_slice(&(self.rx.capacity() as u32).to_le_bytes()); // dwNtbOutMaxSize
+        params[20..22].copy_from_slice(&NTB_ALIGNMENT.to_le_bytes()); // wNdpOutDivisor
+        params[22..24].copy_from_slice(&0u16.to_le_bytes()); // wNdpOutPayloadRemainder
+        params[24..26].copy_from_slice(&NTB_ALIGNMENT.to_le_bytes()); // wNdpOutAlignment
+        params[26..28].copy_from_slice(&0u16.to_le_bytes()); // wNtbOutMaxDatagrams: no limit
+
+        params
+    }
+
+    fn is_for_function(&self, req: &control::Request) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == u8::from(self.comm_if).into()
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for CdcNcmClass<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.iad(self.comm_if, 2, USB_CLASS_CDC, subclass::NCM, 0x00, None)?;
+
+        writer.interface(self.comm_if, USB_CLASS_CDC, subclass::NCM, 0x00)?;
+
+        let bcd_cdc = BCD_CDC.to_le_bytes();
+        writer.write(
+            CS_INTERFACE,
+            &[functional_descriptor::HEADER, bcd_cdc[0], bcd_cdc[1]],
+        )?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                functional_descriptor::UNION,
+                self.comm_if.into(), // bControlInterface
+                self.data_if.into(), // bSubordinateInterface
+            ],
+        )?;
+
+        write_ethernet_networking(writer, self.mac_address_string)?;
+
+        let bcd_ncm = BCD_NCM.to_le_bytes();
+        writer.write(
+            CS_INTERFACE,
+            &[
+                functional_descriptor::NCM,
+                bcd_ncm[0],
+                bcd_ncm[1], // bcdNcmVersion
+                0x00,       // bmNetworkCapabilities
+            ],
+        )?;
+        writer.endpoint(&self.comm_ep)?;
+
+        writer.interface(self.data_if, USB_CLASS_CDC_DATA, 0x00, NCM_DATA_PROTOCOL)?;
+        writer.interface_alt(
+            self.data_if,
+            DATA_ALT_ACTIVE,
+            USB_CLASS_CDC_DATA,
+            0x00,
+            NCM_DATA_PROTOCOL,
+            None,
+        )?;
+        writer.endpoint(&self.write_ep)?;
+        writer.endpoint(&self.read_ep)?;
+
+        Ok(())
+    }
+
+    fn get_string(&self, index: StringIndex, _lang_id: LangID) -> Option<&str> {
+        if index == self.mac_address_string {
+            core::str::from_utf8(&self.mac_address).ok()
+        } else {
+            None
+        }
+    }
+
+    fn reset(&mut self) {
+        self.data_alt = DATA_ALT_IDLE;
+        self.packet_filter = 0;
+        self.reset_data();
+    }
+
+    fn endpoint_out(&mut self, addr: EndpointAddress) {
+        if addr == self.read_ep.address() && self.is_active() {
+            self.rx.endpoint_out(&self.read_ep);
+
+            // Drop the NTB right away if it has no valid datagrams.
+            self.current_datagram();
+        }
+    }
+
+    fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+        if addr == self.write_ep.address() {
+            self.tx.endpoint_in_complete(&self.write_ep);
+        } else if addr == self.comm_ep.address() {
+            self.link.endpoint_in_complete(&self.comm_ep, self.comm_if);
+        }
+    }
+
+    fn get_alt_setting(&mut self, interface: InterfaceNumber) -> Option<u8> {
+        if interface == self.data_if {
+            Some(self.data_alt)
+        } else {
+            None
+        }
+    }
+
+    fn set_alt_setting(&mut self, interface: InterfaceNumber, alternative: u8) -> bool {
+        if interface != self.data_if || alternative > DATA_ALT_ACTIVE {
+            return false;
+        }
+
+        // Selecting either setting resets the function, and the host expects to be told the
+        // state of the link when the data interface becomes active.
+        self.data_alt = alternative;
+        self.reset_data();
+
+        if alternative == DATA_ALT_ACTIVE {
+            self.link.notify_all();
+            self.send_notification();
+        }
+
+        true
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+
+        if !self.is_for_function(&req) {
+            return;
+        }
+
+        match req.request {
+            cdc_request::GET_NTB_PARAMETERS => {
+                xfer.accept_with(&self.ntb_parameters()).ok();
+            }
+            cdc_request::GET_NTB_FORMAT => {
+                xfer.accept_with(&NTB16_FORMAT.to_le_bytes()).ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+
+        if !self.is_for_function(&req) {
+            return;
+        }
+
+        match req.request {
+            cdc_request::SET_ETHERNET_PACKET_FILTER => {
+                self.packet_filter = req.value;
+                xfer.accept().ok();
+            }
+            cdc_request::SET_NTB_FORMAT if req.value == NTB16_FORMAT => {
+                xfer.accept().ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+}
+
+// Validates the transfer header of an NTB and gets the offset of its first NDP.
+fn parse_nth(ntb: &[u8]) -> Option<usize> {
+    let valid = ntb.len() >= NTH16_LENGTH
+        && read_u32(ntb, 0) == NTH16_SIGNATURE
+        && usize::from(read_u16(ntb, 4)) == NTH16_LENGTH
+        && usize::from(read_u16(ntb, 8)) <= ntb.len();
+
+    if valid {
+        Some(usize::from(read_u16(ntb, 10)))
+    } else {
+        None
+    }
+}
+
+// Gets datagram number `datagram` of the NDP at `ndp`, or `None` after the last one.
+fn parse_datagram(ntb: &[u8], ndp: usize, datagram: usize) -> Result<Option<Range<usize>>> {
+    if ndp + 8 > ntb.len() || read_u32(ntb, ndp) != NDP16_SIGNATURE {
+        return Err(UsbError::ParseError);
+    }
+
+    let ndp_length = usize::from(read_u16(ntb, ndp + 4));
+    let pointer = ndp + 8 + datagram * 4;
+    if ndp + ndp_length > ntb.len() || pointer + 4 > ndp + ndp_length {
+        return Err(UsbError::ParseError);
+    }
+
+    let index = usize::from(read_u16(ntb, pointer));
+    let length = usize::from(read_u16(ntb, pointer + 2));
+
+    if index == 0 || length == 0 {
+        return Ok(None);
+    }
+
+    if index + length > ntb.len() {
+        return Err(UsbError::ParseError);
+    }
+
+    Ok(Some(index..index + length))
+}
+
+impl<B: UsbBus> EthernetFunction for CdcNcmClass<'_, B> {
+    fn is_active(&self) -> bool {
+        self.data_alt == DATA_ALT_ACTIVE
+    }
+
+    fn is_connected(&self) -> bool {
+        self.link.is_connected()
+    }
+
+    fn set_connected(&mut self, connected: bool) {
+        self.link.set_connected(connected);
+        self.send_notification();
+    }
+
+    fn set_connection_speed(&mut self, downlink_bit_rate: u32, uplink_bit_rate: u32) {
+        self.link.set_speed(downlink_bit_rate, uplink_bit_rate);
+        self.send_notification();
+    }
+
+    fn can_receive(&mut self) -> bool {
+        self.current_datagram().is_some()
+    }
+
+    fn can_transmit(&self) -> bool {
+        self.is_active() && !self.tx.is_busy()
+    }
+
+    fn receive_frame<R>(&mut self, f: impl FnOnce(&mut [u8]) -> R) -> Result<R> {
+        let range = self.current_datagram().ok_or(UsbError::WouldBlock)?;
+        let ntb = self.rx.transfer_mut().ok_or(UsbError::WouldBlock)?;
+        let result = f(&mut ntb[range]);
+
+        if let Some((_, datagram)) = &mut self.rx_cursor {
+            *datagram += 1;
+        }
+
+        // Release the NTB right away if this was its last frame.
+        self.current_datagram();
+
+        Ok(result)
+    }
+
+    fn transmit_frame<R>(&mut self, len: usize, f: impl FnOnce(&mut [u8]) -> R) -> Result<R> {
+        if !self.is_active() {
+            return Err(UsbError::InvalidState);
+        }
+
+        if self.tx.is_busy() {
+            return Err(UsbError::WouldBlock);
+        }
+
+        let block_length = DATAGRAM_OFFSET + len;
+        if block_length > self.tx.capacity() {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        let sequence = self.tx_sequence;
+        self.tx_sequence = self.tx_sequence.wrapping_add(1);
+
+        let buf = self.tx.buf_mut();
+
+        let nth = &mut buf[..NTH16_LENGTH];
+        nth[0..4].copy_from_slice(&NTH16_SIGNATURE.to_le_bytes()); // dwSignature
+        nth[4..6].copy_from_slice(&(NTH16_LENGTH as u16).to_le_bytes()); // wHeaderLength
+        nth[6..8].copy_from_slice(&sequence.to_le_bytes()); // wSequence
+        nth[8..10].copy_from_slice(&(block_length as u16).to_le_bytes()); // wBlockLength
+        nth[10..12].copy_from_slice(&(NTH16_LENGTH as u16).to_le_bytes()); // wNdpIndex
+
+        let ndp = &mut buf[NTH16_LENGTH..DATAGRAM_OFFSET];
+        ndp[0..4].copy_from_slice(&NDP16_SIGNATURE.to_le_bytes()); // dwSignature
+        ndp[4..6].copy_from_slice(&(NDP16_LENGTH as u16).to_le_bytes()); // wLength
+        ndp[6..8].copy_from_slice(&0u16.to_le_bytes()); // wNextNdpIndex
+        ndp[8..10].copy_from_slice(&(DATAGRAM_OFFSET as u16).to_le_bytes()); // wDatagramIndex
+        ndp[10..12].copy_from_slice(&(len as u16).to_le_bytes()); // wDatagramLength
+        ndp[12..16].fill(0); // Null datagram pointer
+
+        let result = f(&mut buf[DATAGRAM_OFFSET..block_length]);
+
+        self.tx.send(block_length, &self.write_ep);
+
+        Ok(result)
+    }
+}
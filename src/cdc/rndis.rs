@@ -0,0 +1,746 @@
+use crate::cdc::{
+    cdc_request, functional_descriptor, read_u32, EthernetFunction, LinkState, TransferReceiver,
+    TransferSender, BCD_CDC, CS_INTERFACE, MAX_SEGMENT_SIZE, USB_CLASS_CDC_DATA,
+};
+use crate::class_prelude::*;
+use crate::control::{Recipient, RequestType};
+use crate::Result;
+use core::ops::Range;
+
+/// Interface class code of the RNDIS communication interface, "Wireless Controller".
+pub const INTERFACE_CLASS_RNDIS: u8 = 0xe0;
+
+/// Interface subclass code of the RNDIS communication interface.
+pub const INTERFACE_SUBCLASS_RNDIS: u8 = 0x01;
+
+/// Interface protocol code of the RNDIS communication interface.
+pub const INTERFACE_PROTOCOL_RNDIS: u8 = 0x03;
+
+/// Length of the header of the REMOTE_NDIS_PACKET_MSG that wraps every frame.
+pub const PACKET_MSG_HEADER_LENGTH: usize = 44;
+
+/// Smallest buffer size accepted by [`RndisClass::new`], which fits one full-size frame.
+pub const MIN_BUFFER_SIZE: usize = PACKET_MSG_HEADER_LENGTH + MAX_SEGMENT_SIZE;
+
+/// RNDIS message types
+#[allow(missing_docs)]
+pub mod message {
+    pub const PACKET: u32 = 0x0000_0001;
+    pub const INITIALIZE: u32 = 0x0000_0002;
+    pub const HALT: u32 = 0x0000_0003;
+    pub const QUERY: u32 = 0x0000_0004;
+    pub const SET: u32 = 0x0000_0005;
+    pub const RESET: u32 = 0x0000_0006;
+    pub const INDICATE_STATUS: u32 = 0x0000_0007;
+    pub const KEEPALIVE: u32 = 0x0000_0008;
+
+    /// Set in the type of the completion message for a request.
+    pub const COMPLETION: u32 = 0x8000_0000;
+}
+
+/// RNDIS status codes
+#[allow(missing_docs)]
+pub mod status {
+    pub const SUCCESS: u32 = 0x0000_0000;
+    pub const FAILURE: u32 = 0xc000_0001;
+    pub const INVALID_DATA: u32 = 0xc001_0015;
+    pub const NOT_SUPPORTED: u32 = 0xc000_00bb;
+    pub const MEDIA_CONNECT: u32 = 0x4001_000b;
+    pub const MEDIA_DISCONNECT: u32 = 0x4001_000c;
+}
+
+/// NDIS object identifiers handled by the function
+#[allow(missing_docs)]
+pub mod oid {
+    pub const GEN_SUPPORTED_LIST: u32 = 0x0001_0101;
+    pub const GEN_HARDWARE_STATUS: u32 = 0x0001_0102;
+    pub const GEN_MEDIA_SUPPORTED: u32 = 0x0001_0103;
+    pub const GEN_MEDIA_IN_USE: u32 = 0x0001_0104;
+    pub const GEN_MAXIMUM_FRAME_SIZE: u32 = 0x0001_0106;
+    pub const GEN_LINK_SPEED: u32 = 0x0001_0107;
+    pub const GEN_TRANSMIT_BLOCK_SIZE: u32 = 0x0001_010a;
+    pub const GEN_RECEIVE_BLOCK_SIZE: u32 = 0x0001_010b;
+    pub const GEN_VENDOR_ID: u32 = 0x0001_010c;
+    pub const GEN_VENDOR_DESCRIPTION: u32 = 0x0001_010d;
+    pub const GEN_CURRENT_PACKET_FILTER: u32 = 0x0001_010e;
+    pub const GEN_MAXIMUM_TOTAL_SIZE: u32 = 0x0001_0111;
+    pub const GEN_MEDIA_CONNECT_STATUS: u32 = 0x0001_0114;
+    pub const GEN_PHYSICAL_MEDIUM: u32 = 0x0001_0202;
+    pub const GEN_XMIT_OK: u32 = 0x0002_0101;
+    pub const GEN_RCV_OK: u32 = 0x0002_0102;
+    pub const GEN_XMIT_ERROR: u32 = 0x0002_0103;
+    pub const GEN_RCV_ERROR: u32 = 0x0002_0104;
+    pub const GEN_RCV_NO_BUFFER: u32 = 0x0002_0105;
+    pub const PERMANENT_ADDRESS_802_3: u32 = 0x0101_0101;
+    pub const CURRENT_ADDRESS_802_3: u32 = 0x0101_0102;
+    pub const MULTICAST_LIST_802_3: u32 = 0x0101_0103;
+    pub const MAXIMUM_LIST_SIZE_802_3: u32 = 0x0101_0104;
+    pub const MAC_OPTIONS_802_3: u32 = 0x0101_0105;
+    pub const RCV_ERROR_ALIGNMENT_802_3: u32 = 0x0102_0101;
+    pub const XMIT_ONE_COLLISION_802_3: u32 = 0x0102_0102;
+    pub const XMIT_MORE_COLLISIONS_802_3: u32 = 0x0102_0103;
+}
+
+const SUPPORTED_OIDS: [u32; 27] = [
+    oid::GEN_SUPPORTED_LIST,
+    oid::GEN_HARDWARE_STATUS,
+    oid::GEN_MEDIA_SUPPORTED,
+    oid::GEN_MEDIA_IN_USE,
+    oid::GEN_MAXIMUM_FRAME_SIZE,
+    oid::GEN_LINK_SPEED,
+    oid::GEN_TRANSMIT_BLOCK_SIZE,
+    oid::GEN_RECEIVE_BLOCK_SIZE,
+    oid::GEN_VENDOR_ID,
+    oid::GEN_VENDOR_DESCRIPTION,
+    oid::GEN_CURRENT_PACKET_FILTER,
+    oid::GEN_MAXIMUM_TOTAL_SIZE,
+    oid::GEN_MEDIA_CONNECT_STATUS,
+    oid::GEN_PHYSICAL_MEDIUM,
+    oid::GEN_XMIT_OK,
+    oid::GEN_RCV_OK,
+    oid::GEN_XMIT_ERROR,
+    oid::GEN_RCV_ERROR,
+    oid::GEN_RCV_NO_BUFFER,
+    oid::PERMANENT_ADDRESS_802_3,
+    oid::CURRENT_ADDRESS_802_3,
+    oid::MULTICAST_LIST_802_3,
+    oid::MAXIMUM_LIST_SIZE_802_3,
+    oid::MAC_OPTIONS_802_3,
+    oid::RCV_ERROR_ALIGNMENT_802_3,
+    oid::XMIT_ONE_COLLISION_802_3,
+    oid::XMIT_MORE_COLLISIONS_802_3,
+];
+
+const VENDOR_DESCRIPTION: &[u8] = b"usb-device RNDIS\0";
+
+// Vendor ID reported to the host when the vendor has no IEEE-registered ID.
+const VENDOR_ID_NONE: u32 = 0x00ff_ffff;
+
+const RNDIS_MAJOR_VERSION: u32 = 1;
+const RNDIS_MINOR_VERSION: u32 = 0;
+
+// RNDIS_DF_CONNECTIONLESS
+const DEVICE_FLAGS: u32 = 0x0000_0010;
+
+// NdisMedium802_3
+const MEDIUM_802_3: u32 = 0;
+
+// Offset of the information buffer in a query completion, relative to the request ID.
+const QUERY_INFO_OFFSET: usize = 16;
+
+// Offset of the frame in a packet message, relative to the DataOffset field.
+const PACKET_DATA_OFFSET: usize = PACKET_MSG_HEADER_LENGTH - 8;
+
+// The largest response is the query completion with the list of supported OIDs.
+const RESPONSE_MAX_LENGTH: usize = 24 + 4 * SUPPORTED_OIDS.len();
+
+const NOTIFICATION_MAX_PACKET_SIZE: u16 = 8;
+const NOTIFICATION_INTERVAL: u8 = 32;
+
+/// A Remote NDIS function, which appears as an Ethernet adapter on Windows hosts that don't
+/// support NCM. Linux also supports it through its `rndis_host` driver.
+///
+/// The host sends RNDIS control messages with SEND_ENCAPSULATED_COMMAND, and reads the replies with
+/// GET_ENCAPSULATED_RESPONSE after the function signals RESPONSE_AVAILABLE on its notification
+/// endpoint. Every Ethernet frame is wrapped in a REMOTE_NDIS_PACKET_MSG. The host only exchanges
+/// frames after it has initialized the function and set a packet filter.
+///
+/// The control buffer of the device must be at least 256 bytes for the longest replies. Windows
+/// versions that don't load the RNDIS driver for the interface class codes need a Microsoft OS
+/// descriptor with the compatible ID "RNDIS" and sub-compatible ID "5162001" for the function.
+///
+/// Frames are exchanged through the [`EthernetFunction`] trait.
+///
+/// ```no_run
+/// use usb_device::cdc::rndis::RndisClass;
+/// use usb_device::cdc::EthernetFunction;
+/// use usb_device::class_prelude::*;
+/// use usb_device::dummy::DummyUsbBus;
+/// use usb_device::prelude::*;
+///
+/// let usb_bus = UsbBusAllocator::new(DummyUsbBus::new());
+///
+/// let (mut rx_buf, mut tx_buf) = ([0u8; 1600], [0u8; 1600]);
+/// let mac_address = [0x02, 0x00, 0x00, 0x12, 0x34, 0x56];
+/// let mut rndis = RndisClass::new(&usb_bus, mac_address, 64, &mut rx_buf, &mut tx_buf).unwrap();
+///
+/// let mut control_buffer = [0u8; 256];
+/// let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+///     .composite_with_iads()
+///     .build()
+///     .unwrap();
+///
+/// rndis.set_connected(true);
+///
+/// loop {
+///     usb_dev.poll(&mut [&mut rndis]);
+///
+///     let mut frame = [0u8; 1514];
+///     if let Ok(len) = rndis.read_frame(&mut frame) {
+///         // Hand the frame to the network stack
+///     }
+/// }
+/// ```
+pub struct RndisClass<'a, B: UsbBus> {
+    comm_if: InterfaceNumber,
+    comm_ep: EndpointIn<'a, B>,
+    data_if: InterfaceNumber,
+    read_ep: EndpointOut<'a, B>,
+    write_ep: EndpointIn<'a, B>,
+    mac_address: [u8; 6],
+    initialized: bool,
+    packet_filter: u32,
+    response: [u8; RESPONSE_MAX_LENGTH],
+    response_len: usize,
+    status_pending: bool,
+    notification_pending: bool,
+    notification_in_flight: bool,
+    rx: TransferReceiver<'a>,
+    // Offset of the message that is read next from the received transfer.
+    rx_pos: usize,
+    tx: TransferSender<'a>,
+    link: LinkState,
+    xmit_ok: u32,
+    rcv_ok: u32,
+    rcv_error: u32,
+}
+
+impl<'a, B: UsbBus> RndisClass<'a, B> {
+    /// Creates a new RNDIS function.
+    ///
+    /// # Arguments
+    ///
+    /// * `mac_address` - The MAC address of the host side of the link. Use a locally administered
+    ///   address that differs from the one used by the device's network stack.
+    /// * `max_packet_size` - Maximum packet size of the bulk endpoints: 64 at full speed, 512 at
+    ///   high speed.
+    /// * `rx_buf` - Buffer for transfers received from the host, at least [`MIN_BUFFER_SIZE`]
+    ///   bytes. Its length is reported to the host as the largest transfer it may send.
+    /// * `tx_buf` - Buffer for transfers sent to the host, at least [`MIN_BUFFER_SIZE`] bytes.
+    ///
+    /// # Errors
+    ///
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - A buffer is too small.
+    pub fn new(
+        alloc: &'a UsbBusAllocator<B>,
+        mac_address: [u8; 6],
+        max_packet_size: u16,
+        rx_buf: &'a mut [u8],
+        tx_buf: &'a mut [u8],
+    ) -> Result<Self> {
+        if rx_buf.len() < MIN_BUFFER_SIZE || tx_buf.len() < MIN_BUFFER_SIZE {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        // The host doesn't know the length of a message, so every message that ends with a full
+        // packet is terminated with a zero-length packet.
+        Ok(RndisClass {
+            comm_if: alloc.interface(),
+            comm_ep: alloc.interrupt(NOTIFICATION_MAX_PACKET_SIZE, NOTIFICATION_INTERVAL),
+            data_if: alloc.interface(),
+            read_ep: alloc.bulk(max_packet_size),
+            write_ep: alloc.bulk(max_packet_size),
+            mac_address,
+            initialized: false,
+            packet_filter: 0,
+            response: [0; RESPONSE_MAX_LENGTH],
+            response_len: 0,
+            status_pending: false,
+            notification_pending: false,
+            notification_in_flight: false,
+            rx: TransferReceiver::new(rx_buf),
+            rx_pos: 0,
+            tx: TransferSender::new(tx_buf, usize::MAX),
+            link: LinkState::new(),
+            xmit_ok: 0,
+            rcv_ok: 0,
+            rcv_error: 0,
+        })
+    }
+
+    /// Gets the NDIS packet filter set by the host with OID_GEN_CURRENT_PACKET_FILTER.
+    pub fn packet_filter(&self) -> u32 {
+        self.packet_filter
+    }
+
+    // Gets the frame that is read next, skipping to the next message as needed. Releases the
+    // transfer and starts receiving the next one when all messages have been read.
+    fn current_frame(&mut self) -> Option<Range<usize>> {
+        let transfer = self.rx.transfer()?;
+
+        // Hosts may end a transfer with a padding byte instead of a zero-length packet.
+        if self.rx_pos + 8 <= transfer.len() {
+            match parse_packet_msg(&transfer[self.rx_pos..]) {
+                Some(range) => {
+                    return Some(self.rx_pos + range.start..self.rx_pos + range.end);
+                }
+                None => {
+                    usb_debug!("Dropping invalid RNDIS packet message");
+                    self.rcv_error = self.rcv_error.wrapping_add(1);
+                }
+            }
+        }
+
+        self.rx_pos = 0;
+        self.rx.release(&self.read_ep);
+
+        None
+    }
+
+    // Handles a control message received with SEND_ENCAPSULATED_COMMAND.
+    fn command(&mut self, msg: &[u8]) -> Result<()> {
+        if msg.len() < 12 {
+            return Err(UsbError::ParseError);
+        }
+
+        let msg_type = read_u32(msg, 0);
+        let request_id = read_u32(msg, 8);
+
+        let len = match msg_type {
+            message::INITIALIZE => {
+                self.initialized = true;
+                self.packet_filter = 0;
+                self.reset_data();
+
+                let max_transfer_size = self.rx.capacity() as u32;
+                self.response_header(message::INITIALIZE, request_id, status::SUCCESS);
+                write_u32(&mut self.response, 16, RNDIS_MAJOR_VERSION);
+                write_u32(&mut self.response, 20, RNDIS_MINOR_VERSION);
+                write_u32(&mut self.response, 24, DEVICE_FLAGS);
+                write_u32(&mut self.response, 28, MEDIUM_802_3);
+                write_u32(&mut self.response, 32, 1); // MaxPacketsPerTransfer
+                write_u32(&mut self.response, 36, max_transfer_size);
+                write_u32(&mut self.response, 40, 0); // PacketAlignmentFactor
+                write_u32(&mut self.response, 44, 0); // AFListOffset
+                write_u32(&mut self.response, 48, 0); // AFListSize
+                52
+            }
+            message::HALT => {
+                self.initialized = false;
+                self.packet_filter = 0;
+                self.reset_data();
+                return Ok(());
+            }
+            message::QUERY => {
+                let oid = msg.get(12..16).map(|_| read_u32(msg, 12));
+                let (status, info_len) = match oid.and_then(|oid| self.query(oid)) {
+                    Some(info_len) => (status::SUCCESS, info_len),
+                    None => (status::NOT_SUPPORTED, 0),
+                };
+
+                let info_offset = if info_len == 0 { 0 } else { QUERY_INFO_OFFSET };
+                self.response_header(message::QUERY, request_id, status);
+                write_u32(&mut self.response, 16, info_len as u32);
+                write_u32(&mut self.response, 20, info_offset as u32);
+                24 + info_len
+            }
+            message::SET => {
+                let status = match parse_set_msg(msg) {
+                    Some((oid, info)) => self.set(oid, info),
+                    None => status::INVALID_DATA,
+                };
+
+                self.response_header(message::SET, request_id, status);
+                16
+            }
+            message::RESET => {
+                self.packet_filter = 0;
+                self.reset_data();
+
+                // The reset completion has no request ID.
+                self.response_header(message::RESET, status::SUCCESS, 1); // AddressingReset
+                16
+            }
+            message::KEEPALIVE => {
+                self.response_header(message::KEEPALIVE, request_id, status::SUCCESS);
+                16
+            }
+            _ => {
+                usb_debug!("Ignoring RNDIS message {:x}", msg_type);
+                return Ok(());
+            }
+        };
+
+        write_u32(&mut self.response, 4, len as u32); // MessageLength
+        self.response_len = len;
+        self.notify();
+
+        Ok(())
+    }
+
+    // Writes the message type and the two fields that follow the length in all completions, which
+    // are the request ID and the status except in the reset completion.
+    fn response_header(&mut self, msg_type: u32, field8: u32, field12: u32) {
+        write_u32(&mut self.response, 0, msg_type | message::COMPLETION);
+        write_u32(&mut self.response, 8, field8);
+        write_u32(&mut self.response, 12, field12);
+    }
+
+    // Writes the value of `oid` into the information buffer of the query completion and returns
+    // its length.
+    fn query(&mut self, oid: u32) -> Option<usize> {
+        let info = &mut self.response[24..];
+
+        let value = match oid {
+            oid::GEN_SUPPORTED_LIST => {
+                for (i, &oid) in SUPPORTED_OIDS.iter().enumerate() {
+                    write_u32(info, i * 4, oid);
+                }
+                return Some(SUPPORTED_OIDS.len() * 4);
+            }
+            oid::GEN_VENDOR_DESCRIPTION => {
+                info[..VENDOR_DESCRIPTION.len()].copy_from_slice(VENDOR_DESCRIPTION);
+                return Some(VENDOR_DESCRIPTION.len());
+            }
+            oid::PERMANENT_ADDRESS_802_3 | oid::CURRENT_ADDRESS_802_3 => {
+                info[..6].copy_from_slice(&self.mac_address);
+                return Some(6);
+            }
+            oid::MULTICAST_LIST_802_3 => return Some(0),
+            oid::GEN_HARDWARE_STATUS => 0, // NdisHardwareStatusReady
+            oid::GEN_MEDIA_SUPPORTED | oid::GEN_MEDIA_IN_USE => MEDIUM_802_3,
+            // The frame size excludes the Ethernet header.
+            oid::GEN_MAXIMUM_FRAME_SIZE => (MAX_SEGMENT_SIZE - 14) as u32,
+            oid::GEN_LINK_SPEED => self.link.downlink_bit_rate() / 100,
+            oid::GEN_TRANSMIT_BLOCK_SIZE | oid::GEN_RECEIVE_BLOCK_SIZE => MAX_SEGMENT_SIZE as u32,
+            oid::GEN_VENDOR_ID => VENDOR_ID_NONE,
+            oid::GEN_CURRENT_PACKET_FILTER => self.packet_filter,
+            oid::GEN_MAXIMUM_TOTAL_SIZE => MIN_BUFFER_SIZE as u32,
+            oid::GEN_MEDIA_CONNECT_STATUS => (!self.link.is_connected()).into(),
+            oid::GEN_PHYSICAL_MEDIUM => 0, // NdisPhysicalMediumUnspecified
+            oid::GEN_XMIT_OK => self.xmit_ok,
+            oid::GEN_RCV_OK => self.rcv_ok,
+            oid::GEN_RCV_ERROR => self.rcv_error,
+            oid::GEN_XMIT_ERROR | oid::GEN_RCV_NO_BUFFER => 0,
+            oid::MAXIMUM_LIST_SIZE_802_3 => 1,
+            oid::MAC_OPTIONS_802_3 => 0,
+            oid::RCV_ERROR_ALIGNMENT_802_3
+            | oid::XMIT_ONE_COLLISION_802_3
+            | oid::XMIT_MORE_COLLISIONS_802_3 => 0,
+            _ => {
+                usb_debug!("Unsupported RNDIS query {:x}", oid);
+                return None;
+            }
+        };
+
+        write_u32(info, 0, value);
+        Some(4)
+    }
+
+    // Sets the value of `oid` and returns the status of the set completion.
+    fn set(&mut self, oid: u32, info: &[u8]) -> u32 {
+        match oid {
+            oid::GEN_CURRENT_PACKET_FILTER if info.len() == 4 => {
+                self.packet_filter = read_u32(info, 0);
+                status::SUCCESS
+            }
+            oid::GEN_CURRENT_PACKET_FILTER => status::INVALID_DATA,
+            // All multicast frames are passed on, so the list is not needed.
+            oid::MULTICAST_LIST_802_3 => status::SUCCESS,
+            _ => {
+                usb_debug!("Unsupported RNDIS set {:x}", oid);
+                status::NOT_SUPPORTED
+            }
+        }
+    }
+
+    // Tells the host that a response is waiting to be read.
+    fn notify(&mut self) {
+        self.notification_pending = true;
+        self.send_notification();
+    }
+
+    fn send_notification(&mut self) {
+        if self.notification_in_flight || !self.notification_pending {
+            return;
+        }
+
+        // RESPONSE_AVAILABLE
+        if self.comm_ep.write(&[0x01, 0, 0, 0, 0, 0, 0, 0]).is_ok() {
+            self.notification_pending = false;
+            self.notification_in_flight = true;
+        }
+    }
+
+    fn reset_data(&mut self) {
+        self.rx.reset();
+        self.rx_pos = 0;
+        self.tx.reset();
+    }
+
+    fn is_for_function(&self, req: &control::Request) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == u8::from(self.comm_if).into()
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for RndisClass<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.iad(
+            self.comm_if,
+            2,
+            INTERFACE_CLASS_RNDIS,
+            INTERFACE_SUBCLASS_RNDIS,
+            INTERFACE_PROTOCOL_RNDIS,
+            None,
+        )?;
+
+        writer.interface(
+            self.comm_if,
+            INTERFACE_CLASS_RNDIS,
+            INTERFACE_SUBCLASS_RNDIS,
+            INTERFACE_PROTOCOL_RNDIS,
+        )?;
+
+        let bcd_cdc = BCD_CDC.to_le_bytes();
+        writer.write(
+            CS_INTERFACE,
+            &[functional_descriptor::HEADER, bcd_cdc[0], bcd_cdc[1]],
+        )?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                functional_descriptor::CALL_MANAGEMENT,
+                0x00,                // bmCapabilities
+                self.data_if.into(), // bDataInterface
+            ],
+        )?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                functional_descriptor::ACM,
+                0x00, // bmCapabilities
+            ],
+        )?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                functional_descriptor::UNION,
+                self.comm_if.into(), // bControlInterface
+                self.data_if.into(), // bSubordinateInterface
+            ],
+        )?;
+        writer.endpoint(&self.comm_ep)?;
+
+        writer.interface(self.data_if, USB_CLASS_CDC_DATA, 0x00, 0x00)?;
+        writer.endpoint(&self.write_ep)?;
+        writer.endpoint(&self.read_ep)?;
+
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.initialized = false;
+        self.packet_filter = 0;
+        self.response_len = 0;
+        self.status_pending = false;
+        self.notification_pending = false;
+        self.notification_in_flight = false;
+        self.reset_data();
+    }
+
+    fn endpoint_out(&mut self, addr: EndpointAddress) {
+        if addr == self.read_ep.address() && self.is_active() {
+            self.rx.endpoint_out(&self.read_ep);
+            self.current_frame();
+        }
+    }
+
+    fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+        if addr == self.write_ep.address() {
+            self.tx.endpoint_in_complete(&self.write_ep);
+        } else if addr == self.comm_ep.address() {
+            self.notification_in_flight = false;
+            self.send_notification();
+        }
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+
+        if !self.is_for_function(&req) {
+            return;
+        }
+
+        match req.request {
+            cdc_request::GET_ENCAPSULATED_RESPONSE => {
+                if self.response_len != 0 {
+                    let len = core::mem::replace(&mut self.response_len, 0);
+                    xfer.accept_with(&self.response[..len]).ok();
+                } else if self.status_pending {
+                    self.status_pending = false;
+
+                    let status = if self.link.is_connected() {
+                        status::MEDIA_CONNECT
+                    } else {
+                        status::MEDIA_DISCONNECT
+                    };
+
+                    let mut msg = [0u8; 20];
+                    write_u32(&mut msg, 0, message::INDICATE_STATUS);
+                    write_u32(&mut msg, 4, 20); // MessageLength
+                    write_u32(&mut msg, 8, status);
+                    xfer.accept_with(&msg).ok();
+                } else {
+                    // No response is available.
+                    xfer.accept_with(&[0]).ok();
+                }
+
+                if self.status_pending {
+                    self.notify();
+                }
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+
+        if !self.is_for_function(&req) {
+            return;
+        }
+
+        match req.request {
+            cdc_request::SEND_ENCAPSULATED_COMMAND => match self.command(xfer.data()) {
+                Ok(()) => {
+                    xfer.accept().ok();
+                }
+                Err(_err) => {
+                    usb_debug!("Invalid RNDIS command: {:?}", _err);
+                    xfer.reject().ok();
+                }
+            },
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+}
+
+impl<B: UsbBus> EthernetFunction for RndisClass<'_, B> {
+    fn is_active(&self) -> bool {
+        self.initialized && self.packet_filter != 0
+    }
+
+    fn is_connected(&self) -> bool {
+        self.link.is_connected()
+    }
+
+    fn set_connected(&mut self, connected: bool) {
+        self.link.set_connected(connected);
+
+        if self.initialized {
+            self.status_pending = true;
+            self.notify();
+        }
+    }
+
+    fn set_connection_speed(&mut self, downlink_bit_rate: u32, uplink_bit_rate: u32) {
+        // The host queries OID_GEN_LINK_SPEED when it needs the speed.
+        self.link.set_speed(downlink_bit_rate, uplink_bit_rate);
+    }
+
+    fn can_receive(&mut self) -> bool {
+        self.current_frame().is_some()
+    }
+
+    fn can_transmit(&self) -> bool {
+        self.is_active() && !self.tx.is_busy()
+    }
+
+    fn receive_frame<R>(&mut self, f: impl FnOnce(&mut [u8]) -> R) -> Result<R> {
+        let range = self.current_frame().ok_or(UsbError::WouldBlock)?;
+        let transfer = self.rx.transfer_mut().ok_or(UsbError::WouldBlock)?;
+
+        let msg_len = read_u32(transfer, self.rx_pos + 4) as usize;
+        let result = f(&mut transfer[range]);
+
+        self.rx_pos += msg_len;
+        self.rcv_ok = self.rcv_ok.wrapping_add(1);
+
+        // Release the transfer right away if this was its last message.
+        self.current_frame();
+
+        Ok(result)
+    }
+
+    fn transmit_frame<R>(&mut self, len: usize, f: impl FnOnce(&mut [u8]) -> R) -> Result<R> {
+        if !self.is_active() {
+            return Err(UsbError::InvalidState);
+        }
+
+        if self.tx.is_busy() {
+            return Err(UsbError::WouldBlock);
+        }
+
+        if len > MAX_SEGMENT_SIZE {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        let msg_len = PACKET_MSG_HEADER_LENGTH + len;
+
+        let buf = self.tx.buf_mut();
+        buf[..PACKET_MSG_HEADER_LENGTH].fill(0);
+        write_u32(buf, 0, message::PACKET); // MessageType
+        write_u32(buf, 4, msg_len as u32); // MessageLength
+        write_u32(buf, 8, PACKET_DATA_OFFSET as u32); // DataOffset
+        write_u32(buf, 12, len as u32); // DataLength
+
+        let result = f(&mut buf[PACKET_MSG_HEADER_LENGTH..msg_len]);
+
+        self.tx.send(msg_len, &self.write_ep);
+        self.xmit_ok = self.xmit_ok.wrapping_add(1);
+
+        Ok(result)
+    }
+}
+
+// Validates the REMOTE_NDIS_PACKET_MSG at the start of `msg` and gets the range of its frame.
+fn parse_packet_msg(msg: &[u8]) -> Option<Range<usize>> {
+    if msg.len() < PACKET_MSG_HEADER_LENGTH || read_u32(msg, 0) != message::PACKET {
+        return None;
+    }
+
+    let msg_len = read_u32(msg, 4) as usize;
+    let data_offset = read_u32(msg, 8) as usize;
+    let data_len = read_u32(msg, 12) as usize;
+
+    let start = data_offset.checked_add(8)?;
+    let end = start.checked_add(data_len)?;
+
+    if msg_len < PACKET_MSG_HEADER_LENGTH
+        || msg_len > msg.len()
+        || start < PACKET_MSG_HEADER_LENGTH
+        || end > msg_len
+        || data_len == 0
+    {
+        return None;
+    }
+
+    Some(start..end)
+}
+
+// Gets the OID and the information buffer of a REMOTE_NDIS_SET_MSG.
+fn parse_set_msg(msg: &[u8]) -> Option<(u32, &[u8])> {
+    if msg.len() < 24 {
+        return None;
+    }
+
+    let oid = read_u32(msg, 12);
+    let info_len = read_u32(msg, 16) as usize;
+    let info_offset = read_u32(msg, 20) as usize;
+
+    // The offset is relative to the request ID.
+    let start = info_offset.checked_add(8)?;
+    let info = msg.get(start..start.checked_add(info_len)?)?;
+
+    Some((oid, info))
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
@@ -53,9 +53,28 @@ pub trait UsbClass<B: UsbBus> {
     /// Called after a USB reset after the bus reset sequence is complete.
     fn reset(&mut self) {}
 
+    /// Called when the host selects a configuration with SET_CONFIGURATION. `configuration` is the
+    /// `bConfigurationValue` of the selected configuration, or
+    /// [`CONFIGURATION_NONE`](crate::device::CONFIGURATION_NONE) if the host deconfigured the
+    /// device.
+    ///
+    /// Classes that only take part in some configurations of the device can use this to ignore
+    /// requests and endpoint events while another configuration is active.
+    fn set_configuration(&mut self, configuration: u8) {
+        let _ = configuration;
+    }
+
     /// Called whenever the `UsbDevice` is polled.
     fn poll(&mut self) {}
 
+    /// Called when a start-of-frame packet has been received, if the [`UsbBus`] implementation
+    /// reports them. `frame_number` is the 11-bit frame number of the packet.
+    ///
+    /// Isochronous classes can use this to pace their data or to measure the host clock.
+    fn sof(&mut self, frame_number: u16) {
+        let _ = frame_number;
+    }
+
     /// Called when a control request is received with direction HostToDevice.
     ///
     /// All requests are passed to classes in turn, which can choose to accept, ignore or report an
@@ -94,6 +113,22 @@ pub trait UsbClass<B: UsbBus> {
         let _ = xfer;
     }
 
+    /// Called when the status stage of a control OUT transfer has completed, which is the point
+    /// at which the host knows the request was accepted.
+    ///
+    /// Classes that need to act after the host has seen the result of a request, such as detaching
+    /// from the bus, should do so here instead of in [`control_out`](UsbClass::control_out).
+    ///
+    /// Note: This method is called for every control OUT transfer, including ones meant for other
+    /// classes, so implementations should check `req` in the same way as in `control_out`.
+    ///
+    /// # Arguments
+    ///
+    /// * `req` - The request from the SETUP packet.
+    fn control_out_complete(&mut self, req: &control::Request) {
+        let _ = req;
+    }
+
     /// Called when endpoint with address `addr` has received a SETUP packet. Implementing this
     /// shouldn't be necessary in most cases, but is provided for completeness' sake.
     ///
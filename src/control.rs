@@ -97,6 +97,10 @@ impl Request {
     /// Standard USB feature Device Remote Wakeup for Set/Clear Feature
     pub const FEATURE_DEVICE_REMOTE_WAKEUP: u16 = 1;
 
+    /// Standard USB feature Test Mode for Set Feature. The test selector is in the high byte of
+    /// `index`.
+    pub const FEATURE_TEST_MODE: u16 = 2;
+
     pub(crate) fn parse(buf: &[u8]) -> Result<Request> {
         if buf.len() != 8 {
             return Err(UsbError::ParseError);
@@ -107,9 +111,9 @@ impl Request {
 
         Ok(Request {
             direction: rt.into(),
-            request_type: unsafe { mem::transmute((rt >> 5) & 0b11) },
+            request_type: unsafe { mem::transmute::<u8, RequestType>((rt >> 5) & 0b11) },
             recipient: if recipient <= 3 {
-                unsafe { mem::transmute(recipient) }
+                unsafe { mem::transmute::<u8, Recipient>(recipient) }
             } else {
                 Recipient::Reserved
             },
@@ -14,9 +14,9 @@ enum ControlState {
     DataInLast,
     CompleteIn(Request),
     StatusOut,
-    CompleteOut,
+    CompleteOut(Request),
     DataOut(Request),
-    StatusIn,
+    StatusIn(Request),
     Error,
 }
 
@@ -51,7 +51,7 @@ impl<B: UsbBus> ControlPipe<'_, B> {
     pub fn waiting_for_response(&self) -> bool {
         matches!(
             self.state,
-            ControlState::CompleteOut | ControlState::CompleteIn(_)
+            ControlState::CompleteOut(_) | ControlState::CompleteIn(_)
         )
     }
 
@@ -113,7 +113,7 @@ impl<B: UsbBus> ControlPipe<'_, B> {
                 // No data stage
 
                 self.len = 0;
-                self.state = ControlState::CompleteOut;
+                self.state = ControlState::CompleteOut(req);
                 return Some(req);
             }
         } else {
@@ -151,7 +151,7 @@ impl<B: UsbBus> ControlPipe<'_, B> {
 
                 if self.i >= self.len {
                     usb_debug!("Request OUT complete: {:?}", req);
-                    self.state = ControlState::CompleteOut;
+                    self.state = ControlState::CompleteOut(req);
                     return Ok(Some(req));
                 }
             }
@@ -184,7 +184,8 @@ impl<B: UsbBus> ControlPipe<'_, B> {
         Ok(None)
     }
 
-    pub fn handle_in_complete(&mut self) -> Result<bool> {
+    // Returns the request whose status stage has completed, if any.
+    pub fn handle_in_complete(&mut self) -> Result<Option<Request>> {
         match self.state {
             ControlState::DataIn => {
                 self.write_in_chunk()?;
@@ -198,9 +199,9 @@ impl<B: UsbBus> ControlPipe<'_, B> {
                 self.ep_out.unstall();
                 self.state = ControlState::StatusOut;
             }
-            ControlState::StatusIn => {
+            ControlState::StatusIn(req) => {
                 self.state = ControlState::Idle;
-                return Ok(true);
+                return Ok(Some(req));
             }
             ControlState::Idle => {
                 // If we received a message on EP0 while sending the last portion of an IN
@@ -214,13 +215,13 @@ impl<B: UsbBus> ControlPipe<'_, B> {
             }
         };
 
-        Ok(false)
+        Ok(None)
     }
 
     fn write_in_chunk(&mut self) -> Result<()> {
         let count = min(self.len - self.i, self.ep_in.max_packet_size() as usize);
 
-        let buffer = self.static_in_buf.unwrap_or(&self.buf);
+        let buffer = self.static_in_buf.unwrap_or(self.buf);
         let count = self.ep_in.write(&buffer[self.i..(self.i + count)])?;
         usb_trace!("wrote EP0: {:?}", &buffer[self.i..(self.i + count)]);
 
@@ -240,8 +241,8 @@ impl<B: UsbBus> ControlPipe<'_, B> {
     }
 
     pub fn accept_out(&mut self) -> Result<()> {
-        match self.state {
-            ControlState::CompleteOut => {}
+        let req = match self.state {
+            ControlState::CompleteOut(req) => req,
             _ => {
                 usb_debug!("Cannot ACK, invalid state: {:?}", self.state);
                 return Err(UsbError::InvalidState);
@@ -249,7 +250,7 @@ impl<B: UsbBus> ControlPipe<'_, B> {
         };
 
         self.ep_in.write(&[])?;
-        self.state = ControlState::StatusIn;
+        self.state = ControlState::StatusIn(req);
         Ok(())
     }
 
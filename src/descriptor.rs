@@ -1,7 +1,7 @@
 use core::cmp::min;
 
-use crate::bus::{InterfaceNumber, StringIndex, UsbBus};
-use crate::device;
+use crate::bus::{InterfaceNumber, StringIndex, UsbBus, UsbSpeed};
+use crate::device::{self, UsbConfiguration};
 use crate::endpoint::{Endpoint, EndpointDirection};
 use crate::{Result, UsbError};
 
@@ -13,14 +13,26 @@ pub mod descriptor_type {
     pub const STRING: u8 = 3;
     pub const INTERFACE: u8 = 4;
     pub const ENDPOINT: u8 = 5;
+    pub const DEVICE_QUALIFIER: u8 = 6;
+    pub const OTHER_SPEED_CONFIGURATION: u8 = 7;
     pub const IAD: u8 = 11;
     pub const BOS: u8 = 15;
     pub const CAPABILITY: u8 = 16;
 }
 
+/// HID class descriptors and a typed HID report descriptor writer and parser.
+pub mod hid;
+
 /// String descriptor language IDs.
 pub mod lang_id;
 
+/// Parsing and validation of descriptors, for example to check the descriptors of a device in
+/// tests.
+pub mod parser;
+
+/// Configuration descriptors built at compile time.
+pub mod static_config;
+
 /// Standard capability descriptor types
 #[allow(missing_docs)]
 pub mod capability_type {
@@ -38,6 +50,8 @@ pub struct DescriptorWriter<'a> {
     num_interfaces_mark: Option<usize>,
     num_endpoints_mark: Option<usize>,
     write_iads: bool,
+    configuration_value: u8,
+    speed: UsbSpeed,
 }
 
 impl DescriptorWriter<'_> {
@@ -48,6 +62,8 @@ impl DescriptorWriter<'_> {
             num_interfaces_mark: None,
             num_endpoints_mark: None,
             write_iads: false,
+            configuration_value: device::CONFIGURATION_NONE,
+            speed: UsbSpeed::Full,
         }
     }
 
@@ -56,6 +72,24 @@ impl DescriptorWriter<'_> {
         self.position
     }
 
+    /// Gets the `bConfigurationValue` of the configuration descriptor being written. Classes that
+    /// only take part in some configurations of the device should only write their descriptors
+    /// for those.
+    pub fn configuration_value(&self) -> u8 {
+        self.configuration_value
+    }
+
+    /// Gets the bus speed the configuration descriptor being written describes the device at. This
+    /// is the current speed of the device, except for OTHER_SPEED_CONFIGURATION requests from the
+    /// host, which ask how the device would look at the other speed it supports.
+    ///
+    /// Classes that can operate at high speed should use this to describe their endpoints with the
+    /// packet sizes and intervals allowed at each speed, see
+    /// [`endpoint_sized`](DescriptorWriter::endpoint_sized).
+    pub fn speed(&self) -> UsbSpeed {
+        self.speed
+    }
+
     /// Writes an arbitrary (usually class-specific) descriptor.
     pub fn write(&mut self, descriptor_type: u8, descriptor: &[u8]) -> Result<()> {
         self.write_with(descriptor_type, |buf| {
@@ -138,31 +172,58 @@ impl DescriptorWriter<'_> {
                         0
                     }
                 }),
-                1, // bNumConfigurations
+                config.configurations.len() as u8, // bNumConfigurations
             ],
         )
     }
 
-    pub(crate) fn configuration(&mut self, config: &device::Config) -> Result<()> {
+    pub(crate) fn device_qualifier(&mut self, config: &device::Config) -> Result<()> {
+        self.write(
+            descriptor_type::DEVICE_QUALIFIER,
+            &[
+                (config.usb_rev as u16) as u8,
+                (config.usb_rev as u16 >> 8) as u8, // bcdUSB
+                config.device_class,                // bDeviceClass
+                config.device_sub_class,            // bDeviceSubClass
+                config.device_protocol,             // bDeviceProtocol
+                config.max_packet_size_0,           // bMaxPacketSize0
+                config.configurations.len() as u8,  // bNumConfigurations
+                0,                                  // bReserved
+            ],
+        )
+    }
+
+    pub(crate) fn configuration(
+        &mut self,
+        config: &device::Config,
+        configuration: &UsbConfiguration,
+        descriptor_type: u8,
+        speed: UsbSpeed,
+    ) -> Result<()> {
         self.num_interfaces_mark = Some(self.position + 4);
 
         self.write_iads = config.composite_with_iads;
+        self.configuration_value = configuration.value;
+        self.speed = speed;
 
         self.write(
-            descriptor_type::CONFIGURATION,
+            descriptor_type,
             &[
                 0,
-                0,                           // wTotalLength
-                0,                           // bNumInterfaces
-                device::CONFIGURATION_VALUE, // bConfigurationValue
-                0,                           // iConfiguration
-                0x80 | if config.self_powered { 0x40 } else { 0x00 }
-                    | if config.supports_remote_wakeup {
-                        0x20
-                    } else {
-                        0x00
-                    }, // bmAttributes
-                config.max_power,            // bMaxPower
+                0,                                            // wTotalLength
+                0,                                            // bNumInterfaces
+                configuration.value,                          // bConfigurationValue
+                configuration.string.map_or(0, |n| n.into()), // iConfiguration
+                0x80 | if configuration.self_powered {
+                    0x40
+                } else {
+                    0x00
+                } | if configuration.supports_remote_wakeup {
+                    0x20
+                } else {
+                    0x00
+                }, // bmAttributes
+                configuration.max_power,                      // bMaxPower
             ],
         )
     }
@@ -262,7 +323,6 @@ impl DescriptorWriter<'_> {
     /// * `interface_sub_class` - Sub-class code. Depends on class.
     /// * `interface_protocol` - Protocol code. Depends on class and sub-class.
     /// * `interface_string` - Index of string descriptor describing this interface
-
     pub fn interface_alt(
         &mut self,
         number: InterfaceNumber,
@@ -312,6 +372,39 @@ impl DescriptorWriter<'_> {
         self.endpoint_ex(endpoint, |_| Ok(0))
     }
 
+    /// Writes an endpoint descriptor with a maximum packet size and interval that differ from the
+    /// ones the endpoint was allocated with.
+    ///
+    /// This is used by classes that can operate at more than one speed. The endpoint is allocated
+    /// with the largest packet size it will ever use, and described with the packet size and
+    /// interval for the [`speed`](DescriptorWriter::speed) being described, for example a 512 byte
+    /// bulk endpoint at high speed and a 64 byte one at full speed.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - Endpoint previously allocated with
+    ///   [`UsbBusAllocator`](crate::bus::UsbBusAllocator).
+    /// * `max_packet_size` - Maximum packet size to describe, which must not be larger than the
+    ///   one the endpoint was allocated with.
+    /// * `interval` - Polling interval to describe.
+    ///
+    /// # Errors
+    ///
+    /// * [`EndpointMemoryOverflow`](crate::UsbError::EndpointMemoryOverflow) - `max_packet_size` is
+    ///   larger than the packet buffer allocated for the endpoint.
+    pub fn endpoint_sized<B: UsbBus, D: EndpointDirection>(
+        &mut self,
+        endpoint: &Endpoint<'_, B, D>,
+        max_packet_size: u16,
+        interval: u8,
+    ) -> Result<()> {
+        if max_packet_size > endpoint.max_packet_size() {
+            return Err(UsbError::EndpointMemoryOverflow);
+        }
+
+        self.write_endpoint(endpoint, max_packet_size, interval, |_| Ok(0))
+    }
+
     /// Writes an endpoint descriptor with extra trailing data.
     ///
     /// This is rarely needed and shouldn't be used except for compatibility with standard USB
@@ -327,6 +420,16 @@ impl DescriptorWriter<'_> {
         &mut self,
         endpoint: &Endpoint<'_, B, D>,
         f: impl FnOnce(&mut [u8]) -> Result<usize>,
+    ) -> Result<()> {
+        self.write_endpoint(endpoint, endpoint.max_packet_size(), endpoint.interval(), f)
+    }
+
+    fn write_endpoint<B: UsbBus, D: EndpointDirection>(
+        &mut self,
+        endpoint: &Endpoint<'_, B, D>,
+        max_packet_size: u16,
+        interval: u8,
+        f: impl FnOnce(&mut [u8]) -> Result<usize>,
     ) -> Result<()> {
         match self.num_endpoints_mark {
             Some(mark) => self.buf[mark] += 1,
@@ -338,13 +441,11 @@ impl DescriptorWriter<'_> {
                 return Err(UsbError::BufferOverflow);
             }
 
-            let mps = endpoint.max_packet_size();
-
             buf[0] = endpoint.address().into();
             buf[1] = endpoint.ep_type().to_bm_attributes();
-            buf[2] = mps as u8;
-            buf[3] = (mps >> 8) as u8;
-            buf[4] = endpoint.interval();
+            buf[2] = max_packet_size as u8;
+            buf[3] = (max_packet_size >> 8) as u8;
+            buf[4] = interval;
 
             Ok(f(&mut buf[5..])? + 5)
         })
@@ -394,7 +495,7 @@ impl<'w, 'a: 'w> BosWriter<'w, 'a> {
         }
     }
 
-    pub(crate) fn bos(&mut self) -> Result<()> {
+    pub(crate) fn bos(&mut self, config: &device::Config) -> Result<()> {
         self.num_caps_mark = Some(self.writer.position + 4);
         self.writer.write(
             descriptor_type::BOS,
@@ -404,7 +505,31 @@ impl<'w, 'a: 'w> BosWriter<'w, 'a> {
             ],
         )?;
 
-        self.capability(capability_type::USB_2_0_EXTENSION, &[0; 4])?;
+        let mut attributes: u32 = 0;
+        if config.lpm {
+            // LPM, with the BESL and alternate HIRD definitions
+            attributes |= 0x0000_0006;
+
+            if let Some(besl) = config.baseline_besl {
+                attributes |= 0x0000_0008 | (u32::from(besl) << 8);
+            }
+
+            if let Some(besl) = config.deep_besl {
+                attributes |= 0x0000_0010 | (u32::from(besl) << 12);
+            }
+        }
+
+        self.capability(
+            capability_type::USB_2_0_EXTENSION,
+            &attributes.to_le_bytes(), // bmAttributes
+        )?;
+
+        if let Some(device::ContainerId::Uuid(uuid)) = config.container_id {
+            let mut data = [0u8; 17];
+            data[1..].copy_from_slice(&uuid); // ContainerID
+
+            self.capability(capability_type::CONTAINER_ID, &data)?;
+        }
 
         Ok(())
     }
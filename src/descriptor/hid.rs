@@ -0,0 +1,648 @@
+use crate::{Result, UsbError};
+
+/// Descriptor type of the HID class descriptor.
+pub const HID_DESCRIPTOR_TYPE: u8 = 0x21;
+
+/// Descriptor type of the HID report descriptor.
+pub const REPORT_DESCRIPTOR_TYPE: u8 = 0x22;
+
+/// Descriptor type of the HID physical descriptor.
+pub const PHYSICAL_DESCRIPTOR_TYPE: u8 = 0x23;
+
+/// Returns the contents of a HID class descriptor, to be written with
+/// [`DescriptorWriter::write`](crate::descriptor::DescriptorWriter::write) and
+/// [`HID_DESCRIPTOR_TYPE`] right after the interface descriptor.
+///
+/// # Arguments
+///
+/// * `bcd_hid` - The HID specification release in BCD, usually `0x0111`.
+/// * `country_code` - The country code of localized hardware, or 0.
+/// * `report_descriptor_len` - The length of the report descriptor.
+pub fn hid_descriptor(bcd_hid: u16, country_code: u8, report_descriptor_len: u16) -> [u8; 7] {
+    let bcd_hid = bcd_hid.to_le_bytes();
+    let len = report_descriptor_len.to_le_bytes();
+
+    [
+        bcd_hid[0],
+        bcd_hid[1],             // bcdHID
+        country_code,           // bCountryCode
+        1,                      // bNumDescriptors
+        REPORT_DESCRIPTOR_TYPE, // bDescriptorType
+        len[0],
+        len[1], // wDescriptorLength
+    ]
+}
+
+/// Common usage pages
+#[allow(missing_docs)]
+pub mod usage_page {
+    pub const GENERIC_DESKTOP: u16 = 0x01;
+    pub const SIMULATION_CONTROLS: u16 = 0x02;
+    pub const GAME_CONTROLS: u16 = 0x05;
+    pub const GENERIC_DEVICE_CONTROLS: u16 = 0x06;
+    pub const KEYBOARD: u16 = 0x07;
+    pub const LED: u16 = 0x08;
+    pub const BUTTON: u16 = 0x09;
+    pub const CONSUMER: u16 = 0x0c;
+    pub const DIGITIZER: u16 = 0x0d;
+    pub const FIDO_ALLIANCE: u16 = 0xf1d0;
+    pub const VENDOR_DEFINED_START: u16 = 0xff00;
+}
+
+/// Common usages of the Generic Desktop page
+#[allow(missing_docs)]
+pub mod generic_desktop {
+    pub const POINTER: u16 = 0x01;
+    pub const MOUSE: u16 = 0x02;
+    pub const JOYSTICK: u16 = 0x04;
+    pub const GAMEPAD: u16 = 0x05;
+    pub const KEYBOARD: u16 = 0x06;
+    pub const KEYPAD: u16 = 0x07;
+    pub const X: u16 = 0x30;
+    pub const Y: u16 = 0x31;
+    pub const Z: u16 = 0x32;
+    pub const WHEEL: u16 = 0x38;
+}
+
+/// Flags of input, output and feature items. Combine them with `|`; the flags that are 0 are the
+/// defaults and only exist for readability.
+#[allow(missing_docs)]
+pub mod item_flags {
+    pub const DATA: u16 = 0x000;
+    pub const CONSTANT: u16 = 0x001;
+    pub const ARRAY: u16 = 0x000;
+    pub const VARIABLE: u16 = 0x002;
+    pub const ABSOLUTE: u16 = 0x000;
+    pub const RELATIVE: u16 = 0x004;
+    pub const WRAP: u16 = 0x008;
+    pub const NON_LINEAR: u16 = 0x010;
+    pub const NO_PREFERRED_STATE: u16 = 0x020;
+    pub const NULL_STATE: u16 = 0x040;
+    pub const VOLATILE: u16 = 0x080;
+    pub const BUFFERED_BYTES: u16 = 0x100;
+}
+
+/// Type of a collection.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CollectionType {
+    /// A group of axes, such as x, y and z.
+    Physical = 0x00,
+    /// A group of items that form a meaningful whole to applications, such as a mouse.
+    Application = 0x01,
+    /// A group of items that are related, such as a key and its modifiers.
+    Logical = 0x02,
+    /// A group of items that form a report.
+    Report = 0x03,
+    /// An array of selectors.
+    NamedArray = 0x04,
+    /// A group of usages that modify the meaning of a usage.
+    UsageSwitch = 0x05,
+    /// A group of usages that modify another usage.
+    UsageModifier = 0x06,
+}
+
+/// Type of a short item, which determines the meaning of its tag.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ItemType {
+    /// An item that defines or groups data fields, such as Input or Collection.
+    Main = 0,
+    /// An item that describes the data fields and applies to all following main items.
+    Global = 1,
+    /// An item that describes the data fields and only applies to the next main item.
+    Local = 2,
+}
+
+// Item types, as found in item prefixes
+const MAIN: u8 = ItemType::Main as u8;
+const GLOBAL: u8 = ItemType::Global as u8;
+
+// Main item tags
+const INPUT: u8 = 0x8;
+const OUTPUT: u8 = 0x9;
+const COLLECTION: u8 = 0xa;
+const FEATURE: u8 = 0xb;
+const END_COLLECTION: u8 = 0xc;
+
+// Global item tags
+const USAGE_PAGE: u8 = 0x0;
+const LOGICAL_MINIMUM: u8 = 0x1;
+const LOGICAL_MAXIMUM: u8 = 0x2;
+const PHYSICAL_MINIMUM: u8 = 0x3;
+const PHYSICAL_MAXIMUM: u8 = 0x4;
+const UNIT_EXPONENT: u8 = 0x5;
+const UNIT: u8 = 0x6;
+const REPORT_SIZE: u8 = 0x7;
+const REPORT_ID: u8 = 0x8;
+const REPORT_COUNT: u8 = 0x9;
+const PUSH: u8 = 0xa;
+const POP: u8 = 0xb;
+
+// Local item tags
+const USAGE: u8 = 0x0;
+const USAGE_MINIMUM: u8 = 0x1;
+const USAGE_MAXIMUM: u8 = 0x2;
+
+// Prefix of a long item.
+const LONG_ITEM: u8 = 0xfe;
+
+// Maximum depth of the global item stack used by PUSH and POP.
+const STACK_DEPTH: usize = 4;
+
+/// A writer for HID report descriptors.
+///
+/// Each item is written with the smallest data size that can hold its value, and collections are
+/// checked to be balanced when the descriptor is finished.
+///
+/// ```
+/// use usb_device::descriptor::hid::{
+///     generic_desktop, item_flags::*, usage_page, CollectionType, ReportDescriptorWriter,
+///     ReportSizes,
+/// };
+///
+/// let mut buf = [0u8; 64];
+/// let mut w = ReportDescriptorWriter::new(&mut buf);
+///
+/// w.usage_page(usage_page::GENERIC_DESKTOP).unwrap();
+/// w.usage(generic_desktop::MOUSE).unwrap();
+/// w.collection(CollectionType::Application).unwrap();
+/// w.usage(generic_desktop::POINTER).unwrap();
+/// w.collection(CollectionType::Physical).unwrap();
+///
+/// // Three buttons and five bits of padding
+/// w.usage_page(usage_page::BUTTON).unwrap();
+/// w.usage_minimum(1).unwrap();
+/// w.usage_maximum(3).unwrap();
+/// w.logical_minimum(0).unwrap();
+/// w.logical_maximum(1).unwrap();
+/// w.report_size(1).unwrap();
+/// w.report_count(3).unwrap();
+/// w.input(DATA | VARIABLE | ABSOLUTE).unwrap();
+/// w.report_size(5).unwrap();
+/// w.report_count(1).unwrap();
+/// w.input(CONSTANT).unwrap();
+///
+/// // Relative X and Y movement
+/// w.usage_page(usage_page::GENERIC_DESKTOP).unwrap();
+/// w.usage(generic_desktop::X).unwrap();
+/// w.usage(generic_desktop::Y).unwrap();
+/// w.logical_minimum(-127).unwrap();
+/// w.logical_maximum(127).unwrap();
+/// w.report_size(8).unwrap();
+/// w.report_count(2).unwrap();
+/// w.input(DATA | VARIABLE | RELATIVE).unwrap();
+///
+/// w.end_collection().unwrap();
+/// w.end_collection().unwrap();
+///
+/// let descriptor = w.finish().unwrap();
+///
+/// let sizes = ReportSizes::parse(descriptor).unwrap();
+/// assert_eq!(sizes.get(0).unwrap().input, 3);
+/// ```
+pub struct ReportDescriptorWriter<'a> {
+    buf: &'a mut [u8],
+    position: usize,
+    depth: usize,
+}
+
+impl<'a> ReportDescriptorWriter<'a> {
+    /// Creates a writer that writes the report descriptor into `buf`.
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        ReportDescriptorWriter {
+            buf,
+            position: 0,
+            depth: 0,
+        }
+    }
+
+    /// Gets the current position in the buffer, i.e. the number of bytes written so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Writes a short item with an unsigned value, using the smallest possible data size.
+    ///
+    /// # Errors
+    ///
+    /// * [`ParseError`](crate::UsbError::ParseError) - `tag` doesn't fit in four bits.
+    pub fn item(&mut self, item_type: ItemType, tag: u8, value: u32) -> Result<()> {
+        let size = match value {
+            0 => 0,
+            1..=0xff => 1,
+            0x100..=0xffff => 2,
+            _ => 4,
+        };
+
+        self.write_item(item_type, tag, &value.to_le_bytes()[..size])
+    }
+
+    /// Writes a short item with a signed value, using the smallest possible data size.
+    ///
+    /// # Errors
+    ///
+    /// * [`ParseError`](crate::UsbError::ParseError) - `tag` doesn't fit in four bits.
+    pub fn signed_item(&mut self, item_type: ItemType, tag: u8, value: i32) -> Result<()> {
+        let size = if value == 0 {
+            0
+        } else if (-0x80..=0x7f).contains(&value) {
+            1
+        } else if (-0x8000..=0x7fff).contains(&value) {
+            2
+        } else {
+            4
+        };
+
+        self.write_item(item_type, tag, &value.to_le_bytes()[..size])
+    }
+
+    /// Writes a Usage Page item. See [`usage_page`] for common values.
+    pub fn usage_page(&mut self, usage_page: u16) -> Result<()> {
+        self.item(ItemType::Global, USAGE_PAGE, usage_page.into())
+    }
+
+    /// Writes a Usage item for a usage on the current usage page.
+    pub fn usage(&mut self, usage: u16) -> Result<()> {
+        self.item(ItemType::Local, USAGE, usage.into())
+    }
+
+    /// Writes a Usage item for a usage on another usage page.
+    pub fn extended_usage(&mut self, usage_page: u16, usage: u16) -> Result<()> {
+        let value = (u32::from(usage_page) << 16) | u32::from(usage);
+        self.write_item(ItemType::Local, USAGE, &value.to_le_bytes())
+    }
+
+    /// Writes a Usage Minimum item.
+    pub fn usage_minimum(&mut self, usage: u16) -> Result<()> {
+        self.item(ItemType::Local, USAGE_MINIMUM, usage.into())
+    }
+
+    /// Writes a Usage Maximum item.
+    pub fn usage_maximum(&mut self, usage: u16) -> Result<()> {
+        self.item(ItemType::Local, USAGE_MAXIMUM, usage.into())
+    }
+
+    /// Writes a Logical Minimum item.
+    pub fn logical_minimum(&mut self, value: i32) -> Result<()> {
+        self.signed_item(ItemType::Global, LOGICAL_MINIMUM, value)
+    }
+
+    /// Writes a Logical Maximum item.
+    pub fn logical_maximum(&mut self, value: i32) -> Result<()> {
+        self.signed_item(ItemType::Global, LOGICAL_MAXIMUM, value)
+    }
+
+    /// Writes a Physical Minimum item.
+    pub fn physical_minimum(&mut self, value: i32) -> Result<()> {
+        self.signed_item(ItemType::Global, PHYSICAL_MINIMUM, value)
+    }
+
+    /// Writes a Physical Maximum item.
+    pub fn physical_maximum(&mut self, value: i32) -> Result<()> {
+        self.signed_item(ItemType::Global, PHYSICAL_MAXIMUM, value)
+    }
+
+    /// Writes a Unit Exponent item.
+    pub fn unit_exponent(&mut self, exponent: i8) -> Result<()> {
+        self.signed_item(ItemType::Global, UNIT_EXPONENT, exponent.into())
+    }
+
+    /// Writes a Unit item.
+    pub fn unit(&mut self, unit: u32) -> Result<()> {
+        self.item(ItemType::Global, UNIT, unit)
+    }
+
+    /// Writes a Report Size item, the size of each field in bits.
+    pub fn report_size(&mut self, bits: u32) -> Result<()> {
+        self.item(ItemType::Global, REPORT_SIZE, bits)
+    }
+
+    /// Writes a Report Count item, the number of fields.
+    pub fn report_count(&mut self, count: u32) -> Result<()> {
+        self.item(ItemType::Global, REPORT_COUNT, count)
+    }
+
+    /// Writes a Report ID item.
+    ///
+    /// # Errors
+    ///
+    /// * [`ParseError`](crate::UsbError::ParseError) - `report_id` is zero, which is reserved.
+    pub fn report_id(&mut self, report_id: u8) -> Result<()> {
+        if report_id == 0 {
+            return Err(UsbError::ParseError);
+        }
+
+        self.write_item(ItemType::Global, REPORT_ID, &[report_id])
+    }
+
+    /// Writes a Push item, which saves the current global items.
+    pub fn push(&mut self) -> Result<()> {
+        self.write_item(ItemType::Global, PUSH, &[])
+    }
+
+    /// Writes a Pop item, which restores the global items saved by the last Push item.
+    pub fn pop(&mut self) -> Result<()> {
+        self.write_item(ItemType::Global, POP, &[])
+    }
+
+    /// Starts a collection. Every collection must be closed with
+    /// [`end_collection`](Self::end_collection).
+    pub fn collection(&mut self, collection_type: CollectionType) -> Result<()> {
+        self.write_item(ItemType::Main, COLLECTION, &[collection_type as u8])?;
+        self.depth += 1;
+
+        Ok(())
+    }
+
+    /// Ends the innermost collection.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - There is no open collection.
+    pub fn end_collection(&mut self) -> Result<()> {
+        if self.depth == 0 {
+            return Err(UsbError::InvalidState);
+        }
+
+        self.write_item(ItemType::Main, END_COLLECTION, &[])?;
+        self.depth -= 1;
+
+        Ok(())
+    }
+
+    /// Writes an Input item with the given [`item_flags`].
+    pub fn input(&mut self, flags: u16) -> Result<()> {
+        self.item(ItemType::Main, INPUT, flags.into())
+    }
+
+    /// Writes an Output item with the given [`item_flags`].
+    pub fn output(&mut self, flags: u16) -> Result<()> {
+        self.item(ItemType::Main, OUTPUT, flags.into())
+    }
+
+    /// Writes a Feature item with the given [`item_flags`].
+    pub fn feature(&mut self, flags: u16) -> Result<()> {
+        self.item(ItemType::Main, FEATURE, flags.into())
+    }
+
+    /// Finishes the report descriptor and returns it.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - A collection has not been closed.
+    pub fn finish(self) -> Result<&'a [u8]> {
+        if self.depth != 0 {
+            return Err(UsbError::InvalidState);
+        }
+
+        Ok(&self.buf[..self.position])
+    }
+
+    fn write_item(&mut self, item_type: ItemType, tag: u8, data: &[u8]) -> Result<()> {
+        if tag > 0x0f {
+            return Err(UsbError::ParseError);
+        }
+
+        let size_code = match data.len() {
+            0 => 0,
+            1 => 1,
+            2 => 2,
+            4 => 3,
+            _ => return Err(UsbError::InvalidState),
+        };
+
+        let end = self.position + 1 + data.len();
+        if end > self.buf.len() {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        self.buf[self.position] = (tag << 4) | ((item_type as u8) << 2) | size_code;
+        self.buf[self.position + 1..end].copy_from_slice(data);
+        self.position = end;
+
+        Ok(())
+    }
+}
+
+/// Error returned when a report descriptor cannot be parsed.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReportDescriptorError {
+    /// An item extends beyond the end of the descriptor.
+    Truncated,
+    /// Collections are not balanced.
+    UnbalancedCollection,
+    /// Push and Pop items are not balanced, or nested too deeply.
+    UnbalancedPush,
+    /// A Report ID item is zero, or reports are used both with and without a report ID.
+    InvalidReportId,
+    /// The descriptor describes more reports than are supported.
+    TooManyReports,
+}
+
+/// The sizes of the reports with one report ID, in bytes and without the report ID prefix.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReportSize {
+    /// The report ID, or 0 if the descriptor doesn't use report IDs.
+    pub report_id: u8,
+    /// Size of the input report.
+    pub input: usize,
+    /// Size of the output report.
+    pub output: usize,
+    /// Size of the feature report.
+    pub feature: usize,
+}
+
+// Report sizes in bits while parsing.
+#[derive(Copy, Clone, Default)]
+struct ReportBits {
+    report_id: u8,
+    input: u32,
+    output: u32,
+    feature: u32,
+}
+
+#[derive(Copy, Clone, Default)]
+struct Globals {
+    report_id: u8,
+    report_size: u32,
+    report_count: u32,
+}
+
+/// The report sizes described by a report descriptor, computed by [`ReportSizes::parse`].
+///
+/// When the descriptor uses report IDs, the report ID is sent as an extra first byte of every
+/// report, which is not included in the sizes.
+#[derive(Clone, Debug)]
+pub struct ReportSizes {
+    reports: heapless::Vec<ReportSize, 16>,
+}
+
+impl ReportSizes {
+    /// Parses a report descriptor and computes the sizes of its reports.
+    pub fn parse(descriptor: &[u8]) -> core::result::Result<Self, ReportDescriptorError> {
+        let mut reports: heapless::Vec<ReportBits, 16> = heapless::Vec::new();
+        let mut globals = Globals::default();
+        let mut stack: heapless::Vec<Globals, STACK_DEPTH> = heapless::Vec::new();
+        let mut depth = 0usize;
+        let mut uses_report_ids = false;
+        let mut pos = 0;
+
+        while pos < descriptor.len() {
+            let prefix = descriptor[pos];
+
+            if prefix == LONG_ITEM {
+                let size = *descriptor
+                    .get(pos + 1)
+                    .ok_or(ReportDescriptorError::Truncated)? as usize;
+                pos += 3 + size;
+                if pos > descriptor.len() {
+                    return Err(ReportDescriptorError::Truncated);
+                }
+                continue;
+            }
+
+            let size = match prefix & 0x03 {
+                3 => 4,
+                size => size as usize,
+            };
+            let data = descriptor
+                .get(pos + 1..pos + 1 + size)
+                .ok_or(ReportDescriptorError::Truncated)?;
+            pos += 1 + size;
+
+            let mut bytes = [0u8; 4];
+            bytes[..size].copy_from_slice(data);
+            let value = u32::from_le_bytes(bytes);
+
+            match ((prefix >> 2) & 0x03, prefix >> 4) {
+                (MAIN, COLLECTION) => depth += 1,
+                (MAIN, END_COLLECTION) => {
+                    depth = depth
+                        .checked_sub(1)
+                        .ok_or(ReportDescriptorError::UnbalancedCollection)?;
+                }
+                (MAIN, tag @ (INPUT | OUTPUT | FEATURE)) => {
+                    if uses_report_ids && globals.report_id == 0 {
+                        return Err(ReportDescriptorError::InvalidReportId);
+                    }
+
+                    let index = match reports
+                        .iter()
+                        .position(|r| r.report_id == globals.report_id)
+                    {
+                        Some(index) => index,
+                        None => {
+                            reports
+                                .push(ReportBits {
+                                    report_id: globals.report_id,
+                                    ..Default::default()
+                                })
+                                .map_err(|_| ReportDescriptorError::TooManyReports)?;
+                            reports.len() - 1
+                        }
+                    };
+
+                    let report = &mut reports[index];
+                    let bits = globals.report_size.saturating_mul(globals.report_count);
+                    let total = match tag {
+                        INPUT => &mut report.input,
+                        OUTPUT => &mut report.output,
+                        _ => &mut report.feature,
+                    };
+                    *total = total.saturating_add(bits);
+                }
+                (GLOBAL, REPORT_SIZE) => globals.report_size = value,
+                (GLOBAL, REPORT_COUNT) => globals.report_count = value,
+                (GLOBAL, REPORT_ID) => {
+                    // Reports without an ID must not be mixed with reports with IDs.
+                    if value == 0 || value > 0xff || reports.iter().any(|r| r.report_id == 0) {
+                        return Err(ReportDescriptorError::InvalidReportId);
+                    }
+
+                    globals.report_id = value as u8;
+                    uses_report_ids = true;
+                }
+                (GLOBAL, PUSH) => stack
+                    .push(globals)
+                    .map_err(|_| ReportDescriptorError::UnbalancedPush)?,
+                (GLOBAL, POP) => {
+                    globals = stack.pop().ok_or(ReportDescriptorError::UnbalancedPush)?;
+                }
+                _ => {}
+            }
+        }
+
+        if depth != 0 {
+            return Err(ReportDescriptorError::UnbalancedCollection);
+        }
+
+        if !stack.is_empty() {
+            return Err(ReportDescriptorError::UnbalancedPush);
+        }
+
+        let bytes = |bits: u32| bits.div_ceil(8) as usize;
+
+        Ok(ReportSizes {
+            reports: reports
+                .iter()
+                .map(|r| ReportSize {
+                    report_id: r.report_id,
+                    input: bytes(r.input),
+                    output: bytes(r.output),
+                    feature: bytes(r.feature),
+                })
+                .collect(),
+        })
+    }
+
+    /// Gets whether the descriptor uses report IDs.
+    pub fn uses_report_ids(&self) -> bool {
+        self.reports.iter().any(|r| r.report_id != 0)
+    }
+
+    /// Gets the sizes of the reports with a report ID, or with 0 if the descriptor doesn't use
+    /// report IDs.
+    pub fn get(&self, report_id: u8) -> Option<&ReportSize> {
+        self.reports.iter().find(|r| r.report_id == report_id)
+    }
+
+    /// Iterates over the sizes of all reports.
+    pub fn iter(&self) -> impl Iterator<Item = &ReportSize> {
+        self.reports.iter()
+    }
+
+    /// Gets the size of the largest input report, including the report ID prefix if report IDs
+    /// are used.
+    pub fn max_input_size(&self) -> usize {
+        self.max_size(|r| r.input)
+    }
+
+    /// Gets the size of the largest output report, including the report ID prefix if report IDs
+    /// are used.
+    pub fn max_output_size(&self) -> usize {
+        self.max_size(|r| r.output)
+    }
+
+    /// Gets the size of the largest feature report, including the report ID prefix if report IDs
+    /// are used.
+    pub fn max_feature_size(&self) -> usize {
+        self.max_size(|r| r.feature)
+    }
+
+    fn max_size(&self, size: impl Fn(&ReportSize) -> usize) -> usize {
+        let prefix = usize::from(self.uses_report_ids());
+
+        self.reports
+            .iter()
+            .map(&size)
+            .filter(|&size| size > 0)
+            .map(|size| size + prefix)
+            .max()
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,540 @@
+use crate::descriptor::descriptor_type;
+use crate::endpoint::EndpointAddress;
+
+/// Error returned when a descriptor cannot be parsed.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ParseError {
+    /// The descriptor at `offset` extends beyond the end of the buffer.
+    Truncated {
+        /// Offset of the descriptor in the buffer.
+        offset: usize,
+    },
+
+    /// The `bLength` of the descriptor at `offset` is too small for its type.
+    InvalidLength {
+        /// Offset of the descriptor in the buffer.
+        offset: usize,
+    },
+}
+
+/// A device descriptor.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeviceDescriptor {
+    /// `bcdUSB`
+    pub usb_version: u16,
+    /// `bDeviceClass`
+    pub device_class: u8,
+    /// `bDeviceSubClass`
+    pub device_sub_class: u8,
+    /// `bDeviceProtocol`
+    pub device_protocol: u8,
+    /// `bMaxPacketSize0`
+    pub max_packet_size_0: u8,
+    /// `idVendor`
+    pub vendor_id: u16,
+    /// `idProduct`
+    pub product_id: u16,
+    /// `bcdDevice`
+    pub device_release: u16,
+    /// `iManufacturer`
+    pub manufacturer_string: u8,
+    /// `iProduct`
+    pub product_string: u8,
+    /// `iSerialNumber`
+    pub serial_number_string: u8,
+    /// `bNumConfigurations`
+    pub num_configurations: u8,
+}
+
+/// A configuration descriptor. The same format is used for other speed configuration descriptors.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ConfigurationDescriptor {
+    /// `wTotalLength`
+    pub total_length: u16,
+    /// `bNumInterfaces`
+    pub num_interfaces: u8,
+    /// `bConfigurationValue`
+    pub configuration_value: u8,
+    /// `iConfiguration`
+    pub configuration_string: u8,
+    /// `bmAttributes`
+    pub attributes: u8,
+    /// `bMaxPower`, in units of 2 mA.
+    pub max_power: u8,
+}
+
+/// An interface association descriptor.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InterfaceAssociationDescriptor {
+    /// `bFirstInterface`
+    pub first_interface: u8,
+    /// `bInterfaceCount`
+    pub interface_count: u8,
+    /// `bFunctionClass`
+    pub function_class: u8,
+    /// `bFunctionSubClass`
+    pub function_sub_class: u8,
+    /// `bFunctionProtocol`
+    pub function_protocol: u8,
+    /// `iFunction`
+    pub function_string: u8,
+}
+
+impl InterfaceAssociationDescriptor {
+    /// Gets the number of the last interface in the association.
+    pub fn last_interface(&self) -> u8 {
+        self.first_interface
+            .saturating_add(self.interface_count.saturating_sub(1))
+    }
+}
+
+/// An interface descriptor.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InterfaceDescriptor {
+    /// `bInterfaceNumber`
+    pub interface_number: u8,
+    /// `bAlternateSetting`
+    pub alternate_setting: u8,
+    /// `bNumEndpoints`
+    pub num_endpoints: u8,
+    /// `bInterfaceClass`
+    pub interface_class: u8,
+    /// `bInterfaceSubClass`
+    pub interface_sub_class: u8,
+    /// `bInterfaceProtocol`
+    pub interface_protocol: u8,
+    /// `iInterface`
+    pub interface_string: u8,
+}
+
+/// An endpoint descriptor.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EndpointDescriptor {
+    /// `bEndpointAddress`
+    pub address: EndpointAddress,
+    /// `bmAttributes`
+    pub attributes: u8,
+    /// `wMaxPacketSize`
+    pub max_packet_size: u16,
+    /// `bInterval`
+    pub interval: u8,
+}
+
+/// A descriptor parsed by [`parse`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Descriptor<'a> {
+    /// A device descriptor.
+    Device(DeviceDescriptor),
+    /// A configuration or other speed configuration descriptor.
+    Configuration(ConfigurationDescriptor),
+    /// An interface association descriptor.
+    InterfaceAssociation(InterfaceAssociationDescriptor),
+    /// An interface descriptor.
+    Interface(InterfaceDescriptor),
+    /// An endpoint descriptor.
+    Endpoint(EndpointDescriptor),
+    /// Any other descriptor, such as a class-specific one.
+    Other {
+        /// `bDescriptorType`
+        descriptor_type: u8,
+        /// The contents of the descriptor after the `bDescriptorType` field.
+        data: &'a [u8],
+    },
+}
+
+/// Iterator over the descriptors in a buffer, returned by [`parse`].
+pub struct Descriptors<'a> {
+    buf: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Descriptors<'a> {
+    /// Gets the offset of the next descriptor in the buffer.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'a> Iterator for Descriptors<'a> {
+    type Item = Result<Descriptor<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.offset;
+        let rest = self.buf.get(offset..).filter(|rest| !rest.is_empty())?;
+
+        let len = rest[0] as usize;
+        if len < 2 {
+            self.offset = self.buf.len();
+            return Some(Err(ParseError::InvalidLength { offset }));
+        }
+
+        if len > rest.len() {
+            self.offset = self.buf.len();
+            return Some(Err(ParseError::Truncated { offset }));
+        }
+
+        self.offset += len;
+
+        let d = &rest[..len];
+        let u16_at = |i: usize| u16::from_le_bytes([d[i], d[i + 1]]);
+
+        let min_len = match d[1] {
+            descriptor_type::DEVICE => 18,
+            descriptor_type::CONFIGURATION | descriptor_type::OTHER_SPEED_CONFIGURATION => 9,
+            descriptor_type::IAD => 8,
+            descriptor_type::INTERFACE => 9,
+            descriptor_type::ENDPOINT => 7,
+            _ => 2,
+        };
+
+        if len < min_len {
+            self.offset = self.buf.len();
+            return Some(Err(ParseError::InvalidLength { offset }));
+        }
+
+        Some(Ok(match d[1] {
+            descriptor_type::DEVICE => Descriptor::Device(DeviceDescriptor {
+                usb_version: u16_at(2),
+                device_class: d[4],
+                device_sub_class: d[5],
+                device_protocol: d[6],
+                max_packet_size_0: d[7],
+                vendor_id: u16_at(8),
+                product_id: u16_at(10),
+                device_release: u16_at(12),
+                manufacturer_string: d[14],
+                product_string: d[15],
+                serial_number_string: d[16],
+                num_configurations: d[17],
+            }),
+            descriptor_type::CONFIGURATION | descriptor_type::OTHER_SPEED_CONFIGURATION => {
+                Descriptor::Configuration(ConfigurationDescriptor {
+                    total_length: u16_at(2),
+                    num_interfaces: d[4],
+                    configuration_value: d[5],
+                    configuration_string: d[6],
+                    attributes: d[7],
+                    max_power: d[8],
+                })
+            }
+            descriptor_type::IAD => {
+                Descriptor::InterfaceAssociation(InterfaceAssociationDescriptor {
+                    first_interface: d[2],
+                    interface_count: d[3],
+                    function_class: d[4],
+                    function_sub_class: d[5],
+                    function_protocol: d[6],
+                    function_string: d[7],
+                })
+            }
+            descriptor_type::INTERFACE => Descriptor::Interface(InterfaceDescriptor {
+                interface_number: d[2],
+                alternate_setting: d[3],
+                num_endpoints: d[4],
+                interface_class: d[5],
+                interface_sub_class: d[6],
+                interface_protocol: d[7],
+                interface_string: d[8],
+            }),
+            descriptor_type::ENDPOINT => Descriptor::Endpoint(EndpointDescriptor {
+                address: EndpointAddress::from(d[2]),
+                attributes: d[3],
+                max_packet_size: u16_at(4),
+                interval: d[6],
+            }),
+            descriptor_type => Descriptor::Other {
+                descriptor_type,
+                data: &d[2..],
+            },
+        }))
+    }
+}
+
+/// Parses the descriptors in `buf`, such as a complete configuration descriptor with all of its
+/// interface, endpoint and class-specific descriptors. Parsing stops after the first error.
+pub fn parse(buf: &[u8]) -> Descriptors<'_> {
+    Descriptors { buf, offset: 0 }
+}
+
+/// A problem found by [`validate_configuration`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ValidationError {
+    /// The descriptors could not be parsed.
+    Parse(ParseError),
+
+    /// The buffer does not start with a configuration descriptor.
+    NotConfiguration,
+
+    /// `wTotalLength` does not match the length of the buffer.
+    TotalLength {
+        /// The `wTotalLength` of the configuration descriptor.
+        total_length: u16,
+        /// The length of the buffer.
+        actual: usize,
+    },
+
+    /// `bNumInterfaces` does not match the number of interfaces described.
+    NumInterfaces {
+        /// The `bNumInterfaces` of the configuration descriptor.
+        num_interfaces: u8,
+        /// The number of distinct interface numbers described.
+        actual: u16,
+    },
+
+    /// An interface number is not smaller than `bNumInterfaces`, so the interfaces are not
+    /// numbered consecutively from zero.
+    InterfaceNumber {
+        /// The interface number.
+        interface: u8,
+    },
+
+    /// `bNumEndpoints` of an interface does not match the number of endpoint descriptors that
+    /// follow it.
+    NumEndpoints {
+        /// The interface number.
+        interface: u8,
+        /// The alternate setting.
+        alternate_setting: u8,
+        /// The `bNumEndpoints` of the interface descriptor.
+        num_endpoints: u8,
+        /// The number of endpoint descriptors that follow the interface descriptor.
+        actual: u8,
+    },
+
+    /// An endpoint descriptor appears before the first interface descriptor.
+    EndpointOutsideInterface {
+        /// The endpoint address.
+        address: EndpointAddress,
+    },
+
+    /// An endpoint address is used for endpoint 0, more than once in the same alternate setting,
+    /// or by more than one interface.
+    EndpointAddress {
+        /// The interface number.
+        interface: u8,
+        /// The endpoint address.
+        address: EndpointAddress,
+    },
+
+    /// An interface association descriptor refers to interfaces that are not described, overlaps
+    /// another association or is not followed by the first interface of its range.
+    InterfaceAssociation {
+        /// The `bFirstInterface` of the interface association descriptor.
+        first_interface: u8,
+        /// The `bInterfaceCount` of the interface association descriptor.
+        interface_count: u8,
+    },
+
+    /// A string index refers to a string descriptor that does not exist.
+    StringIndex(u8),
+}
+
+impl From<ParseError> for ValidationError {
+    fn from(err: ParseError) -> Self {
+        ValidationError::Parse(err)
+    }
+}
+
+// A set of 256 flags indexed by a u8.
+#[derive(Default)]
+struct ByteSet([u32; 8]);
+
+impl ByteSet {
+    fn contains(&self, value: u8) -> bool {
+        (self.0[value as usize / 32] & (1 << (value % 32))) != 0
+    }
+
+    // Inserts a value and returns true if it was not already present.
+    fn insert(&mut self, value: u8) -> bool {
+        let present = self.contains(value);
+        self.0[value as usize / 32] |= 1 << (value % 32);
+        !present
+    }
+
+    // There are at most 256 values, which don't fit in a u8.
+    fn len(&self) -> u16 {
+        self.0.iter().map(|word| word.count_ones() as u16).sum()
+    }
+}
+
+/// Validates a complete configuration descriptor as returned by GET_DESCRIPTOR(CONFIGURATION),
+/// and returns the first problem found.
+///
+/// Besides the structure of the descriptors, this checks `wTotalLength`, `bNumInterfaces`,
+/// `bNumEndpoints`, that endpoint addresses are unique, that interface association descriptors
+/// refer to valid interface ranges, and that every non-zero string index is accepted by
+/// `string_exists`.
+pub fn validate_configuration(
+    buf: &[u8],
+    mut string_exists: impl FnMut(u8) -> bool,
+) -> Result<(), ValidationError> {
+    let mut check_string = |index: u8| {
+        if index != 0 && !string_exists(index) {
+            return Err(ValidationError::StringIndex(index));
+        }
+
+        Ok(())
+    };
+
+    let mut descriptors = parse(buf);
+
+    let config = match descriptors.next() {
+        Some(Ok(Descriptor::Configuration(config))) => config,
+        Some(Err(err)) => return Err(err.into()),
+        _ => return Err(ValidationError::NotConfiguration),
+    };
+
+    if config.total_length as usize != buf.len() {
+        return Err(ValidationError::TotalLength {
+            total_length: config.total_length,
+            actual: buf.len(),
+        });
+    }
+
+    check_string(config.configuration_string)?;
+
+    let mut interfaces = ByteSet::default();
+    let mut associated = ByteSet::default();
+    // The interface that uses each endpoint address, indexed by direction and endpoint number.
+    let mut endpoint_owners: [Option<u8>; 32] = [None; 32];
+    // The endpoint addresses used by the current alternate setting.
+    let mut alt_endpoints: u32 = 0;
+    let mut current: Option<(InterfaceDescriptor, u8)> = None;
+    let mut pending_association: Option<InterfaceAssociationDescriptor> = None;
+    let mut associations: heapless::Vec<InterfaceAssociationDescriptor, 32> = heapless::Vec::new();
+
+    let check_num_endpoints = |current: Option<(InterfaceDescriptor, u8)>| match current {
+        Some((iface, count)) if iface.num_endpoints != count => {
+            Err(ValidationError::NumEndpoints {
+                interface: iface.interface_number,
+                alternate_setting: iface.alternate_setting,
+                num_endpoints: iface.num_endpoints,
+                actual: count,
+            })
+        }
+        _ => Ok(()),
+    };
+
+    for descriptor in descriptors {
+        match descriptor? {
+            Descriptor::InterfaceAssociation(iad) => {
+                let invalid = ValidationError::InterfaceAssociation {
+                    first_interface: iad.first_interface,
+                    interface_count: iad.interface_count,
+                };
+
+                if iad.interface_count == 0
+                    || iad.first_interface as usize + iad.interface_count as usize > 256
+                    || pending_association.is_some()
+                {
+                    return Err(invalid);
+                }
+
+                for interface in iad.first_interface..=iad.last_interface() {
+                    if !associated.insert(interface) {
+                        return Err(invalid);
+                    }
+                }
+
+                check_string(iad.function_string)?;
+
+                pending_association = Some(iad);
+                associations.push(iad).map_err(|_| invalid)?;
+            }
+
+            Descriptor::Interface(iface) => {
+                check_num_endpoints(current)?;
+
+                if let Some(iad) = pending_association.take() {
+                    if iface.interface_number != iad.first_interface {
+                        return Err(ValidationError::InterfaceAssociation {
+                            first_interface: iad.first_interface,
+                            interface_count: iad.interface_count,
+                        });
+                    }
+                }
+
+                if iface.interface_number >= config.num_interfaces {
+                    return Err(ValidationError::InterfaceNumber {
+                        interface: iface.interface_number,
+                    });
+                }
+
+                check_string(iface.interface_string)?;
+
+                interfaces.insert(iface.interface_number);
+                alt_endpoints = 0;
+                current = Some((iface, 0));
+            }
+
+            Descriptor::Endpoint(ep) => {
+                let Some((iface, count)) = current.as_mut() else {
+                    return Err(ValidationError::EndpointOutsideInterface {
+                        address: ep.address,
+                    });
+                };
+
+                let invalid = ValidationError::EndpointAddress {
+                    interface: iface.interface_number,
+                    address: ep.address,
+                };
+
+                let raw = u8::from(ep.address);
+                if raw & 0x70 != 0 || ep.address.index() == 0 {
+                    return Err(invalid);
+                }
+
+                let slot = ep.address.index() + if ep.address.is_in() { 16 } else { 0 };
+
+                if alt_endpoints & (1 << slot) != 0 {
+                    return Err(invalid);
+                }
+                alt_endpoints |= 1 << slot;
+
+                match endpoint_owners[slot] {
+                    Some(owner) if owner != iface.interface_number => return Err(invalid),
+                    _ => endpoint_owners[slot] = Some(iface.interface_number),
+                }
+
+                *count = count.saturating_add(1);
+            }
+
+            _ => {}
+        }
+    }
+
+    check_num_endpoints(current)?;
+
+    if let Some(iad) = pending_association {
+        return Err(ValidationError::InterfaceAssociation {
+            first_interface: iad.first_interface,
+            interface_count: iad.interface_count,
+        });
+    }
+
+    for iad in &associations {
+        if (iad.first_interface..=iad.last_interface()).any(|i| !interfaces.contains(i)) {
+            return Err(ValidationError::InterfaceAssociation {
+                first_interface: iad.first_interface,
+                interface_count: iad.interface_count,
+            });
+        }
+    }
+
+    if interfaces.len() != u16::from(config.num_interfaces) {
+        return Err(ValidationError::NumInterfaces {
+            num_interfaces: config.num_interfaces,
+            actual: interfaces.len(),
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,315 @@
+use crate::descriptor::descriptor_type;
+use crate::endpoint::EndpointType;
+
+// Offsets of fields in the configuration descriptor header.
+const TOTAL_LENGTH: usize = 2;
+const NUM_INTERFACES: usize = 4;
+const CONFIGURATION_STRING: usize = 6;
+const ATTRIBUTES: usize = 7;
+const MAX_POWER: usize = 8;
+
+// Offset of bNumEndpoints in an interface descriptor.
+const NUM_ENDPOINTS: usize = 4;
+
+/// A complete configuration descriptor, including its interface, endpoint and class-specific
+/// descriptors, built by const functions so that it can be evaluated at compile time and stored
+/// in flash.
+///
+/// `N` is the capacity of the buffer. `wTotalLength`, `bNumInterfaces` and `bNumEndpoints` are
+/// filled in automatically. Mistakes such as exceeding the capacity, numbering interfaces
+/// non-consecutively or describing an endpoint outside of an interface panic, which turns them
+/// into compile errors when the descriptor is built in a `const` or `static` item.
+///
+/// The descriptor is served by passing it to
+/// [`UsbDeviceBuilder::static_configuration`](crate::device::UsbDeviceBuilder::static_configuration).
+/// The classes of the device must allocate exactly the interfaces and endpoints described in it.
+///
+/// ```
+/// use usb_device::class_prelude::*;
+/// use usb_device::descriptor::static_config::StaticConfiguration;
+/// use usb_device::prelude::*;
+///
+/// const CONFIGURATION: StaticConfiguration<32> = StaticConfiguration::new(1)
+///     .max_power(100)
+///     .interface(0, 0, 0xff, 0x00, 0x00, 0)
+///     .endpoint(0x81, EndpointType::Bulk, 64, 0)
+///     .endpoint(0x01, EndpointType::Bulk, 64, 0);
+///
+/// // Exactly the size of the descriptor
+/// static CONFIGURATION_DESCRIPTOR: [u8; CONFIGURATION.total_length()] = CONFIGURATION.to_array();
+///
+/// fn build<B: UsbBus>(alloc: &UsbBusAllocator<B>, control_buffer: &mut [u8]) {
+///     let _interface = alloc.interface();
+///     let _ep_in: EndpointIn<B> = alloc.bulk(64);
+///     let _ep_out: EndpointOut<B> = alloc.bulk(64);
+///
+///     let _device = UsbDeviceBuilder::new(alloc, UsbVidPid(0x1209, 0x0001), control_buffer)
+///         .static_configuration(&CONFIGURATION_DESCRIPTOR)
+///         .build()
+///         .unwrap();
+/// }
+/// # assert_eq!(CONFIGURATION_DESCRIPTOR.len(), 32);
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct StaticConfiguration<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    // Offset of the last interface descriptor, if any.
+    interface: Option<usize>,
+    // First interface of an interface association that has not been followed by an interface.
+    pending_association: Option<u8>,
+}
+
+impl<const N: usize> StaticConfiguration<N> {
+    /// Starts a configuration descriptor with the given `bConfigurationValue`. The configuration
+    /// is bus-powered, doesn't support remote wakeup and draws up to 100 mA unless specified
+    /// otherwise.
+    pub const fn new(configuration_value: u8) -> Self {
+        assert!(
+            configuration_value != 0,
+            "configuration value must not be zero"
+        );
+
+        let config = StaticConfiguration {
+            buf: [0; N],
+            len: 0,
+            interface: None,
+            pending_association: None,
+        };
+
+        config.write(
+            descriptor_type::CONFIGURATION,
+            &[
+                0,
+                0, // wTotalLength
+                0, // bNumInterfaces
+                configuration_value,
+                0,    // iConfiguration
+                0x80, // bmAttributes
+                50,   // bMaxPower
+            ],
+        )
+    }
+
+    /// Sets the `iConfiguration` string index. The class serving the descriptor has to provide the
+    /// string.
+    pub const fn string(mut self, index: u8) -> Self {
+        self.buf[CONFIGURATION_STRING] = index;
+        self
+    }
+
+    /// Sets whether the device may have an external power source in this configuration.
+    pub const fn self_powered(mut self, self_powered: bool) -> Self {
+        if self_powered {
+            self.buf[ATTRIBUTES] |= 0x40;
+        } else {
+            self.buf[ATTRIBUTES] &= !0x40;
+        }
+        self
+    }
+
+    /// Sets whether the device supports remotely waking up the host in this configuration.
+    pub const fn supports_remote_wakeup(mut self, supports_remote_wakeup: bool) -> Self {
+        if supports_remote_wakeup {
+            self.buf[ATTRIBUTES] |= 0x20;
+        } else {
+            self.buf[ATTRIBUTES] &= !0x20;
+        }
+        self
+    }
+
+    /// Sets the maximum current drawn from the USB bus in this configuration in milliamps, up to
+    /// 500 mA.
+    pub const fn max_power(mut self, max_power_ma: usize) -> Self {
+        assert!(max_power_ma <= 500, "power draw too high");
+
+        self.buf[MAX_POWER] = (max_power_ma / 2) as u8;
+        self
+    }
+
+    /// Writes an interface association descriptor. It must be followed by the interface
+    /// `first_interface`.
+    pub const fn iad(
+        self,
+        first_interface: u8,
+        interface_count: u8,
+        function_class: u8,
+        function_sub_class: u8,
+        function_protocol: u8,
+        function_string: u8,
+    ) -> Self {
+        assert!(
+            interface_count != 0,
+            "interface association without interfaces"
+        );
+        assert!(
+            first_interface == self.buf[NUM_INTERFACES],
+            "interface association does not start at the next interface"
+        );
+
+        let mut config = self.write(
+            descriptor_type::IAD,
+            &[
+                first_interface,
+                interface_count,
+                function_class,
+                function_sub_class,
+                function_protocol,
+                function_string,
+            ],
+        );
+
+        config.pending_association = Some(first_interface);
+        config
+    }
+
+    /// Writes an interface descriptor. Interfaces must be numbered consecutively from zero, and
+    /// alternate settings other than 0 must follow their interface. `bNumEndpoints` is counted
+    /// from the endpoints that follow.
+    pub const fn interface(
+        self,
+        number: u8,
+        alternate_setting: u8,
+        interface_class: u8,
+        interface_sub_class: u8,
+        interface_protocol: u8,
+        interface_string: u8,
+    ) -> Self {
+        let num_interfaces = self.buf[NUM_INTERFACES];
+
+        if alternate_setting == 0 {
+            assert!(
+                number == num_interfaces,
+                "interfaces are not numbered consecutively"
+            );
+        } else {
+            assert!(
+                number < num_interfaces,
+                "alternate setting of an undescribed interface"
+            );
+        }
+
+        if let Some(first_interface) = self.pending_association {
+            assert!(
+                number == first_interface,
+                "interface association is not followed by its first interface"
+            );
+        }
+
+        let position = self.len;
+        let mut config = self.write(
+            descriptor_type::INTERFACE,
+            &[
+                number,
+                alternate_setting,
+                0, // bNumEndpoints
+                interface_class,
+                interface_sub_class,
+                interface_protocol,
+                interface_string,
+            ],
+        );
+
+        if alternate_setting == 0 {
+            config.buf[NUM_INTERFACES] += 1;
+        }
+
+        config.interface = Some(position);
+        config.pending_association = None;
+        config
+    }
+
+    /// Writes an endpoint descriptor for the endpoint with the given `bEndpointAddress`, which
+    /// belongs to the last interface.
+    pub const fn endpoint(
+        self,
+        address: u8,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval: u8,
+    ) -> Self {
+        assert!(
+            address & 0x0f != 0 && address & 0x70 == 0,
+            "invalid endpoint address"
+        );
+
+        let interface = match self.interface {
+            Some(interface) => interface,
+            None => panic!("endpoint outside of an interface"),
+        };
+
+        let mps = max_packet_size.to_le_bytes();
+        let mut config = self.write(
+            descriptor_type::ENDPOINT,
+            &[
+                address,
+                ep_type.to_bm_attributes(),
+                mps[0],
+                mps[1],
+                interval,
+            ],
+        );
+
+        config.buf[interface + NUM_ENDPOINTS] += 1;
+        config
+    }
+
+    /// Writes an arbitrary descriptor, such as a class-specific one. `descriptor` is the contents
+    /// after the `bLength` and `bDescriptorType` fields.
+    pub const fn write(mut self, descriptor_type: u8, descriptor: &[u8]) -> Self {
+        let length = descriptor.len() + 2;
+
+        assert!(length <= 255, "descriptor too long");
+        assert!(self.len + length <= N, "descriptor buffer too small");
+        assert!(
+            self.len + length <= u16::MAX as usize,
+            "wTotalLength too large"
+        );
+
+        self.buf[self.len] = length as u8;
+        self.buf[self.len + 1] = descriptor_type;
+
+        let mut i = 0;
+        while i < descriptor.len() {
+            self.buf[self.len + 2 + i] = descriptor[i];
+            i += 1;
+        }
+
+        self.len += length;
+
+        let total_length = (self.len as u16).to_le_bytes();
+        self.buf[TOTAL_LENGTH] = total_length[0];
+        self.buf[TOTAL_LENGTH + 1] = total_length[1];
+
+        self
+    }
+
+    /// Gets the length of the descriptor, i.e. its `wTotalLength`.
+    pub const fn total_length(&self) -> usize {
+        self.len
+    }
+
+    /// Gets the descriptor.
+    pub const fn as_slice(&self) -> &[u8] {
+        self.buf.split_at(self.len).0
+    }
+
+    /// Copies the descriptor into an array of exactly its length, so that no space is wasted.
+    /// Use [`total_length`](Self::total_length) to size the array.
+    pub const fn to_array<const M: usize>(&self) -> [u8; M] {
+        assert!(
+            M == self.len,
+            "array length does not match descriptor length"
+        );
+
+        let mut array = [0; M];
+
+        let mut i = 0;
+        while i < M {
+            array[i] = self.buf[i];
+            i += 1;
+        }
+
+        array
+    }
+}
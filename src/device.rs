@@ -1,11 +1,20 @@
-use crate::bus::{InterfaceNumber, PollResult, StringIndex, UsbBus, UsbBusAllocator};
+use core::ops::Range;
+
+use crate::bus::{
+    endpoint_bit, InterfaceNumber, PollResult, StringIndex, TestMode, UsbBus, UsbBusAllocator,
+    UsbSpeed,
+};
 use crate::class::{ControlIn, ControlOut, UsbClass};
 use crate::control;
 use crate::control_pipe::ControlPipe;
 use crate::descriptor::{descriptor_type, lang_id::LangID, BosWriter, DescriptorWriter};
-pub use crate::device_builder::{StringDescriptors, UsbDeviceBuilder, UsbVidPid};
+pub use crate::device_builder::{
+    ContainerId, StringDescriptors, UsbConfiguration, UsbDeviceBuilder, UsbVidPid,
+};
 use crate::endpoint::{EndpointAddress, EndpointType};
-use crate::{Result, UsbDirection};
+use crate::ms_os_10::{self, MsOs10Descriptors};
+use crate::webusb::WebUsb;
+use crate::{Result, UsbDirection, UsbError};
 
 /// The global state of the USB device.
 ///
@@ -27,6 +36,100 @@ pub enum UsbDeviceState {
     Suspend,
 }
 
+/// A state change of the device, reported by [`UsbDevice::events`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum UsbDeviceEvent {
+    /// The host reset the device.
+    Reset,
+
+    /// The host assigned an address to the device.
+    Addressed(u8),
+
+    /// The host selected the configuration with the contained `bConfigurationValue`.
+    Configured(u8),
+
+    /// The host returned the device from the configured to the addressed state.
+    Deconfigured,
+
+    /// The bus was suspended.
+    Suspended,
+
+    /// The bus was resumed after being suspended.
+    Resumed,
+
+    /// The host selected a different alternate setting for an interface.
+    AltSettingChanged {
+        /// The interface whose alternate setting changed.
+        interface: InterfaceNumber,
+        /// The new alternate setting.
+        alt_setting: u8,
+    },
+
+    /// The host enabled remote wakeup.
+    RemoteWakeupEnabled,
+
+    /// The host disabled remote wakeup.
+    RemoteWakeupDisabled,
+
+    /// The host halted an endpoint with SET_FEATURE(ENDPOINT_HALT).
+    EndpointHalted(EndpointAddress),
+
+    /// The host cleared an endpoint halt with CLEAR_FEATURE(ENDPOINT_HALT).
+    EndpointCleared(EndpointAddress),
+
+    /// The host put the bus into the L1 sleep state with Link Power Management. The device stays
+    /// configured, and should avoid drawing more power than needed until the bus resumes.
+    SleepEntered {
+        /// The Best Effort Service Latency (BESL) requested by the host, from 0 to 15.
+        besl: u8,
+
+        /// Whether the device may wake the host with [`UsbDevice::remote_wakeup`].
+        remote_wakeup: bool,
+    },
+
+    /// The bus was resumed after being put into the L1 sleep state.
+    SleepExited,
+
+    /// The peripheral entered a test mode selected by the host with SET_FEATURE(TEST_MODE). The
+    /// device stops functioning normally until it is power cycled.
+    TestModeEntered(TestMode),
+}
+
+// Number of events kept until they are read with `UsbDevice::events`.
+const MAX_EVENTS: usize = 16;
+
+struct EventQueue(heapless::Deque<UsbDeviceEvent, MAX_EVENTS>);
+
+impl EventQueue {
+    fn push(&mut self, event: UsbDeviceEvent) {
+        // Drop the oldest event if nobody is reading them.
+        if self.0.is_full() {
+            self.0.pop_front();
+        }
+
+        let _ = self.0.push_back(event);
+    }
+
+    fn pop_front(&mut self) -> Option<UsbDeviceEvent> {
+        self.0.pop_front()
+    }
+}
+
+/// An error that occurred while handling a control transfer on endpoint 0, returned by
+/// [`UsbDevice::try_poll`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ControlError {
+    /// The error that occurred.
+    pub error: UsbError,
+
+    /// The request that was being handled, or `None` if the error occurred in the data or status
+    /// stage of a transfer.
+    pub request: Option<control::Request>,
+}
+
 // Maximum number of endpoints in one direction. Specified by the USB specification.
 const MAX_ENDPOINTS: usize = 16;
 
@@ -56,6 +159,16 @@ pub struct UsbDevice<'a, B: UsbBus> {
     self_powered: bool,
     suspended_device_state: Option<UsbDeviceState>,
     pending_address: u8,
+    pending_test_mode: Option<TestMode>,
+    num_interfaces: u8,
+    endpoints: u32,
+    isochronous_endpoints: u32,
+    configuration: u8,
+    lpm_accepted: bool,
+    // Whether remote wakeup is allowed while in the L1 sleep state.
+    sleep_remote_wakeup: Option<bool>,
+    speed: UsbSpeed,
+    events: EventQueue,
 }
 
 pub(crate) struct Config<'a> {
@@ -68,16 +181,25 @@ pub(crate) struct Config<'a> {
     pub usb_rev: UsbRev,
     pub device_release: u16,
     pub string_descriptors: heapless::Vec<StringDescriptors<'a>, 16>,
+    pub configurations: heapless::Vec<UsbConfiguration<'a>, 8>,
+    pub ms_os_10: Option<MsOs10Descriptors<'a>>,
+    pub webusb: Option<WebUsb<'a>>,
+    pub lpm: bool,
+    pub baseline_besl: Option<u8>,
+    pub deep_besl: Option<u8>,
+    pub container_id: Option<ContainerId>,
     pub self_powered: bool,
     pub supports_remote_wakeup: bool,
     pub composite_with_iads: bool,
     pub max_power: u8,
+    pub static_configuration: Option<&'static [u8]>,
 }
 
 /// The bConfiguration value for the not configured state.
 pub const CONFIGURATION_NONE: u8 = 0;
 
-/// The bConfiguration value for the single configuration supported by this device.
+/// The bConfiguration value for the single configuration of devices that don't specify their
+/// [configurations](UsbDeviceBuilder::configurations).
 pub const CONFIGURATION_VALUE: u8 = 1;
 
 /// The default value for bAlternateSetting for all interfaces.
@@ -85,6 +207,16 @@ pub const DEFAULT_ALTERNATE_SETTING: u8 = 0;
 
 type ClassList<'a, B> = [&'a mut dyn UsbClass<B>];
 
+// Gets the classes in `range`, as selected by `UsbConfiguration::classes`.
+fn configuration_classes<'c, 'a, B: UsbBus>(
+    classes: &'c mut ClassList<'a, B>,
+    range: Range<usize>,
+) -> &'c mut ClassList<'a, B> {
+    let end = range.end.min(classes.len());
+    let start = range.start.min(end);
+    &mut classes[start..end]
+}
+
 impl<B: UsbBus> UsbDevice<'_, B> {
     pub(crate) fn build<'a>(
         alloc: &'a UsbBusAllocator<B>,
@@ -109,6 +241,9 @@ impl<B: UsbBus> UsbDevice<'_, B> {
             )
             .expect("failed to alloc control endpoint");
 
+        let num_interfaces = alloc.num_interfaces();
+        let endpoints = alloc.endpoints();
+        let isochronous_endpoints = alloc.isochronous_endpoints();
         let bus = alloc.freeze();
 
         UsbDevice {
@@ -120,6 +255,15 @@ impl<B: UsbBus> UsbDevice<'_, B> {
             self_powered: false,
             suspended_device_state: None,
             pending_address: 0,
+            pending_test_mode: None,
+            num_interfaces,
+            endpoints,
+            isochronous_endpoints,
+            configuration: CONFIGURATION_NONE,
+            lpm_accepted: true,
+            sleep_remote_wakeup: None,
+            speed: UsbSpeed::Full,
+            events: EventQueue(heapless::Deque::new()),
         }
     }
 
@@ -139,6 +283,65 @@ impl<B: UsbBus> UsbDevice<'_, B> {
         self.device_state
     }
 
+    /// Gets the `bConfigurationValue` of the active configuration, or [`CONFIGURATION_NONE`] if the
+    /// device is not configured.
+    pub fn configuration(&self) -> u8 {
+        match self.device_state {
+            UsbDeviceState::Configured => self.configuration,
+            UsbDeviceState::Suspend
+                if self.suspended_device_state == Some(UsbDeviceState::Configured) =>
+            {
+                self.configuration
+            }
+            _ => CONFIGURATION_NONE,
+        }
+    }
+
+    // Gets the range of classes of the active configuration, or of all classes while the device is
+    // not configured.
+    fn active_classes(&self) -> Range<usize> {
+        let value = self.configuration();
+        self.config
+            .configurations
+            .iter()
+            .find(|configuration| configuration.value == value)
+            .map_or(0..usize::MAX, UsbConfiguration::class_range)
+    }
+
+    // Gets whether the active configuration supports remote wakeup. While the device is not
+    // configured, any configuration that supports it is enough.
+    fn supports_remote_wakeup(&self) -> bool {
+        let value = self.configuration();
+        self.config
+            .configurations
+            .iter()
+            .filter(|configuration| value == CONFIGURATION_NONE || configuration.value == value)
+            .any(|configuration| configuration.supports_remote_wakeup)
+    }
+
+    // Gets the endpoint addressed by the wIndex of an endpoint request, if it has been allocated.
+    // Only endpoint 0 can be addressed until the device is configured.
+    fn allocated_endpoint(&self, index: u16) -> Option<EndpointAddress> {
+        if index & !0x8f != 0 {
+            return None;
+        }
+
+        let ep_addr = EndpointAddress::from(index as u8);
+        let usable = ep_addr.index() == 0 || self.device_state == UsbDeviceState::Configured;
+        (usable && self.endpoints & endpoint_bit(ep_addr) != 0).then_some(ep_addr)
+    }
+
+    // Gets whether the wIndex of an interface request refers to an allocated interface. Interfaces
+    // can only be addressed once the device is configured.
+    fn interface_exists(&self, index: u16) -> bool {
+        self.device_state == UsbDeviceState::Configured && index < u16::from(self.num_interfaces)
+    }
+
+    /// Gets the bus speed negotiated with the host during the last USB reset.
+    pub fn speed(&self) -> UsbSpeed {
+        self.speed
+    }
+
     /// Gets whether host remote wakeup has been enabled by the host.
     pub fn remote_wakeup_enabled(&self) -> bool {
         self.remote_wakeup_enabled
@@ -154,6 +357,105 @@ impl<B: UsbBus> UsbDevice<'_, B> {
         self.self_powered = is_self_powered;
     }
 
+    /// Returns the events that occurred since the last call, oldest first. Events are recorded by
+    /// [`poll`](UsbDevice::poll) and allow reacting to state changes without comparing
+    /// [`state`](UsbDevice::state) snapshots.
+    ///
+    /// Only the 16 most recent events are kept, so this should be called after every poll.
+    ///
+    /// ``` ignore
+    /// usb_dev.poll(&mut [&mut class]);
+    ///
+    /// for event in usb_dev.events() {
+    ///     match event {
+    ///         UsbDeviceEvent::Configured(_) => led.on(),
+    ///         UsbDeviceEvent::Reset | UsbDeviceEvent::Deconfigured => led.off(),
+    ///         _ => {}
+    ///     }
+    /// }
+    /// ```
+    pub fn events(&mut self) -> impl Iterator<Item = UsbDeviceEvent> + '_ {
+        let events = &mut self.events;
+        core::iter::from_fn(move || events.pop_front())
+    }
+
+    /// Wakes up the host from suspend with remote wakeup signalling, for example because a key was
+    /// pressed. On success the device leaves the [`UsbDeviceState::Suspend`] state and a
+    /// [`UsbDeviceEvent::Resumed`] event is recorded.
+    ///
+    /// The USB specification requires the bus to have been suspended for at least 5 milliseconds
+    /// before a remote wakeup is signalled.
+    ///
+    /// This also wakes up the host from the L1 sleep state if it allowed remote wakeup when it put
+    /// the bus to sleep, in which case a [`UsbDeviceEvent::SleepExited`] event is recorded instead.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - The device is not suspended, the host
+    ///   has not enabled remote wakeup, or the active configuration doesn't support remote wakeup.
+    /// * [`Unsupported`](crate::UsbError::Unsupported) - The [`UsbBus`] implementation doesn't
+    ///   support remote wakeup.
+    pub fn remote_wakeup(&mut self) -> Result<()> {
+        if !self.supports_remote_wakeup() {
+            return Err(UsbError::InvalidState);
+        }
+
+        if let Some(remote_wakeup) = self.sleep_remote_wakeup {
+            if !remote_wakeup {
+                return Err(UsbError::InvalidState);
+            }
+
+            usb_debug!("Signalling remote wakeup from L1 sleep");
+            self.bus.remote_wakeup()?;
+
+            self.sleep_remote_wakeup = None;
+            self.events.push(UsbDeviceEvent::SleepExited);
+
+            return Ok(());
+        }
+
+        if self.device_state != UsbDeviceState::Suspend || !self.remote_wakeup_enabled {
+            return Err(UsbError::InvalidState);
+        }
+
+        usb_debug!("Signalling remote wakeup");
+        self.bus.resume();
+
+        if let Err(err) = self.bus.remote_wakeup() {
+            self.bus.suspend();
+            return Err(err);
+        }
+
+        self.device_state = self
+            .suspended_device_state
+            .expect("Unknown state before suspend");
+        self.suspended_device_state = None;
+        self.events.push(UsbDeviceEvent::Resumed);
+
+        Ok(())
+    }
+
+    /// Sets whether the device currently accepts being put into the L1 sleep state by the host,
+    /// for example to refuse it while a transfer with tight latency requirements is in progress.
+    /// LPM is accepted by default.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - LPM was not enabled with
+    ///   [`UsbDeviceBuilder::lpm`].
+    /// * [`Unsupported`](crate::UsbError::Unsupported) - The [`UsbBus`] implementation doesn't
+    ///   support LPM.
+    pub fn set_lpm_accepted(&mut self, accepted: bool) -> Result<()> {
+        if !self.config.lpm {
+            return Err(UsbError::InvalidState);
+        }
+
+        self.bus.set_lpm_accepted(accepted)?;
+        self.lpm_accepted = accepted;
+
+        Ok(())
+    }
+
     /// Simulates a disconnect from the USB bus, causing the host to reset and re-enumerate the
     /// device.
     ///
@@ -180,13 +482,36 @@ impl<B: UsbBus> UsbDevice<'_, B> {
     ///
     /// Strictly speaking the list of classes is allowed to change between polls if the device has
     /// been reset, which is indicated by `state` being equal to [`UsbDeviceState::Default`].
+    ///
+    /// Errors that occur while handling control transfers are discarded. Use
+    /// [`try_poll`](UsbDevice::try_poll) to receive them.
     pub fn poll(&mut self, classes: &mut ClassList<'_, B>) -> bool {
+        match self.try_poll(classes) {
+            Ok(result) => result,
+            Err(_err) => {
+                usb_debug!("Failed to handle control transfer: {:?}", _err);
+                true
+            }
+        }
+    }
+
+    /// Same as [`poll`](UsbDevice::poll), but returns errors that occur while handling control
+    /// transfers on endpoint 0 instead of discarding them.
+    ///
+    /// An error does not interrupt the processing of other events, so when `Err` is returned all
+    /// events have still been dispatched to the classes as if `Ok(true)` had been returned. If the
+    /// failed transfer was still waiting for a response, it has been rejected by stalling the
+    /// control pipe.
+    pub fn try_poll(
+        &mut self,
+        classes: &mut ClassList<'_, B>,
+    ) -> core::result::Result<bool, ControlError> {
         let pr = self.bus.poll();
 
         if self.device_state == UsbDeviceState::Suspend {
             match pr {
                 PollResult::Suspend | PollResult::None => {
-                    return false;
+                    return Ok(false);
                 }
                 _ => {
                     self.bus.resume();
@@ -195,6 +520,17 @@ impl<B: UsbBus> UsbDevice<'_, B> {
                         .suspended_device_state
                         .expect("Unknown state before suspend");
                     self.suspended_device_state = None;
+                    self.events.push(UsbDeviceEvent::Resumed);
+                }
+            }
+        }
+
+        if self.sleep_remote_wakeup.is_some() {
+            match pr {
+                PollResult::Sleep { .. } | PollResult::None => {}
+                _ => {
+                    self.sleep_remote_wakeup = None;
+                    self.events.push(UsbDeviceEvent::SleepExited);
                 }
             }
         }
@@ -206,10 +542,13 @@ impl<B: UsbBus> UsbDevice<'_, B> {
                 ep_out,
                 ep_in_complete,
                 ep_setup,
+                sof,
             } => {
                 // Combine bit fields for quick tests
                 let mut eps = ep_out | ep_in_complete | ep_setup;
 
+                let mut error = None;
+
                 // Pending events for endpoint 0?
                 if (eps & 1) != 0 {
                     usb_debug!(
@@ -224,9 +563,12 @@ impl<B: UsbBus> UsbDevice<'_, B> {
                     } else if (ep_out & 1) != 0 {
                         match self.control.handle_out() {
                             Ok(req) => req,
-                            Err(_err) => {
-                                // TODO: Propagate error out of `poll()`
-                                usb_debug!("Failed to handle EP0: {:?}", _err);
+                            Err(err) => {
+                                usb_debug!("Failed to handle EP0: {:?}", err);
+                                error = Some(ControlError {
+                                    error: err,
+                                    request: None,
+                                });
                                 None
                             }
                         }
@@ -236,15 +578,15 @@ impl<B: UsbBus> UsbDevice<'_, B> {
 
                     match req {
                         Some(req) if req.direction == UsbDirection::In => {
-                            if let Err(_err) = self.control_in(classes, req) {
-                                // TODO: Propagate error out of `poll()`
-                                usb_debug!("Failed to handle input control request: {:?}", _err);
+                            if let Err(err) = self.control_in(classes, req) {
+                                usb_debug!("Failed to handle input control request: {:?}", err);
+                                error = Some(self.control_error(err, req));
                             }
                         }
                         Some(req) if req.direction == UsbDirection::Out => {
-                            if let Err(_err) = self.control_out(classes, req) {
-                                // TODO: Propagate error out of `poll()`
-                                usb_debug!("Failed to handle output control request: {:?}", _err);
+                            if let Err(err) = self.control_out(classes, req) {
+                                usb_debug!("Failed to handle output control request: {:?}", err);
+                                error = Some(self.control_error(err, req));
                             }
                         }
 
@@ -256,25 +598,51 @@ impl<B: UsbBus> UsbDevice<'_, B> {
                             // continue with the next transfer.
                             let completed = match self.control.handle_in_complete() {
                                 Ok(completed) => completed,
-                                Err(_err) => {
-                                    // TODO: Propagate this out of `poll()`
+                                Err(err) => {
                                     usb_debug!(
                                         "Failed to process control-input complete: {:?}",
-                                        _err
+                                        err
                                     );
-                                    false
+                                    error = Some(ControlError {
+                                        error: err,
+                                        request: None,
+                                    });
+                                    None
                                 }
                             };
 
                             if !B::QUIRK_SET_ADDRESS_BEFORE_STATUS
-                                && completed
+                                && completed.is_some()
                                 && self.pending_address != 0
                             {
                                 self.bus.set_device_address(self.pending_address);
+                                self.events
+                                    .push(UsbDeviceEvent::Addressed(self.pending_address));
                                 self.pending_address = 0;
 
                                 self.device_state = UsbDeviceState::Addressed;
                             }
+
+                            if let Some(req) = completed {
+                                for cls in configuration_classes(classes, self.active_classes()) {
+                                    cls.control_out_complete(&req);
+                                }
+
+                                if let Some(mode) = self.pending_test_mode.take() {
+                                    usb_debug!("Entering test mode {:?}", mode);
+                                    match self.bus.set_test_mode(mode) {
+                                        Ok(()) => {
+                                            self.events.push(UsbDeviceEvent::TestModeEntered(mode))
+                                        }
+                                        Err(err) => {
+                                            error = Some(ControlError {
+                                                error: err,
+                                                request: None,
+                                            })
+                                        }
+                                    }
+                                }
+                            }
                         }
 
                         _ => (),
@@ -283,6 +651,16 @@ impl<B: UsbBus> UsbDevice<'_, B> {
                     eps &= !1;
                 }
 
+                // Classes of other configurations don't use their endpoints. The configuration may
+                // have been changed by a request above.
+                let classes = configuration_classes(classes, self.active_classes());
+
+                if let Some(frame_number) = sof {
+                    for cls in classes.iter_mut() {
+                        cls.sof(frame_number);
+                    }
+                }
+
                 // Pending events for other endpoints?
                 if eps != 0 {
                     let mut bit = 2u16;
@@ -328,24 +706,71 @@ impl<B: UsbBus> UsbDevice<'_, B> {
                     cls.poll();
                 }
 
-                return true;
+                return match error {
+                    Some(err) => Err(err),
+                    None => Ok(true),
+                };
             }
             PollResult::Resume => {}
+            PollResult::Sleep {
+                besl,
+                remote_wakeup,
+            } => {
+                usb_debug!("Entering L1 sleep, BESL {}", besl);
+                self.sleep_remote_wakeup = Some(remote_wakeup);
+                self.events.push(UsbDeviceEvent::SleepEntered {
+                    besl,
+                    remote_wakeup,
+                });
+            }
             PollResult::Suspend => {
                 usb_debug!("Suspending bus");
                 self.bus.suspend();
                 self.suspended_device_state = Some(self.device_state);
                 self.device_state = UsbDeviceState::Suspend;
+                self.events.push(UsbDeviceEvent::Suspended);
             }
         }
 
-        false
+        Ok(false)
+    }
+
+    fn control_error(&mut self, error: UsbError, request: control::Request) -> ControlError {
+        // Don't leave the host waiting for a response that will never come.
+        if self.control.waiting_for_response() {
+            let _ = self.control.reject();
+        }
+
+        ControlError {
+            error,
+            request: Some(request),
+        }
     }
 
     fn control_in(&mut self, classes: &mut ClassList<'_, B>, req: control::Request) -> Result<()> {
         use crate::control::{Recipient, Request};
 
-        for cls in classes.iter_mut() {
+        // The MS OS 1.0 and WebUSB vendor codes are reserved for the device, so classes that
+        // handle all vendor requests don't get to see them.
+        if let Some(ms_os_10) = &self.config.ms_os_10 {
+            ms_os_10.control_in(ControlIn::new(&mut self.control, &req))?;
+
+            if !self.control.waiting_for_response() {
+                return Ok(());
+            }
+        }
+
+        if let Some(webusb) = &self.config.webusb {
+            webusb.control_in(ControlIn::new(&mut self.control, &req))?;
+
+            if !self.control.waiting_for_response() {
+                return Ok(());
+            }
+        }
+
+        let active_classes = self.active_classes();
+
+        for cls in configuration_classes(classes, active_classes.clone()) {
             cls.control_in(ControlIn::new(&mut self.control, &req));
 
             if !self.control.waiting_for_response() {
@@ -354,6 +779,8 @@ impl<B: UsbBus> UsbDevice<'_, B> {
         }
 
         if req.request_type == control::RequestType::Standard {
+            let endpoint = self.allocated_endpoint(req.index);
+            let interface_exists = self.interface_exists(req.index);
             let xfer = ControlIn::new(&mut self.control, &req);
 
             match (req.recipient, req.request) {
@@ -371,6 +798,10 @@ impl<B: UsbBus> UsbDevice<'_, B> {
 
                 (Recipient::Interface, Request::GET_STATUS) => {
                     usb_trace!("Processing Interface::GetStatus");
+                    if !interface_exists {
+                        return xfer.reject();
+                    }
+
                     let status: u16 = 0x0000;
 
                     xfer.accept_with(&status.to_le_bytes())?;
@@ -378,7 +809,9 @@ impl<B: UsbBus> UsbDevice<'_, B> {
 
                 (Recipient::Endpoint, Request::GET_STATUS) => {
                     usb_trace!("Processing EP::GetStatus");
-                    let ep_addr = ((req.index as u8) & 0x8f).into();
+                    let Some(ep_addr) = endpoint else {
+                        return xfer.reject();
+                    };
 
                     let status: u16 = if self.bus.is_stalled(ep_addr) {
                         0x0001
@@ -389,15 +822,36 @@ impl<B: UsbBus> UsbDevice<'_, B> {
                     xfer.accept_with(&status.to_le_bytes())?;
                 }
 
+                (Recipient::Endpoint, Request::SYNCH_FRAME) => {
+                    usb_trace!("Processing EP::SynchFrame");
+                    // Classes with isochronous endpoints that use an explicit synchronization
+                    // pattern can answer this themselves.
+                    let ep_addr = ((req.index as u8) & 0x8f).into();
+                    let isochronous = self.isochronous_endpoints & endpoint_bit(ep_addr) != 0;
+
+                    match self.bus.frame_number() {
+                        Ok(frame_number) if isochronous && req.value == 0 && req.length == 2 => {
+                            xfer.accept_with(&frame_number.to_le_bytes())?
+                        }
+                        _ => xfer.reject()?,
+                    }
+                }
+
                 (Recipient::Device, Request::GET_DESCRIPTOR) => {
                     usb_trace!("Processing Device::GetDescriptor");
-                    UsbDevice::get_descriptor(&self.config, classes, xfer)?;
+                    UsbDevice::get_descriptor(
+                        &self.config,
+                        self.speed,
+                        self.bus.max_speed(),
+                        classes,
+                        xfer,
+                    )?;
                 }
 
                 (Recipient::Device, Request::GET_CONFIGURATION) => {
                     usb_trace!("Processing Device::GetConfiguration");
                     let config = match self.device_state {
-                        UsbDeviceState::Configured => CONFIGURATION_VALUE,
+                        UsbDeviceState::Configured => self.configuration,
                         _ => CONFIGURATION_NONE,
                     };
 
@@ -406,14 +860,13 @@ impl<B: UsbBus> UsbDevice<'_, B> {
 
                 (Recipient::Interface, Request::GET_INTERFACE) => {
                     usb_trace!("Processing Interface::GetInterface");
-                    // Reject interface numbers bigger than 255
-                    if req.index > core::u8::MAX.into() {
+                    if !interface_exists {
                         return xfer.reject();
                     }
 
                     // Ask class implementations, whether they know the alternate setting
                     // of the interface in question
-                    for cls in classes {
+                    for cls in configuration_classes(classes, active_classes) {
                         if let Some(setting) = cls.get_alt_setting(InterfaceNumber(req.index as u8))
                         {
                             return xfer.accept_with(&setting.to_le_bytes());
@@ -439,7 +892,9 @@ impl<B: UsbBus> UsbDevice<'_, B> {
     fn control_out(&mut self, classes: &mut ClassList<'_, B>, req: control::Request) -> Result<()> {
         use crate::control::{Recipient, Request};
 
-        for cls in classes.iter_mut() {
+        let active_classes = self.active_classes();
+
+        for cls in configuration_classes(classes, active_classes.clone()) {
             cls.control_out(ControlOut::new(&mut self.control, &req));
 
             if !self.control.waiting_for_response() {
@@ -448,10 +903,12 @@ impl<B: UsbBus> UsbDevice<'_, B> {
         }
 
         if req.request_type == control::RequestType::Standard {
+            let endpoint = self.allocated_endpoint(req.index);
+            let interface_exists = self.interface_exists(req.index);
+            let supports_remote_wakeup = self.supports_remote_wakeup();
             let xfer = ControlOut::new(&mut self.control, &req);
 
             const CONFIGURATION_NONE_U16: u16 = CONFIGURATION_NONE as u16;
-            const CONFIGURATION_VALUE_U16: u16 = CONFIGURATION_VALUE as u16;
             const DEFAULT_ALTERNATE_SETTING_U16: u16 = DEFAULT_ALTERNATE_SETTING as u16;
 
             match (req.recipient, req.request, req.value) {
@@ -462,13 +919,18 @@ impl<B: UsbBus> UsbDevice<'_, B> {
                 ) => {
                     usb_debug!("Remote wakeup disabled");
                     self.remote_wakeup_enabled = false;
+                    self.events.push(UsbDeviceEvent::RemoteWakeupDisabled);
                     xfer.accept()?;
                 }
 
                 (Recipient::Endpoint, Request::CLEAR_FEATURE, Request::FEATURE_ENDPOINT_HALT) => {
+                    let Some(ep_addr) = endpoint else {
+                        return xfer.reject();
+                    };
+
                     usb_debug!("EP{} halt removed", req.index & 0x8f);
-                    self.bus
-                        .set_stalled(((req.index as u8) & 0x8f).into(), false);
+                    self.bus.set_stalled(ep_addr, false);
+                    self.events.push(UsbDeviceEvent::EndpointCleared(ep_addr));
                     xfer.accept()?;
                 }
 
@@ -477,15 +939,48 @@ impl<B: UsbBus> UsbDevice<'_, B> {
                     Request::SET_FEATURE,
                     Request::FEATURE_DEVICE_REMOTE_WAKEUP,
                 ) => {
+                    // The bmAttributes of the configuration must allow remote wakeup.
+                    if !supports_remote_wakeup {
+                        return xfer.reject();
+                    }
+
                     usb_debug!("Remote wakeup enabled");
                     self.remote_wakeup_enabled = true;
+                    self.events.push(UsbDeviceEvent::RemoteWakeupEnabled);
                     xfer.accept()?;
                 }
 
+                (Recipient::Device, Request::SET_FEATURE, Request::FEATURE_TEST_MODE) => {
+                    // The low byte of wIndex must be zero for the device test modes.
+                    let mode = TestMode::from_selector((req.index >> 8) as u8)
+                        .filter(|_| req.index & 0xff == 0);
+
+                    match mode {
+                        Some(mode) if self.bus.max_speed() == UsbSpeed::High => {
+                            usb_debug!("Test mode {:?} requested", mode);
+                            // The test mode must only be entered after the status stage.
+                            self.pending_test_mode = Some(mode);
+                            xfer.accept()?;
+                        }
+                        _ => xfer.reject()?,
+                    }
+                }
+
                 (Recipient::Endpoint, Request::SET_FEATURE, Request::FEATURE_ENDPOINT_HALT) => {
+                    let Some(ep_addr) = endpoint else {
+                        return xfer.reject();
+                    };
+
                     usb_debug!("EP{} halted", req.index & 0x8f);
-                    self.bus
-                        .set_stalled(((req.index as u8) & 0x8f).into(), true);
+                    self.bus.set_stalled(ep_addr, true);
+                    self.events.push(UsbDeviceEvent::EndpointHalted(ep_addr));
+                    xfer.accept()?;
+                }
+
+                (Recipient::Device, Request::SET_ADDRESS, 0)
+                    if self.device_state == UsbDeviceState::Default =>
+                {
+                    // The device stays in the Default state.
                     xfer.accept()?;
                 }
 
@@ -494,24 +989,30 @@ impl<B: UsbBus> UsbDevice<'_, B> {
                     if B::QUIRK_SET_ADDRESS_BEFORE_STATUS {
                         self.bus.set_device_address(req.value as u8);
                         self.device_state = UsbDeviceState::Addressed;
+                        self.events.push(UsbDeviceEvent::Addressed(req.value as u8));
                     } else {
                         self.pending_address = req.value as u8;
                     }
                     xfer.accept()?;
                 }
 
-                (Recipient::Device, Request::SET_CONFIGURATION, CONFIGURATION_VALUE_U16) => {
-                    usb_debug!("Device configured");
-                    self.device_state = UsbDeviceState::Configured;
-                    xfer.accept()?;
-                }
-
                 (Recipient::Device, Request::SET_CONFIGURATION, CONFIGURATION_NONE_U16) => {
                     usb_debug!("Device deconfigured");
                     match self.device_state {
                         UsbDeviceState::Default => {
                             xfer.accept()?;
                         }
+                        UsbDeviceState::Configured => {
+                            self.device_state = UsbDeviceState::Addressed;
+                            self.configuration = CONFIGURATION_NONE;
+                            self.events.push(UsbDeviceEvent::Deconfigured);
+
+                            for cls in classes {
+                                cls.set_configuration(CONFIGURATION_NONE);
+                            }
+
+                            xfer.accept()?;
+                        }
                         _ => {
                             self.device_state = UsbDeviceState::Addressed;
                             xfer.accept()?;
@@ -519,23 +1020,60 @@ impl<B: UsbBus> UsbDevice<'_, B> {
                     }
                 }
 
+                (Recipient::Device, Request::SET_CONFIGURATION, value)
+                    if self
+                        .config
+                        .configurations
+                        .iter()
+                        .any(|c| u16::from(c.value) == value) =>
+                {
+                    usb_debug!("Device configured with configuration {}", value);
+                    self.device_state = UsbDeviceState::Configured;
+                    self.configuration = value as u8;
+                    self.events.push(UsbDeviceEvent::Configured(value as u8));
+
+                    for cls in classes {
+                        cls.set_configuration(value as u8);
+                    }
+
+                    xfer.accept()?;
+                }
+
                 (Recipient::Interface, Request::SET_INTERFACE, alt_setting) => {
-                    // Reject interface numbers and alt settings bigger than 255
-                    if req.index > core::u8::MAX.into() || alt_setting > core::u8::MAX.into() {
+                    // Reject unknown interfaces and alt settings bigger than 255
+                    if !interface_exists || alt_setting > u8::MAX.into() {
                         xfer.reject()?;
                         return Ok(());
                     }
 
+                    let interface = InterfaceNumber(req.index as u8);
+                    let classes = configuration_classes(classes, active_classes);
+
+                    // Only report the setting if it differs from the current one.
+                    let previous = classes
+                        .iter_mut()
+                        .find_map(|cls| cls.get_alt_setting(interface))
+                        .unwrap_or(DEFAULT_ALTERNATE_SETTING);
+                    let event = (previous != alt_setting as u8).then_some(
+                        UsbDeviceEvent::AltSettingChanged {
+                            interface,
+                            alt_setting: alt_setting as u8,
+                        },
+                    );
+
                     // Ask class implementations, whether they accept the alternate interface setting.
                     for cls in classes {
-                        if cls.set_alt_setting(InterfaceNumber(req.index as u8), alt_setting as u8)
-                        {
+                        if cls.set_alt_setting(interface, alt_setting as u8) {
+                            if let Some(event) = event {
+                                self.events.push(event);
+                            }
                             xfer.accept()?;
                             return Ok(());
                         }
                     }
 
                     // Default behaviour, if no class implementation accepted the alternate setting.
+                    // No class reports a setting for the interface, so it stays at the default.
                     if alt_setting == DEFAULT_ALTERNATE_SETTING_U16 {
                         usb_debug!("Accepting unused alternate settings");
                         xfer.accept()?;
@@ -562,6 +1100,8 @@ impl<B: UsbBus> UsbDevice<'_, B> {
 
     fn get_descriptor(
         config: &Config,
+        speed: UsbSpeed,
+        max_speed: UsbSpeed,
         classes: &mut ClassList<'_, B>,
         xfer: ControlIn<B>,
     ) -> Result<()> {
@@ -585,7 +1125,11 @@ impl<B: UsbBus> UsbDevice<'_, B> {
         match dtype {
             descriptor_type::BOS if config.usb_rev > UsbRev::Usb200 => accept_writer(xfer, |w| {
                 let mut bw = BosWriter::new(w);
-                bw.bos()?;
+                bw.bos(config)?;
+
+                if let Some(webusb) = &config.webusb {
+                    webusb.get_bos_descriptors(&mut bw)?;
+                }
 
                 for cls in classes {
                     cls.get_bos_descriptors(&mut bw)?;
@@ -598,22 +1142,63 @@ impl<B: UsbBus> UsbDevice<'_, B> {
 
             descriptor_type::DEVICE => accept_writer(xfer, |w| w.device(config))?,
 
-            descriptor_type::CONFIGURATION => accept_writer(xfer, |w| {
-                w.configuration(config)?;
+            descriptor_type::DEVICE_QUALIFIER if max_speed == UsbSpeed::High => {
+                accept_writer(xfer, |w| w.device_qualifier(config))?
+            }
 
-                for cls in classes {
-                    cls.get_configuration_descriptors(w)?;
-                    w.end_class();
+            descriptor_type::CONFIGURATION | descriptor_type::OTHER_SPEED_CONFIGURATION => {
+                let speed = match dtype {
+                    descriptor_type::CONFIGURATION => Some(speed),
+                    // Only devices that can operate at high speed have an other speed.
+                    _ if max_speed == UsbSpeed::High => Some(match speed {
+                        UsbSpeed::High => UsbSpeed::Full,
+                        _ => UsbSpeed::High,
+                    }),
+                    _ => None,
+                };
+
+                let (Some(speed), Some(configuration)) =
+                    (speed, config.configurations.get(index as usize))
+                else {
+                    xfer.reject()?;
+                    return Ok(());
+                };
+
+                if let Some(descriptor) = config.static_configuration {
+                    // The same descriptor describes the device at both speeds.
+                    if dtype == descriptor_type::CONFIGURATION {
+                        xfer.accept_with_static(descriptor)?;
+                    } else {
+                        xfer.accept(|buf| {
+                            let buf = buf
+                                .get_mut(..descriptor.len())
+                                .ok_or(UsbError::BufferOverflow)?;
+                            buf.copy_from_slice(descriptor);
+                            buf[1] = dtype;
+                            Ok(buf.len())
+                        })?;
+                    }
+
+                    return Ok(());
                 }
 
-                w.end_configuration();
+                accept_writer(xfer, |w| {
+                    w.configuration(config, configuration, dtype, speed)?;
 
-                Ok(())
-            })?,
+                    for cls in configuration_classes(classes, configuration.class_range()) {
+                        cls.get_configuration_descriptors(w)?;
+                        w.end_class();
+                    }
 
-            descriptor_type::STRING => match index {
+                    w.end_configuration();
+
+                    Ok(())
+                })?;
+            }
+
+            descriptor_type::STRING => match (index, &config.ms_os_10) {
                 // first STRING Request
-                0 => {
+                (0, _) => {
                     let mut lang_id_bytes = [0u8; 32];
                     for (lang, buf) in config
                         .string_descriptors
@@ -630,6 +1215,13 @@ impl<B: UsbBus> UsbDevice<'_, B> {
                     })?;
                 }
 
+                // The OS string descriptor is the same in every language.
+                (ms_os_10::STRING_INDEX, Some(ms_os_10)) => {
+                    accept_writer(xfer, |w| {
+                        w.write(descriptor_type::STRING, &ms_os_10.os_string())
+                    })?;
+                }
+
                 // rest STRING Requests
                 _ => {
                     let lang_id = LangID::from(req.index);
@@ -655,9 +1247,16 @@ impl<B: UsbBus> UsbDevice<'_, B> {
                         }
                         _ => {
                             let index = StringIndex::new(index);
-                            classes
+                            config
+                                .configurations
                                 .iter()
-                                .find_map(|cls| cls.get_string(index, lang_id))
+                                .find(|c| c.string == Some(index))
+                                .and_then(|c| c.name)
+                                .or_else(|| {
+                                    classes
+                                        .iter()
+                                        .find_map(|cls| cls.get_string(index, lang_id))
+                                })
                         }
                     };
 
@@ -679,15 +1278,27 @@ impl<B: UsbBus> UsbDevice<'_, B> {
 
     fn reset(&mut self, classes: &mut ClassList<'_, B>) {
         self.bus.reset();
+        self.speed = self.bus.speed();
 
         self.device_state = UsbDeviceState::Default;
         self.suspended_device_state = None; // We may reset during Suspend
         self.remote_wakeup_enabled = false;
         self.pending_address = 0;
+        self.pending_test_mode = None;
+        self.configuration = CONFIGURATION_NONE;
+        self.sleep_remote_wakeup = None;
+
+        if self.config.lpm {
+            if let Err(_err) = self.bus.set_lpm_accepted(self.lpm_accepted) {
+                usb_debug!("Failed to configure LPM: {:?}", _err);
+            }
+        }
 
         self.control.reset();
 
-        for cls in classes {
+        self.events.push(UsbDeviceEvent::Reset);
+
+        for cls in configuration_classes(classes, self.active_classes()) {
             cls.reset();
         }
     }
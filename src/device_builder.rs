@@ -1,6 +1,12 @@
-use crate::bus::{UsbBus, UsbBusAllocator};
+use core::ops::Range;
+
+use crate::bus::{endpoint_bit, StringIndex, UsbBus, UsbBusAllocator};
 use crate::descriptor::lang_id::LangID;
-use crate::device::{Config, UsbDevice, UsbRev};
+use crate::descriptor::parser::{self, Descriptor};
+use crate::device::{Config, UsbDevice, UsbRev, CONFIGURATION_NONE, CONFIGURATION_VALUE};
+use crate::ms_os_10::MsOs10Descriptors;
+use crate::uuid;
+use crate::webusb::WebUsb;
 
 /// A USB vendor ID and product ID pair.
 pub struct UsbVidPid(pub u16, pub u16);
@@ -25,6 +31,7 @@ macro_rules! builder_fields {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
+#[non_exhaustive]
 /// Error type for the USB device builder
 pub enum BuilderError {
     /// String descriptors were provided in more languages than are supported
@@ -35,6 +42,25 @@ pub enum BuilderError {
     PowerTooHigh,
     /// The provided control buffer is too small for the provided maximum packet size.
     ControlBufferTooSmall,
+    /// More configurations were provided than are supported
+    TooManyConfigurations,
+    /// A configuration value is zero or used by more than one configuration
+    InvalidConfigurationValue,
+    /// A Microsoft OS compatible ID is longer than 8 characters or not ASCII
+    InvalidCompatibleId,
+    /// A URL does not fit in a WebUSB URL descriptor
+    UrlTooLong,
+    /// A feature that is described in the BOS descriptor was enabled, but the device does not use
+    /// `UsbRev::Usb210`
+    BosRequiresUsb210,
+    /// A Best Effort Service Latency (BESL) value is larger than 15, or the deep BESL is smaller
+    /// than the baseline BESL
+    InvalidBesl,
+    /// A Container ID should be derived from the serial number, but no serial number was provided
+    NoSerialNumber,
+    /// A static configuration descriptor is invalid, doesn't describe the allocated interfaces and
+    /// endpoints, or doesn't match the only configuration of the device
+    InvalidStaticConfiguration,
 }
 
 /// Provides basic string descriptors about the device, including the manufacturer, product name,
@@ -83,6 +109,114 @@ impl<'a> StringDescriptors<'a> {
     }
 }
 
+/// Describes one of the configurations the host can select with SET_CONFIGURATION, including its
+/// power requirements and an optional name.
+///
+/// Devices that don't call [`UsbDeviceBuilder::configurations`] have a single configuration with
+/// value [`CONFIGURATION_VALUE`], described by the builder's own `self_powered`,
+/// `supports_remote_wakeup` and `max_power` settings.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct UsbConfiguration<'a> {
+    pub(crate) value: u8,
+    pub(crate) name: Option<&'a str>,
+    pub(crate) string: Option<StringIndex>,
+    pub(crate) self_powered: bool,
+    pub(crate) supports_remote_wakeup: bool,
+    pub(crate) max_power: u8,
+    classes_start: usize,
+    classes_end: usize,
+}
+
+impl<'a> UsbConfiguration<'a> {
+    /// Creates a configuration with the provided `bConfigurationValue`, which must be non-zero and
+    /// unique within the device.
+    pub fn new(value: u8) -> Self {
+        Self {
+            value,
+            name: None,
+            string: None,
+            self_powered: false,
+            supports_remote_wakeup: false,
+            max_power: 50,
+            classes_start: 0,
+            classes_end: usize::MAX,
+        }
+    }
+
+    /// Specify the classes that make up this configuration, as a range of indices into the list of
+    /// classes passed to [`UsbDevice::poll`](crate::device::UsbDevice::poll). Only these classes
+    /// are written into the configuration descriptor, and while the configuration is active only
+    /// they are polled and receive control requests and endpoint events. This lets the classes of
+    /// different configurations use the same interface numbers, as allocated after
+    /// [`UsbBusAllocator::next_configuration`](crate::bus::UsbBusAllocator::next_configuration).
+    /// Every class is still told about configuration changes through
+    /// [`UsbClass::set_configuration`](crate::class::UsbClass::set_configuration).
+    ///
+    /// Default: all classes
+    pub fn classes(mut self, classes: Range<usize>) -> Self {
+        self.classes_start = classes.start;
+        self.classes_end = classes.end;
+        self
+    }
+
+    /// Specify a name for this configuration, reported to the host in `iConfiguration`. The same
+    /// name is used for all languages.
+    pub fn name(mut self, name: &'a str) -> Self {
+        self.name.replace(name);
+        self
+    }
+
+    /// Sets whether the device may have an external power source in this configuration.
+    ///
+    /// Default: `false`
+    pub fn self_powered(mut self, self_powered: bool) -> Self {
+        self.self_powered = self_powered;
+        self
+    }
+
+    /// Sets whether the device supports remotely waking up the host in this configuration.
+    ///
+    /// Default: `false`
+    pub fn supports_remote_wakeup(mut self, supports_remote_wakeup: bool) -> Self {
+        self.supports_remote_wakeup = supports_remote_wakeup;
+        self
+    }
+
+    /// Sets the maximum current drawn from the USB bus in this configuration in milliamps.
+    ///
+    /// Default: 100mA
+    pub fn max_power(mut self, max_power_ma: usize) -> Result<Self, BuilderError> {
+        if max_power_ma > 500 {
+            return Err(BuilderError::PowerTooHigh);
+        }
+
+        self.max_power = (max_power_ma / 2) as u8;
+        Ok(self)
+    }
+
+    /// Gets the `bConfigurationValue` of this configuration.
+    pub fn value(&self) -> u8 {
+        self.value
+    }
+
+    pub(crate) fn class_range(&self) -> Range<usize> {
+        self.classes_start..self.classes_end
+    }
+}
+
+/// The Container ID of a device, which Windows uses to group all functions of the same physical
+/// device, even if they are connected through different ports or buses.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ContainerId {
+    /// A UUID in the byte order used in the descriptor.
+    Uuid([u8; 16]),
+
+    /// A version 5 UUID derived from the vendor ID, product ID and serial number of the device, so
+    /// that every physical device reports the same Container ID wherever it is connected. The
+    /// serial number of the first [`StringDescriptors`] that has one is used.
+    FromSerialNumber,
+}
+
 impl<'a, B: UsbBus> UsbDeviceBuilder<'a, B> {
     /// Creates a builder for constructing a new [`UsbDevice`].
     pub fn new(
@@ -103,20 +237,77 @@ impl<'a, B: UsbBus> UsbDeviceBuilder<'a, B> {
                 usb_rev: UsbRev::Usb210,
                 device_release: 0x0010,
                 string_descriptors: heapless::Vec::new(),
+                configurations: heapless::Vec::new(),
+                ms_os_10: None,
+                webusb: None,
+                lpm: false,
+                baseline_besl: None,
+                deep_besl: None,
+                container_id: None,
                 self_powered: false,
                 supports_remote_wakeup: false,
                 composite_with_iads: false,
                 max_power: 50,
+                static_configuration: None,
             },
         }
     }
 
     /// Creates the [`UsbDevice`] instance with the configuration in this builder.
-    pub fn build(self) -> Result<UsbDevice<'a, B>, BuilderError> {
+    pub fn build(mut self) -> Result<UsbDevice<'a, B>, BuilderError> {
         if self.control_buffer.len() < self.config.max_packet_size_0 as usize {
             return Err(BuilderError::ControlBufferTooSmall);
         }
 
+        // Only USB 2.1 devices are asked for their BOS descriptor.
+        if (self.config.webusb.is_some() || self.config.lpm || self.config.container_id.is_some())
+            && self.config.usb_rev < UsbRev::Usb210
+        {
+            return Err(BuilderError::BosRequiresUsb210);
+        }
+
+        if self.config.container_id == Some(ContainerId::FromSerialNumber) {
+            let serial = self
+                .config
+                .string_descriptors
+                .iter()
+                .find_map(|lang| lang.serial)
+                .ok_or(BuilderError::NoSerialNumber)?;
+
+            let uuid = uuid::v5(
+                &uuid::DEVICE_NAMESPACE,
+                &[
+                    &self.config.vendor_id.to_le_bytes(),
+                    &self.config.product_id.to_le_bytes(),
+                    serial.as_bytes(),
+                ],
+            );
+
+            self.config.container_id = Some(ContainerId::Uuid(uuid::to_guid_bytes(uuid)));
+        }
+
+        if self.config.configurations.is_empty() {
+            let configuration = UsbConfiguration {
+                self_powered: self.config.self_powered,
+                supports_remote_wakeup: self.config.supports_remote_wakeup,
+                max_power: self.config.max_power,
+                ..UsbConfiguration::new(CONFIGURATION_VALUE)
+            };
+
+            // Cannot fail, the list is empty.
+            let _ = self.config.configurations.push(configuration);
+        }
+
+        for configuration in self.config.configurations.iter_mut() {
+            if configuration.name.is_some() {
+                configuration.string = Some(self.alloc.string());
+            }
+        }
+
+        if let Some(descriptor) = self.config.static_configuration {
+            self.check_static_configuration(descriptor)?;
+        }
+
         Ok(UsbDevice::build(
             self.alloc,
             self.config,
@@ -124,6 +315,52 @@ impl<'a, B: UsbBus> UsbDeviceBuilder<'a, B> {
         ))
     }
 
+    fn check_static_configuration(&self, descriptor: &[u8]) -> Result<(), BuilderError> {
+        let invalid = BuilderError::InvalidStaticConfiguration;
+
+        // The descriptor replaces that of the only configuration.
+        let [configuration] = &self.config.configurations[..] else {
+            return Err(invalid);
+        };
+
+        let strings = &self.config.string_descriptors;
+        parser::validate_configuration(descriptor, |index| match index {
+            1 => strings.iter().any(|lang| lang.manufacturer.is_some()),
+            2 => strings.iter().any(|lang| lang.product.is_some()),
+            3 => strings.iter().any(|lang| lang.serial.is_some()),
+            _ => self.alloc.string_allocated(index),
+        })
+        .map_err(|_err| {
+            usb_debug!("Invalid static configuration: {:?}", _err);
+            invalid
+        })?;
+
+        let mut header = None;
+        let mut endpoints = 0;
+
+        for descriptor in parser::parse(descriptor).flatten() {
+            match descriptor {
+                Descriptor::Configuration(config) => header = Some(config),
+                Descriptor::Endpoint(ep) => endpoints |= endpoint_bit(ep.address),
+                _ => {}
+            }
+        }
+
+        let header = header.ok_or(invalid)?;
+
+        if header.configuration_value != configuration.value
+            || (header.attributes & 0x40 != 0) != configuration.self_powered
+            || (header.attributes & 0x20 != 0) != configuration.supports_remote_wakeup
+            || header.max_power != configuration.max_power
+            || header.num_interfaces != self.alloc.num_interfaces()
+            || endpoints != self.alloc.endpoints()
+        {
+            return Err(invalid);
+        }
+
+        Ok(())
+    }
+
     builder_fields! {
         /// Sets the device class code assigned by USB.org. Set to `0xff` for vendor-specific
         /// devices that do not conform to any class.
@@ -191,6 +428,127 @@ impl<'a, B: UsbBus> UsbDeviceBuilder<'a, B> {
         Ok(self)
     }
 
+    /// Specify the configurations of the device, in the order they are reported to the host. The
+    /// host usually selects the first one.
+    ///
+    /// When this is used, the `self_powered`, `supports_remote_wakeup` and `max_power` settings of
+    /// the builder are ignored in favor of those of each [`UsbConfiguration`]. Use
+    /// [`UsbConfiguration::classes`] to select the classes of each configuration, and
+    /// [`UsbBusAllocator::next_configuration`](crate::bus::UsbBusAllocator::next_configuration) to
+    /// number their interfaces from zero in each configuration. Classes are told about the selected
+    /// configuration through
+    /// [`UsbClass::set_configuration`](crate::class::UsbClass::set_configuration).
+    ///
+    /// # Note
+    /// Up to 8 configurations may be provided.
+    pub fn configurations(
+        mut self,
+        configurations: &[UsbConfiguration<'a>],
+    ) -> Result<Self, BuilderError> {
+        for (i, configuration) in configurations.iter().enumerate() {
+            if configuration.value == CONFIGURATION_NONE
+                || configurations[..i]
+                    .iter()
+                    .any(|other| other.value == configuration.value)
+            {
+                return Err(BuilderError::InvalidConfigurationValue);
+            }
+        }
+
+        self.config.configurations = heapless::Vec::from_slice(configurations)
+            .map_err(|_| BuilderError::TooManyConfigurations)?;
+
+        Ok(self)
+    }
+
+    /// Specify a complete configuration descriptor that is stored in flash, such as one built with
+    /// [`StaticConfiguration`](crate::descriptor::static_config::StaticConfiguration), instead of
+    /// having the classes write theirs on every request. The device then has a single
+    /// configuration, which is described by this descriptor at every speed, and classes are not
+    /// asked for their configuration descriptors.
+    ///
+    /// [`build`](Self::build) returns [`BuilderError::InvalidStaticConfiguration`] unless the
+    /// descriptor is valid, describes exactly the interfaces and endpoints allocated by the classes
+    /// and only refers to existing strings. Its `bConfigurationValue`, `bmAttributes` and
+    /// `bMaxPower` must also match the device's configuration, i.e. [`CONFIGURATION_VALUE`] and
+    /// the `self_powered`, `supports_remote_wakeup` and `max_power` settings of the builder, or
+    /// a single [`UsbConfiguration`] passed to [`configurations`](Self::configurations).
+    pub fn static_configuration(mut self, descriptor: &'static [u8]) -> Self {
+        self.config.static_configuration = Some(descriptor);
+        self
+    }
+
+    /// Specify Microsoft OS 1.0 descriptors for the device. The device then answers the OS string
+    /// descriptor request and the vendor requests for the Extended Compat ID and Extended
+    /// Properties descriptors, which older versions of Windows use to bind drivers such as WinUSB
+    /// without an INF file.
+    ///
+    /// The control buffer must be large enough to hold the descriptors.
+    pub fn ms_os_10(mut self, descriptors: MsOs10Descriptors<'a>) -> Self {
+        self.config.ms_os_10 = Some(descriptors);
+        self
+    }
+
+    /// Enables WebUSB, which lets browsers discover the device and suggest its landing page. The
+    /// device then advertises the WebUSB platform capability in its BOS descriptor and answers the
+    /// GET_URL vendor request.
+    ///
+    /// The device must use [`UsbRev::Usb210`], otherwise [`build`](Self::build) returns
+    /// [`BuilderError::BosRequiresUsb210`].
+    pub fn webusb(mut self, webusb: WebUsb<'a>) -> Self {
+        self.config.webusb = Some(webusb);
+        self
+    }
+
+    /// Enables Link Power Management (LPM), which lets the host put the bus into the L1 sleep state
+    /// to save power between transfers. The support is advertised in the USB 2.0 Extension
+    /// capability of the BOS descriptor, and the [`UsbBus`] implementation has to support it
+    /// through [`UsbBus::set_lpm_accepted`](crate::bus::UsbBus::set_lpm_accepted).
+    ///
+    /// # Arguments
+    ///
+    /// * `baseline_besl` - The recommended Best Effort Service Latency (BESL) value, from 0 to 15,
+    ///   that the host should use for the device.
+    /// * `deep_besl` - The recommended deep BESL value, from 0 to 15, for longer periods of
+    ///   inactivity. Must not be smaller than `baseline_besl`.
+    ///
+    /// The device must use [`UsbRev::Usb210`], otherwise [`build`](Self::build) returns
+    /// [`BuilderError::BosRequiresUsb210`].
+    pub fn lpm(
+        mut self,
+        baseline_besl: Option<u8>,
+        deep_besl: Option<u8>,
+    ) -> Result<Self, BuilderError> {
+        if baseline_besl.is_some_and(|besl| besl > 15)
+            || deep_besl.is_some_and(|besl| besl > 15)
+            || matches!((baseline_besl, deep_besl), (Some(baseline), Some(deep)) if deep < baseline)
+        {
+            return Err(BuilderError::InvalidBesl);
+        }
+
+        self.config.lpm = true;
+        self.config.baseline_besl = baseline_besl;
+        self.config.deep_besl = deep_besl;
+        Ok(self)
+    }
+
+    /// Specify the Container ID of the device, which is reported in the Container ID capability of
+    /// the BOS descriptor.
+    ///
+    /// With [`ContainerId::FromSerialNumber`], the ID is the version 5 UUID in the namespace
+    /// {5ae4b1c2-6f0e-4b1a-9d8e-3c7f2a9d1e64} of the name formed by the little-endian vendor ID,
+    /// the little-endian product ID and the UTF-8 serial number. It is reported in the byte order
+    /// of a Windows GUID, so Windows shows it as the same UUID string. [`build`](Self::build)
+    /// returns [`BuilderError::NoSerialNumber`] if no serial number was provided with
+    /// [`strings`](Self::strings).
+    ///
+    /// The device must use [`UsbRev::Usb210`], otherwise [`build`](Self::build) returns
+    /// [`BuilderError::BosRequiresUsb210`].
+    pub fn container_id(mut self, container_id: ContainerId) -> Self {
+        self.config.container_id = Some(container_id);
+        self
+    }
+
     /// Sets the maximum packet size in bytes for the control endpoint 0.
     ///
     /// Valid values are 8, 16, 32 and 64. There's generally no need to change this from the default
@@ -0,0 +1,857 @@
+use crate::class_prelude::*;
+use crate::control::{Recipient, RequestType};
+use crate::device;
+use crate::Result;
+
+/// Interface class code of DFU interfaces, "Application Specific".
+pub const INTERFACE_CLASS_APPLICATION: u8 = 0xfe;
+
+/// Interface subclass code of DFU interfaces.
+pub const SUBCLASS_DFU: u8 = 0x01;
+
+/// Interface protocol code of the DFU interface of an application in run-time mode.
+pub const PROTOCOL_RUNTIME: u8 = 0x01;
+
+/// Interface protocol code of the DFU interface in DFU mode.
+pub const PROTOCOL_DFU_MODE: u8 = 0x02;
+
+/// Descriptor type of the DFU functional descriptor.
+pub const DFU_FUNCTIONAL_DESCRIPTOR_TYPE: u8 = 0x21;
+
+/// DFU specification release implemented, in BCD.
+pub const BCD_DFU: u16 = 0x0110;
+
+/// DFU release reported by DfuSe devices, in BCD.
+pub const BCD_DFUSE: u16 = 0x011a;
+
+/// DFU class request codes
+#[allow(missing_docs)]
+pub mod dfu_request {
+    pub const DETACH: u8 = 0x00;
+    pub const DNLOAD: u8 = 0x01;
+    pub const UPLOAD: u8 = 0x02;
+    pub const GETSTATUS: u8 = 0x03;
+    pub const CLRSTATUS: u8 = 0x04;
+    pub const GETSTATE: u8 = 0x05;
+    pub const ABORT: u8 = 0x06;
+}
+
+/// Bits of `bmAttributes` in the DFU functional descriptor
+pub mod attributes {
+    /// The device accepts downloads.
+    pub const CAN_DNLOAD: u8 = 0x01;
+
+    /// The device supports uploads.
+    pub const CAN_UPLOAD: u8 = 0x02;
+
+    /// The device can communicate after manifestation, instead of waiting for a bus reset.
+    pub const MANIFESTATION_TOLERANT: u8 = 0x04;
+
+    /// The device detaches itself after DETACH, instead of waiting for the host to reset the bus.
+    pub const WILL_DETACH: u8 = 0x08;
+}
+
+/// DfuSe command codes, sent in block 0 of a download
+#[allow(missing_docs)]
+pub mod dfuse_command {
+    pub const GET_COMMANDS: u8 = 0x00;
+    pub const SET_ADDRESS_POINTER: u8 = 0x21;
+    pub const ERASE: u8 = 0x41;
+    pub const READ_UNPROTECT: u8 = 0x92;
+}
+
+// Detach timeout reported in DFU mode, where DETACH is not used.
+const DFU_MODE_DETACH_TIMEOUT: u16 = 1000;
+
+/// States of the DFU state machine.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+#[allow(missing_docs)]
+pub enum DfuState {
+    AppIdle = 0,
+    AppDetach = 1,
+    DfuIdle = 2,
+    DnloadSync = 3,
+    DnBusy = 4,
+    DnloadIdle = 5,
+    ManifestSync = 6,
+    Manifest = 7,
+    ManifestWaitReset = 8,
+    UploadIdle = 9,
+    Error = 10,
+}
+
+/// Error statuses of the DFU state machine, reported to the host with GETSTATUS.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum DfuError {
+    /// The file is not targeted for this device.
+    Target = 0x01,
+    /// The file fails a vendor-specific verification.
+    File = 0x02,
+    /// The memory could not be written.
+    Write = 0x03,
+    /// The memory could not be erased.
+    Erase = 0x04,
+    /// The memory was not erased as expected.
+    CheckErased = 0x05,
+    /// Programming the memory failed.
+    Prog = 0x06,
+    /// The programmed memory failed verification.
+    Verify = 0x07,
+    /// The address is outside the memory.
+    Address = 0x08,
+    /// The end of the download was received before it was complete.
+    NotDone = 0x09,
+    /// The firmware is corrupt and the device can't return to run-time mode.
+    Firmware = 0x0a,
+    /// A vendor-specific error.
+    Vendor = 0x0b,
+    /// An unexpected USB reset was detected.
+    UsbReset = 0x0c,
+    /// An unexpected power on reset was detected.
+    PowerOnReset = 0x0d,
+    /// Something went wrong.
+    Unknown = 0x0e,
+    /// The device stalled an unexpected request.
+    StalledPacket = 0x0f,
+}
+
+/// The memory that a [`DfuClass`] downloads firmware to and uploads it from.
+///
+/// With plain DFU, addresses are offsets into the firmware image, and downloads start at 0 and
+/// program consecutive blocks. The implementation should erase memory as needed before
+/// programming it. With DfuSe, the host chooses the addresses and erases the pages itself.
+pub trait DfuMemory {
+    /// Time in milliseconds the host should wait after a block has been sent before asking whether
+    /// it has been programmed.
+    const PROGRAM_TIME_MS: u32 = 5;
+
+    /// Time in milliseconds the host should wait after a DfuSe erase before asking whether it has
+    /// completed.
+    const ERASE_TIME_MS: u32 = 50;
+
+    /// Time in milliseconds the host should wait for manifestation.
+    const MANIFESTATION_TIME_MS: u32 = 1;
+
+    /// Reads memory at `address` into `buf` for an upload, and returns the number of bytes read.
+    /// Reading less than `buf.len()` bytes ends the upload.
+    fn read(&mut self, address: u32, buf: &mut [u8]) -> core::result::Result<usize, DfuError>;
+
+    /// Programs `data` at `address`.
+    fn program(&mut self, address: u32, data: &[u8]) -> core::result::Result<(), DfuError>;
+
+    /// Erases the page that contains `address`, for DfuSe.
+    fn erase(&mut self, address: u32) -> core::result::Result<(), DfuError> {
+        let _ = address;
+        Err(DfuError::Erase)
+    }
+
+    /// Erases the whole memory, for DfuSe.
+    fn erase_all(&mut self) -> core::result::Result<(), DfuError> {
+        Err(DfuError::Erase)
+    }
+
+    /// Completes the download, for example by validating the new firmware.
+    fn manifest(&mut self) -> core::result::Result<(), DfuError> {
+        Ok(())
+    }
+
+    /// Called on a bus reset after manifestation, which is when a device that is not
+    /// [manifestation tolerant](attributes::MANIFESTATION_TOLERANT) should start the new firmware.
+    fn reboot(&mut self) {}
+}
+
+/// The DFU interface of an application in run-time mode, through which the host requests a switch
+/// to DFU mode with DETACH.
+///
+/// After DETACH, `on_detach` is called to switch to DFU mode, usually by rebooting into a
+/// bootloader. Devices with the [`WILL_DETACH`](attributes::WILL_DETACH) attribute are expected
+/// to detach from the bus themselves, so it is called once the status stage of DETACH has
+/// completed. Otherwise it is called when the host resets the bus. Applications that re-enumerate
+/// in DFU mode without rebooting can instead check
+/// [`is_detach_requested`](DfuRuntimeClass::is_detach_requested) and call [`UsbDevice::force_reset`](crate::device::UsbDevice::force_reset).
+///
+/// ```no_run
+/// use usb_device::class_prelude::*;
+/// use usb_device::dfu::{attributes, DfuRuntimeClass};
+/// use usb_device::dummy::DummyUsbBus;
+/// use usb_device::prelude::*;
+///
+/// fn reboot_into_bootloader() {}
+///
+/// let usb_bus = UsbBusAllocator::new(DummyUsbBus::new());
+///
+/// let mut dfu = DfuRuntimeClass::new(
+///     &usb_bus,
+///     attributes::CAN_DNLOAD | attributes::WILL_DETACH,
+///     1000,
+///     1024,
+///     reboot_into_bootloader,
+/// );
+///
+/// let mut control_buffer = [0u8; 64];
+/// let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+///     .build()
+///     .unwrap();
+///
+/// loop {
+///     usb_dev.poll(&mut [&mut dfu]);
+/// }
+/// ```
+pub struct DfuRuntimeClass<F: FnMut()> {
+    interface: InterfaceNumber,
+    attributes: u8,
+    detach_timeout: u16,
+    transfer_size: u16,
+    on_detach: F,
+    state: DfuState,
+}
+
+impl<F: FnMut()> DfuRuntimeClass<F> {
+    /// Creates a new DFU run-time interface.
+    ///
+    /// # Arguments
+    ///
+    /// * `attributes` - The [`attributes`] of the DFU mode interface.
+    /// * `detach_timeout` - Time in milliseconds the device waits for a bus reset after DETACH.
+    /// * `transfer_size` - The largest download or upload block of the DFU mode interface.
+    /// * `on_detach` - Switches to DFU mode.
+    pub fn new<B: UsbBus>(
+        alloc: &UsbBusAllocator<B>,
+        attributes: u8,
+        detach_timeout: u16,
+        transfer_size: u16,
+        on_detach: F,
+    ) -> Self {
+        DfuRuntimeClass {
+            interface: alloc.interface(),
+            attributes,
+            detach_timeout,
+            transfer_size,
+            on_detach,
+            state: DfuState::AppIdle,
+        }
+    }
+
+    /// Gets the interface number.
+    pub fn interface(&self) -> InterfaceNumber {
+        self.interface
+    }
+
+    /// Gets whether the host has requested a switch to DFU mode.
+    pub fn is_detach_requested(&self) -> bool {
+        self.state == DfuState::AppDetach
+    }
+
+    fn is_for_interface(&self, req: &control::Request) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == u8::from(self.interface).into()
+    }
+}
+
+impl<B: UsbBus, F: FnMut()> UsbClass<B> for DfuRuntimeClass<F> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(
+            self.interface,
+            INTERFACE_CLASS_APPLICATION,
+            SUBCLASS_DFU,
+            PROTOCOL_RUNTIME,
+        )?;
+        writer.write(
+            DFU_FUNCTIONAL_DESCRIPTOR_TYPE,
+            &functional_descriptor(
+                self.attributes,
+                self.detach_timeout,
+                self.transfer_size,
+                BCD_DFU,
+            ),
+        )
+    }
+
+    fn reset(&mut self) {
+        if self.state == DfuState::AppDetach {
+            (self.on_detach)();
+        }
+
+        self.state = DfuState::AppIdle;
+    }
+
+    fn control_out_complete(&mut self, req: &control::Request) {
+        if !self.is_for_interface(req) || req.request != dfu_request::DETACH {
+            return;
+        }
+
+        if self.state == DfuState::AppDetach && self.attributes & attributes::WILL_DETACH != 0 {
+            self.state = DfuState::AppIdle;
+            (self.on_detach)();
+        }
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+
+        if !self.is_for_interface(&req) {
+            return;
+        }
+
+        match req.request {
+            dfu_request::GETSTATUS => {
+                xfer.accept_with(&status_response(None, 0, self.state)).ok();
+            }
+            dfu_request::GETSTATE => {
+                xfer.accept_with(&[self.state as u8]).ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+
+        if !self.is_for_interface(&req) {
+            return;
+        }
+
+        match req.request {
+            dfu_request::DETACH if self.state == DfuState::AppIdle => {
+                usb_debug!("DFU detach requested");
+                self.state = DfuState::AppDetach;
+                xfer.accept().ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+}
+
+// A download operation that is carried out while the device reports dfuDNBUSY.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Operation {
+    None,
+    Program { address: u32, len: usize },
+    SetAddress(u32),
+    Erase(u32),
+    EraseAll,
+}
+
+/// The DFU interface of a device in DFU mode, usually a bootloader, which downloads firmware to
+/// a [`DfuMemory`] and uploads it back.
+///
+/// Downloaded blocks are copied into `buf`, whose length is reported to the host as the largest
+/// block, and programmed from [`UsbDevice::poll`](crate::device::UsbDevice::poll) after the host
+/// has asked for the status. The control buffer of the device must be at least as long as `buf`.
+///
+/// [`new_dfuse`](DfuClass::new_dfuse) enables the DfuSe extensions used by STM32 tools such as
+/// STM32CubeProgrammer and `dfu-util -s`, with which the host sets the address of the blocks and
+/// erases pages with commands in block 0. The memory layout is reported in the interface string,
+/// for example `"@Internal Flash  /0x08000000/04*016Kg,01*064Kg,07*128Kg"`.
+///
+/// ```no_run
+/// use usb_device::class_prelude::*;
+/// use usb_device::dfu::{DfuClass, DfuError, DfuMemory};
+/// use usb_device::dummy::DummyUsbBus;
+/// use usb_device::prelude::*;
+///
+/// struct Flash;
+///
+/// impl DfuMemory for Flash {
+///     fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<usize, DfuError> {
+///         // Copy the firmware at `address` into `buf`
+///         Ok(0)
+///     }
+///
+///     fn program(&mut self, address: u32, data: &[u8]) -> Result<(), DfuError> {
+///         // Erase as needed and program `data` at `address`
+///         Ok(())
+///     }
+/// }
+///
+/// let usb_bus = UsbBusAllocator::new(DummyUsbBus::new());
+///
+/// let mut buf = [0u8; 1024];
+/// let mut dfu = DfuClass::new(&usb_bus, Flash, &mut buf).unwrap();
+///
+/// let mut control_buffer = [0u8; 1024];
+/// let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+///     .build()
+///     .unwrap();
+///
+/// loop {
+///     usb_dev.poll(&mut [&mut dfu]);
+/// }
+/// ```
+pub struct DfuClass<'a, M: DfuMemory> {
+    interface: InterfaceNumber,
+    interface_string: Option<StringIndex>,
+    memory_layout: Option<&'a str>,
+    memory: M,
+    buf: &'a mut [u8],
+    attributes: u8,
+    state: DfuState,
+    status: Option<DfuError>,
+    operation: Operation,
+    // Offset of the next block with plain DFU, or the address pointer with DfuSe.
+    address: u32,
+    manifested: bool,
+}
+
+impl<'a, M: DfuMemory> DfuClass<'a, M> {
+    /// Creates a new DFU mode interface.
+    ///
+    /// # Errors
+    ///
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - `buf` is empty.
+    pub fn new<B: UsbBus>(
+        alloc: &UsbBusAllocator<B>,
+        memory: M,
+        buf: &'a mut [u8],
+    ) -> Result<Self> {
+        if buf.is_empty() {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        // Transfer sizes are 16-bit.
+        let len = buf.len().min(u16::MAX.into());
+
+        Ok(DfuClass {
+            interface: alloc.interface(),
+            interface_string: None,
+            memory_layout: None,
+            memory,
+            buf: &mut buf[..len],
+            attributes: attributes::CAN_DNLOAD
+                | attributes::CAN_UPLOAD
+                | attributes::MANIFESTATION_TOLERANT,
+            state: DfuState::DfuIdle,
+            status: None,
+            operation: Operation::None,
+            address: 0,
+            manifested: false,
+        })
+    }
+
+    /// Creates a new DFU mode interface with the DfuSe extensions.
+    ///
+    /// # Arguments
+    ///
+    /// * `memory_layout` - The DfuSe memory layout, reported in the interface string.
+    ///
+    /// # Errors
+    ///
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - `buf` is shorter than a DfuSe
+    ///   command.
+    pub fn new_dfuse<B: UsbBus>(
+        alloc: &UsbBusAllocator<B>,
+        memory: M,
+        buf: &'a mut [u8],
+        memory_layout: &'a str,
+    ) -> Result<Self> {
+        if buf.len() < 5 {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        let mut class = Self::new(alloc, memory, buf)?;
+        class.interface_string = Some(alloc.string());
+        class.memory_layout = Some(memory_layout);
+
+        Ok(class)
+    }
+
+    /// Sets the [`attributes`] reported in the functional descriptor. By default, the interface
+    /// can download and upload, and is manifestation tolerant.
+    pub fn attributes(mut self, attributes: u8) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
+    /// Gets the interface number.
+    pub fn interface(&self) -> InterfaceNumber {
+        self.interface
+    }
+
+    /// Gets the state of the DFU state machine.
+    pub fn state(&self) -> DfuState {
+        self.state
+    }
+
+    /// Gets the memory.
+    pub fn memory(&self) -> &M {
+        &self.memory
+    }
+
+    /// Gets the memory for modification.
+    pub fn memory_mut(&mut self) -> &mut M {
+        &mut self.memory
+    }
+
+    fn is_dfuse(&self) -> bool {
+        self.memory_layout.is_some()
+    }
+
+    fn transfer_size(&self) -> usize {
+        self.buf.len()
+    }
+
+    fn can(&self, attribute: u8) -> bool {
+        self.attributes & attribute != 0
+    }
+
+    // Handles DNLOAD in dfuIDLE and dfuDNLOAD-IDLE.
+    fn download(&mut self, block: u16, data: &[u8]) -> core::result::Result<(), DfuError> {
+        if data.is_empty() {
+            // The end of the download
+            if self.state != DfuState::DnloadIdle {
+                return Err(DfuError::StalledPacket);
+            }
+
+            self.manifested = false;
+            self.state = DfuState::ManifestSync;
+            return Ok(());
+        }
+
+        if data.len() > self.transfer_size() {
+            return Err(DfuError::StalledPacket);
+        }
+
+        self.operation = if self.is_dfuse() {
+            match block {
+                0 => dfuse_command(data)?,
+                1 => return Err(DfuError::StalledPacket),
+                _ => Operation::Program {
+                    address: self.block_address(block),
+                    len: data.len(),
+                },
+            }
+        } else {
+            if self.state == DfuState::DfuIdle {
+                self.address = 0;
+            }
+
+            Operation::Program {
+                address: self.address,
+                len: data.len(),
+            }
+        };
+
+        if let Operation::Program { len, .. } = self.operation {
+            self.buf[..len].copy_from_slice(data);
+        }
+
+        self.state = DfuState::DnloadSync;
+        Ok(())
+    }
+
+    // Handles UPLOAD in dfuIDLE and dfuUPLOAD-IDLE, and returns the length of the block in the
+    // buffer.
+    fn upload(&mut self, block: u16, len: usize) -> core::result::Result<usize, DfuError> {
+        let len = len.min(self.transfer_size());
+
+        let count = if self.is_dfuse() {
+            match block {
+                0 => {
+                    let commands = [
+                        dfuse_command::GET_COMMANDS,
+                        dfuse_command::SET_ADDRESS_POINTER,
+                        dfuse_command::ERASE,
+                    ];
+                    let count = commands.len().min(len);
+                    self.buf[..count].copy_from_slice(&commands[..count]);
+                    count
+                }
+                1 => return Err(DfuError::StalledPacket),
+                _ => {
+                    let address = self.block_address(block);
+                    self.memory.read(address, &mut self.buf[..len])?
+                }
+            }
+        } else {
+            if self.state == DfuState::DfuIdle {
+                self.address = 0;
+            }
+
+            let count = self.memory.read(self.address, &mut self.buf[..len])?;
+            self.address = self.address.wrapping_add(count as u32);
+            count
+        };
+
+        // A short block ends the upload.
+        self.state = if count < len {
+            DfuState::DfuIdle
+        } else {
+            DfuState::UploadIdle
+        };
+
+        Ok(count)
+    }
+
+    // Gets the address of a DfuSe block, which follows the address pointer.
+    fn block_address(&self, block: u16) -> u32 {
+        let offset = u32::from(block - 2) * self.transfer_size() as u32;
+        self.address.wrapping_add(offset)
+    }
+
+    // Handles GETSTATUS, which starts the pending operation or manifestation, and returns the
+    // poll timeout.
+    fn get_status(&mut self) -> u32 {
+        match self.state {
+            DfuState::DnloadSync if self.operation != Operation::None => {
+                self.state = DfuState::DnBusy;
+
+                match self.operation {
+                    Operation::Program { .. } => M::PROGRAM_TIME_MS,
+                    Operation::Erase(_) | Operation::EraseAll => M::ERASE_TIME_MS,
+                    _ => 0,
+                }
+            }
+            DfuState::DnloadSync => {
+                self.state = DfuState::DnloadIdle;
+                0
+            }
+            DfuState::ManifestSync if !self.manifested => {
+                self.state = DfuState::Manifest;
+                M::MANIFESTATION_TIME_MS
+            }
+            DfuState::ManifestSync => {
+                self.state = DfuState::DfuIdle;
+                0
+            }
+            _ => 0,
+        }
+    }
+
+    // Carries out the pending operation or manifestation after the status has been reported.
+    fn execute(&mut self) {
+        let result = match self.state {
+            DfuState::DnBusy => {
+                let operation = core::mem::replace(&mut self.operation, Operation::None);
+                self.state = DfuState::DnloadSync;
+
+                match operation {
+                    Operation::Program { address, len } => {
+                        let result = self.memory.program(address, &self.buf[..len]);
+                        if result.is_ok() && !self.is_dfuse() {
+                            self.address = address.wrapping_add(len as u32);
+                        }
+                        result
+                    }
+                    Operation::SetAddress(address) => {
+                        self.address = address;
+                        Ok(())
+                    }
+                    Operation::Erase(address) => self.memory.erase(address),
+                    Operation::EraseAll => self.memory.erase_all(),
+                    Operation::None => Ok(()),
+                }
+            }
+            DfuState::Manifest => {
+                let result = self.memory.manifest();
+                self.manifested = true;
+                self.state = if self.can(attributes::MANIFESTATION_TOLERANT) {
+                    DfuState::ManifestSync
+                } else {
+                    DfuState::ManifestWaitReset
+                };
+                result
+            }
+            _ => return,
+        };
+
+        if let Err(err) = result {
+            self.fail(err);
+        }
+    }
+
+    fn fail(&mut self, err: DfuError) {
+        usb_debug!("DFU error: {:?}", err);
+        self.status = Some(err);
+        self.state = DfuState::Error;
+        self.operation = Operation::None;
+    }
+
+    fn is_for_interface(&self, req: &control::Request) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == u8::from(self.interface).into()
+    }
+}
+
+impl<B: UsbBus, M: DfuMemory> UsbClass<B> for DfuClass<'_, M> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface_alt(
+            self.interface,
+            device::DEFAULT_ALTERNATE_SETTING,
+            INTERFACE_CLASS_APPLICATION,
+            SUBCLASS_DFU,
+            PROTOCOL_DFU_MODE,
+            self.interface_string,
+        )?;
+        writer.write(
+            DFU_FUNCTIONAL_DESCRIPTOR_TYPE,
+            &functional_descriptor(
+                self.attributes,
+                DFU_MODE_DETACH_TIMEOUT,
+                self.transfer_size() as u16,
+                if self.is_dfuse() { BCD_DFUSE } else { BCD_DFU },
+            ),
+        )
+    }
+
+    fn get_string(&self, index: StringIndex, _lang_id: LangID) -> Option<&str> {
+        if Some(index) == self.interface_string {
+            self.memory_layout
+        } else {
+            None
+        }
+    }
+
+    fn reset(&mut self) {
+        if self.state == DfuState::ManifestWaitReset {
+            self.memory.reboot();
+        }
+
+        self.state = DfuState::DfuIdle;
+        self.status = None;
+        self.operation = Operation::None;
+        self.manifested = false;
+    }
+
+    fn poll(&mut self) {
+        self.execute();
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+
+        if !self.is_for_interface(&req) {
+            return;
+        }
+
+        match (req.request, self.state) {
+            (dfu_request::GETSTATUS, _) => {
+                let poll_timeout = self.get_status();
+                xfer.accept_with(&status_response(self.status, poll_timeout, self.state))
+                    .ok();
+            }
+            (dfu_request::GETSTATE, _) => {
+                xfer.accept_with(&[self.state as u8]).ok();
+            }
+            (dfu_request::UPLOAD, DfuState::DfuIdle | DfuState::UploadIdle)
+                if self.can(attributes::CAN_UPLOAD) =>
+            {
+                match self.upload(req.value, req.length.into()) {
+                    Ok(count) => {
+                        xfer.accept_with(&self.buf[..count]).ok();
+                    }
+                    Err(err) => {
+                        self.fail(err);
+                        xfer.reject().ok();
+                    }
+                }
+            }
+            _ => {
+                self.fail(DfuError::StalledPacket);
+                xfer.reject().ok();
+            }
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+
+        if !self.is_for_interface(&req) {
+            return;
+        }
+
+        match (req.request, self.state) {
+            (dfu_request::DNLOAD, DfuState::DfuIdle | DfuState::DnloadIdle)
+                if self.can(attributes::CAN_DNLOAD) =>
+            {
+                match self.download(req.value, xfer.data()) {
+                    Ok(()) => {
+                        xfer.accept().ok();
+                    }
+                    Err(err) => {
+                        self.fail(err);
+                        xfer.reject().ok();
+                    }
+                }
+            }
+            (dfu_request::CLRSTATUS, DfuState::Error) => {
+                self.status = None;
+                self.state = DfuState::DfuIdle;
+                xfer.accept().ok();
+            }
+            (
+                dfu_request::ABORT,
+                DfuState::DfuIdle
+                | DfuState::DnloadSync
+                | DfuState::DnloadIdle
+                | DfuState::ManifestSync
+                | DfuState::UploadIdle,
+            ) => {
+                self.state = DfuState::DfuIdle;
+                self.operation = Operation::None;
+                xfer.accept().ok();
+            }
+            _ => {
+                self.fail(DfuError::StalledPacket);
+                xfer.reject().ok();
+            }
+        }
+    }
+}
+
+// Parses a DfuSe command from block 0 of a download.
+fn dfuse_command(data: &[u8]) -> core::result::Result<Operation, DfuError> {
+    let address = || u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+
+    match (data[0], data.len()) {
+        (dfuse_command::SET_ADDRESS_POINTER, 5) => Ok(Operation::SetAddress(address())),
+        (dfuse_command::ERASE, 5) => Ok(Operation::Erase(address())),
+        (dfuse_command::ERASE, 1) => Ok(Operation::EraseAll),
+        _ => Err(DfuError::StalledPacket),
+    }
+}
+
+// The DFU functional descriptor, without its length and type.
+fn functional_descriptor(
+    attributes: u8,
+    detach_timeout: u16,
+    transfer_size: u16,
+    bcd_dfu: u16,
+) -> [u8; 7] {
+    let detach_timeout = detach_timeout.to_le_bytes();
+    let transfer_size = transfer_size.to_le_bytes();
+    let bcd_dfu = bcd_dfu.to_le_bytes();
+
+    [
+        attributes,        // bmAttributes
+        detach_timeout[0], // wDetachTimeOut
+        detach_timeout[1],
+        transfer_size[0], // wTransferSize
+        transfer_size[1],
+        bcd_dfu[0], // bcdDFUVersion
+        bcd_dfu[1],
+    ]
+}
+
+// The response to GETSTATUS.
+fn status_response(status: Option<DfuError>, poll_timeout: u32, state: DfuState) -> [u8; 6] {
+    let poll_timeout = poll_timeout.to_le_bytes();
+
+    [
+        status.map_or(0, |err| err as u8), // bStatus
+        poll_timeout[0],                   // bwPollTimeout
+        poll_timeout[1],
+        poll_timeout[2],
+        state as u8, // bState
+        0,           // iString
+    ]
+}
@@ -80,7 +80,7 @@ pub enum EndpointType {
 
 impl EndpointType {
     /// Format EndpointType for use in bmAttributes transfer type field USB 2.0 spec section 9.6.6
-    pub fn to_bm_attributes(&self) -> u8 {
+    pub const fn to_bm_attributes(&self) -> u8 {
         match self {
             EndpointType::Control => 0b00,
             EndpointType::Isochronous {
@@ -173,6 +173,12 @@ impl<B: UsbBus, D: EndpointDirection> Endpoint<'_, B, D> {
     pub fn unstall(&self) {
         self.bus().set_stalled(self.address, false);
     }
+
+    /// Gets whether the endpoint is stalled, either by [`stall`](Endpoint::stall) or by the host
+    /// with SET_FEATURE(ENDPOINT_HALT).
+    pub fn is_stalled(&self) -> bool {
+        self.bus().is_stalled(self.address)
+    }
 }
 
 impl<B: UsbBus> Endpoint<'_, B, In> {
@@ -0,0 +1,597 @@
+use crate::class_prelude::*;
+use crate::control::{Recipient, Request, RequestType};
+use crate::descriptor::hid::{
+    hid_descriptor, ReportSize, ReportSizes, HID_DESCRIPTOR_TYPE, REPORT_DESCRIPTOR_TYPE,
+};
+use crate::Result;
+use core::ops::Range;
+
+/// Interface class code of HID interfaces.
+pub const INTERFACE_CLASS_HID: u8 = 0x03;
+
+/// HID class request codes
+#[allow(missing_docs)]
+pub mod hid_request {
+    pub const GET_REPORT: u8 = 0x01;
+    pub const GET_IDLE: u8 = 0x02;
+    pub const GET_PROTOCOL: u8 = 0x03;
+    pub const SET_REPORT: u8 = 0x09;
+    pub const SET_IDLE: u8 = 0x0a;
+    pub const SET_PROTOCOL: u8 = 0x0b;
+}
+
+/// Report descriptor of the boot keyboard, with an 8 byte input report for the modifiers and up
+/// to six keys, and a 1 byte output report for the LEDs.
+pub const BOOT_KEYBOARD_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01, 0x09, 0x06, 0xa1, 0x01, 0x05, 0x07, 0x19, 0xe0, 0x29, 0xe7, 0x15, 0x00, 0x25, 0x01,
+    0x75, 0x01, 0x95, 0x08, 0x81, 0x02, 0x95, 0x01, 0x75, 0x08, 0x81, 0x01, 0x95, 0x05, 0x75, 0x01,
+    0x05, 0x08, 0x19, 0x01, 0x29, 0x05, 0x91, 0x02, 0x95, 0x01, 0x75, 0x03, 0x91, 0x01, 0x95, 0x06,
+    0x75, 0x08, 0x15, 0x00, 0x25, 0x65, 0x05, 0x07, 0x19, 0x00, 0x29, 0x65, 0x81, 0x00, 0xc0,
+];
+
+/// Report descriptor of the boot mouse, with a 3 byte input report for three buttons and relative
+/// X and Y movement.
+pub const BOOT_MOUSE_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01, 0x09, 0x02, 0xa1, 0x01, 0x09, 0x01, 0xa1, 0x00, 0x05, 0x09, 0x19, 0x01, 0x29, 0x03,
+    0x15, 0x00, 0x25, 0x01, 0x95, 0x03, 0x75, 0x01, 0x81, 0x02, 0x95, 0x01, 0x75, 0x05, 0x81, 0x01,
+    0x05, 0x01, 0x09, 0x30, 0x09, 0x31, 0x15, 0x81, 0x25, 0x7f, 0x75, 0x08, 0x95, 0x02, 0x81, 0x06,
+    0xc0, 0xc0,
+];
+
+const BCD_HID: u16 = 0x0111;
+
+// Largest report, including the report ID, that fits in one full-speed interrupt packet.
+const MAX_REPORT_SIZE: usize = 64;
+
+// Default idle rate of boot keyboards in units of 4 ms.
+const KEYBOARD_IDLE: u8 = 125;
+
+/// Boot interface device, which BIOSes can use without parsing the report descriptor.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BootDevice {
+    /// Not a boot device.
+    None = 0,
+    /// Boot keyboard. Input reports in the boot protocol follow
+    /// [`BOOT_KEYBOARD_REPORT_DESCRIPTOR`].
+    Keyboard = 1,
+    /// Boot mouse. Input reports in the boot protocol follow [`BOOT_MOUSE_REPORT_DESCRIPTOR`].
+    Mouse = 2,
+}
+
+/// Protocol selected by the host with SET_PROTOCOL.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HidProtocol {
+    /// Reports have the fixed format of the boot device and no report ID.
+    Boot = 0,
+    /// Reports have the format described by the report descriptor.
+    Report = 1,
+}
+
+/// Type of a report, as used in GET_REPORT and SET_REPORT.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReportType {
+    /// Report sent from the device to the host.
+    Input = 1,
+    /// Report sent from the host to the device.
+    Output = 2,
+    /// Configuration report that can be read and written by the host.
+    Feature = 3,
+}
+
+impl ReportType {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(ReportType::Input),
+            2 => Some(ReportType::Output),
+            3 => Some(ReportType::Feature),
+            _ => None,
+        }
+    }
+
+    fn size(self, report: &ReportSize) -> usize {
+        match self {
+            ReportType::Input => report.input,
+            ReportType::Output => report.output,
+            ReportType::Feature => report.feature,
+        }
+    }
+}
+
+/// A HID interface with an interrupt IN endpoint and, if the report descriptor describes output
+/// reports, an interrupt OUT endpoint.
+///
+/// The class answers the HID class requests itself. The last input and feature report of every
+/// report ID are kept in a buffer supplied by the application, so that GET_REPORT can be answered
+/// at any time, and output reports sent with SET_REPORT are kept there until they are read with
+/// [`read_output`](HidClass::read_output).
+///
+/// Report IDs are handled by the class: reports are passed to and from the application without
+/// the report ID prefix that is added on the wire when the report descriptor uses report IDs.
+///
+/// ```no_run
+/// use usb_device::class_prelude::*;
+/// use usb_device::dummy::DummyUsbBus;
+/// use usb_device::hid::{BootDevice, HidClass, BOOT_KEYBOARD_REPORT_DESCRIPTOR};
+///
+/// let usb_bus = UsbBusAllocator::new(DummyUsbBus::new());
+///
+/// let mut report_buf = [0u8; 16];
+/// let mut keyboard = HidClass::new(&usb_bus, BOOT_KEYBOARD_REPORT_DESCRIPTOR, &mut report_buf, 10)
+///     .unwrap()
+///     .boot_device(BootDevice::Keyboard);
+///
+/// // Press the A key
+/// keyboard.push_input(0, &[0, 0, 0x04, 0, 0, 0, 0, 0]).ok();
+/// ```
+pub struct HidClass<'a, B: UsbBus> {
+    interface: InterfaceNumber,
+    ep_in: EndpointIn<'a, B>,
+    ep_out: Option<EndpointOut<'a, B>>,
+    report_descriptor: &'a [u8],
+    sizes: ReportSizes,
+    report_buf: &'a mut [u8],
+    boot_device: BootDevice,
+    protocol: HidProtocol,
+    idle: [u8; 16],
+    pending_output: u16,
+}
+
+impl<'a, B: UsbBus> HidClass<'a, B> {
+    /// Creates a new HID interface.
+    ///
+    /// # Arguments
+    ///
+    /// * `report_descriptor` - The report descriptor, for example written with
+    ///   [`ReportDescriptorWriter`](crate::descriptor::hid::ReportDescriptorWriter).
+    /// * `report_buf` - Buffer for the last input, output and feature report of every report ID.
+    ///   It must be at least as large as all these reports together.
+    /// * `poll_interval` - Polling interval of the interrupt endpoints in milliseconds.
+    ///
+    /// # Errors
+    ///
+    /// * [`ParseError`](crate::UsbError::ParseError) - The report descriptor is invalid.
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - `report_buf` is too small, or a
+    ///   report doesn't fit in a 64 byte interrupt packet.
+    pub fn new(
+        alloc: &'a UsbBusAllocator<B>,
+        report_descriptor: &'a [u8],
+        report_buf: &'a mut [u8],
+        poll_interval: u8,
+    ) -> Result<Self> {
+        let sizes = ReportSizes::parse(report_descriptor).map_err(|_err| UsbError::ParseError)?;
+
+        let total: usize = sizes.iter().map(|r| r.input + r.output + r.feature).sum();
+        if total > report_buf.len() {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        let max_in = sizes.max_input_size();
+        let max_out = sizes.max_output_size();
+        if max_in > MAX_REPORT_SIZE || max_out > MAX_REPORT_SIZE {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        Ok(HidClass {
+            interface: alloc.interface(),
+            ep_in: alloc.interrupt(max_in.max(8) as u16, poll_interval),
+            ep_out: if max_out > 0 {
+                Some(alloc.interrupt(max_out.max(8) as u16, poll_interval))
+            } else {
+                None
+            },
+            report_descriptor,
+            sizes,
+            report_buf,
+            boot_device: BootDevice::None,
+            protocol: HidProtocol::Report,
+            idle: [0; 16],
+            pending_output: 0,
+        })
+    }
+
+    /// Declares the interface as a boot keyboard or mouse, which makes it support the boot
+    /// protocol. The report descriptor should describe reports compatible with the boot reports.
+    pub fn boot_device(mut self, boot_device: BootDevice) -> Self {
+        self.boot_device = boot_device;
+        self.reset_state();
+        self
+    }
+
+    /// Gets the interface number.
+    pub fn interface(&self) -> InterfaceNumber {
+        self.interface
+    }
+
+    /// Gets the protocol currently selected by the host. Input reports must be sent in the boot
+    /// format while the boot protocol is selected.
+    pub fn protocol(&self) -> HidProtocol {
+        self.protocol
+    }
+
+    /// Gets the idle rate the host selected for `report_id` in milliseconds. While the idle rate
+    /// is not zero, the application should repeat the last input report whenever that much time
+    /// has passed without a change. Zero means that reports are only sent when they change.
+    pub fn idle_ms(&self, report_id: u8) -> u32 {
+        let index = self.report_index(report_id).unwrap_or(0);
+        u32::from(self.idle[index]) * 4
+    }
+
+    /// Sends an input report on the interrupt IN endpoint and keeps it for GET_REPORT. Reports
+    /// shorter than described are padded with zeros.
+    ///
+    /// While the boot protocol is selected, `report_id` is ignored and `data` is sent as is.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - There is no input report with this
+    ///   report ID.
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - `data` is longer than the report.
+    /// * [`WouldBlock`](crate::UsbError::WouldBlock) - The previous report has not been sent
+    ///   yet. The report has still been kept for GET_REPORT.
+    pub fn push_input(&mut self, report_id: u8, data: &[u8]) -> Result<usize> {
+        if self.protocol == HidProtocol::Boot {
+            return self.ep_in.write(data);
+        }
+
+        self.store(ReportType::Input, report_id, data)?;
+
+        let mut packet = [0u8; MAX_REPORT_SIZE];
+        let len = self.report_with_id(ReportType::Input, report_id, &mut packet)?;
+
+        self.ep_in.write(&packet[..len])
+    }
+
+    /// Sets the feature report that is returned for GET_REPORT. Reports shorter than described are
+    /// padded with zeros.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - There is no feature report with this
+    ///   report ID.
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - `data` is longer than the report.
+    pub fn set_feature_report(&mut self, report_id: u8, data: &[u8]) -> Result<()> {
+        self.store(ReportType::Feature, report_id, data)
+    }
+
+    /// Gets the feature report with `report_id`, as last set by the host or the application.
+    pub fn feature_report(&self, report_id: u8) -> Option<&[u8]> {
+        let range = self.slot(ReportType::Feature, report_id)?;
+        Some(&self.report_buf[range])
+    }
+
+    /// Reads an output report sent by the host with SET_REPORT or on the interrupt OUT endpoint.
+    /// Returns the report ID, which is 0 if the report descriptor doesn't use report IDs or the
+    /// boot protocol is selected, and the length of the report.
+    ///
+    /// # Errors
+    ///
+    /// * [`WouldBlock`](crate::UsbError::WouldBlock) - No output report has been received.
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - `buf` is too short for the report.
+    /// * [`ParseError`](crate::UsbError::ParseError) - The report has an unknown report ID.
+    pub fn read_output(&mut self, buf: &mut [u8]) -> Result<(u8, usize)> {
+        if self.pending_output != 0 {
+            let index = self.pending_output.trailing_zeros() as usize;
+            self.pending_output &= !(1 << index);
+
+            let report_id = self.sizes.iter().nth(index).map_or(0, |r| r.report_id);
+            let range = self
+                .slot(ReportType::Output, report_id)
+                .ok_or(UsbError::InvalidState)?;
+            let data = &self.report_buf[range];
+
+            let dest = buf.get_mut(..data.len()).ok_or(UsbError::BufferOverflow)?;
+            dest.copy_from_slice(data);
+
+            return Ok((report_id, data.len()));
+        }
+
+        let ep_out = self.ep_out.as_ref().ok_or(UsbError::WouldBlock)?;
+
+        let mut packet = [0u8; MAX_REPORT_SIZE];
+        let len = ep_out.read(&mut packet)?;
+
+        let (report_id, data) = if self.protocol == HidProtocol::Report {
+            self.split_report_id(ReportType::Output, &packet[..len])?
+        } else {
+            (0, &packet[..len])
+        };
+
+        let dest = buf.get_mut(..data.len()).ok_or(UsbError::BufferOverflow)?;
+        dest.copy_from_slice(data);
+
+        Ok((report_id, data.len()))
+    }
+
+    fn reset_state(&mut self) {
+        self.protocol = HidProtocol::Report;
+        self.pending_output = 0;
+        self.idle = [match self.boot_device {
+            BootDevice::Keyboard => KEYBOARD_IDLE,
+            _ => 0,
+        }; 16];
+    }
+
+    fn report_index(&self, report_id: u8) -> Option<usize> {
+        self.sizes.iter().position(|r| r.report_id == report_id)
+    }
+
+    // Gets the range of a report in the report buffer. Reports are stored by report ID, each with
+    // its input, output and feature report.
+    fn slot(&self, report_type: ReportType, report_id: u8) -> Option<Range<usize>> {
+        let mut start = 0;
+
+        for report in self.sizes.iter() {
+            if report.report_id == report_id {
+                let start = start
+                    + match report_type {
+                        ReportType::Input => 0,
+                        ReportType::Output => report.input,
+                        ReportType::Feature => report.input + report.output,
+                    };
+                let size = report_type.size(report);
+
+                return if size > 0 {
+                    Some(start..start + size)
+                } else {
+                    None
+                };
+            }
+
+            start += report.input + report.output + report.feature;
+        }
+
+        None
+    }
+
+    fn store(&mut self, report_type: ReportType, report_id: u8, data: &[u8]) -> Result<()> {
+        let range = self
+            .slot(report_type, report_id)
+            .ok_or(UsbError::InvalidState)?;
+        if data.len() > range.len() {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        let slot = &mut self.report_buf[range];
+        slot[..data.len()].copy_from_slice(data);
+        slot[data.len()..].fill(0);
+
+        Ok(())
+    }
+
+    // Copies a stored report into `buf`, prefixed with its report ID if report IDs are used.
+    fn report_with_id(
+        &self,
+        report_type: ReportType,
+        report_id: u8,
+        buf: &mut [u8],
+    ) -> Result<usize> {
+        let range = self
+            .slot(report_type, report_id)
+            .ok_or(UsbError::InvalidState)?;
+        let prefix = usize::from(self.sizes.uses_report_ids());
+        let len = prefix + range.len();
+
+        if len > buf.len() {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        buf[0] = report_id;
+        buf[prefix..len].copy_from_slice(&self.report_buf[range]);
+
+        Ok(len)
+    }
+
+    // Splits a report received from the host into its report ID and data.
+    fn split_report_id<'d>(
+        &self,
+        report_type: ReportType,
+        data: &'d [u8],
+    ) -> Result<(u8, &'d [u8])> {
+        let (report_id, data) = if self.sizes.uses_report_ids() {
+            match data.split_first() {
+                Some((&report_id, data)) => (report_id, data),
+                None => return Err(UsbError::ParseError),
+            }
+        } else {
+            (0, data)
+        };
+
+        match self.slot(report_type, report_id) {
+            Some(_) => Ok((report_id, data)),
+            None => Err(UsbError::ParseError),
+        }
+    }
+
+    fn hid_descriptor(&self) -> [u8; 7] {
+        hid_descriptor(BCD_HID, 0, self.report_descriptor.len() as u16)
+    }
+
+    fn control_in_standard(&self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+
+        if req.request != Request::GET_DESCRIPTOR {
+            return;
+        }
+
+        match req.descriptor_type_index().0 {
+            HID_DESCRIPTOR_TYPE => {
+                let mut descriptor = [0u8; 9];
+                descriptor[0] = 9; // bLength
+                descriptor[1] = HID_DESCRIPTOR_TYPE; // bDescriptorType
+                descriptor[2..].copy_from_slice(&self.hid_descriptor());
+
+                xfer.accept_with(&descriptor).ok();
+            }
+            REPORT_DESCRIPTOR_TYPE => {
+                xfer.accept_with(self.report_descriptor).ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+
+    fn control_in_class(&self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+        let [report_id, value_high] = req.value.to_le_bytes();
+
+        match req.request {
+            hid_request::GET_REPORT => {
+                let report_type = match ReportType::from_u8(value_high) {
+                    Some(report_type) => report_type,
+                    None => {
+                        xfer.reject().ok();
+                        return;
+                    }
+                };
+
+                if self.slot(report_type, report_id).is_none() {
+                    xfer.reject().ok();
+                    return;
+                }
+
+                xfer.accept(|buf| self.report_with_id(report_type, report_id, buf))
+                    .ok();
+            }
+            hid_request::GET_IDLE => match self.report_index(report_id) {
+                Some(index) => {
+                    xfer.accept_with(&[self.idle[index]]).ok();
+                }
+                None if report_id == 0 => {
+                    xfer.accept_with(&[self.idle[0]]).ok();
+                }
+                None => {
+                    xfer.reject().ok();
+                }
+            },
+            hid_request::GET_PROTOCOL if self.boot_device != BootDevice::None => {
+                xfer.accept_with(&[self.protocol as u8]).ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+
+    fn control_out_class(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+        let [report_id, value_high] = req.value.to_le_bytes();
+
+        match req.request {
+            hid_request::SET_REPORT => {
+                let report_type = match ReportType::from_u8(value_high) {
+                    Some(report_type @ (ReportType::Output | ReportType::Feature)) => report_type,
+                    _ => {
+                        xfer.reject().ok();
+                        return;
+                    }
+                };
+
+                let stored = match self.split_report_id(report_type, xfer.data()) {
+                    Ok((id, data)) if id == report_id => self.store(report_type, id, data),
+                    Ok(_) => Err(UsbError::ParseError),
+                    Err(err) => Err(err),
+                };
+
+                if stored.is_err() {
+                    xfer.reject().ok();
+                    return;
+                }
+
+                if report_type == ReportType::Output {
+                    if let Some(index) = self.report_index(report_id) {
+                        self.pending_output |= 1 << index;
+                    }
+                }
+
+                xfer.accept().ok();
+            }
+            hid_request::SET_IDLE => {
+                if report_id == 0 {
+                    self.idle = [value_high; 16];
+                } else if let Some(index) = self.report_index(report_id) {
+                    self.idle[index] = value_high;
+                } else {
+                    xfer.reject().ok();
+                    return;
+                }
+
+                xfer.accept().ok();
+            }
+            hid_request::SET_PROTOCOL if self.boot_device != BootDevice::None => {
+                self.protocol = match req.value {
+                    0 => HidProtocol::Boot,
+                    1 => HidProtocol::Report,
+                    _ => {
+                        xfer.reject().ok();
+                        return;
+                    }
+                };
+
+                xfer.accept().ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+
+    fn is_for_interface(&self, req: &control::Request) -> bool {
+        req.recipient == Recipient::Interface && req.index == u8::from(self.interface).into()
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for HidClass<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        let sub_class = match self.boot_device {
+            BootDevice::None => 0,
+            _ => 1,
+        };
+
+        writer.interface(
+            self.interface,
+            INTERFACE_CLASS_HID,
+            sub_class,
+            self.boot_device as u8,
+        )?;
+        writer.write(HID_DESCRIPTOR_TYPE, &self.hid_descriptor())?;
+        writer.endpoint(&self.ep_in)?;
+        if let Some(ep_out) = &self.ep_out {
+            writer.endpoint(ep_out)?;
+        }
+
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.reset_state();
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+
+        if !self.is_for_interface(&req) {
+            return;
+        }
+
+        match req.request_type {
+            RequestType::Standard => self.control_in_standard(xfer),
+            RequestType::Class => self.control_in_class(xfer),
+            _ => {}
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+
+        if self.is_for_interface(&req) && req.request_type == RequestType::Class {
+            self.control_out_class(xfer);
+        }
+    }
+}
@@ -36,6 +36,9 @@
 #![no_std]
 #![warn(missing_docs)]
 
+#[cfg(feature = "sim")]
+extern crate std;
+
 #[macro_use]
 mod macros;
 
@@ -72,6 +75,18 @@ pub enum UsbError {
     InvalidState,
 }
 
+#[cfg(feature = "embedded-io")]
+impl embedded_io::Error for UsbError {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            UsbError::ParseError => embedded_io::ErrorKind::InvalidData,
+            UsbError::BufferOverflow => embedded_io::ErrorKind::OutOfMemory,
+            UsbError::Unsupported => embedded_io::ErrorKind::Unsupported,
+            _ => embedded_io::ErrorKind::Other,
+        }
+    }
+}
+
 /// Direction of USB traffic. Note that in the USB standard the direction is always indicated from
 /// the perspective of the host, which is backward for devices, but the standard directions are used
 /// for consistency.
@@ -179,6 +194,137 @@ pub mod descriptor;
 
 pub use descriptor::lang_id::LangID;
 
+/// Microsoft OS 1.0 descriptors
+///
+/// Versions of Windows before Windows 8.1 don't understand MS OS 2.0 descriptors, but read the
+/// older MS OS 1.0 descriptors to, for example, bind the WinUSB driver to a vendor-specific
+/// function without an INF file. These descriptors are served by the
+/// [`UsbDevice`](device::UsbDevice) itself once they have been specified with
+/// [`UsbDeviceBuilder::ms_os_10`](device::UsbDeviceBuilder::ms_os_10).
+///
+/// ```no_run
+/// use usb_device::class_prelude::*;
+/// use usb_device::dummy::DummyUsbBus;
+/// use usb_device::ms_os_10::{CompatibleId, ExtendedProperty, MsOs10Descriptors};
+/// use usb_device::prelude::*;
+///
+/// let usb_bus = UsbBusAllocator::new(DummyUsbBus::new());
+/// let vendor_interface = usb_bus.interface();
+///
+/// let compatible_ids = [CompatibleId::new(vendor_interface, "WINUSB", "").unwrap()];
+/// let properties = [ExtendedProperty::device_interface_guid(
+///     vendor_interface,
+///     "{3b4a6f2e-2b5c-4a0e-8f5d-1c8e3d7a9b10}",
+/// )];
+///
+/// let mut control_buffer = [0u8; 256];
+/// let usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+///     .ms_os_10(
+///         MsOs10Descriptors::new(0x20)
+///             .compatible_ids(&compatible_ids)
+///             .properties(&properties),
+///     )
+///     .build()
+///     .unwrap();
+/// ```
+pub mod ms_os_10;
+
+/// Microsoft OS 2.0 descriptors
+///
+/// Windows reads an MS OS 2.0 descriptor set from devices that advertise it in their BOS
+/// descriptor. The set can, among other things, make Windows bind the WinUSB driver to a
+/// vendor-specific device or function without an INF file. Write the set with
+/// [`DescriptorSetWriter`](ms_os_20::DescriptorSetWriter) and pass it to an
+/// [`MsOs20Class`](ms_os_20::MsOs20Class) that is polled along with the other classes.
+///
+/// ```
+/// use usb_device::class_prelude::*;
+/// use usb_device::dummy::DummyUsbBus;
+/// use usb_device::ms_os_20::{windows_version, CompatibleId, DescriptorSetWriter, MsOs20Class};
+///
+/// let usb_bus = UsbBusAllocator::new(DummyUsbBus::new());
+/// let vendor_interface = usb_bus.interface();
+///
+/// let mut buf = [0u8; 256];
+/// let mut writer = DescriptorSetWriter::new(&mut buf, windows_version::WIN81).unwrap();
+/// writer.configuration_subset(0).unwrap();
+/// writer.function_subset(vendor_interface).unwrap();
+/// writer
+///     .compatible_id(CompatibleId::new("WINUSB", "").unwrap())
+///     .unwrap();
+/// writer
+///     .device_interface_guids(&["{3b4a6f2e-2b5c-4a0e-8f5d-1c8e3d7a9b10}"])
+///     .unwrap();
+///
+/// let ms_os = MsOs20Class::new(writer.finish(), 0x20);
+/// ```
+pub mod ms_os_20;
+
+/// WebUSB support
+///
+/// Browsers that implement WebUSB look for the WebUSB platform capability in the BOS descriptor of
+/// a device, and may suggest visiting its landing page when it is connected. These descriptors are
+/// served by the [`UsbDevice`](device::UsbDevice) itself once WebUSB has been enabled with
+/// [`UsbDeviceBuilder::webusb`](device::UsbDeviceBuilder::webusb).
+///
+/// ```no_run
+/// use usb_device::class_prelude::*;
+/// use usb_device::dummy::DummyUsbBus;
+/// use usb_device::prelude::*;
+/// use usb_device::webusb::WebUsb;
+///
+/// let usb_bus = UsbBusAllocator::new(DummyUsbBus::new());
+///
+/// let mut control_buffer = [0u8; 256];
+/// let usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+///     .webusb(
+///         WebUsb::new(0x22)
+///             .landing_page("https://example.com/device")
+///             .unwrap(),
+///     )
+///     .build()
+///     .unwrap();
+/// ```
+pub mod webusb;
+
+/// Communications Device Class
+///
+/// Functions of the CDC class, such as [`CdcAcmClass`](cdc::acm::CdcAcmClass) for virtual serial
+/// ports, and the class codes and requests they share.
+pub mod cdc;
+
+/// Human Interface Device class
+///
+/// [`HidClass`](hid::HidClass) implements a HID interface, including the boot protocol of
+/// keyboards and mice. Report descriptors can be written with
+/// [`ReportDescriptorWriter`](descriptor::hid::ReportDescriptorWriter).
+pub mod hid;
+
+/// Mass Storage Class
+///
+/// [`MscClass`](msc::MscClass) implements a USB flash drive with the Bulk-Only Transport and the
+/// SCSI transparent command set, storing its data on a [`BlockDevice`](msc::BlockDevice).
+pub mod msc;
+
+/// Device Firmware Upgrade
+///
+/// [`DfuRuntimeClass`](dfu::DfuRuntimeClass) lets the host switch an application into DFU mode,
+/// and [`DfuClass`](dfu::DfuClass) downloads firmware to a [`DfuMemory`](dfu::DfuMemory) in DFU
+/// mode, optionally with the DfuSe extensions used by STM32 tools.
+pub mod dfu;
+
+/// USB Audio Device Class
+///
+/// [`AudioClass`](audio::AudioClass) implements an Audio Device Class 1.0 or 2.0 playback or
+/// capture function with volume and mute controls, selectable sample rates and explicit feedback.
+pub mod audio;
+
+/// USB MIDI
+///
+/// [`MidiClass`](midi::MidiClass) implements a USB MIDI 1.0 function with virtual cables that
+/// carry [`EventPacket`](midi::EventPacket)s.
+pub mod midi;
+
 /// Test USB class for testing USB driver implementations. Peripheral driver implementations should
 /// include an example called "test_class" that creates a device with this class to enable the
 /// driver to be tested with the test_class_host example in this crate.
@@ -197,14 +343,61 @@ pub mod test_class;
 /// ```
 pub mod dummy;
 
+/// Simulated bus for testing devices and classes without hardware.
+///
+/// [`SimUsbBus`](sim::SimUsbBus) is a working [`UsbBus`](bus::UsbBus) that keeps all packets in
+/// memory, and [`SimHost`](sim::SimHost) plays the role of the USB host. This allows
+/// [`UsbDevice`](device::UsbDevice) and any [`UsbClass`](class::UsbClass) to be tested with plain
+/// `cargo test`. Requires the `sim` feature, which depends on `std`.
+///
+/// ```
+/// use usb_device::class_prelude::*;
+/// use usb_device::prelude::*;
+/// use usb_device::sim::{request_type, SimUsbBus};
+/// use usb_device::control::{Recipient, Request, RequestType};
+/// use usb_device::UsbDirection;
+///
+/// let bus = SimUsbBus::new();
+/// let host = bus.host();
+/// let alloc = UsbBusAllocator::new(bus);
+///
+/// let mut control_buffer = [0u8; 256];
+/// let mut usb_dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x1209, 0x0001), &mut control_buffer)
+///     .build()
+///     .unwrap();
+///
+/// host.reset();
+/// usb_dev.poll(&mut []);
+///
+/// let descriptor = host
+///     .control_in(
+///         || {
+///             usb_dev.poll(&mut []);
+///         },
+///         request_type(UsbDirection::In, RequestType::Standard, Recipient::Device),
+///         Request::GET_DESCRIPTOR,
+///         0x0100,
+///         0,
+///         64,
+///     )
+///     .unwrap();
+///
+/// assert_eq!(descriptor.len(), 18);
+/// ```
+#[cfg(feature = "sim")]
+pub mod sim;
+
 mod control_pipe;
 
 mod device_builder;
 
+mod uuid;
+
 /// Prelude for device implementors.
 pub mod prelude {
     pub use crate::device::{
-        StringDescriptors, UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid,
+        ContainerId, ControlError, StringDescriptors, UsbConfiguration, UsbDevice,
+        UsbDeviceBuilder, UsbDeviceEvent, UsbDeviceState, UsbVidPid,
     };
     pub use crate::device_builder::BuilderError;
     pub use crate::LangID;
@@ -213,7 +406,9 @@ pub mod prelude {
 
 /// Prelude for class implementors.
 pub mod class_prelude {
-    pub use crate::bus::{InterfaceNumber, StringIndex, UsbBus, UsbBusAllocator};
+    pub use crate::bus::{
+        InterfaceNumber, StringIndex, TestMode, UsbBus, UsbBusAllocator, UsbSpeed,
+    };
     pub use crate::class::{ControlIn, ControlOut, UsbClass};
     pub use crate::control;
     pub use crate::descriptor::{BosWriter, DescriptorWriter};
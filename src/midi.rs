@@ -0,0 +1,535 @@
+use crate::audio::{CS_ENDPOINT, CS_INTERFACE, USB_CLASS_AUDIO};
+use crate::class_prelude::*;
+use crate::endpoint::{Endpoint, EndpointDirection};
+use crate::Result;
+
+/// Audio interface subclass code of MIDIStreaming interfaces.
+pub const SUBCLASS_MIDISTREAMING: u8 = 0x03;
+
+/// Largest number of virtual cables in each direction.
+pub const MAX_CABLES: u8 = 16;
+
+/// Size of a USB-MIDI event packet.
+pub const EVENT_PACKET_SIZE: usize = 4;
+
+/// Jack types
+#[allow(missing_docs)]
+pub mod jack_type {
+    pub const EMBEDDED: u8 = 0x01;
+    pub const EXTERNAL: u8 = 0x02;
+}
+
+/// Code Index Numbers, which classify the MIDI message of an event packet
+#[allow(missing_docs)]
+pub mod code_index {
+    pub const MISC: u8 = 0x0;
+    pub const CABLE_EVENT: u8 = 0x1;
+    pub const SYSTEM_COMMON_2: u8 = 0x2;
+    pub const SYSTEM_COMMON_3: u8 = 0x3;
+    pub const SYSEX_START: u8 = 0x4;
+    pub const SYSEX_END_1: u8 = 0x5;
+    pub const SYSEX_END_2: u8 = 0x6;
+    pub const SYSEX_END_3: u8 = 0x7;
+    pub const NOTE_OFF: u8 = 0x8;
+    pub const NOTE_ON: u8 = 0x9;
+    pub const POLY_KEY_PRESS: u8 = 0xa;
+    pub const CONTROL_CHANGE: u8 = 0xb;
+    pub const PROGRAM_CHANGE: u8 = 0xc;
+    pub const CHANNEL_PRESSURE: u8 = 0xd;
+    pub const PITCH_BEND: u8 = 0xe;
+    pub const SINGLE_BYTE: u8 = 0xf;
+}
+
+// Class-specific descriptor subtypes
+const AC_HEADER: u8 = 0x01;
+const MS_HEADER: u8 = 0x01;
+const MIDI_IN_JACK: u8 = 0x02;
+const MIDI_OUT_JACK: u8 = 0x03;
+const MS_GENERAL: u8 = 0x01;
+
+const BCD_MSC: u16 = 0x0100;
+
+const SYSEX_START: u8 = 0xf0;
+const SYSEX_END: u8 = 0xf7;
+
+/// A USB-MIDI event packet, which carries one MIDI message or a segment of a System Exclusive
+/// message on one virtual cable.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EventPacket([u8; EVENT_PACKET_SIZE]);
+
+impl EventPacket {
+    /// Creates an event packet from its raw bytes.
+    pub fn from_bytes(bytes: [u8; EVENT_PACKET_SIZE]) -> Self {
+        EventPacket(bytes)
+    }
+
+    /// Packs a complete MIDI message other than System Exclusive.
+    ///
+    /// # Errors
+    ///
+    /// * [`ParseError`](crate::UsbError::ParseError) - `cable` is not below [`MAX_CABLES`], or
+    ///   `message` is not a complete message with a valid status byte.
+    pub fn from_message(cable: u8, message: &[u8]) -> Result<Self> {
+        let status = *message.first().ok_or(UsbError::ParseError)?;
+
+        let (cin, len) = match status {
+            0x80..=0xbf | 0xe0..=0xef => (status >> 4, 3),
+            0xc0..=0xdf => (status >> 4, 2),
+            0xf1 | 0xf3 => (code_index::SYSTEM_COMMON_2, 2),
+            0xf2 => (code_index::SYSTEM_COMMON_3, 3),
+            // Tune Request is a single-byte System Common message.
+            0xf6 => (code_index::SYSEX_END_1, 1),
+            0xf8..=0xff => (code_index::SINGLE_BYTE, 1),
+            _ => return Err(UsbError::ParseError),
+        };
+
+        if message.len() != len || message[1..].iter().any(|&b| b & 0x80 != 0) {
+            return Err(UsbError::ParseError);
+        }
+
+        Self::with_bytes(cable, cin, message)
+    }
+
+    fn with_bytes(cable: u8, cin: u8, bytes: &[u8]) -> Result<Self> {
+        if cable >= MAX_CABLES {
+            return Err(UsbError::ParseError);
+        }
+
+        let mut packet = [cable << 4 | cin, 0, 0, 0];
+        packet[1..1 + bytes.len()].copy_from_slice(bytes);
+
+        Ok(EventPacket(packet))
+    }
+
+    /// Gets the raw bytes of the packet.
+    pub fn as_bytes(&self) -> &[u8; EVENT_PACKET_SIZE] {
+        &self.0
+    }
+
+    /// Gets the virtual cable number.
+    pub fn cable_number(&self) -> u8 {
+        self.0[0] >> 4
+    }
+
+    /// Gets the [Code Index Number](code_index).
+    pub fn code_index_number(&self) -> u8 {
+        self.0[0] & 0x0f
+    }
+
+    /// Gets whether the packet carries a segment of a System Exclusive message.
+    pub fn is_sysex(&self) -> bool {
+        match self.code_index_number() {
+            code_index::SYSEX_START | code_index::SYSEX_END_2 | code_index::SYSEX_END_3 => true,
+            // A single byte is either the end of a System Exclusive message or Tune Request.
+            code_index::SYSEX_END_1 => self.0[1] != 0xf6,
+            _ => false,
+        }
+    }
+
+    /// Gets the MIDI bytes carried by the packet. Appending the bytes of consecutive System
+    /// Exclusive packets on a cable restores the whole message.
+    pub fn midi_bytes(&self) -> &[u8] {
+        let len = match self.code_index_number() {
+            code_index::SYSEX_END_1 | code_index::SINGLE_BYTE => 1,
+            code_index::SYSTEM_COMMON_2
+            | code_index::SYSEX_END_2
+            | code_index::PROGRAM_CHANGE
+            | code_index::CHANNEL_PRESSURE => 2,
+            code_index::SYSTEM_COMMON_3
+            | code_index::SYSEX_START
+            | code_index::SYSEX_END_3
+            | code_index::NOTE_OFF
+            | code_index::NOTE_ON
+            | code_index::POLY_KEY_PRESS
+            | code_index::CONTROL_CHANGE
+            | code_index::PITCH_BEND => 3,
+            // Reserved for future extensions
+            _ => 0,
+        };
+
+        &self.0[1..1 + len]
+    }
+
+    /// Unpacks the event packets of a received USB packet, skipping empty padding packets.
+    /// Trailing bytes that don't form a whole event packet are ignored.
+    pub fn parse(data: &[u8]) -> impl Iterator<Item = EventPacket> + '_ {
+        data.chunks_exact(EVENT_PACKET_SIZE)
+            .map(|chunk| EventPacket([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .filter(|packet| packet.0 != [0; EVENT_PACKET_SIZE])
+    }
+}
+
+/// An iterator that splits a System Exclusive message into event packets.
+///
+/// The message includes its `0xf0` start and `0xf7` end bytes. Every packet carries three bytes,
+/// except for the last one which carries the remaining one to three.
+#[derive(Clone, Debug)]
+pub struct SysExPackets<'a> {
+    cable: u8,
+    data: &'a [u8],
+}
+
+impl<'a> SysExPackets<'a> {
+    /// Creates an iterator over the event packets of a System Exclusive message.
+    ///
+    /// # Errors
+    ///
+    /// * [`ParseError`](crate::UsbError::ParseError) - `cable` is not below [`MAX_CABLES`], or
+    ///   `message` doesn't start with `0xf0` and end with `0xf7`.
+    pub fn new(cable: u8, message: &'a [u8]) -> Result<Self> {
+        if cable >= MAX_CABLES
+            || message.len() < 2
+            || message[0] != SYSEX_START
+            || message[message.len() - 1] != SYSEX_END
+        {
+            return Err(UsbError::ParseError);
+        }
+
+        Ok(SysExPackets {
+            cable,
+            data: message,
+        })
+    }
+}
+
+impl Iterator for SysExPackets<'_> {
+    type Item = EventPacket;
+
+    fn next(&mut self) -> Option<EventPacket> {
+        let (cin, len) = match self.data.len() {
+            0 => return None,
+            1 => (code_index::SYSEX_END_1, 1),
+            2 => (code_index::SYSEX_END_2, 2),
+            3 => (code_index::SYSEX_END_3, 3),
+            _ => (code_index::SYSEX_START, 3),
+        };
+
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+
+        EventPacket::with_bytes(self.cable, cin, bytes).ok()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.data.len().div_ceil(3);
+        (len, Some(len))
+    }
+}
+
+/// A USB MIDI 1.0 function with virtual cables from the host to the device and back, which
+/// appears as MIDI ports on the host without additional drivers.
+///
+/// Each cable is an embedded jack. With [`external_jacks`](MidiClass::external_jacks), each
+/// embedded jack is connected to an external jack that stands for a physical MIDI connector of
+/// the device. Jack IDs are assigned from 1, first to the cables from the host with their
+/// embedded MIDI IN jack and external MIDI OUT jack, then to the cables to the host with their
+/// embedded MIDI OUT jack and external MIDI IN jack.
+///
+/// Messages are exchanged as [`EventPacket`]s over bulk endpoints. A USB packet carries several
+/// event packets.
+///
+/// ```no_run
+/// use usb_device::class_prelude::*;
+/// use usb_device::dummy::DummyUsbBus;
+/// use usb_device::midi::{EventPacket, MidiClass};
+/// use usb_device::prelude::*;
+///
+/// let usb_bus = UsbBusAllocator::new(DummyUsbBus::new());
+///
+/// let mut midi = MidiClass::new(&usb_bus, 1, 1, 64).unwrap();
+///
+/// let mut control_buffer = [0u8; 64];
+/// let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+///     .build()
+///     .unwrap();
+///
+/// loop {
+///     usb_dev.poll(&mut [&mut midi]);
+///
+///     let mut buf = [0u8; 64];
+///     if let Ok(len) = midi.read(&mut buf) {
+///         for packet in EventPacket::parse(&buf[..len]) {
+///             // Echo notes back to the host
+///             midi.send(packet).ok();
+///         }
+///     }
+/// }
+/// ```
+pub struct MidiClass<'a, B: UsbBus> {
+    control_if: InterfaceNumber,
+    stream_if: InterfaceNumber,
+    read_ep: Option<EndpointOut<'a, B>>,
+    write_ep: Option<EndpointIn<'a, B>>,
+    in_cables: u8,
+    out_cables: u8,
+    external_jacks: bool,
+}
+
+impl<'a, B: UsbBus> MidiClass<'a, B> {
+    /// Creates a new MIDI function.
+    ///
+    /// # Arguments
+    ///
+    /// * `in_cables` - Number of cables from the host to the device. The OUT endpoint is only
+    ///   allocated if there are any.
+    /// * `out_cables` - Number of cables from the device to the host. The IN endpoint is only
+    ///   allocated if there are any.
+    /// * `max_packet_size` - Maximum packet size of the bulk endpoints: 64 at full speed, 512 at
+    ///   high speed.
+    ///
+    /// # Errors
+    ///
+    /// * [`Unsupported`](crate::UsbError::Unsupported) - There are no cables, or more than
+    ///   [`MAX_CABLES`] in a direction.
+    pub fn new(
+        alloc: &'a UsbBusAllocator<B>,
+        in_cables: u8,
+        out_cables: u8,
+        max_packet_size: u16,
+    ) -> Result<Self> {
+        if (in_cables == 0 && out_cables == 0) || in_cables > MAX_CABLES || out_cables > MAX_CABLES
+        {
+            return Err(UsbError::Unsupported);
+        }
+
+        Ok(MidiClass {
+            control_if: alloc.interface(),
+            stream_if: alloc.interface(),
+            read_ep: (in_cables > 0).then(|| alloc.bulk(max_packet_size)),
+            write_ep: (out_cables > 0).then(|| alloc.bulk(max_packet_size)),
+            in_cables,
+            out_cables,
+            external_jacks: false,
+        })
+    }
+
+    /// Sets whether each embedded jack is connected to an external jack.
+    pub fn external_jacks(mut self, external_jacks: bool) -> Self {
+        self.external_jacks = external_jacks;
+        self
+    }
+
+    /// Gets the number of the MIDIStreaming interface.
+    pub fn streaming_interface(&self) -> InterfaceNumber {
+        self.stream_if
+    }
+
+    /// Reads a USB packet of event packets received from the host, to be unpacked with
+    /// [`EventPacket::parse`].
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - There are no cables from the host.
+    /// * [`WouldBlock`](crate::UsbError::WouldBlock) - No packet has been received.
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - `data` is shorter than the packet.
+    pub fn read(&self, data: &mut [u8]) -> Result<usize> {
+        self.read_ep
+            .as_ref()
+            .ok_or(UsbError::InvalidState)?
+            .read(data)
+    }
+
+    /// Writes a USB packet of event packets to send to the host.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - There are no cables to the host.
+    /// * [`WouldBlock`](crate::UsbError::WouldBlock) - The previous packet has not been sent yet.
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - `data` is longer than a packet.
+    pub fn write(&self, data: &[u8]) -> Result<usize> {
+        self.write_ep
+            .as_ref()
+            .ok_or(UsbError::InvalidState)?
+            .write(data)
+    }
+
+    /// Sends a single event packet to the host. See [`write`](MidiClass::write) for the errors.
+    pub fn send(&self, packet: EventPacket) -> Result<()> {
+        self.write(packet.as_bytes()).map(|_| ())
+    }
+
+    fn external(&self) -> u8 {
+        self.external_jacks as u8
+    }
+
+    fn embedded_in_jack(&self, cable: u8) -> u8 {
+        1 + cable
+    }
+
+    fn external_out_jack(&self, cable: u8) -> u8 {
+        1 + self.in_cables + cable
+    }
+
+    fn embedded_out_jack(&self, cable: u8) -> u8 {
+        1 + self.in_cables * (1 + self.external()) + cable
+    }
+
+    fn external_in_jack(&self, cable: u8) -> u8 {
+        1 + self.in_cables * (1 + self.external()) + self.out_cables + cable
+    }
+
+    // Gets wTotalLength of the MIDIStreaming interface, which includes its endpoints.
+    fn streaming_total_length(&self) -> u16 {
+        let (in_cables, out_cables) = (u16::from(self.in_cables), u16::from(self.out_cables));
+        let external = u16::from(self.external());
+
+        let mut len = 7 + in_cables * (6 + 9 * external) + out_cables * (7 + 8 * external);
+
+        for cables in [in_cables, out_cables].iter().filter(|&&c| c > 0) {
+            len += 9 + 4 + cables;
+        }
+
+        len
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for MidiClass<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.iad(self.control_if, 2, USB_CLASS_AUDIO, 0x00, 0x00, None)?;
+
+        // MIDIStreaming interfaces belong to an Audio Device Class 1.0 AudioControl interface.
+        writer.interface(
+            self.control_if,
+            USB_CLASS_AUDIO,
+            crate::audio::subclass::AUDIOCONTROL,
+            0x00,
+        )?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                AC_HEADER,
+                0x00, // bcdADC
+                0x01,
+                9, // wTotalLength
+                0,
+                1,                     // bInCollection
+                self.stream_if.into(), // baInterfaceNr
+            ],
+        )?;
+
+        writer.interface(
+            self.stream_if,
+            USB_CLASS_AUDIO,
+            SUBCLASS_MIDISTREAMING,
+            0x00,
+        )?;
+
+        let bcd_msc = BCD_MSC.to_le_bytes();
+        let total_length = self.streaming_total_length().to_le_bytes();
+        writer.write(
+            CS_INTERFACE,
+            &[
+                MS_HEADER,
+                bcd_msc[0], // bcdMSC
+                bcd_msc[1],
+                total_length[0], // wTotalLength
+                total_length[1],
+            ],
+        )?;
+
+        for cable in 0..self.in_cables {
+            let jack = self.embedded_in_jack(cable);
+            writer.write(CS_INTERFACE, &[MIDI_IN_JACK, jack_type::EMBEDDED, jack, 0])?;
+
+            if self.external_jacks {
+                writer.write(
+                    CS_INTERFACE,
+                    &[
+                        MIDI_OUT_JACK,
+                        jack_type::EXTERNAL,
+                        self.external_out_jack(cable), // bJackID
+                        1,                             // bNrInputPins
+                        jack,                          // baSourceID
+                        1,                             // baSourcePin
+                        0,                             // iJack
+                    ],
+                )?;
+            }
+        }
+
+        for cable in 0..self.out_cables {
+            let jack = self.embedded_out_jack(cable);
+
+            if self.external_jacks {
+                let source = self.external_in_jack(cable);
+                writer.write(
+                    CS_INTERFACE,
+                    &[MIDI_IN_JACK, jack_type::EXTERNAL, source, 0],
+                )?;
+                writer.write(
+                    CS_INTERFACE,
+                    &[
+                        MIDI_OUT_JACK,
+                        jack_type::EMBEDDED,
+                        jack,   // bJackID
+                        1,      // bNrInputPins
+                        source, // baSourceID
+                        1,      // baSourcePin
+                        0,      // iJack
+                    ],
+                )?;
+            } else {
+                writer.write(
+                    CS_INTERFACE,
+                    &[MIDI_OUT_JACK, jack_type::EMBEDDED, jack, 0, 0],
+                )?;
+            }
+        }
+
+        if let Some(ep) = &self.read_ep {
+            write_endpoint(
+                writer,
+                ep,
+                (0..self.in_cables).map(|c| self.embedded_in_jack(c)),
+            )?;
+        }
+
+        if let Some(ep) = &self.write_ep {
+            write_endpoint(
+                writer,
+                ep,
+                (0..self.out_cables).map(|c| self.embedded_out_jack(c)),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// Writes a MIDIStreaming bulk endpoint and the embedded jacks associated with it.
+fn write_endpoint<B: UsbBus, D: EndpointDirection>(
+    writer: &mut DescriptorWriter,
+    endpoint: &Endpoint<'_, B, D>,
+    jacks: impl Iterator<Item = u8>,
+) -> Result<()> {
+    // Audio Device Class 1.0 endpoint descriptors have bRefresh and bSynchAddress.
+    writer.endpoint_ex(endpoint, |buf| {
+        if buf.len() < 2 {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        buf[0] = 0; // bRefresh
+        buf[1] = 0; // bSynchAddress
+
+        Ok(2)
+    })?;
+
+    writer.write_with(CS_ENDPOINT, |buf| {
+        if buf.is_empty() {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        buf[0] = MS_GENERAL;
+
+        let mut len = 2;
+        for jack in jacks {
+            *buf.get_mut(len).ok_or(UsbError::BufferOverflow)? = jack; // baAssocJackID
+            len += 1;
+        }
+
+        *buf.get_mut(1).ok_or(UsbError::BufferOverflow)? = (len - 2) as u8; // bNumEmbMIDIJack
+
+        Ok(len)
+    })
+}
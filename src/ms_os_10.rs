@@ -0,0 +1,288 @@
+use crate::bus::{InterfaceNumber, UsbBus};
+use crate::class::ControlIn;
+use crate::control::{Recipient, RequestType};
+use crate::device_builder::BuilderError;
+use crate::ms_os_20::{check_compatible_id, write_utf16z, PropertyDataType};
+use crate::{Result, UsbError};
+
+/// Index of the string descriptor Windows reads to find out whether a device supports MS OS 1.0
+/// descriptors.
+pub const STRING_INDEX: u8 = 0xee;
+
+/// `wIndex` of the vendor request that retrieves the Extended Compat ID descriptor.
+pub const EXTENDED_COMPAT_ID_INDEX: u16 = 4;
+
+/// `wIndex` of the vendor request that retrieves the Extended Properties descriptor.
+pub const EXTENDED_PROPERTIES_INDEX: u16 = 5;
+
+const BCD_VERSION: u16 = 0x0100;
+
+/// Assigns a compatible ID to the function starting at an interface, for example `"WINUSB"` to
+/// have Windows bind the WinUSB driver to it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CompatibleId<'a> {
+    first_interface: u8,
+    compatible_id: &'a str,
+    sub_compatible_id: &'a str,
+}
+
+impl<'a> CompatibleId<'a> {
+    /// Creates a compatible ID for the function whose first interface is `first_interface`. Both
+    /// IDs are at most 8 ASCII characters long, and `sub_compatible_id` may be empty.
+    pub fn new(
+        first_interface: InterfaceNumber,
+        compatible_id: &'a str,
+        sub_compatible_id: &'a str,
+    ) -> core::result::Result<Self, BuilderError> {
+        check_compatible_id(compatible_id)?;
+        check_compatible_id(sub_compatible_id)?;
+
+        Ok(CompatibleId {
+            first_interface: first_interface.into(),
+            compatible_id,
+            sub_compatible_id,
+        })
+    }
+}
+
+/// The value of an extended property.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PropertyValue<'a> {
+    /// A string (REG_SZ).
+    Str(&'a str),
+    /// A string that includes environment variables (REG_EXPAND_SZ).
+    ExpandStr(&'a str),
+    /// Free-form binary data (REG_BINARY).
+    Binary(&'a [u8]),
+    /// A 32-bit integer (REG_DWORD_LITTLE_ENDIAN).
+    Dword(u32),
+    /// A list of strings (REG_MULTI_SZ).
+    MultiStr(&'a [&'a str]),
+}
+
+impl PropertyValue<'_> {
+    fn data_type(&self) -> PropertyDataType {
+        match self {
+            PropertyValue::Str(_) => PropertyDataType::Sz,
+            PropertyValue::ExpandStr(_) => PropertyDataType::ExpandSz,
+            PropertyValue::Binary(_) => PropertyDataType::Binary,
+            PropertyValue::Dword(_) => PropertyDataType::DwordLittleEndian,
+            PropertyValue::MultiStr(_) => PropertyDataType::MultiSz,
+        }
+    }
+
+    // Writes the property data and returns the number of bytes written.
+    fn write(&self, buf: &mut [u8]) -> Result<usize> {
+        match *self {
+            PropertyValue::Str(value) | PropertyValue::ExpandStr(value) => write_utf16z(buf, value),
+            PropertyValue::Binary(data) => {
+                if data.len() > buf.len() {
+                    return Err(UsbError::BufferOverflow);
+                }
+
+                buf[..data.len()].copy_from_slice(data);
+                Ok(data.len())
+            }
+            PropertyValue::Dword(value) => {
+                if buf.len() < 4 {
+                    return Err(UsbError::BufferOverflow);
+                }
+
+                buf[..4].copy_from_slice(&value.to_le_bytes());
+                Ok(4)
+            }
+            PropertyValue::MultiStr(values) => {
+                let mut len = 0;
+
+                for value in values {
+                    len += write_utf16z(&mut buf[len..], value)?;
+                }
+
+                len += write_utf16z(&mut buf[len..], "")?;
+
+                Ok(len)
+            }
+        }
+    }
+}
+
+/// A registry property that Windows stores for the function starting at an interface.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ExtendedProperty<'a> {
+    interface: u8,
+    name: &'a str,
+    value: PropertyValue<'a>,
+}
+
+impl<'a> ExtendedProperty<'a> {
+    /// Creates a property for the function whose first interface is `interface`.
+    pub fn new(interface: InterfaceNumber, name: &'a str, value: PropertyValue<'a>) -> Self {
+        ExtendedProperty {
+            interface: interface.into(),
+            name,
+            value,
+        }
+    }
+
+    /// Creates the `DeviceInterfaceGUID` property, which is used by applications to find a
+    /// function that uses WinUSB. The GUID is formatted like
+    /// `"{12345678-1234-1234-1234-123456789abc}"`.
+    pub fn device_interface_guid(interface: InterfaceNumber, guid: &'a str) -> Self {
+        Self::new(interface, "DeviceInterfaceGUID", PropertyValue::Str(guid))
+    }
+}
+
+/// Microsoft OS 1.0 descriptors served by [`UsbDevice`](crate::device::UsbDevice) itself, see
+/// [`UsbDeviceBuilder::ms_os_10`](crate::device::UsbDeviceBuilder::ms_os_10).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MsOs10Descriptors<'a> {
+    vendor_code: u8,
+    compatible_ids: &'a [CompatibleId<'a>],
+    properties: &'a [ExtendedProperty<'a>],
+}
+
+impl<'a> MsOs10Descriptors<'a> {
+    /// Creates a new set of descriptors without any compatible IDs or properties.
+    ///
+    /// `vendor_code` is the `bRequest` of the vendor request used to retrieve the descriptors.
+    /// Choose a value that is not used by any other vendor request of the device.
+    pub fn new(vendor_code: u8) -> Self {
+        MsOs10Descriptors {
+            vendor_code,
+            compatible_ids: &[],
+            properties: &[],
+        }
+    }
+
+    /// Specify the compatible IDs reported in the Extended Compat ID descriptor.
+    pub fn compatible_ids(mut self, compatible_ids: &'a [CompatibleId<'a>]) -> Self {
+        self.compatible_ids = compatible_ids;
+        self
+    }
+
+    /// Specify the properties reported in the Extended Properties descriptors.
+    pub fn properties(mut self, properties: &'a [ExtendedProperty<'a>]) -> Self {
+        self.properties = properties;
+        self
+    }
+
+    /// Gets the vendor request code used to retrieve the descriptors.
+    pub fn vendor_code(&self) -> u8 {
+        self.vendor_code
+    }
+
+    // The contents of the OS string descriptor.
+    pub(crate) fn os_string(&self) -> [u8; 16] {
+        let mut data = [0u8; 16];
+
+        for (c, buf) in "MSFT100".encode_utf16().zip(data.chunks_exact_mut(2)) {
+            buf.copy_from_slice(&c.to_le_bytes());
+        }
+
+        data[14] = self.vendor_code; // bMS_VendorCode
+        data[15] = 0; // bPad
+
+        data
+    }
+
+    pub(crate) fn control_in<B: UsbBus>(&self, xfer: ControlIn<B>) -> Result<()> {
+        let req = *xfer.request();
+
+        if req.request_type != RequestType::Vendor || req.request != self.vendor_code {
+            return Ok(());
+        }
+
+        match (req.recipient, req.index) {
+            (Recipient::Device, EXTENDED_COMPAT_ID_INDEX) if !self.compatible_ids.is_empty() => {
+                xfer.accept(|buf| self.write_compatible_ids(buf))
+            }
+
+            (Recipient::Device | Recipient::Interface, EXTENDED_PROPERTIES_INDEX) => {
+                // Windows asks for the properties of the device with interface 0, and for those
+                // of a function with its first interface in the low byte of wValue.
+                let interface = match req.recipient {
+                    Recipient::Interface => req.value as u8,
+                    _ => 0,
+                };
+
+                if self.properties.iter().any(|p| p.interface == interface) {
+                    xfer.accept(|buf| self.write_properties(interface, buf))
+                } else {
+                    xfer.reject()
+                }
+            }
+
+            _ => Ok(()),
+        }
+    }
+
+    fn write_compatible_ids(&self, buf: &mut [u8]) -> Result<usize> {
+        let length = 16 + 24 * self.compatible_ids.len();
+        if length > buf.len() {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        buf[..length].fill(0);
+        buf[0..4].copy_from_slice(&(length as u32).to_le_bytes()); // dwLength
+        buf[4..6].copy_from_slice(&BCD_VERSION.to_le_bytes()); // bcdVersion
+        buf[6..8].copy_from_slice(&EXTENDED_COMPAT_ID_INDEX.to_le_bytes()); // wIndex
+        buf[8] = self.compatible_ids.len() as u8; // bCount
+
+        for (id, section) in self
+            .compatible_ids
+            .iter()
+            .zip(buf[16..length].chunks_exact_mut(24))
+        {
+            section[0] = id.first_interface; // bFirstInterfaceNumber
+            section[1] = 0x01; // Reserved
+            section[2..2 + id.compatible_id.len()].copy_from_slice(id.compatible_id.as_bytes());
+            section[10..10 + id.sub_compatible_id.len()]
+                .copy_from_slice(id.sub_compatible_id.as_bytes());
+        }
+
+        Ok(length)
+    }
+
+    fn write_properties(&self, interface: u8, buf: &mut [u8]) -> Result<usize> {
+        if buf.len() < 10 {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        let mut position = 10;
+        let mut count: u16 = 0;
+
+        for property in self.properties.iter().filter(|p| p.interface == interface) {
+            let section = &mut buf[position..];
+            if section.len() < 10 {
+                return Err(UsbError::BufferOverflow);
+            }
+
+            let name_len = write_utf16z(&mut section[10..], property.name)?;
+            let data_pos = 10 + name_len + 4;
+            if section.len() < data_pos {
+                return Err(UsbError::BufferOverflow);
+            }
+
+            let data_len = property.value.write(&mut section[data_pos..])?;
+            let size = data_pos + data_len;
+
+            let data_type = property.value.data_type() as u32;
+
+            // dwSize, dwPropertyDataType, wPropertyNameLength and dwPropertyDataLength
+            section[0..4].copy_from_slice(&(size as u32).to_le_bytes());
+            section[4..8].copy_from_slice(&data_type.to_le_bytes());
+            section[8..10].copy_from_slice(&(name_len as u16).to_le_bytes());
+            section[data_pos - 4..data_pos].copy_from_slice(&(data_len as u32).to_le_bytes());
+
+            position += size;
+            count += 1;
+        }
+
+        buf[0..4].copy_from_slice(&(position as u32).to_le_bytes()); // dwLength
+        buf[4..6].copy_from_slice(&BCD_VERSION.to_le_bytes()); // bcdVersion
+        buf[6..8].copy_from_slice(&EXTENDED_PROPERTIES_INDEX.to_le_bytes()); // wIndex
+        buf[8..10].copy_from_slice(&count.to_le_bytes()); // wCount
+
+        Ok(position)
+    }
+}
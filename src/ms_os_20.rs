@@ -0,0 +1,483 @@
+use crate::bus::{InterfaceNumber, UsbBus};
+use crate::class::{ControlIn, UsbClass};
+use crate::control::{Recipient, RequestType};
+use crate::descriptor::{capability_type, BosWriter};
+use crate::device_builder::BuilderError;
+use crate::{Result, UsbError};
+
+/// UUID of the MS OS 2.0 platform capability, {D8DD60DF-4589-4CC7-9CD2-659D9E648A9F}, in the byte
+/// order used in the descriptor.
+pub const PLATFORM_CAPABILITY_UUID: [u8; 16] = [
+    0xdf, 0x60, 0xdd, 0xd8, 0x89, 0x45, 0xc7, 0x4c, 0x9c, 0xd2, 0x65, 0x9d, 0x9e, 0x64, 0x8a, 0x9f,
+];
+
+/// `wIndex` of the vendor request that retrieves the MS OS 2.0 descriptor set.
+pub const DESCRIPTOR_INDEX: u16 = 7;
+
+/// `wIndex` of the vendor request that selects an alternate enumeration.
+pub const SET_ALT_ENUMERATION: u16 = 8;
+
+/// Values for `dwWindowsVersion`, the minimum Windows version a descriptor set applies to.
+#[allow(missing_docs)]
+pub mod windows_version {
+    pub const WIN81: u32 = 0x0603_0000;
+    pub const WIN10: u32 = 0x0a00_0000;
+}
+
+/// MS OS 2.0 descriptor types
+#[allow(missing_docs)]
+pub mod descriptor_type {
+    pub const SET_HEADER: u16 = 0;
+    pub const SUBSET_HEADER_CONFIGURATION: u16 = 1;
+    pub const SUBSET_HEADER_FUNCTION: u16 = 2;
+    pub const FEATURE_COMPATIBLE_ID: u16 = 3;
+    pub const FEATURE_REG_PROPERTY: u16 = 4;
+    pub const FEATURE_MIN_RESUME_TIME: u16 = 5;
+    pub const FEATURE_MODEL_ID: u16 = 6;
+    pub const FEATURE_CCGP_DEVICE: u16 = 7;
+    pub const FEATURE_VENDOR_REVISION: u16 = 8;
+}
+
+/// Data type of a registry property.
+#[repr(u16)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PropertyDataType {
+    /// A NULL-terminated Unicode string (REG_SZ).
+    Sz = 1,
+    /// A NULL-terminated Unicode string that includes environment variables (REG_EXPAND_SZ).
+    ExpandSz = 2,
+    /// Free-form binary data (REG_BINARY).
+    Binary = 3,
+    /// A little-endian 32-bit integer (REG_DWORD_LITTLE_ENDIAN).
+    DwordLittleEndian = 4,
+    /// A big-endian 32-bit integer (REG_DWORD_BIG_ENDIAN).
+    DwordBigEndian = 5,
+    /// A NULL-terminated Unicode string that contains a symbolic link (REG_LINK).
+    Link = 6,
+    /// Multiple NULL-terminated Unicode strings, followed by an extra NULL (REG_MULTI_SZ).
+    MultiSz = 7,
+}
+
+/// A compatible ID for the device or a function, for example `"WINUSB"` to have Windows bind the
+/// WinUSB driver to it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CompatibleId<'a> {
+    compatible_id: &'a str,
+    sub_compatible_id: &'a str,
+}
+
+impl<'a> CompatibleId<'a> {
+    /// Creates a compatible ID. Both IDs are at most 8 ASCII characters long, and
+    /// `sub_compatible_id` may be empty.
+    pub fn new(
+        compatible_id: &'a str,
+        sub_compatible_id: &'a str,
+    ) -> core::result::Result<Self, BuilderError> {
+        check_compatible_id(compatible_id)?;
+        check_compatible_id(sub_compatible_id)?;
+
+        Ok(CompatibleId {
+            compatible_id,
+            sub_compatible_id,
+        })
+    }
+}
+
+pub(crate) fn check_compatible_id(id: &str) -> core::result::Result<(), BuilderError> {
+    if id.len() > 8 || !id.is_ascii() {
+        return Err(BuilderError::InvalidCompatibleId);
+    }
+
+    Ok(())
+}
+
+/// A writer for an MS OS 2.0 descriptor set.
+///
+/// The set header is written when the writer is created. Features that apply to the whole device
+/// are written first, followed by configuration subsets, each of which may contain function
+/// subsets for the interfaces of a composite device. The lengths of the set and the subsets are
+/// filled in automatically.
+pub struct DescriptorSetWriter<'a> {
+    buf: &'a mut [u8],
+    position: usize,
+    configuration_mark: Option<usize>,
+    function_mark: Option<usize>,
+}
+
+impl<'a> DescriptorSetWriter<'a> {
+    /// Creates a writer for a descriptor set that applies to `windows_version` and newer. See
+    /// [`windows_version`] for the possible values.
+    pub fn new(buf: &'a mut [u8], windows_version: u32) -> Result<Self> {
+        let mut writer = DescriptorSetWriter {
+            buf,
+            position: 0,
+            configuration_mark: None,
+            function_mark: None,
+        };
+
+        let version = windows_version.to_le_bytes();
+
+        writer.write(
+            descriptor_type::SET_HEADER,
+            &[
+                version[0], version[1], version[2], version[3], // dwWindowsVersion
+                0, 0, // wTotalLength
+            ],
+        )?;
+
+        Ok(writer)
+    }
+
+    /// Gets the current position in the buffer, i.e. the number of bytes written so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Writes an arbitrary MS OS 2.0 descriptor.
+    pub fn write(&mut self, descriptor_type: u16, descriptor: &[u8]) -> Result<()> {
+        self.write_with(descriptor_type, |buf| {
+            if descriptor.len() > buf.len() {
+                return Err(UsbError::BufferOverflow);
+            }
+
+            buf[..descriptor.len()].copy_from_slice(descriptor);
+
+            Ok(descriptor.len())
+        })
+    }
+
+    /// Writes an arbitrary MS OS 2.0 descriptor by using a callback to write the contents after
+    /// the `wLength` and `wDescriptorType` fields. The callback returns the number of bytes
+    /// written.
+    ///
+    /// # Errors
+    ///
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - The descriptor doesn't fit in the
+    ///   buffer, or the descriptor set would be longer than the 65535 bytes `wTotalLength` can
+    ///   describe.
+    pub fn write_with(
+        &mut self,
+        descriptor_type: u16,
+        f: impl FnOnce(&mut [u8]) -> Result<usize>,
+    ) -> Result<()> {
+        // Limiting the whole set keeps every length field within a u16.
+        let end = self.buf.len().min(u16::MAX as usize);
+
+        if self.position + 4 > end {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        let data_buf = &mut self.buf[self.position + 4..end];
+        let available = data_buf.len();
+        let data_len = f(data_buf)?;
+
+        if data_len > available {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        let length = data_len + 4;
+
+        self.buf[self.position..self.position + 2].copy_from_slice(&(length as u16).to_le_bytes());
+        self.buf[self.position + 2..self.position + 4]
+            .copy_from_slice(&descriptor_type.to_le_bytes());
+
+        self.position += length;
+
+        Ok(())
+    }
+
+    /// Starts a configuration subset. The following function subsets and features apply to the
+    /// configuration with the index `configuration_index` only.
+    ///
+    /// Note that this is the index of the configuration descriptor, not its
+    /// `bConfigurationValue`.
+    pub fn configuration_subset(&mut self, configuration_index: u8) -> Result<()> {
+        self.end_function_subset();
+        self.end_configuration_subset();
+
+        let mark = self.position;
+        self.write(
+            descriptor_type::SUBSET_HEADER_CONFIGURATION,
+            &[
+                configuration_index, // bConfigurationValue
+                0,                   // bReserved
+                0,
+                0, // wTotalLength
+            ],
+        )?;
+        self.configuration_mark = Some(mark);
+
+        Ok(())
+    }
+
+    /// Starts a function subset inside the current configuration subset. The following features
+    /// apply to the function whose first interface is `first_interface` only.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - No configuration subset has been
+    ///   started.
+    pub fn function_subset(&mut self, first_interface: InterfaceNumber) -> Result<()> {
+        if self.configuration_mark.is_none() {
+            return Err(UsbError::InvalidState);
+        }
+
+        self.end_function_subset();
+
+        let mark = self.position;
+        self.write(
+            descriptor_type::SUBSET_HEADER_FUNCTION,
+            &[
+                first_interface.into(), // bFirstInterface
+                0,                      // bReserved
+                0,
+                0, // wSubsetLength
+            ],
+        )?;
+        self.function_mark = Some(mark);
+
+        Ok(())
+    }
+
+    /// Writes a compatible ID descriptor for the device or the current function subset.
+    pub fn compatible_id(&mut self, id: CompatibleId) -> Result<()> {
+        let mut data = [0u8; 16];
+        data[..id.compatible_id.len()].copy_from_slice(id.compatible_id.as_bytes());
+        data[8..8 + id.sub_compatible_id.len()].copy_from_slice(id.sub_compatible_id.as_bytes());
+
+        self.write(descriptor_type::FEATURE_COMPATIBLE_ID, &data)
+    }
+
+    /// Writes a registry property descriptor with raw property data.
+    pub fn registry_property(
+        &mut self,
+        name: &str,
+        data_type: PropertyDataType,
+        data: &[u8],
+    ) -> Result<()> {
+        self.registry_property_with(name, data_type, |buf| {
+            if data.len() > buf.len() {
+                return Err(UsbError::BufferOverflow);
+            }
+
+            buf[..data.len()].copy_from_slice(data);
+
+            Ok(data.len())
+        })
+    }
+
+    /// Writes a registry property descriptor with a string value of type
+    /// [`Sz`](PropertyDataType::Sz).
+    pub fn registry_property_str(&mut self, name: &str, value: &str) -> Result<()> {
+        self.registry_property_with(name, PropertyDataType::Sz, |buf| write_utf16z(buf, value))
+    }
+
+    /// Writes a registry property descriptor with a list of string values of type
+    /// [`MultiSz`](PropertyDataType::MultiSz).
+    pub fn registry_property_multi_str(&mut self, name: &str, values: &[&str]) -> Result<()> {
+        self.registry_property_with(name, PropertyDataType::MultiSz, |buf| {
+            let mut len = 0;
+
+            for value in values {
+                len += write_utf16z(&mut buf[len..], value)?;
+            }
+
+            len += write_utf16z(&mut buf[len..], "")?;
+
+            Ok(len)
+        })
+    }
+
+    /// Writes the `DeviceInterfaceGUIDs` registry property, which is used by applications to find
+    /// a device or function that uses WinUSB. Each GUID is formatted like
+    /// `"{12345678-1234-1234-1234-123456789abc}"`.
+    pub fn device_interface_guids(&mut self, guids: &[&str]) -> Result<()> {
+        self.registry_property_multi_str("DeviceInterfaceGUIDs", guids)
+    }
+
+    /// Writes a minimum USB resume time descriptor.
+    ///
+    /// # Arguments
+    ///
+    /// * `resume_recovery_time` - Milliseconds the device needs to recover after resume, 0 to 10.
+    /// * `resume_signaling_time` - Milliseconds the device signals resume for during a remote
+    ///   wakeup, 1 to 20.
+    ///
+    /// # Errors
+    ///
+    /// * [`ParseError`](crate::UsbError::ParseError) - One of the times is out of range.
+    pub fn min_resume_time(
+        &mut self,
+        resume_recovery_time: u8,
+        resume_signaling_time: u8,
+    ) -> Result<()> {
+        if resume_recovery_time > 10 || !(1..=20).contains(&resume_signaling_time) {
+            return Err(UsbError::ParseError);
+        }
+
+        self.write(
+            descriptor_type::FEATURE_MIN_RESUME_TIME,
+            &[resume_recovery_time, resume_signaling_time],
+        )
+    }
+
+    /// Writes a model ID descriptor, a UUID that uniquely identifies the physical device model
+    /// across its functions and buses.
+    pub fn model_id(&mut self, model_id: &[u8; 16]) -> Result<()> {
+        self.write(descriptor_type::FEATURE_MODEL_ID, model_id)
+    }
+
+    /// Writes a CCGP device descriptor, which makes Windows treat the device as a composite
+    /// device even if it doesn't look like one.
+    pub fn ccgp_device(&mut self) -> Result<()> {
+        self.write(descriptor_type::FEATURE_CCGP_DEVICE, &[])
+    }
+
+    /// Writes a vendor revision descriptor. Changing the revision makes Windows read the
+    /// descriptor set again instead of using a cached copy.
+    pub fn vendor_revision(&mut self, revision: u16) -> Result<()> {
+        self.write(
+            descriptor_type::FEATURE_VENDOR_REVISION,
+            &revision.to_le_bytes(),
+        )
+    }
+
+    /// Finishes the descriptor set and returns it.
+    pub fn finish(mut self) -> &'a [u8] {
+        self.end_function_subset();
+        self.end_configuration_subset();
+
+        let position = self.position;
+        self.buf[8..10].copy_from_slice(&(position as u16).to_le_bytes());
+
+        &self.buf[..position]
+    }
+
+    fn registry_property_with(
+        &mut self,
+        name: &str,
+        data_type: PropertyDataType,
+        f: impl FnOnce(&mut [u8]) -> Result<usize>,
+    ) -> Result<()> {
+        self.write_with(descriptor_type::FEATURE_REG_PROPERTY, |buf| {
+            if buf.len() < 4 {
+                return Err(UsbError::BufferOverflow);
+            }
+
+            buf[0..2].copy_from_slice(&(data_type as u16).to_le_bytes());
+
+            let name_len = write_utf16z(&mut buf[4..], name)?;
+            buf[2..4].copy_from_slice(&(name_len as u16).to_le_bytes());
+
+            let pos = 4 + name_len;
+            if buf.len() < pos + 2 {
+                return Err(UsbError::BufferOverflow);
+            }
+
+            let data_len = f(&mut buf[pos + 2..])?;
+            buf[pos..pos + 2].copy_from_slice(&(data_len as u16).to_le_bytes());
+
+            Ok(pos + 2 + data_len)
+        })
+    }
+
+    fn end_function_subset(&mut self) {
+        if let Some(mark) = self.function_mark.take() {
+            let length = (self.position - mark) as u16;
+            self.buf[mark + 6..mark + 8].copy_from_slice(&length.to_le_bytes());
+        }
+    }
+
+    fn end_configuration_subset(&mut self) {
+        if let Some(mark) = self.configuration_mark.take() {
+            let length = (self.position - mark) as u16;
+            self.buf[mark + 6..mark + 8].copy_from_slice(&length.to_le_bytes());
+        }
+    }
+}
+
+// Writes a NULL-terminated UTF-16LE string and returns the number of bytes written.
+pub(crate) fn write_utf16z(buf: &mut [u8], s: &str) -> Result<usize> {
+    let mut len = 0;
+
+    for c in s.encode_utf16().chain(core::iter::once(0)) {
+        if len + 2 > buf.len() {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        buf[len..len + 2].copy_from_slice(&c.to_le_bytes());
+        len += 2;
+    }
+
+    Ok(len)
+}
+
+/// Provides an MS OS 2.0 descriptor set to Windows hosts, for example to bind the WinUSB driver
+/// without an INF file.
+///
+/// The class adds the MS OS 2.0 platform capability to the BOS descriptor and answers the vendor
+/// request Windows uses to retrieve the descriptor set. It has no interfaces of its own, so it can
+/// be combined with any other classes. The device must use
+/// [`UsbRev::Usb210`](crate::device::UsbRev::Usb210), and its control buffer must be large enough
+/// to hold the descriptor set.
+pub struct MsOs20Class<'a> {
+    descriptor_set: &'a [u8],
+    vendor_code: u8,
+}
+
+impl<'a> MsOs20Class<'a> {
+    /// Creates a new `MsOs20Class`.
+    ///
+    /// # Arguments
+    ///
+    /// * `descriptor_set` - A descriptor set written with [`DescriptorSetWriter`].
+    /// * `vendor_code` - The `bRequest` of the vendor request used to retrieve the descriptor
+    ///   set. Choose a value that is not used by any other vendor request of the device.
+    pub fn new(descriptor_set: &'a [u8], vendor_code: u8) -> Self {
+        MsOs20Class {
+            descriptor_set,
+            vendor_code,
+        }
+    }
+
+    /// Gets the vendor request code used to retrieve the descriptor set.
+    pub fn vendor_code(&self) -> u8 {
+        self.vendor_code
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for MsOs20Class<'_> {
+    fn get_bos_descriptors(&self, writer: &mut BosWriter) -> Result<()> {
+        // The Windows version is taken from the set header.
+        let version = self
+            .descriptor_set
+            .get(4..8)
+            .ok_or(UsbError::InvalidState)?;
+        let length = (self.descriptor_set.len() as u16).to_le_bytes();
+
+        let mut data = [0u8; 25];
+        data[1..17].copy_from_slice(&PLATFORM_CAPABILITY_UUID);
+        data[17..21].copy_from_slice(version); // dwWindowsVersion
+        data[21..23].copy_from_slice(&length); // wMSOSDescriptorSetTotalLength
+        data[23] = self.vendor_code; // bMS_VendorCode
+        data[24] = 0; // bAltEnumCode
+
+        writer.capability(capability_type::PLATFORM, &data)
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+
+        if req.request_type == RequestType::Vendor
+            && req.recipient == Recipient::Device
+            && req.request == self.vendor_code
+            && req.index == DESCRIPTOR_INDEX
+        {
+            // The device stalls the request if it could not be accepted.
+            if let Err(_err) = xfer.accept_with(self.descriptor_set) {
+                usb_debug!("Failed to send MS OS 2.0 descriptor set: {:?}", _err);
+            }
+        }
+    }
+}
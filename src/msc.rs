@@ -0,0 +1,876 @@
+use crate::class_prelude::*;
+use crate::control::{Recipient, RequestType};
+use crate::Result;
+
+/// Interface class code of mass storage interfaces.
+pub const INTERFACE_CLASS_MSC: u8 = 0x08;
+
+/// Interface subclass code of the SCSI transparent command set.
+pub const SUBCLASS_SCSI: u8 = 0x06;
+
+/// Interface protocol code of the Bulk-Only Transport.
+pub const PROTOCOL_BULK_ONLY: u8 = 0x50;
+
+/// Signature of a Command Block Wrapper, "USBC".
+pub const CBW_SIGNATURE: u32 = 0x4342_5355;
+
+/// Signature of a Command Status Wrapper, "USBS".
+pub const CSW_SIGNATURE: u32 = 0x5342_5355;
+
+/// Mass storage class request codes
+#[allow(missing_docs)]
+pub mod msc_request {
+    pub const GET_MAX_LUN: u8 = 0xfe;
+    pub const BULK_ONLY_MASS_STORAGE_RESET: u8 = 0xff;
+}
+
+/// SCSI operation codes
+#[allow(missing_docs)]
+pub mod scsi_command {
+    pub const TEST_UNIT_READY: u8 = 0x00;
+    pub const REQUEST_SENSE: u8 = 0x03;
+    pub const INQUIRY: u8 = 0x12;
+    pub const MODE_SENSE_6: u8 = 0x1a;
+    pub const PREVENT_ALLOW_MEDIUM_REMOVAL: u8 = 0x1e;
+    pub const READ_CAPACITY_10: u8 = 0x25;
+    pub const READ_10: u8 = 0x28;
+    pub const WRITE_10: u8 = 0x2a;
+    pub const MODE_SENSE_10: u8 = 0x5a;
+    pub const SERVICE_ACTION_IN_16: u8 = 0x9e;
+}
+
+// Service action of SERVICE ACTION IN(16) for READ CAPACITY(16).
+const READ_CAPACITY_16: u8 = 0x10;
+
+const CBW_LENGTH: usize = 31;
+const CSW_LENGTH: usize = 13;
+
+// bCSWStatus
+const STATUS_PASSED: u8 = 0x00;
+const STATUS_FAILED: u8 = 0x01;
+const STATUS_PHASE_ERROR: u8 = 0x02;
+
+const INQUIRY_LENGTH: usize = 36;
+
+/// Errors reported by a [`BlockDevice`]. They are reported to the host in the sense data of the
+/// failed command.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BlockDeviceError {
+    /// No medium is present.
+    NotReady,
+
+    /// The block could not be read or written.
+    MediumError,
+
+    /// The device failed.
+    HardwareError,
+
+    /// The medium is write-protected.
+    WriteProtected,
+}
+
+/// The storage behind an [`MscClass`], which is accessed in fixed-size blocks.
+pub trait BlockDevice {
+    /// Gets the size of a block in bytes, usually 512. It must be a multiple of the maximum packet
+    /// size of the bulk endpoints.
+    fn block_size(&self) -> usize;
+
+    /// Gets the number of blocks.
+    fn block_count(&self) -> u64;
+
+    /// Reads block `lba` into `buf`, which is one block long.
+    fn read_block(
+        &mut self,
+        lba: u64,
+        buf: &mut [u8],
+    ) -> core::result::Result<(), BlockDeviceError>;
+
+    /// Writes `data`, which is one block long, to block `lba`.
+    fn write_block(&mut self, lba: u64, data: &[u8]) -> core::result::Result<(), BlockDeviceError>;
+
+    /// Gets whether a medium is present. Hosts poll this while the medium is absent.
+    fn is_ready(&self) -> bool {
+        true
+    }
+
+    /// Gets whether the medium is write-protected.
+    fn is_write_protected(&self) -> bool {
+        false
+    }
+}
+
+// Sense key and additional sense code of the last failed command.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct Sense {
+    key: u8,
+    asc: u8,
+}
+
+impl Sense {
+    const NO_SENSE: Sense = Sense::new(0x00, 0x00);
+    const MEDIUM_NOT_PRESENT: Sense = Sense::new(0x02, 0x3a);
+    const UNRECOVERED_READ_ERROR: Sense = Sense::new(0x03, 0x11);
+    const WRITE_ERROR: Sense = Sense::new(0x03, 0x0c);
+    const INTERNAL_TARGET_FAILURE: Sense = Sense::new(0x04, 0x44);
+    const INVALID_COMMAND_OPERATION_CODE: Sense = Sense::new(0x05, 0x20);
+    const LBA_OUT_OF_RANGE: Sense = Sense::new(0x05, 0x21);
+    const INVALID_FIELD_IN_CDB: Sense = Sense::new(0x05, 0x24);
+    const WRITE_PROTECTED: Sense = Sense::new(0x07, 0x27);
+
+    const fn new(key: u8, asc: u8) -> Self {
+        Sense { key, asc }
+    }
+
+    fn from_read_error(err: BlockDeviceError) -> Self {
+        match err {
+            BlockDeviceError::MediumError => Sense::UNRECOVERED_READ_ERROR,
+            _ => Sense::from_error(err),
+        }
+    }
+
+    fn from_write_error(err: BlockDeviceError) -> Self {
+        match err {
+            BlockDeviceError::MediumError => Sense::WRITE_ERROR,
+            _ => Sense::from_error(err),
+        }
+    }
+
+    fn from_error(err: BlockDeviceError) -> Self {
+        match err {
+            BlockDeviceError::NotReady => Sense::MEDIUM_NOT_PRESENT,
+            BlockDeviceError::MediumError | BlockDeviceError::HardwareError => {
+                Sense::INTERNAL_TARGET_FAILURE
+            }
+            BlockDeviceError::WriteProtected => Sense::WRITE_PROTECTED,
+        }
+    }
+}
+
+// The data stage of a command, as intended by the device.
+enum Data {
+    None,
+    // The first `len` bytes of the buffer.
+    In(usize),
+    Read { lba: u64, count: u32 },
+    Write { lba: u64, count: u32 },
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum State {
+    // Waiting for a CBW.
+    Command,
+    // Sending data to the host.
+    DataIn,
+    // Receiving data from the host.
+    DataOut,
+    // Waiting for the host to clear the halt of the IN endpoint before the CSW is sent.
+    StatusStalled,
+    // Sending the CSW.
+    Status,
+    // An invalid CBW was received. Both endpoints stay halted until the host resets the function
+    // with a Bulk-Only Mass Storage Reset.
+    ResetRecovery,
+}
+
+/// A USB Mass Storage Class interface with the Bulk-Only Transport and the SCSI transparent command
+/// set, which appears as a removable disk on the host.
+///
+/// The commands that hosts send to USB flash drives are supported: INQUIRY, READ CAPACITY(10) and
+/// (16), READ(10), WRITE(10), MODE SENSE(6) and (10), REQUEST SENSE, TEST UNIT READY and PREVENT
+/// ALLOW MEDIUM REMOVAL. Blocks are transferred through `buf` one at a time, with the
+/// [`BlockDevice`] called from [`UsbDevice::poll`](crate::device::UsbDevice::poll).
+///
+/// Transfers that differ in length or direction from what the host announced in the CBW are
+/// handled as the Bulk-Only Transport specifies, by halting the bulk endpoints until the host
+/// clears them.
+///
+/// ```no_run
+/// use usb_device::class_prelude::*;
+/// use usb_device::dummy::DummyUsbBus;
+/// use usb_device::msc::{BlockDevice, BlockDeviceError, MscClass};
+/// use usb_device::prelude::*;
+///
+/// struct RamDisk([u8; 64 * 512]);
+///
+/// impl BlockDevice for RamDisk {
+///     fn block_size(&self) -> usize {
+///         512
+///     }
+///
+///     fn block_count(&self) -> u64 {
+///         64
+///     }
+///
+///     fn read_block(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockDeviceError> {
+///         let start = lba as usize * 512;
+///         buf.copy_from_slice(&self.0[start..start + 512]);
+///         Ok(())
+///     }
+///
+///     fn write_block(&mut self, lba: u64, data: &[u8]) -> Result<(), BlockDeviceError> {
+///         let start = lba as usize * 512;
+///         self.0[start..start + 512].copy_from_slice(data);
+///         Ok(())
+///     }
+/// }
+///
+/// let usb_bus = UsbBusAllocator::new(DummyUsbBus::new());
+///
+/// let mut buf = [0u8; 512];
+/// let mut msc = MscClass::new(&usb_bus, 64, RamDisk([0; 64 * 512]), &mut buf)
+///     .unwrap()
+///     .inquiry("Example", "RAM disk", "1.0");
+///
+/// let mut control_buffer = [0u8; 64];
+/// let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+///     .build()
+///     .unwrap();
+///
+/// loop {
+///     usb_dev.poll(&mut [&mut msc]);
+/// }
+/// ```
+pub struct MscClass<'a, B: UsbBus, D: BlockDevice> {
+    interface: InterfaceNumber,
+    read_ep: EndpointOut<'a, B>,
+    write_ep: EndpointIn<'a, B>,
+    device: D,
+    buf: &'a mut [u8],
+    inquiry: [u8; INQUIRY_LENGTH],
+    state: State,
+    out_pending: bool,
+    in_flight: bool,
+    tag: u32,
+    direction_in: bool,
+    // Bytes of dCBWDataTransferLength that have not been transferred yet.
+    residue: u32,
+    status: u8,
+    // Whether the last packet of the data sent to the host was short, which ends the data stage.
+    short_packet: bool,
+    buf_len: usize,
+    buf_pos: usize,
+    // Next block to read or write, and the number of blocks left in the command.
+    lba: u64,
+    blocks: u32,
+    sense: Sense,
+    prevent_removal: bool,
+}
+
+impl<'a, B: UsbBus, D: BlockDevice> MscClass<'a, B, D> {
+    /// Creates a new mass storage interface.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_packet_size` - Maximum packet size of the bulk endpoints: 64 at full speed, 512 at
+    ///   high speed.
+    /// * `device` - The storage.
+    /// * `buf` - Buffer for the blocks being transferred, at least one block long.
+    ///
+    /// # Errors
+    ///
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - `buf` is shorter than a block or a
+    ///   packet.
+    /// * [`Unsupported`](crate::UsbError::Unsupported) - The block size is not a non-zero multiple
+    ///   of `max_packet_size`.
+    pub fn new(
+        alloc: &'a UsbBusAllocator<B>,
+        max_packet_size: u16,
+        device: D,
+        buf: &'a mut [u8],
+    ) -> Result<Self> {
+        let block_size = device.block_size();
+
+        // Blocks are sent in whole packets, as a short packet would end the data stage.
+        let packet_size = usize::from(max_packet_size);
+        if block_size == 0 || block_size % packet_size != 0 {
+            return Err(UsbError::Unsupported);
+        }
+
+        if buf.len() < block_size.max(INQUIRY_LENGTH) || buf.len() < max_packet_size.into() {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        let mut class = MscClass {
+            interface: alloc.interface(),
+            read_ep: alloc.bulk(max_packet_size),
+            write_ep: alloc.bulk(max_packet_size),
+            device,
+            buf,
+            inquiry: [0; INQUIRY_LENGTH],
+            state: State::Command,
+            out_pending: false,
+            in_flight: false,
+            tag: 0,
+            direction_in: false,
+            residue: 0,
+            status: STATUS_PASSED,
+            short_packet: false,
+            buf_len: 0,
+            buf_pos: 0,
+            lba: 0,
+            blocks: 0,
+            sense: Sense::NO_SENSE,
+            prevent_removal: false,
+        };
+
+        class.inquiry[0] = 0x00; // Peripheral device type: direct access block device
+        class.inquiry[2] = 0x04; // Version: SPC-2
+        class.inquiry[3] = 0x02; // Response data format
+        class.inquiry[4] = (INQUIRY_LENGTH - 5) as u8; // Additional length
+
+        Ok(class.removable(true).inquiry("", "", ""))
+    }
+
+    /// Sets the vendor, product and revision reported in the INQUIRY data. They are truncated or
+    /// padded with spaces to 8, 16 and 4 characters.
+    pub fn inquiry(mut self, vendor: &str, product: &str, revision: &str) -> Self {
+        for (range, value) in [(8..16, vendor), (16..32, product), (32..36, revision)].iter() {
+            let field = &mut self.inquiry[range.clone()];
+            field.fill(b' ');
+
+            let len = value.len().min(field.len());
+            field[..len].copy_from_slice(&value.as_bytes()[..len]);
+        }
+
+        self
+    }
+
+    /// Sets whether the medium is reported as removable, which it is by default. Hosts show
+    /// removable media as drives that can be ejected.
+    pub fn removable(mut self, removable: bool) -> Self {
+        self.inquiry[1] = if removable { 0x80 } else { 0x00 };
+        self
+    }
+
+    /// Gets the interface number.
+    pub fn interface(&self) -> InterfaceNumber {
+        self.interface
+    }
+
+    /// Gets the storage.
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    /// Gets the storage for modification, for example to swap the medium.
+    pub fn device_mut(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    /// Gets whether the host has prevented the removal of the medium with PREVENT ALLOW MEDIUM
+    /// REMOVAL, which it does while the medium is mounted.
+    pub fn is_medium_removal_prevented(&self) -> bool {
+        self.prevent_removal
+    }
+
+    // Reads a CBW and starts executing its command.
+    fn read_command(&mut self) {
+        let max_packet_size = usize::from(self.read_ep.max_packet_size());
+
+        let count = match self.read_ep.read(&mut self.buf[..max_packet_size]) {
+            Ok(count) => count,
+            Err(UsbError::WouldBlock) => return,
+            Err(_err) => {
+                usb_debug!("Failed to read CBW: {:?}", _err);
+                0
+            }
+        };
+
+        let cbw = &self.buf[..count];
+
+        // Only LUN 0 exists.
+        let valid = count == CBW_LENGTH
+            && read_u32(cbw, 0) == CBW_SIGNATURE
+            && cbw[13] == 0
+            && (1..=16).contains(&cbw[14]);
+
+        if !valid {
+            usb_debug!("Invalid CBW");
+            self.read_ep.stall();
+            self.write_ep.stall();
+            self.state = State::ResetRecovery;
+            return;
+        }
+
+        self.tag = read_u32(cbw, 4);
+        self.residue = read_u32(cbw, 8);
+        self.direction_in = cbw[12] & 0x80 != 0;
+        self.short_packet = false;
+        self.buf_len = 0;
+        self.buf_pos = 0;
+
+        let mut cb = [0u8; 16];
+        let cb_len = usize::from(cbw[14]);
+        cb[..cb_len].copy_from_slice(&cbw[15..15 + cb_len]);
+
+        // Sense data describes the last command, unless it is being requested.
+        if cb[0] != scsi_command::REQUEST_SENSE {
+            self.sense = Sense::NO_SENSE;
+        }
+
+        match self.execute(&cb) {
+            Ok(data) => self.start_data(data),
+            Err(sense) => {
+                usb_debug!("SCSI command {:x} failed", cb[0]);
+                self.sense = sense;
+                self.finish(STATUS_FAILED);
+            }
+        }
+    }
+
+    // Executes a SCSI command and gets its data stage.
+    fn execute(&mut self, cb: &[u8; 16]) -> core::result::Result<Data, Sense> {
+        match cb[0] {
+            scsi_command::TEST_UNIT_READY => {
+                self.check_ready()?;
+                Ok(Data::None)
+            }
+            scsi_command::REQUEST_SENSE => {
+                let sense = core::mem::replace(&mut self.sense, Sense::NO_SENSE);
+
+                let data = &mut self.buf[..18];
+                data.fill(0);
+                data[0] = 0x70; // Response code: current, fixed format
+                data[2] = sense.key;
+                data[7] = 10; // Additional sense length
+                data[12] = sense.asc;
+
+                Ok(Data::In(18.min(cb[4].into())))
+            }
+            scsi_command::INQUIRY => {
+                // Vital product data pages are not supported.
+                if cb[1] & 0x01 != 0 {
+                    return Err(Sense::INVALID_FIELD_IN_CDB);
+                }
+
+                self.buf[..INQUIRY_LENGTH].copy_from_slice(&self.inquiry);
+
+                let allocation_length = usize::from(read_u16_be(cb, 3));
+                Ok(Data::In(INQUIRY_LENGTH.min(allocation_length)))
+            }
+            scsi_command::MODE_SENSE_6 => {
+                // Only the header is returned, without block descriptors or mode pages.
+                let device_specific_parameter = self.device_specific_parameter();
+
+                let data = &mut self.buf[..4];
+                data[0] = 3; // Mode data length
+                data[1] = 0; // Medium type
+                data[2] = device_specific_parameter;
+                data[3] = 0; // Block descriptor length
+
+                Ok(Data::In(4.min(cb[4].into())))
+            }
+            scsi_command::MODE_SENSE_10 => {
+                let device_specific_parameter = self.device_specific_parameter();
+
+                let data = &mut self.buf[..8];
+                data.fill(0);
+                data[1] = 6; // Mode data length
+                data[3] = device_specific_parameter;
+
+                let allocation_length = usize::from(read_u16_be(cb, 7));
+                Ok(Data::In(8.min(allocation_length)))
+            }
+            scsi_command::PREVENT_ALLOW_MEDIUM_REMOVAL => {
+                self.prevent_removal = cb[4] & 0x01 != 0;
+                Ok(Data::None)
+            }
+            scsi_command::READ_CAPACITY_10 => {
+                self.check_ready()?;
+
+                // Larger media need READ CAPACITY(16).
+                let last_lba = self.device.block_count().saturating_sub(1).min(0xffff_ffff);
+
+                let data = &mut self.buf[..8];
+                data[0..4].copy_from_slice(&(last_lba as u32).to_be_bytes());
+                data[4..8].copy_from_slice(&(self.device.block_size() as u32).to_be_bytes());
+
+                Ok(Data::In(8))
+            }
+            scsi_command::SERVICE_ACTION_IN_16 if cb[1] & 0x1f == READ_CAPACITY_16 => {
+                self.check_ready()?;
+
+                let last_lba = self.device.block_count().saturating_sub(1);
+
+                let data = &mut self.buf[..32];
+                data.fill(0);
+                data[0..8].copy_from_slice(&last_lba.to_be_bytes());
+                data[8..12].copy_from_slice(&(self.device.block_size() as u32).to_be_bytes());
+
+                let allocation_length = read_u32_be(cb, 10) as usize;
+                Ok(Data::In(32.min(allocation_length)))
+            }
+            scsi_command::READ_10 => {
+                let (lba, count) = self.check_range(cb)?;
+                Ok(Data::Read { lba, count })
+            }
+            scsi_command::WRITE_10 => {
+                let (lba, count) = self.check_range(cb)?;
+
+                if self.device.is_write_protected() {
+                    return Err(Sense::WRITE_PROTECTED);
+                }
+
+                Ok(Data::Write { lba, count })
+            }
+            _ => Err(Sense::INVALID_COMMAND_OPERATION_CODE),
+        }
+    }
+
+    fn check_ready(&self) -> core::result::Result<(), Sense> {
+        if self.device.is_ready() {
+            Ok(())
+        } else {
+            Err(Sense::MEDIUM_NOT_PRESENT)
+        }
+    }
+
+    // Gets the blocks accessed by a READ(10) or WRITE(10) command.
+    fn check_range(&self, cb: &[u8; 16]) -> core::result::Result<(u64, u32), Sense> {
+        self.check_ready()?;
+
+        let lba = u64::from(read_u32_be(cb, 2));
+        let count = u32::from(read_u16_be(cb, 7));
+
+        if lba + u64::from(count) > self.device.block_count() {
+            return Err(Sense::LBA_OUT_OF_RANGE);
+        }
+
+        Ok((lba, count))
+    }
+
+    // The device-specific parameter of the mode parameter header, with the write-protect bit.
+    fn device_specific_parameter(&self) -> u8 {
+        if self.device.is_write_protected() {
+            0x80
+        } else {
+            0x00
+        }
+    }
+
+    // Starts the data stage after checking it against the direction and length announced in the
+    // CBW, following the thirteen cases of the Bulk-Only Transport.
+    fn start_data(&mut self, data: Data) {
+        let block_size = self.device.block_size() as u64;
+
+        let (direction_in, len) = match data {
+            Data::None | Data::In(0) => return self.finish(STATUS_PASSED),
+            Data::Read { count: 0, .. } | Data::Write { count: 0, .. } => {
+                return self.finish(STATUS_PASSED)
+            }
+            // Short responses are truncated to the length the host expects.
+            Data::In(len) => (true, (len as u64).min(self.residue.into())),
+            Data::Read { count, .. } => (true, u64::from(count) * block_size),
+            Data::Write { count, .. } => (false, u64::from(count) * block_size),
+        };
+
+        if self.residue == 0 || direction_in != self.direction_in || len > self.residue.into() {
+            usb_debug!("Phase error");
+            return self.finish(STATUS_PHASE_ERROR);
+        }
+
+        match data {
+            Data::In(_) => {
+                self.buf_len = len as usize;
+                self.blocks = 0;
+                self.state = State::DataIn;
+                self.flush_in();
+            }
+            Data::Read { lba, count } => {
+                self.lba = lba;
+                self.blocks = count;
+                self.state = State::DataIn;
+                self.flush_in();
+            }
+            Data::Write { lba, count } => {
+                self.lba = lba;
+                self.blocks = count;
+                self.state = State::DataOut;
+                self.fill_out();
+            }
+            Data::None => {}
+        }
+    }
+
+    // Sends the next packet of the data stage, reading the next block as needed.
+    fn flush_in(&mut self) {
+        if self.state != State::DataIn || self.in_flight {
+            return;
+        }
+
+        if self.buf_pos == self.buf_len {
+            if self.blocks == 0 {
+                return self.finish(STATUS_PASSED);
+            }
+
+            let block_size = self.device.block_size();
+            if let Err(err) = self
+                .device
+                .read_block(self.lba, &mut self.buf[..block_size])
+            {
+                usb_debug!("Failed to read block {}: {:?}", self.lba, err);
+                self.sense = Sense::from_read_error(err);
+                return self.finish(STATUS_FAILED);
+            }
+
+            self.lba += 1;
+            self.blocks -= 1;
+            self.buf_len = block_size;
+            self.buf_pos = 0;
+        }
+
+        let max_packet_size = usize::from(self.write_ep.max_packet_size());
+        let end = self.buf_len.min(self.buf_pos + max_packet_size);
+
+        match self.write_ep.write(&self.buf[self.buf_pos..end]) {
+            Ok(count) => {
+                self.buf_pos += count;
+                self.residue -= count as u32;
+                self.short_packet = count < max_packet_size;
+                self.in_flight = true;
+            }
+            Err(UsbError::WouldBlock) => {}
+            Err(_err) => {
+                usb_debug!("Failed to send data: {:?}", _err);
+            }
+        }
+    }
+
+    // Receives the next packet of the data stage, writing each block once it is complete.
+    fn fill_out(&mut self) {
+        if self.state != State::DataOut || !self.out_pending {
+            return;
+        }
+
+        let block_size = self.device.block_size();
+        let max_packet_size = usize::from(self.read_ep.max_packet_size());
+        let end = block_size.min(self.buf_len + max_packet_size);
+
+        let count = match self.read_ep.read(&mut self.buf[self.buf_len..end]) {
+            Ok(count) => count,
+            Err(UsbError::WouldBlock) => return,
+            Err(_err) => {
+                usb_debug!("Failed to receive data: {:?}", _err);
+                self.out_pending = false;
+                return self.finish(STATUS_PHASE_ERROR);
+            }
+        };
+
+        self.out_pending = false;
+        self.buf_len += count;
+        self.residue = self.residue.saturating_sub(count as u32);
+
+        if self.buf_len == block_size {
+            if let Err(err) = self.device.write_block(self.lba, &self.buf[..block_size]) {
+                usb_debug!("Failed to write block {}: {:?}", self.lba, err);
+                self.sense = Sense::from_write_error(err);
+                return self.finish(STATUS_FAILED);
+            }
+
+            self.lba += 1;
+            self.blocks -= 1;
+            self.buf_len = 0;
+
+            if self.blocks == 0 {
+                self.finish(STATUS_PASSED);
+            }
+        } else if count < max_packet_size {
+            // The host ended the data stage before all blocks were sent.
+            self.finish(STATUS_PHASE_ERROR);
+        }
+    }
+
+    // Ends the data stage and sends the CSW. Data the host expects but was not transferred is
+    // refused by halting the endpoint, except when a short packet already ended the data sent to
+    // the host.
+    fn finish(&mut self, status: u8) {
+        self.status = status;
+
+        if self.residue > 0 {
+            if self.direction_in {
+                if !self.short_packet {
+                    self.write_ep.stall();
+                    self.state = State::StatusStalled;
+                    return;
+                }
+            } else {
+                self.read_ep.stall();
+            }
+        }
+
+        self.state = State::Status;
+        self.send_status();
+    }
+
+    fn send_status(&mut self) {
+        if self.state != State::Status || self.in_flight {
+            return;
+        }
+
+        let mut csw = [0u8; CSW_LENGTH];
+        csw[0..4].copy_from_slice(&CSW_SIGNATURE.to_le_bytes()); // dCSWSignature
+        csw[4..8].copy_from_slice(&self.tag.to_le_bytes()); // dCSWTag
+        csw[8..12].copy_from_slice(&self.residue.to_le_bytes()); // dCSWDataResidue
+        csw[12] = self.status; // bCSWStatus
+
+        if self.write_ep.write(&csw).is_ok() {
+            self.in_flight = true;
+        }
+    }
+
+    fn reset_state(&mut self) {
+        self.state = State::Command;
+        self.out_pending = false;
+        self.in_flight = false;
+        self.short_packet = false;
+        self.residue = 0;
+        self.buf_len = 0;
+        self.buf_pos = 0;
+        self.blocks = 0;
+    }
+
+    fn is_for_interface(&self, req: &control::Request) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == u8::from(self.interface).into()
+    }
+}
+
+impl<B: UsbBus, D: BlockDevice> UsbClass<B> for MscClass<'_, B, D> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(
+            self.interface,
+            INTERFACE_CLASS_MSC,
+            SUBCLASS_SCSI,
+            PROTOCOL_BULK_ONLY,
+        )?;
+        writer.endpoint(&self.write_ep)?;
+        writer.endpoint(&self.read_ep)?;
+
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.reset_state();
+        self.sense = Sense::NO_SENSE;
+        self.prevent_removal = false;
+    }
+
+    fn poll(&mut self) {
+        match self.state {
+            State::ResetRecovery => {
+                // Clearing the halts doesn't end the reset recovery, so halt them again.
+                if !self.read_ep.is_stalled() {
+                    self.read_ep.stall();
+                }
+                if !self.write_ep.is_stalled() {
+                    self.write_ep.stall();
+                }
+            }
+            State::StatusStalled => {
+                if !self.write_ep.is_stalled() {
+                    self.state = State::Status;
+                    self.send_status();
+                }
+            }
+            State::Status => self.send_status(),
+            State::DataIn => self.flush_in(),
+            State::Command | State::DataOut => {}
+        }
+    }
+
+    fn endpoint_out(&mut self, addr: EndpointAddress) {
+        if addr != self.read_ep.address() {
+            return;
+        }
+
+        match self.state {
+            State::Command => self.read_command(),
+            State::DataOut => {
+                self.out_pending = true;
+                self.fill_out();
+            }
+            // Leave the packet in the endpoint until the CSW has been sent.
+            _ => self.out_pending = true,
+        }
+    }
+
+    fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+        if addr != self.write_ep.address() {
+            return;
+        }
+
+        self.in_flight = false;
+
+        match self.state {
+            State::DataIn => self.flush_in(),
+            State::Status => {
+                self.state = State::Command;
+
+                if core::mem::replace(&mut self.out_pending, false) {
+                    self.read_command();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+
+        if !self.is_for_interface(&req) {
+            return;
+        }
+
+        match req.request {
+            msc_request::GET_MAX_LUN if req.value == 0 && req.length == 1 => {
+                xfer.accept_with(&[0]).ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+
+        if !self.is_for_interface(&req) {
+            return;
+        }
+
+        match req.request {
+            msc_request::BULK_ONLY_MASS_STORAGE_RESET if req.value == 0 && req.length == 0 => {
+                // The host clears the endpoint halts itself after the reset.
+                self.reset_state();
+                xfer.accept().ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+fn read_u16_be(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn read_u32_be(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
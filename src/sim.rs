@@ -0,0 +1,806 @@
+use crate::bus::{PollResult, TestMode, UsbBus, UsbSpeed};
+use crate::control::{Recipient, RequestType};
+use crate::endpoint::{EndpointAddress, EndpointType};
+use crate::{Result, UsbDirection, UsbError};
+use core::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::vec::Vec;
+
+/// USB 2.0 Chapter 9 conformance checks that can be run against any device.
+///
+/// ```ignore
+/// let report = conformance::run(&host, || {
+///     usb_dev.poll(&mut [&mut my_class]);
+/// });
+///
+/// assert!(report.is_compliant(), "{}", report);
+/// ```
+pub mod conformance;
+
+// Maximum number of endpoints in one direction. Specified by the USB specification.
+const MAX_ENDPOINTS: usize = 16;
+
+// Number of times a host transfer helper polls the device while the device keeps NAKing before
+// giving up.
+const MAX_RETRIES: usize = 100;
+
+/// Handshake returned by the device in response to a SETUP or OUT token.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Handshake {
+    /// The device accepted the packet.
+    Ack,
+    /// The device was not ready to accept the packet. The host should try again later.
+    Nak,
+    /// The endpoint is halted or the request is not supported.
+    Stall,
+    /// The device rejected an LPM transaction.
+    Nyet,
+}
+
+/// Response of the device to an IN token.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum InResponse {
+    /// The device sent a data packet. The packet may be empty.
+    Data(Vec<u8>),
+    /// The device had no data to send.
+    Nak,
+    /// The endpoint is halted or the request is not supported.
+    Stall,
+}
+
+/// Error returned by the transfer helpers of [`SimHost`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum TransferError {
+    /// The device stalled the transfer.
+    Stall,
+    /// The device kept NAKing the transfer.
+    Timeout,
+    /// The device sent more data than was requested.
+    Overflow,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum BusEvent {
+    Reset,
+    Suspend,
+    Resume,
+    Sleep { besl: u8, remote_wakeup: bool },
+    Sof(u16),
+}
+
+#[derive(Default)]
+struct EndpointState {
+    ep_type: Option<EndpointType>,
+    max_packet_size: u16,
+    stalled: bool,
+    fifo: VecDeque<Vec<u8>>,
+}
+
+impl EndpointState {
+    fn is_allocated(&self) -> bool {
+        self.ep_type.is_some()
+    }
+}
+
+struct BusState {
+    enabled: bool,
+    address: u8,
+    fifo_depth: usize,
+    low_power: bool,
+    suspended: bool,
+    sleeping: bool,
+    lpm_accepted: bool,
+    max_speed: UsbSpeed,
+    host_speed: UsbSpeed,
+    speed: UsbSpeed,
+    test_mode: Option<TestMode>,
+    remote_wakeups: usize,
+    frame_number: Option<u16>,
+    ep_out: [EndpointState; MAX_ENDPOINTS],
+    ep_in: [EndpointState; MAX_ENDPOINTS],
+    setup: Option<[u8; 8]>,
+    ep_in_complete: u16,
+    events: VecDeque<BusEvent>,
+}
+
+impl BusState {
+    fn endpoint(&self, ep_addr: EndpointAddress) -> Option<&EndpointState> {
+        let eps = match ep_addr.direction() {
+            UsbDirection::Out => &self.ep_out,
+            UsbDirection::In => &self.ep_in,
+        };
+
+        eps.get(ep_addr.index()).filter(|ep| ep.is_allocated())
+    }
+
+    fn endpoint_mut(&mut self, ep_addr: EndpointAddress) -> Option<&mut EndpointState> {
+        let eps = match ep_addr.direction() {
+            UsbDirection::Out => &mut self.ep_out,
+            UsbDirection::In => &mut self.ep_in,
+        };
+
+        eps.get_mut(ep_addr.index()).filter(|ep| ep.is_allocated())
+    }
+
+    fn clear_endpoints(&mut self) {
+        for ep in self.ep_out.iter_mut().chain(self.ep_in.iter_mut()) {
+            ep.stalled = false;
+            ep.fifo.clear();
+        }
+
+        self.setup = None;
+        self.ep_in_complete = 0;
+    }
+
+    fn wake(&mut self) {
+        if self.suspended || self.sleeping {
+            self.suspended = false;
+            self.sleeping = false;
+            self.events.push_back(BusEvent::Resume);
+        }
+    }
+}
+
+/// A fully functional [`UsbBus`] implementation that lives in memory.
+///
+/// Each endpoint has a packet FIFO, a STALL flag and the bus keeps track of the device address.
+/// The other side of the bus is driven by a [`SimHost`] obtained with [`SimUsbBus::host`] before
+/// the bus is moved into a [`UsbBusAllocator`](crate::bus::UsbBusAllocator).
+pub struct SimUsbBus {
+    state: Rc<RefCell<BusState>>,
+}
+
+impl SimUsbBus {
+    /// Creates a new `SimUsbBus` where each endpoint can buffer a single packet, like most
+    /// hardware does.
+    pub fn new() -> Self {
+        Self::with_fifo_depth(1)
+    }
+
+    /// Creates a new `SimUsbBus` where each endpoint can buffer up to `depth` packets before
+    /// NAKing the host or returning `WouldBlock` to the device.
+    pub fn with_fifo_depth(depth: usize) -> Self {
+        SimUsbBus {
+            state: Rc::new(RefCell::new(BusState {
+                enabled: false,
+                address: 0,
+                fifo_depth: depth.max(1),
+                low_power: false,
+                suspended: false,
+                sleeping: false,
+                lpm_accepted: false,
+                max_speed: UsbSpeed::Full,
+                host_speed: UsbSpeed::High,
+                speed: UsbSpeed::Full,
+                test_mode: None,
+                remote_wakeups: 0,
+                frame_number: None,
+                ep_out: Default::default(),
+                ep_in: Default::default(),
+                setup: None,
+                ep_in_complete: 0,
+                events: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Sets the highest speed the simulated peripheral can operate at, which is returned by
+    /// [`UsbBus::max_speed`]. The speed is negotiated with the host on every bus reset.
+    ///
+    /// Default: [`UsbSpeed::Full`]
+    pub fn set_max_speed(&self, speed: UsbSpeed) {
+        self.state.borrow_mut().max_speed = speed;
+    }
+
+    /// Gets a handle for driving the host side of the bus.
+    pub fn host(&self) -> SimHost {
+        SimHost {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl Default for SimUsbBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UsbBus for SimUsbBus {
+    fn alloc_ep(
+        &mut self,
+        ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        _interval: u8,
+    ) -> Result<EndpointAddress> {
+        let mut state = self.state.borrow_mut();
+
+        let eps = match ep_dir {
+            UsbDirection::Out => &mut state.ep_out,
+            UsbDirection::In => &mut state.ep_in,
+        };
+
+        let index = match ep_addr {
+            Some(addr) => {
+                let index = addr.index();
+                if index >= MAX_ENDPOINTS || eps[index].is_allocated() {
+                    return Err(UsbError::InvalidEndpoint);
+                }
+
+                index
+            }
+            None => (1..MAX_ENDPOINTS)
+                .find(|&i| !eps[i].is_allocated())
+                .ok_or(UsbError::EndpointOverflow)?,
+        };
+
+        let ep = &mut eps[index];
+        ep.ep_type = Some(ep_type);
+        ep.max_packet_size = max_packet_size;
+
+        Ok(EndpointAddress::from_parts(index, ep_dir))
+    }
+
+    fn enable(&mut self) {
+        self.state.borrow_mut().enabled = true;
+    }
+
+    fn reset(&self) {
+        let mut state = self.state.borrow_mut();
+        state.clear_endpoints();
+        state.address = 0;
+    }
+
+    fn set_device_address(&self, addr: u8) {
+        self.state.borrow_mut().address = addr;
+    }
+
+    fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> Result<usize> {
+        let mut state = self.state.borrow_mut();
+        let depth = state.fifo_depth;
+
+        let ep = state
+            .endpoint_mut(ep_addr)
+            .filter(|_| ep_addr.is_in())
+            .ok_or(UsbError::InvalidEndpoint)?;
+
+        if buf.len() > ep.max_packet_size as usize {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        if ep.fifo.len() >= depth {
+            return Err(UsbError::WouldBlock);
+        }
+
+        ep.fifo.push_back(buf.to_vec());
+
+        Ok(buf.len())
+    }
+
+    fn read(&self, ep_addr: EndpointAddress, buf: &mut [u8]) -> Result<usize> {
+        let mut state = self.state.borrow_mut();
+
+        if ep_addr.is_out() && ep_addr.index() == 0 {
+            if let Some(setup) = state.setup.take() {
+                if buf.len() < setup.len() {
+                    return Err(UsbError::BufferOverflow);
+                }
+
+                buf[..setup.len()].copy_from_slice(&setup);
+                return Ok(setup.len());
+            }
+        }
+
+        let ep = state
+            .endpoint_mut(ep_addr)
+            .filter(|_| ep_addr.is_out())
+            .ok_or(UsbError::InvalidEndpoint)?;
+
+        let packet = ep.fifo.pop_front().ok_or(UsbError::WouldBlock)?;
+
+        // Like most hardware, an overflowing packet is dropped.
+        if packet.len() > buf.len() {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        buf[..packet.len()].copy_from_slice(&packet);
+
+        Ok(packet.len())
+    }
+
+    fn set_stalled(&self, ep_addr: EndpointAddress, stalled: bool) {
+        if let Some(ep) = self.state.borrow_mut().endpoint_mut(ep_addr) {
+            ep.stalled = stalled;
+        }
+    }
+
+    fn is_stalled(&self, ep_addr: EndpointAddress) -> bool {
+        self.state
+            .borrow()
+            .endpoint(ep_addr)
+            .is_some_and(|ep| ep.stalled)
+    }
+
+    fn suspend(&self) {
+        self.state.borrow_mut().low_power = true;
+    }
+
+    fn resume(&self) {
+        self.state.borrow_mut().low_power = false;
+    }
+
+    fn speed(&self) -> UsbSpeed {
+        self.state.borrow().speed
+    }
+
+    fn max_speed(&self) -> UsbSpeed {
+        self.state.borrow().max_speed
+    }
+
+    fn remote_wakeup(&self) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        if !state.suspended && !state.sleeping {
+            return Err(UsbError::InvalidState);
+        }
+
+        // The host answers the resume signalling by resuming the bus.
+        state.remote_wakeups += 1;
+        state.wake();
+        Ok(())
+    }
+
+    fn frame_number(&self) -> Result<u16> {
+        self.state
+            .borrow()
+            .frame_number
+            .ok_or(UsbError::InvalidState)
+    }
+
+    fn set_lpm_accepted(&self, accepted: bool) -> Result<()> {
+        self.state.borrow_mut().lpm_accepted = accepted;
+        Ok(())
+    }
+
+    fn set_test_mode(&self, mode: TestMode) -> Result<()> {
+        self.state.borrow_mut().test_mode = Some(mode);
+        Ok(())
+    }
+
+    fn poll(&self) -> PollResult {
+        let mut state = self.state.borrow_mut();
+
+        let mut sof = None;
+
+        match state.events.pop_front() {
+            Some(BusEvent::Reset) => return PollResult::Reset,
+            Some(BusEvent::Suspend) => return PollResult::Suspend,
+            Some(BusEvent::Resume) => return PollResult::Resume,
+            Some(BusEvent::Sleep {
+                besl,
+                remote_wakeup,
+            }) => {
+                return PollResult::Sleep {
+                    besl,
+                    remote_wakeup,
+                }
+            }
+            // Reported together with any pending packets.
+            Some(BusEvent::Sof(frame_number)) => sof = Some(frame_number),
+            None => {}
+        }
+
+        if state.suspended || state.sleeping {
+            return PollResult::None;
+        }
+
+        let mut ep_out = 0;
+        for (i, ep) in state.ep_out.iter().enumerate() {
+            if !ep.fifo.is_empty() {
+                ep_out |= 1 << i;
+            }
+        }
+
+        let ep_setup = if state.setup.is_some() { 1 } else { 0 };
+        let ep_in_complete = core::mem::take(&mut state.ep_in_complete);
+
+        if (ep_out | ep_in_complete | ep_setup) == 0 && sof.is_none() {
+            return PollResult::None;
+        }
+
+        PollResult::Data {
+            ep_out,
+            ep_in_complete,
+            ep_setup,
+            sof,
+        }
+    }
+}
+
+/// Builds a `bmRequestType` value for a control request.
+pub fn request_type(
+    direction: UsbDirection,
+    request_type: RequestType,
+    recipient: Recipient,
+) -> u8 {
+    (direction as u8) | ((request_type as u8) << 5) | (recipient as u8)
+}
+
+/// The host side of a [`SimUsbBus`].
+///
+/// The token methods (`setup`, `out` and `in_token`) operate on single packets and return the
+/// response of the device immediately, without ever polling the device. The transfer helpers
+/// (`control_in`, `control_out`, `transfer_out` and `transfer_in`) perform complete transfers and
+/// call the supplied `poll` function, usually a closure that calls
+/// [`UsbDevice::poll`](crate::device::UsbDevice::poll), whenever the device has to make progress.
+#[derive(Clone)]
+pub struct SimHost {
+    state: Rc<RefCell<BusState>>,
+}
+
+impl SimHost {
+    /// Signals a USB bus reset. The device returns to the default address, and the bus speed is
+    /// negotiated as the highest speed supported by both the host and the device.
+    pub fn reset(&self) {
+        let mut state = self.state.borrow_mut();
+        state.clear_endpoints();
+        state.address = 0;
+        state.speed = state.max_speed.min(state.host_speed);
+        state.suspended = false;
+        state.sleeping = false;
+        state.events.push_back(BusEvent::Reset);
+    }
+
+    /// Stops bus activity, causing the device to detect a suspend condition.
+    pub fn suspend(&self) {
+        let mut state = self.state.borrow_mut();
+        if !state.suspended {
+            state.suspended = true;
+            state.events.push_back(BusEvent::Suspend);
+        }
+    }
+
+    /// Resumes bus activity after [`suspend`](SimHost::suspend) or [`lpm`](SimHost::lpm). Sending
+    /// any token also resumes the bus.
+    pub fn resume(&self) {
+        self.state.borrow_mut().wake();
+    }
+
+    /// Sends an LPM transaction that asks the device to enter the L1 sleep state. The device
+    /// answers with [`Ack`](Handshake::Ack) and the bus goes to sleep if it accepts LPM, and with
+    /// [`Nyet`](Handshake::Nyet) otherwise. Use [`resume`](SimHost::resume) or send any token to
+    /// wake the bus up again.
+    pub fn lpm(&self, besl: u8, remote_wakeup: bool) -> Handshake {
+        let mut state = self.state.borrow_mut();
+        state.wake();
+
+        if !state.lpm_accepted {
+            return Handshake::Nyet;
+        }
+
+        state.sleeping = true;
+        state.events.push_back(BusEvent::Sleep {
+            besl,
+            remote_wakeup,
+        });
+
+        Handshake::Ack
+    }
+
+    /// Gets whether the bus is in the L1 sleep state.
+    pub fn is_sleeping(&self) -> bool {
+        self.state.borrow().sleeping
+    }
+
+    /// Sets the highest speed the host port supports, which takes effect on the next
+    /// [`reset`](SimHost::reset).
+    ///
+    /// Default: [`UsbSpeed::High`]
+    pub fn set_speed(&self, speed: UsbSpeed) {
+        self.state.borrow_mut().host_speed = speed;
+    }
+
+    /// Gets the bus speed negotiated during the last reset.
+    pub fn speed(&self) -> UsbSpeed {
+        self.state.borrow().speed
+    }
+
+    /// Sends a start-of-frame packet with the next frame number, and returns the frame number.
+    /// Frame numbers start at 0 and wrap around after 2047.
+    pub fn sof(&self) -> u16 {
+        let mut state = self.state.borrow_mut();
+        state.wake();
+
+        let frame_number = state.frame_number.map_or(0, |n| (n + 1) & 0x7ff);
+        state.frame_number = Some(frame_number);
+        state.events.push_back(BusEvent::Sof(frame_number));
+
+        frame_number
+    }
+
+    /// Gets the number of times the device has woken up the host with remote wakeup signalling.
+    pub fn remote_wakeups(&self) -> usize {
+        self.state.borrow().remote_wakeups
+    }
+
+    /// Gets the test mode the device has put the peripheral into, if any.
+    pub fn test_mode(&self) -> Option<TestMode> {
+        self.state.borrow().test_mode
+    }
+
+    /// Gets whether the device has been enabled by building a `UsbDevice`.
+    pub fn is_enabled(&self) -> bool {
+        self.state.borrow().enabled
+    }
+
+    /// Gets whether the device has put the peripheral into low-power suspend mode.
+    pub fn is_device_suspended(&self) -> bool {
+        self.state.borrow().low_power
+    }
+
+    /// Gets the current address of the device.
+    pub fn address(&self) -> u8 {
+        self.state.borrow().address
+    }
+
+    /// Gets whether the STALL condition is set for an endpoint.
+    pub fn is_stalled(&self, ep_addr: EndpointAddress) -> bool {
+        self.state
+            .borrow()
+            .endpoint(ep_addr)
+            .is_some_and(|ep| ep.stalled)
+    }
+
+    /// Gets the maximum packet size of an endpoint, or `None` if the device has not allocated it.
+    pub fn max_packet_size(&self, ep_addr: EndpointAddress) -> Option<u16> {
+        self.state
+            .borrow()
+            .endpoint(ep_addr)
+            .map(|ep| ep.max_packet_size)
+    }
+
+    /// Sends a SETUP token with the 8 byte `packet` to endpoint 0. SETUP packets are always
+    /// accepted, and they clear the STALL condition and any pending IN data of endpoint 0.
+    pub fn setup(&self, packet: [u8; 8]) -> Handshake {
+        let mut state = self.state.borrow_mut();
+        state.wake();
+
+        state.setup = Some(packet);
+        state.ep_out[0].fifo.clear();
+        state.ep_out[0].stalled = false;
+        state.ep_in[0].fifo.clear();
+        state.ep_in[0].stalled = false;
+        state.ep_in_complete &= !1;
+
+        Handshake::Ack
+    }
+
+    /// Sends an OUT token followed by `data` to the endpoint with index `ep`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the device has not allocated the endpoint or if `data` is longer than its maximum
+    /// packet size.
+    pub fn out(&self, ep: usize, data: &[u8]) -> Handshake {
+        let mut state = self.state.borrow_mut();
+        state.wake();
+
+        let depth = state.fifo_depth;
+        let ep = state
+            .endpoint_mut(EndpointAddress::from_parts(ep, UsbDirection::Out))
+            .expect("OUT token to an unallocated endpoint");
+
+        assert!(
+            data.len() <= ep.max_packet_size as usize,
+            "OUT packet longer than the maximum packet size"
+        );
+
+        if ep.stalled {
+            Handshake::Stall
+        } else if ep.fifo.len() >= depth {
+            Handshake::Nak
+        } else {
+            ep.fifo.push_back(data.to_vec());
+            Handshake::Ack
+        }
+    }
+
+    /// Sends an IN token to the endpoint with index `ep`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the device has not allocated the endpoint.
+    pub fn in_token(&self, ep: usize) -> InResponse {
+        let mut state = self.state.borrow_mut();
+        state.wake();
+
+        let index = ep;
+        let ep = state
+            .endpoint_mut(EndpointAddress::from_parts(index, UsbDirection::In))
+            .expect("IN token to an unallocated endpoint");
+
+        if ep.stalled {
+            return InResponse::Stall;
+        }
+
+        match ep.fifo.pop_front() {
+            Some(packet) => {
+                state.ep_in_complete |= 1 << index;
+                InResponse::Data(packet)
+            }
+            None => InResponse::Nak,
+        }
+    }
+
+    /// Performs a control IN transfer on endpoint 0 and returns the data sent by the device.
+    pub fn control_in(
+        &self,
+        mut poll: impl FnMut(),
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+    ) -> core::result::Result<Vec<u8>, TransferError> {
+        let packet = setup_packet(request_type | 0x80, request, value, index, length);
+        self.setup(packet);
+        poll();
+
+        let data = self.read_packets(&mut poll, 0, length as usize)?;
+
+        self.write_packet(&mut poll, 0, &[])?;
+        poll();
+
+        Ok(data)
+    }
+
+    /// Performs a control OUT transfer on endpoint 0 with `data` in the DATA stage.
+    pub fn control_out(
+        &self,
+        mut poll: impl FnMut(),
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &[u8],
+    ) -> core::result::Result<(), TransferError> {
+        let packet = setup_packet(
+            request_type & !0x80,
+            request,
+            value,
+            index,
+            data.len() as u16,
+        );
+        self.setup(packet);
+        poll();
+
+        let max_packet_size = self.ep0_max_packet_size();
+        for chunk in data.chunks(max_packet_size) {
+            self.write_packet(&mut poll, 0, chunk)?;
+            poll();
+        }
+
+        let status = self.read_packet(&mut poll, 0)?;
+        poll();
+
+        if !status.is_empty() {
+            return Err(TransferError::Overflow);
+        }
+
+        Ok(())
+    }
+
+    /// Writes `data` to the OUT endpoint with index `ep`, split into packets of the maximum packet
+    /// size. A zero-length packet is appended if the data is a non-zero multiple of the maximum
+    /// packet size.
+    pub fn transfer_out(
+        &self,
+        mut poll: impl FnMut(),
+        ep: usize,
+        data: &[u8],
+    ) -> core::result::Result<(), TransferError> {
+        let max_packet_size =
+            self.max_packet_size(EndpointAddress::from_parts(ep, UsbDirection::Out))
+                .expect("OUT transfer to an unallocated endpoint") as usize;
+
+        for chunk in data.chunks(max_packet_size) {
+            self.write_packet(&mut poll, ep, chunk)?;
+            poll();
+        }
+
+        if data.len() % max_packet_size == 0 {
+            self.write_packet(&mut poll, ep, &[])?;
+            poll();
+        }
+
+        Ok(())
+    }
+
+    /// Reads up to `length` bytes from the IN endpoint with index `ep`. The transfer ends with a
+    /// short packet or when `length` bytes have been read.
+    pub fn transfer_in(
+        &self,
+        mut poll: impl FnMut(),
+        ep: usize,
+        length: usize,
+    ) -> core::result::Result<Vec<u8>, TransferError> {
+        self.read_packets(&mut poll, ep, length)
+    }
+
+    fn ep0_max_packet_size(&self) -> usize {
+        self.max_packet_size(EndpointAddress::from_parts(0, UsbDirection::In))
+            .expect("control endpoint not allocated") as usize
+    }
+
+    fn write_packet(
+        &self,
+        poll: &mut impl FnMut(),
+        ep: usize,
+        data: &[u8],
+    ) -> core::result::Result<(), TransferError> {
+        for _ in 0..MAX_RETRIES {
+            match self.out(ep, data) {
+                Handshake::Ack | Handshake::Nyet => return Ok(()),
+                Handshake::Nak => poll(),
+                Handshake::Stall => return Err(TransferError::Stall),
+            }
+        }
+
+        Err(TransferError::Timeout)
+    }
+
+    fn read_packet(
+        &self,
+        poll: &mut impl FnMut(),
+        ep: usize,
+    ) -> core::result::Result<Vec<u8>, TransferError> {
+        for _ in 0..MAX_RETRIES {
+            match self.in_token(ep) {
+                InResponse::Data(data) => return Ok(data),
+                InResponse::Nak => poll(),
+                InResponse::Stall => return Err(TransferError::Stall),
+            }
+        }
+
+        Err(TransferError::Timeout)
+    }
+
+    fn read_packets(
+        &self,
+        poll: &mut impl FnMut(),
+        ep: usize,
+        length: usize,
+    ) -> core::result::Result<Vec<u8>, TransferError> {
+        let max_packet_size =
+            self.max_packet_size(EndpointAddress::from_parts(ep, UsbDirection::In))
+                .expect("IN transfer from an unallocated endpoint") as usize;
+
+        let mut data = Vec::new();
+
+        loop {
+            let packet = self.read_packet(poll, ep)?;
+            data.extend_from_slice(&packet);
+            poll();
+
+            if data.len() > length {
+                return Err(TransferError::Overflow);
+            }
+
+            if packet.len() < max_packet_size || data.len() == length {
+                return Ok(data);
+            }
+        }
+    }
+}
+
+fn setup_packet(request_type: u8, request: u8, value: u16, index: u16, length: u16) -> [u8; 8] {
+    let mut packet = [0; 8];
+    packet[0] = request_type;
+    packet[1] = request;
+    packet[2..4].copy_from_slice(&value.to_le_bytes());
+    packet[4..6].copy_from_slice(&index.to_le_bytes());
+    packet[6..8].copy_from_slice(&length.to_le_bytes());
+    packet
+}
@@ -0,0 +1,1121 @@
+use super::{request_type, SimHost, TransferError};
+use crate::control::{Recipient, Request, RequestType};
+use crate::descriptor::descriptor_type;
+use crate::descriptor::parser::{parse, validate_configuration, Descriptor};
+use crate::endpoint::EndpointAddress;
+use crate::UsbDirection;
+use core::fmt;
+use std::format;
+use std::string::String;
+use std::vec::Vec;
+
+// Address assigned to the device with SET_ADDRESS.
+const DEVICE_ADDRESS: u8 = 0x2a;
+
+// A descriptor type that is reserved by the USB specification.
+const RESERVED_DESCRIPTOR_TYPE: u8 = 0x3f;
+
+// Request codes that are reserved by the USB specification.
+const RESERVED_REQUESTS: [u8; 2] = [2, 4];
+
+/// A single deviation from USB 2.0 Chapter 9 found by [`run`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Deviation {
+    /// Name of the check that failed.
+    pub check: &'static str,
+    /// Description of what went wrong.
+    pub message: String,
+}
+
+/// Result of a conformance run.
+#[derive(Clone, Default, Debug)]
+pub struct Report {
+    /// Number of checks that were performed.
+    pub checks: usize,
+    /// Every deviation that was found, in the order they were found.
+    pub deviations: Vec<Deviation>,
+}
+
+impl Report {
+    /// Returns `true` if no deviations were found.
+    pub fn is_compliant(&self) -> bool {
+        self.deviations.is_empty()
+    }
+
+    fn check(&mut self, check: &'static str, ok: bool, message: impl FnOnce() -> String) -> bool {
+        self.checks += 1;
+
+        if !ok {
+            self.deviations.push(Deviation {
+                check,
+                message: message(),
+            });
+        }
+
+        ok
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} checks, {} deviations",
+            self.checks,
+            self.deviations.len()
+        )?;
+
+        for deviation in &self.deviations {
+            writeln!(f, "  {}: {}", deviation.check, deviation.message)?;
+        }
+
+        Ok(())
+    }
+}
+
+struct Configuration {
+    value: u8,
+    attributes: u8,
+    string: u8,
+    interfaces: Vec<Interface>,
+}
+
+struct Interface {
+    number: u8,
+    alternate_setting: u8,
+    string: u8,
+    endpoints: Vec<Endpoint>,
+}
+
+struct Endpoint {
+    address: u8,
+    attributes: u8,
+}
+
+impl Endpoint {
+    fn is_isochronous(&self) -> bool {
+        (self.attributes & 0b11) == 0b01
+    }
+}
+
+struct Runner<'h, P: FnMut()> {
+    host: &'h SimHost,
+    poll: P,
+    report: Report,
+}
+
+/// Drives a device through the USB 2.0 Chapter 9 enumeration sequence and reports every deviation
+/// from the specification.
+///
+/// The device must be connected to `host` and must not have been enumerated yet. `poll` is called
+/// whenever the device has to make progress, and should poll the
+/// [`UsbDevice`](crate::device::UsbDevice) with the classes under test.
+///
+/// The sequence consists of a bus reset, GET_DESCRIPTOR(DEVICE) with a `wLength` of 64,
+/// SET_ADDRESS and fetching every configuration, string and device qualifier descriptor. Then
+/// each configuration is selected in turn with SET_CONFIGURATION, followed by GET_STATUS on every
+/// recipient, SET_FEATURE and CLEAR_FEATURE(ENDPOINT_HALT) on every non-isochronous endpoint, and
+/// GET_INTERFACE and SET_INTERFACE on every alternate setting. This is followed by negative tests
+/// which check that invalid requests are stalled and that the control pipe recovers from the
+/// stall. Invalid addresses are tried in the Default state, requests for interfaces and endpoints
+/// that don't exist in the Configured state, and requests for interfaces and endpoints other than
+/// endpoint 0 in the Addressed state. The device is left configured in its first configuration.
+pub fn run(host: &SimHost, poll: impl FnMut()) -> Report {
+    let mut runner = Runner {
+        host,
+        poll,
+        report: Report::default(),
+    };
+
+    runner.run();
+    runner.report
+}
+
+impl<P: FnMut()> Runner<'_, P> {
+    fn run(&mut self) {
+        self.host.reset();
+        (self.poll)();
+
+        let Some(device) = self.device_descriptor() else {
+            return;
+        };
+
+        if !self.set_address() {
+            return;
+        }
+
+        let full_device = self.get(
+            "GET_DESCRIPTOR(DEVICE)",
+            Recipient::Device,
+            Request::GET_DESCRIPTOR,
+            (descriptor_type::DEVICE as u16) << 8,
+            0,
+            18,
+        );
+        if let Some(full_device) = full_device {
+            self.report
+                .check("GET_DESCRIPTOR(DEVICE)", full_device == device, || {
+                    String::from("device descriptor changed after SET_ADDRESS")
+                });
+        }
+
+        let num_configurations = device[17];
+        if !self
+            .report
+            .check("GET_DESCRIPTOR(DEVICE)", num_configurations > 0, || {
+                String::from("bNumConfigurations is 0")
+            })
+        {
+            return;
+        }
+
+        let mut configurations = Vec::new();
+        for index in 0..num_configurations {
+            let Some(configuration) = self.configuration_descriptor(index) else {
+                return;
+            };
+
+            configurations.push(configuration);
+        }
+
+        let mut values: Vec<u8> = configurations.iter().map(|c| c.value).collect();
+        values.sort_unstable();
+        values.dedup();
+        self.report.check(
+            "GET_DESCRIPTOR(CONFIGURATION)",
+            values.len() == configurations.len() && values[0] != 0,
+            || {
+                format!(
+                    "bConfigurationValues {:?} are not unique and non-zero",
+                    values
+                )
+            },
+        );
+
+        self.string_descriptors(&device, &configurations);
+        self.device_qualifier(&device, &configurations);
+
+        // Go through the configurations in reverse so that the first one is active for the
+        // negative tests.
+        for configuration in configurations.iter().rev() {
+            self.set_configuration(configuration.value);
+            self.get_status(configuration.attributes, &configuration.interfaces);
+            self.endpoint_halt(&configuration.interfaces);
+            self.interfaces(&configuration.interfaces);
+        }
+
+        self.negative_tests(&configurations);
+    }
+
+    fn control_in(
+        &mut self,
+        recipient: Recipient,
+        request: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+    ) -> Result<Vec<u8>, TransferError> {
+        self.host.control_in(
+            &mut self.poll,
+            request_type(UsbDirection::In, RequestType::Standard, recipient),
+            request,
+            value,
+            index,
+            length,
+        )
+    }
+
+    fn control_out(
+        &mut self,
+        recipient: Recipient,
+        request: u8,
+        value: u16,
+        index: u16,
+    ) -> Result<(), TransferError> {
+        self.host.control_out(
+            &mut self.poll,
+            request_type(UsbDirection::Out, RequestType::Standard, recipient),
+            request,
+            value,
+            index,
+            &[],
+        )
+    }
+
+    fn get(
+        &mut self,
+        check: &'static str,
+        recipient: Recipient,
+        request: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+    ) -> Option<Vec<u8>> {
+        match self.control_in(recipient, request, value, index, length) {
+            Ok(data) => Some(data),
+            Err(err) => {
+                self.report
+                    .check(check, false, || format!("request failed: {:?}", err));
+                None
+            }
+        }
+    }
+
+    fn set(
+        &mut self,
+        check: &'static str,
+        recipient: Recipient,
+        request: u8,
+        value: u16,
+        index: u16,
+    ) -> bool {
+        let res = self.control_out(recipient, request, value, index);
+
+        self.report.check(check, res.is_ok(), || {
+            format!("request failed: {:?}", res.unwrap_err())
+        })
+    }
+
+    fn expect_stall_in(
+        &mut self,
+        check: &'static str,
+        recipient: Recipient,
+        request: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+    ) {
+        let res = self.control_in(recipient, request, value, index, length);
+
+        self.report
+            .check(check, res == Err(TransferError::Stall), || {
+                format!("expected STALL, got {:?}", res)
+            });
+    }
+
+    fn expect_stall_out(
+        &mut self,
+        check: &'static str,
+        recipient: Recipient,
+        request: u8,
+        value: u16,
+        index: u16,
+    ) {
+        let res = self.control_out(recipient, request, value, index);
+
+        self.report
+            .check(check, res == Err(TransferError::Stall), || {
+                format!("expected STALL, got {:?}", res)
+            });
+    }
+
+    fn expect_len(&mut self, check: &'static str, data: &[u8], len: usize) -> bool {
+        self.report.check(check, data.len() == len, || {
+            format!("expected {} bytes, got {}", len, data.len())
+        })
+    }
+
+    fn device_descriptor(&mut self) -> Option<Vec<u8>> {
+        const CHECK: &str = "GET_DESCRIPTOR(DEVICE, 64)";
+
+        let desc = self.get(
+            CHECK,
+            Recipient::Device,
+            Request::GET_DESCRIPTOR,
+            (descriptor_type::DEVICE as u16) << 8,
+            0,
+            64,
+        )?;
+
+        if !self.expect_len(CHECK, &desc, 18) {
+            return None;
+        }
+
+        self.report
+            .check(CHECK, desc[0] == 18, || format!("bLength is {}", desc[0]));
+        self.report
+            .check(CHECK, desc[1] == descriptor_type::DEVICE, || {
+                format!("bDescriptorType is {}", desc[1])
+            });
+
+        let bcd_usb = u16::from_le_bytes([desc[2], desc[3]]);
+        self.report.check(
+            CHECK,
+            matches!(bcd_usb, 0x0100 | 0x0110 | 0x0200 | 0x0201 | 0x0210),
+            || format!("bcdUSB {:#06x} is not a valid USB 2.0 revision", bcd_usb),
+        );
+
+        self.report
+            .check(CHECK, matches!(desc[7], 8 | 16 | 32 | 64), || {
+                format!("bMaxPacketSize0 {} is invalid", desc[7])
+            });
+
+        self.report.check(CHECK, desc[17] >= 1, || {
+            String::from("bNumConfigurations is zero")
+        });
+
+        let short = self.get(
+            "GET_DESCRIPTOR(DEVICE, 8)",
+            Recipient::Device,
+            Request::GET_DESCRIPTOR,
+            (descriptor_type::DEVICE as u16) << 8,
+            0,
+            8,
+        );
+        if let Some(short) = short {
+            self.report
+                .check("GET_DESCRIPTOR(DEVICE, 8)", short[..] == desc[..8], || {
+                    String::from("short read does not match the descriptor prefix")
+                });
+        }
+
+        self.host.reset();
+        (self.poll)();
+
+        Some(desc)
+    }
+
+    fn set_address(&mut self) -> bool {
+        const CHECK: &str = "SET_ADDRESS";
+
+        // Addresses are 7 bits wide.
+        self.expect_stall_out(
+            "SET_ADDRESS(invalid)",
+            Recipient::Device,
+            Request::SET_ADDRESS,
+            128,
+            0,
+        );
+
+        // The device stays in the Default state.
+        if self.set(
+            "SET_ADDRESS(0)",
+            Recipient::Device,
+            Request::SET_ADDRESS,
+            0,
+            0,
+        ) {
+            let address = self.host.address();
+            self.report.check("SET_ADDRESS(0)", address == 0, || {
+                format!("device address is {} instead of 0", address)
+            });
+        }
+
+        if !self.set(
+            CHECK,
+            Recipient::Device,
+            Request::SET_ADDRESS,
+            DEVICE_ADDRESS.into(),
+            0,
+        ) {
+            return false;
+        }
+
+        let address = self.host.address();
+        if !self.report.check(CHECK, address == DEVICE_ADDRESS, || {
+            format!(
+                "device address is {} instead of {}",
+                address, DEVICE_ADDRESS
+            )
+        }) {
+            return false;
+        }
+
+        if let Some(config) = self.get(
+            "GET_CONFIGURATION(Addressed)",
+            Recipient::Device,
+            Request::GET_CONFIGURATION,
+            0,
+            0,
+            1,
+        ) {
+            self.report
+                .check("GET_CONFIGURATION(Addressed)", config == [0], || {
+                    format!("expected [0], got {:?}", config)
+                });
+        }
+
+        true
+    }
+
+    fn configuration_descriptor(&mut self, index: u8) -> Option<Configuration> {
+        const CHECK: &str = "GET_DESCRIPTOR(CONFIGURATION)";
+
+        let header = self.get(
+            CHECK,
+            Recipient::Device,
+            Request::GET_DESCRIPTOR,
+            ((descriptor_type::CONFIGURATION as u16) << 8) | index as u16,
+            0,
+            9,
+        )?;
+
+        if !self.expect_len(CHECK, &header, 9) {
+            return None;
+        }
+
+        self.report.check(CHECK, header[0] == 9, || {
+            format!("bLength is {}", header[0])
+        });
+        self.report
+            .check(CHECK, header[1] == descriptor_type::CONFIGURATION, || {
+                format!("bDescriptorType is {}", header[1])
+            });
+        self.report.check(CHECK, (header[7] & 0x80) != 0, || {
+            String::from("bmAttributes bit 7 must be set")
+        });
+
+        let total_length = u16::from_le_bytes([header[2], header[3]]);
+
+        let desc = self.get(
+            CHECK,
+            Recipient::Device,
+            Request::GET_DESCRIPTOR,
+            ((descriptor_type::CONFIGURATION as u16) << 8) | index as u16,
+            0,
+            total_length,
+        )?;
+
+        if !self.expect_len(CHECK, &desc, total_length as usize) {
+            return None;
+        }
+
+        // String indices are checked separately with the string descriptors.
+        let res = validate_configuration(&desc, |_| true);
+        self.report.check(CHECK, res.is_ok(), || {
+            format!("invalid configuration: {:?}", res.unwrap_err())
+        });
+
+        let mut interfaces: Vec<Interface> = Vec::new();
+
+        for descriptor in parse(&desc).flatten() {
+            match descriptor {
+                Descriptor::Interface(iface) => interfaces.push(Interface {
+                    number: iface.interface_number,
+                    alternate_setting: iface.alternate_setting,
+                    string: iface.interface_string,
+                    endpoints: Vec::new(),
+                }),
+                Descriptor::Endpoint(ep) => {
+                    if let Some(iface) = interfaces.last_mut() {
+                        iface.endpoints.push(Endpoint {
+                            address: ep.address.into(),
+                            attributes: ep.attributes,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Some(Configuration {
+            value: header[5],
+            attributes: header[7],
+            string: header[6],
+            interfaces,
+        })
+    }
+
+    fn string_descriptors(&mut self, device: &[u8], configurations: &[Configuration]) {
+        const CHECK: &str = "GET_DESCRIPTOR(STRING)";
+
+        let mut indices: Vec<u8> = [device[14], device[15], device[16]]
+            .iter()
+            .copied()
+            .chain(configurations.iter().flat_map(|c| {
+                core::iter::once(c.string).chain(c.interfaces.iter().map(|i| i.string))
+            }))
+            .filter(|&i| i != 0)
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+
+        if indices.is_empty() {
+            return;
+        }
+
+        let Some(langs) = self.get(
+            CHECK,
+            Recipient::Device,
+            Request::GET_DESCRIPTOR,
+            (descriptor_type::STRING as u16) << 8,
+            0,
+            255,
+        ) else {
+            return;
+        };
+
+        if !self.report.check(
+            CHECK,
+            langs.len() >= 4 && langs.len() % 2 == 0 && langs[0] as usize == langs.len(),
+            || String::from("language ID table is malformed or empty"),
+        ) {
+            return;
+        }
+
+        let lang_id = u16::from_le_bytes([langs[2], langs[3]]);
+
+        for index in indices {
+            let Some(string) = self.get(
+                CHECK,
+                Recipient::Device,
+                Request::GET_DESCRIPTOR,
+                ((descriptor_type::STRING as u16) << 8) | index as u16,
+                lang_id,
+                255,
+            ) else {
+                continue;
+            };
+
+            self.report.check(
+                CHECK,
+                string.len() >= 2
+                    && string.len() % 2 == 0
+                    && string[0] as usize == string.len()
+                    && string[1] == descriptor_type::STRING,
+                || format!("string descriptor {} is malformed", index),
+            );
+        }
+    }
+
+    fn device_qualifier(&mut self, device: &[u8], configurations: &[Configuration]) {
+        const CHECK: &str = "GET_DESCRIPTOR(DEVICE_QUALIFIER)";
+        const OTHER_SPEED: &str = "GET_DESCRIPTOR(OTHER_SPEED_CONFIGURATION)";
+
+        let qualifier = match self.control_in(
+            Recipient::Device,
+            Request::GET_DESCRIPTOR,
+            (descriptor_type::DEVICE_QUALIFIER as u16) << 8,
+            0,
+            10,
+        ) {
+            Ok(qualifier) => qualifier,
+            // Full speed only devices must stall the request.
+            Err(TransferError::Stall) => {
+                self.expect_stall_in(
+                    OTHER_SPEED,
+                    Recipient::Device,
+                    Request::GET_DESCRIPTOR,
+                    (descriptor_type::OTHER_SPEED_CONFIGURATION as u16) << 8,
+                    0,
+                    64,
+                );
+                return;
+            }
+            Err(err) => {
+                self.report
+                    .check(CHECK, false, || format!("request failed: {:?}", err));
+                return;
+            }
+        };
+
+        if !self.expect_len(CHECK, &qualifier, 10) {
+            return;
+        }
+
+        self.report.check(
+            CHECK,
+            qualifier[0] == 10 && qualifier[1] == descriptor_type::DEVICE_QUALIFIER,
+            || format!("invalid header {:?}", &qualifier[..2]),
+        );
+        self.report.check(
+            CHECK,
+            qualifier[2..7] == device[2..7] && qualifier[8] == device[17],
+            || String::from("fields differ from the device descriptor"),
+        );
+
+        for (index, configuration) in configurations.iter().enumerate() {
+            let Some(other) = self.get(
+                OTHER_SPEED,
+                Recipient::Device,
+                Request::GET_DESCRIPTOR,
+                ((descriptor_type::OTHER_SPEED_CONFIGURATION as u16) << 8) | index as u16,
+                0,
+                9,
+            ) else {
+                continue;
+            };
+
+            if !self.expect_len(OTHER_SPEED, &other, 9) {
+                continue;
+            }
+
+            self.report.check(
+                OTHER_SPEED,
+                other[1] == descriptor_type::OTHER_SPEED_CONFIGURATION
+                    && other[5] == configuration.value,
+                || format!("configuration {} is described inconsistently", index),
+            );
+        }
+    }
+
+    fn set_configuration(&mut self, configuration_value: u8) {
+        if !self.set(
+            "SET_CONFIGURATION",
+            Recipient::Device,
+            Request::SET_CONFIGURATION,
+            configuration_value.into(),
+            0,
+        ) {
+            return;
+        }
+
+        if let Some(config) = self.get(
+            "GET_CONFIGURATION",
+            Recipient::Device,
+            Request::GET_CONFIGURATION,
+            0,
+            0,
+            1,
+        ) {
+            self.report
+                .check("GET_CONFIGURATION", config == [configuration_value], || {
+                    format!("expected [{}], got {:?}", configuration_value, config)
+                });
+        }
+    }
+
+    fn get_status(&mut self, attributes: u8, interfaces: &[Interface]) {
+        if let Some(status) = self.get(
+            "GET_STATUS(Device)",
+            Recipient::Device,
+            Request::GET_STATUS,
+            0,
+            0,
+            2,
+        ) {
+            if self.expect_len("GET_STATUS(Device)", &status, 2) {
+                self.report.check(
+                    "GET_STATUS(Device)",
+                    (status[0] & !0b11) == 0 && status[1] == 0,
+                    || format!("reserved bits set in {:?}", status),
+                );
+                self.report.check(
+                    "GET_STATUS(Device)",
+                    (status[0] & 0b01) == 0 || (attributes & 0x40) != 0,
+                    || String::from("self powered but bmAttributes says bus powered"),
+                );
+                self.report
+                    .check("GET_STATUS(Device)", (status[0] & 0b10) == 0, || {
+                        String::from("remote wakeup enabled without SET_FEATURE")
+                    });
+            }
+        }
+
+        let mut numbers: Vec<u8> = interfaces.iter().map(|i| i.number).collect();
+        numbers.dedup();
+
+        for number in numbers {
+            if let Some(status) = self.get(
+                "GET_STATUS(Interface)",
+                Recipient::Interface,
+                Request::GET_STATUS,
+                0,
+                number.into(),
+                2,
+            ) {
+                self.report
+                    .check("GET_STATUS(Interface)", status == [0, 0], || {
+                        format!("interface {} returned {:?}", number, status)
+                    });
+            }
+        }
+
+        let endpoints = interfaces
+            .iter()
+            .filter(|i| i.alternate_setting == 0)
+            .flat_map(|i| i.endpoints.iter().map(|ep| ep.address))
+            .chain([0x00, 0x80].iter().copied())
+            .collect::<Vec<_>>();
+
+        for address in endpoints {
+            if let Some(status) = self.get(
+                "GET_STATUS(Endpoint)",
+                Recipient::Endpoint,
+                Request::GET_STATUS,
+                0,
+                address.into(),
+                2,
+            ) {
+                self.report
+                    .check("GET_STATUS(Endpoint)", status == [0, 0], || {
+                        format!("endpoint {:#04x} returned {:?}", address, status)
+                    });
+            }
+        }
+    }
+
+    fn endpoint_status(&mut self, check: &'static str, address: u8) -> Option<bool> {
+        let status = self.get(
+            check,
+            Recipient::Endpoint,
+            Request::GET_STATUS,
+            0,
+            address.into(),
+            2,
+        )?;
+
+        if !self.expect_len(check, &status, 2) {
+            return None;
+        }
+
+        Some((status[0] & 1) != 0)
+    }
+
+    fn endpoint_halt(&mut self, interfaces: &[Interface]) {
+        const SET: &str = "SET_FEATURE(ENDPOINT_HALT)";
+        const CLEAR: &str = "CLEAR_FEATURE(ENDPOINT_HALT)";
+
+        let endpoints = interfaces
+            .iter()
+            .filter(|i| i.alternate_setting == 0)
+            .flat_map(|i| i.endpoints.iter())
+            .filter(|ep| !ep.is_isochronous())
+            .map(|ep| ep.address)
+            .collect::<Vec<_>>();
+
+        for address in endpoints {
+            if !self.set(
+                SET,
+                Recipient::Endpoint,
+                Request::SET_FEATURE,
+                Request::FEATURE_ENDPOINT_HALT,
+                address.into(),
+            ) {
+                continue;
+            }
+
+            if let Some(halted) = self.endpoint_status(SET, address) {
+                self.report.check(SET, halted, || {
+                    format!("endpoint {:#04x} is not halted", address)
+                });
+            }
+
+            let ep_addr = EndpointAddress::from(address);
+            let stalled = if self.host.max_packet_size(ep_addr).is_none() {
+                false
+            } else if ep_addr.is_in() {
+                self.host.in_token(ep_addr.index()) == super::InResponse::Stall
+            } else {
+                self.host.out(ep_addr.index(), &[]) == super::Handshake::Stall
+            };
+
+            self.report.check(SET, stalled, || {
+                format!("halted endpoint {:#04x} does not STALL tokens", address)
+            });
+
+            if !self.set(
+                CLEAR,
+                Recipient::Endpoint,
+                Request::CLEAR_FEATURE,
+                Request::FEATURE_ENDPOINT_HALT,
+                address.into(),
+            ) {
+                continue;
+            }
+
+            if let Some(halted) = self.endpoint_status(CLEAR, address) {
+                self.report.check(CLEAR, !halted, || {
+                    format!("endpoint {:#04x} is still halted", address)
+                });
+            }
+        }
+    }
+
+    fn get_interface(&mut self, check: &'static str, number: u8) -> Option<u8> {
+        let alt = self.get(
+            check,
+            Recipient::Interface,
+            Request::GET_INTERFACE,
+            0,
+            number.into(),
+            1,
+        )?;
+
+        if !self.expect_len(check, &alt, 1) {
+            return None;
+        }
+
+        Some(alt[0])
+    }
+
+    fn interfaces(&mut self, interfaces: &[Interface]) {
+        const GET: &str = "GET_INTERFACE";
+        const SET: &str = "SET_INTERFACE";
+
+        for iface in interfaces.iter().filter(|i| i.alternate_setting == 0) {
+            if let Some(alt) = self.get_interface(GET, iface.number) {
+                self.report.check(GET, alt == 0, || {
+                    format!("interface {} is in alternate setting {}", iface.number, alt)
+                });
+            }
+        }
+
+        for iface in interfaces.iter().filter(|i| i.alternate_setting != 0) {
+            if !self.set(
+                SET,
+                Recipient::Interface,
+                Request::SET_INTERFACE,
+                iface.alternate_setting.into(),
+                iface.number.into(),
+            ) {
+                continue;
+            }
+
+            if let Some(alt) = self.get_interface(GET, iface.number) {
+                self.report.check(GET, alt == iface.alternate_setting, || {
+                    format!(
+                        "interface {} is in alternate setting {} instead of {}",
+                        iface.number, alt, iface.alternate_setting
+                    )
+                });
+            }
+
+            self.set(
+                SET,
+                Recipient::Interface,
+                Request::SET_INTERFACE,
+                0,
+                iface.number.into(),
+            );
+        }
+
+        for iface in interfaces.iter().filter(|i| i.alternate_setting == 0) {
+            let max_alt = interfaces
+                .iter()
+                .filter(|i| i.number == iface.number)
+                .map(|i| i.alternate_setting)
+                .max()
+                .unwrap_or(0);
+
+            if max_alt < u8::MAX {
+                self.expect_stall_out(
+                    "SET_INTERFACE(invalid alternate setting)",
+                    Recipient::Interface,
+                    Request::SET_INTERFACE,
+                    u16::from(max_alt) + 1,
+                    iface.number.into(),
+                );
+            }
+        }
+    }
+
+    fn negative_tests(&mut self, configurations: &[Configuration]) {
+        let configuration_value = configurations[0].value;
+
+        self.expect_stall_in(
+            "GET_DESCRIPTOR(reserved type)",
+            Recipient::Device,
+            Request::GET_DESCRIPTOR,
+            (RESERVED_DESCRIPTOR_TYPE as u16) << 8,
+            0,
+            64,
+        );
+
+        self.expect_stall_in(
+            "GET_DESCRIPTOR(invalid configuration index)",
+            Recipient::Device,
+            Request::GET_DESCRIPTOR,
+            ((descriptor_type::CONFIGURATION as u16) << 8) | configurations.len() as u16,
+            0,
+            64,
+        );
+
+        // SYNCH_FRAME is only defined for isochronous endpoints.
+        let endpoints = configurations[0]
+            .interfaces
+            .iter()
+            .flat_map(|interface| interface.endpoints.iter())
+            .filter(|endpoint| !endpoint.is_isochronous())
+            .map(|endpoint| endpoint.address);
+        for address in core::iter::once(0).chain(endpoints).collect::<Vec<_>>() {
+            self.expect_stall_in(
+                "SYNCH_FRAME(non-isochronous endpoint)",
+                Recipient::Endpoint,
+                Request::SYNCH_FRAME,
+                0,
+                address.into(),
+                2,
+            );
+        }
+
+        for &request in RESERVED_REQUESTS.iter() {
+            self.expect_stall_in(
+                "reserved request (IN)",
+                Recipient::Device,
+                request,
+                0,
+                0,
+                64,
+            );
+            self.expect_stall_out("reserved request (OUT)", Recipient::Device, request, 0, 0);
+        }
+
+        self.expect_stall_out(
+            "SET_CONFIGURATION(invalid)",
+            Recipient::Device,
+            Request::SET_CONFIGURATION,
+            (1..=u8::MAX)
+                .find(|&value| configurations.iter().all(|c| c.value != value))
+                .map_or(0, u16::from),
+            0,
+        );
+
+        // The device must still respond normally after a STALL.
+        if let Some(config) = self.get(
+            "recovery after STALL",
+            Recipient::Device,
+            Request::GET_CONFIGURATION,
+            0,
+            0,
+            1,
+        ) {
+            self.report.check(
+                "recovery after STALL",
+                config == [configuration_value],
+                || format!("expected [{}], got {:?}", configuration_value, config),
+            );
+        }
+
+        self.missing_recipients(configurations);
+
+        if self.set(
+            "SET_CONFIGURATION(0)",
+            Recipient::Device,
+            Request::SET_CONFIGURATION,
+            0,
+            0,
+        ) {
+            if let Some(config) = self.get(
+                "SET_CONFIGURATION(0)",
+                Recipient::Device,
+                Request::GET_CONFIGURATION,
+                0,
+                0,
+                1,
+            ) {
+                self.report
+                    .check("SET_CONFIGURATION(0)", config == [0], || {
+                        format!("device still reports configuration {:?}", config)
+                    });
+            }
+
+            self.addressed_state(&configurations[0]);
+        }
+
+        self.set_configuration(configuration_value);
+    }
+
+    // Checks that requests for interfaces and endpoints that are not part of the active
+    // configuration are stalled.
+    fn missing_recipients(&mut self, configurations: &[Configuration]) {
+        let interface = configurations[0]
+            .interfaces
+            .iter()
+            .map(|i| u16::from(i.number) + 1)
+            .max()
+            .unwrap_or(0);
+
+        self.expect_stall_in(
+            "GET_STATUS(missing interface)",
+            Recipient::Interface,
+            Request::GET_STATUS,
+            0,
+            interface,
+            2,
+        );
+        self.expect_stall_in(
+            "GET_INTERFACE(missing interface)",
+            Recipient::Interface,
+            Request::GET_INTERFACE,
+            0,
+            interface,
+            1,
+        );
+        self.expect_stall_out(
+            "CLEAR_FEATURE(missing interface)",
+            Recipient::Interface,
+            Request::CLEAR_FEATURE,
+            0,
+            interface,
+        );
+
+        let used = |address: u8| {
+            configurations
+                .iter()
+                .flat_map(|c| c.interfaces.iter())
+                .any(|i| i.endpoints.iter().any(|ep| ep.address == address))
+        };
+
+        let Some(endpoint) = (1..16u8)
+            .flat_map(|index| [index, index | 0x80])
+            .find(|&address| !used(address))
+        else {
+            return;
+        };
+
+        self.expect_stall_in(
+            "GET_STATUS(missing endpoint)",
+            Recipient::Endpoint,
+            Request::GET_STATUS,
+            0,
+            endpoint.into(),
+            2,
+        );
+        self.expect_stall_out(
+            "CLEAR_FEATURE(missing endpoint)",
+            Recipient::Endpoint,
+            Request::CLEAR_FEATURE,
+            Request::FEATURE_ENDPOINT_HALT,
+            endpoint.into(),
+        );
+    }
+
+    // Checks that interfaces and endpoints other than endpoint 0 can't be addressed in the
+    // Addressed state.
+    fn addressed_state(&mut self, configuration: &Configuration) {
+        if let Some(iface) = configuration.interfaces.first() {
+            self.expect_stall_in(
+                "GET_STATUS(Interface, Addressed)",
+                Recipient::Interface,
+                Request::GET_STATUS,
+                0,
+                iface.number.into(),
+                2,
+            );
+            self.expect_stall_in(
+                "GET_INTERFACE(Addressed)",
+                Recipient::Interface,
+                Request::GET_INTERFACE,
+                0,
+                iface.number.into(),
+                1,
+            );
+        }
+
+        let endpoint = configuration
+            .interfaces
+            .iter()
+            .flat_map(|i| i.endpoints.iter())
+            .next()
+            .map(|ep| ep.address);
+
+        if let Some(address) = endpoint {
+            self.expect_stall_in(
+                "GET_STATUS(Endpoint, Addressed)",
+                Recipient::Endpoint,
+                Request::GET_STATUS,
+                0,
+                address.into(),
+                2,
+            );
+            self.expect_stall_out(
+                "CLEAR_FEATURE(ENDPOINT_HALT, Addressed)",
+                Recipient::Endpoint,
+                Request::CLEAR_FEATURE,
+                Request::FEATURE_ENDPOINT_HALT,
+                address.into(),
+            );
+        }
+    }
+}
@@ -5,6 +5,7 @@ use crate::device::{StringDescriptors, UsbDevice, UsbDeviceBuilder, UsbVidPid};
 use crate::Result;
 use core::cell::UnsafeCell;
 use core::cmp;
+use core::ptr;
 
 #[cfg(feature = "test-class-high-speed")]
 mod sizes {
@@ -12,6 +13,8 @@ mod sizes {
     pub const CONTROL_ENDPOINT: u8 = 64;
     pub const BULK_ENDPOINT: u16 = 512;
     pub const INTERRUPT_ENDPOINT: u16 = 1024;
+    pub const FULL_SPEED_BULK_ENDPOINT: u16 = 64;
+    pub const FULL_SPEED_INTERRUPT_ENDPOINT: u16 = 64;
 }
 
 #[cfg(not(feature = "test-class-high-speed"))]
@@ -20,6 +23,8 @@ mod sizes {
     pub const CONTROL_ENDPOINT: u8 = 8;
     pub const BULK_ENDPOINT: u16 = 64;
     pub const INTERRUPT_ENDPOINT: u16 = 31;
+    pub const FULL_SPEED_BULK_ENDPOINT: u16 = BULK_ENDPOINT;
+    pub const FULL_SPEED_INTERRUPT_ENDPOINT: u16 = INTERRUPT_ENDPOINT;
 }
 
 static mut CONTROL_BUFFER: UnsafeCell<[u8; 256]> = UnsafeCell::new([0; 256]);
@@ -40,6 +45,7 @@ pub struct TestClass<'a, B: UsbBus> {
     interrupt_buf: [u8; sizes::BUFFER],
     len: usize,
     i: usize,
+    alt_setting: u8,
     bench: bool,
     expect_bulk_in_complete: bool,
     expect_bulk_out: bool,
@@ -86,6 +92,7 @@ impl<B: UsbBus> TestClass<'_, B> {
             interrupt_buf: [0; sizes::BUFFER],
             len: 0,
             i: 0,
+            alt_setting: 0,
             bench: false,
             expect_bulk_in_complete: false,
             expect_bulk_out: false,
@@ -115,7 +122,7 @@ impl<B: UsbBus> TestClass<'_, B> {
         usb_bus: &'a UsbBusAllocator<B>,
     ) -> UsbDeviceBuilder<'a, B> {
         UsbDeviceBuilder::new(usb_bus, UsbVidPid(VID, PID), unsafe {
-            CONTROL_BUFFER.get_mut()
+            (*ptr::addr_of_mut!(CONTROL_BUFFER)).get_mut()
         })
         .strings(&[StringDescriptors::default()
             .manufacturer(MANUFACTURER)
@@ -218,6 +225,7 @@ impl<B: UsbBus> UsbClass<B> for TestClass<'_, B> {
     fn reset(&mut self) {
         self.len = 0;
         self.i = 0;
+        self.alt_setting = 0;
         self.bench = false;
         self.expect_bulk_in_complete = false;
         self.expect_bulk_out = false;
@@ -226,11 +234,27 @@ impl<B: UsbBus> UsbClass<B> for TestClass<'_, B> {
     }
 
     fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        let (bulk, interrupt) = match writer.speed() {
+            UsbSpeed::High => (sizes::BULK_ENDPOINT, sizes::INTERRUPT_ENDPOINT),
+            _ => (
+                sizes::FULL_SPEED_BULK_ENDPOINT,
+                sizes::FULL_SPEED_INTERRUPT_ENDPOINT,
+            ),
+        };
+
         writer.interface(self.iface, 0xff, 0x00, 0x00)?;
-        writer.endpoint(&self.ep_bulk_in)?;
-        writer.endpoint(&self.ep_bulk_out)?;
-        writer.endpoint(&self.ep_interrupt_in)?;
-        writer.endpoint(&self.ep_interrupt_out)?;
+        writer.endpoint_sized(&self.ep_bulk_in, bulk, self.ep_bulk_in.interval())?;
+        writer.endpoint_sized(&self.ep_bulk_out, bulk, self.ep_bulk_out.interval())?;
+        writer.endpoint_sized(
+            &self.ep_interrupt_in,
+            interrupt,
+            self.ep_interrupt_in.interval(),
+        )?;
+        writer.endpoint_sized(
+            &self.ep_interrupt_out,
+            interrupt,
+            self.ep_interrupt_out.interval(),
+        )?;
         writer.interface_alt(self.iface, 1, 0xff, 0x01, 0x00, Some(self.interface_string))?;
         writer.endpoint(&self.ep_iso_in)?;
         Ok(())
@@ -248,6 +272,23 @@ impl<B: UsbBus> UsbClass<B> for TestClass<'_, B> {
         None
     }
 
+    fn get_alt_setting(&mut self, interface: InterfaceNumber) -> Option<u8> {
+        if interface == self.iface {
+            Some(self.alt_setting)
+        } else {
+            None
+        }
+    }
+
+    fn set_alt_setting(&mut self, interface: InterfaceNumber, alternative: u8) -> bool {
+        if interface == self.iface && alternative <= 1 {
+            self.alt_setting = alternative;
+            true
+        } else {
+            false
+        }
+    }
+
     fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
         if self.bench {
             return;
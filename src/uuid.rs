@@ -0,0 +1,128 @@
+/// Namespace of the UUIDs derived from a device's identity, {5ae4b1c2-6f0e-4b1a-9d8e-3c7f2a9d1e64}.
+pub(crate) const DEVICE_NAMESPACE: [u8; 16] = [
+    0x5a, 0xe4, 0xb1, 0xc2, 0x6f, 0x0e, 0x4b, 0x1a, 0x9d, 0x8e, 0x3c, 0x7f, 0x2a, 0x9d, 0x1e, 0x64,
+];
+
+/// Creates a version 5 UUID as specified in RFC 4122 from a namespace and a name that is the
+/// concatenation of `name`. The result is in the big-endian byte order of the RFC.
+pub(crate) fn v5(namespace: &[u8; 16], name: &[&[u8]]) -> [u8; 16] {
+    let mut sha1 = Sha1::new();
+    sha1.update(namespace);
+    for part in name {
+        sha1.update(part);
+    }
+
+    let digest = sha1.finish();
+
+    let mut uuid = [0u8; 16];
+    uuid.copy_from_slice(&digest[..16]);
+    uuid[6] = (uuid[6] & 0x0f) | 0x50; // version 5
+    uuid[8] = (uuid[8] & 0x3f) | 0x80; // RFC 4122 variant
+
+    uuid
+}
+
+/// Converts a UUID from the RFC 4122 byte order to the mixed-endian byte order of a Windows GUID,
+/// where the first three fields are little-endian.
+pub(crate) fn to_guid_bytes(uuid: [u8; 16]) -> [u8; 16] {
+    let mut guid = uuid;
+    guid[0..4].reverse();
+    guid[4..6].reverse();
+    guid[6..8].reverse();
+
+    guid
+}
+
+// A minimal SHA-1 implementation. SHA-1 is only used because version 5 UUIDs are defined with it,
+// not for any security purpose.
+struct Sha1 {
+    state: [u32; 5],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+impl Sha1 {
+    fn new() -> Self {
+        Sha1 {
+            state: [
+                0x6745_2301,
+                0xefcd_ab89,
+                0x98ba_dcfe,
+                0x1032_5476,
+                0xc3d2_e1f0,
+            ],
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.total_len += data.len() as u64;
+
+        for &byte in data {
+            self.block[self.block_len] = byte;
+            self.block_len += 1;
+
+            if self.block_len == self.block.len() {
+                self.process_block();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; 20] {
+        let bit_len = self.total_len * 8;
+
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bit_len.to_be_bytes());
+
+        let mut digest = [0u8; 20];
+        for (word, bytes) in self.state.iter().zip(digest.chunks_exact_mut(4)) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+
+        digest
+    }
+
+    fn process_block(&mut self) {
+        let mut w = [0u32; 80];
+        for (word, bytes) in w.iter_mut().zip(self.block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e].iter()) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use crate::bus::UsbBus;
+use crate::class::ControlIn;
+use crate::control::{Recipient, RequestType};
+use crate::descriptor::{capability_type, BosWriter};
+use crate::device_builder::BuilderError;
+use crate::{Result, UsbError};
+
+/// UUID of the WebUSB platform capability, {3408B638-09A9-47A0-8BFD-A0768815B665}, in the byte
+/// order used in the descriptor.
+pub const PLATFORM_CAPABILITY_UUID: [u8; 16] = [
+    0x38, 0xb6, 0x08, 0x34, 0xa9, 0x09, 0xa0, 0x47, 0x8b, 0xfd, 0xa0, 0x76, 0x88, 0x15, 0xb6, 0x65,
+];
+
+/// `wIndex` of the vendor request that retrieves a URL descriptor.
+pub const GET_URL: u16 = 2;
+
+/// Descriptor type of the URL descriptor.
+pub const URL_DESCRIPTOR_TYPE: u8 = 3;
+
+/// Index of the landing page URL descriptor.
+pub const LANDING_PAGE_INDEX: u8 = 1;
+
+/// URL scheme prefixes, as stored in the `bScheme` field of the URL descriptor.
+#[allow(missing_docs)]
+pub mod url_scheme {
+    pub const HTTP: u8 = 0;
+    pub const HTTPS: u8 = 1;
+    /// The URL includes the scheme.
+    pub const NONE: u8 = 255;
+}
+
+const BCD_VERSION: u16 = 0x0100;
+
+// bLength, bDescriptorType and bScheme take 3 bytes of the 255 byte descriptor.
+const MAX_URL_LENGTH: usize = 255 - 3;
+
+/// WebUSB support served by [`UsbDevice`](crate::device::UsbDevice) itself, see
+/// [`UsbDeviceBuilder::webusb`](crate::device::UsbDeviceBuilder::webusb).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WebUsb<'a> {
+    vendor_code: u8,
+    landing_page: Option<(u8, &'a str)>,
+}
+
+impl<'a> WebUsb<'a> {
+    /// Creates WebUSB support without a landing page.
+    ///
+    /// `vendor_code` is the `bRequest` of the vendor request used to retrieve URL descriptors.
+    /// Choose a value that is not used by any other vendor request of the device.
+    pub fn new(vendor_code: u8) -> Self {
+        WebUsb {
+            vendor_code,
+            landing_page: None,
+        }
+    }
+
+    /// Specify the landing page that browsers suggest visiting when the device is connected, for
+    /// example `"https://example.com/device"`. `http://` and `https://` URLs are stored without
+    /// their scheme prefix.
+    ///
+    /// Returns [`BuilderError::UrlTooLong`] if the URL doesn't fit in a URL descriptor.
+    pub fn landing_page(mut self, url: &'a str) -> core::result::Result<Self, BuilderError> {
+        let (scheme, url) = if let Some(url) = url.strip_prefix("https://") {
+            (url_scheme::HTTPS, url)
+        } else if let Some(url) = url.strip_prefix("http://") {
+            (url_scheme::HTTP, url)
+        } else {
+            (url_scheme::NONE, url)
+        };
+
+        if url.len() > MAX_URL_LENGTH {
+            return Err(BuilderError::UrlTooLong);
+        }
+
+        self.landing_page = Some((scheme, url));
+        Ok(self)
+    }
+
+    /// Gets the vendor request code used to retrieve URL descriptors.
+    pub fn vendor_code(&self) -> u8 {
+        self.vendor_code
+    }
+
+    pub(crate) fn get_bos_descriptors(&self, writer: &mut BosWriter) -> Result<()> {
+        let mut data = [0u8; 21];
+        data[1..17].copy_from_slice(&PLATFORM_CAPABILITY_UUID);
+        data[17..19].copy_from_slice(&BCD_VERSION.to_le_bytes()); // bcdVersion
+        data[19] = self.vendor_code; // bVendorCode
+        data[20] = match self.landing_page {
+            Some(_) => LANDING_PAGE_INDEX,
+            None => 0,
+        }; // iLandingPage
+
+        writer.capability(capability_type::PLATFORM, &data)
+    }
+
+    pub(crate) fn control_in<B: UsbBus>(&self, xfer: ControlIn<B>) -> Result<()> {
+        let req = *xfer.request();
+
+        if req.request_type != RequestType::Vendor
+            || req.recipient != Recipient::Device
+            || req.request != self.vendor_code
+            || req.index != GET_URL
+        {
+            return Ok(());
+        }
+
+        match self.landing_page {
+            Some((scheme, url)) if req.value == LANDING_PAGE_INDEX.into() => xfer.accept(|buf| {
+                let length = url.len() + 3;
+                if length > buf.len() {
+                    return Err(UsbError::BufferOverflow);
+                }
+
+                buf[0] = length as u8; // bLength
+                buf[1] = URL_DESCRIPTOR_TYPE; // bDescriptorType
+                buf[2] = scheme; // bScheme
+                buf[3..length].copy_from_slice(url.as_bytes()); // URL
+
+                Ok(length)
+            }),
+            _ => xfer.reject(),
+        }
+    }
+}
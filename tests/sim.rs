@@ -0,0 +1,4205 @@
+//! Runs TestClass against the simulated bus, without any hardware.
+
+use usb_device::class_prelude::*;
+use usb_device::control::Request;
+use usb_device::device::{UsbRev, CONFIGURATION_VALUE};
+use usb_device::prelude::*;
+use usb_device::sim::{conformance, Handshake, InResponse, SimHost, SimUsbBus, TransferError};
+use usb_device::test_class::{self, TestClass};
+use usb_device::{ms_os_10, ms_os_20};
+
+const DEVICE_OUT: u8 = 0x00;
+const DEVICE_IN: u8 = 0x80;
+const INTERFACE_OUT: u8 = 0x01;
+const INTERFACE_IN: u8 = 0x81;
+const ENDPOINT_OUT: u8 = 0x02;
+const ENDPOINT_IN: u8 = 0x82;
+const VENDOR_OUT: u8 = 0x40;
+const VENDOR_IN: u8 = 0xc0;
+const VENDOR_INTERFACE_IN: u8 = 0xc1;
+
+type Device<'a> = UsbDevice<'a, SimUsbBus>;
+type Class<'a> = TestClass<'a, SimUsbBus>;
+
+fn poll(dev: &mut Device, class: &mut Class) {
+    if dev.poll(&mut [class]) {
+        class.poll();
+    }
+}
+
+fn with_device(f: impl FnOnce(&SimHost, &mut Device, &mut Class)) {
+    with_configured_device(|builder| builder, f);
+}
+
+// Like `with_device`, with extra builder settings.
+fn with_configured_device(
+    configure: impl for<'a> FnOnce(UsbDeviceBuilder<'a, SimUsbBus>) -> UsbDeviceBuilder<'a, SimUsbBus>,
+    f: impl FnOnce(&SimHost, &mut Device, &mut Class),
+) {
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut class = TestClass::new(&alloc);
+    let mut control_buffer = [0u8; 256];
+    let builder = UsbDeviceBuilder::new(
+        &alloc,
+        UsbVidPid(test_class::VID, test_class::PID),
+        &mut control_buffer,
+    )
+    .strings(&[StringDescriptors::default()
+        .manufacturer(test_class::MANUFACTURER)
+        .product(test_class::PRODUCT)
+        .serial_number(test_class::SERIAL_NUMBER)])
+    .unwrap();
+    let mut dev = configure(builder).build().unwrap();
+
+    host.reset();
+    poll(&mut dev, &mut class);
+
+    f(&host, &mut dev, &mut class);
+}
+
+fn enumerate(host: &SimHost, dev: &mut Device, class: &mut Class) {
+    host.control_out(
+        || poll(dev, class),
+        DEVICE_OUT,
+        Request::SET_ADDRESS,
+        5,
+        0,
+        &[],
+    )
+    .expect("set address");
+    assert_eq!(host.address(), 5);
+    assert_eq!(dev.state(), UsbDeviceState::Addressed);
+
+    host.control_out(
+        || poll(dev, class),
+        DEVICE_OUT,
+        Request::SET_CONFIGURATION,
+        CONFIGURATION_VALUE.into(),
+        0,
+        &[],
+    )
+    .expect("set configuration");
+    assert_eq!(dev.state(), UsbDeviceState::Configured);
+}
+
+#[test]
+fn device_descriptor() {
+    with_device(|host, dev, class| {
+        let desc = host
+            .control_in(
+                || poll(dev, class),
+                DEVICE_IN,
+                Request::GET_DESCRIPTOR,
+                0x0100,
+                0,
+                64,
+            )
+            .expect("get device descriptor");
+
+        assert_eq!(desc.len(), 18);
+        assert_eq!(desc[1], 1);
+        assert_eq!(u16::from_le_bytes([desc[8], desc[9]]), test_class::VID);
+        assert_eq!(u16::from_le_bytes([desc[10], desc[11]]), test_class::PID);
+    });
+}
+
+#[test]
+fn string_descriptors() {
+    with_device(|host, dev, class| {
+        let product = host
+            .control_in(
+                || poll(dev, class),
+                DEVICE_IN,
+                Request::GET_DESCRIPTOR,
+                0x0302,
+                0x0409,
+                255,
+            )
+            .expect("get product string");
+
+        let product: Vec<u16> = product[2..]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        assert_eq!(String::from_utf16(&product).unwrap(), test_class::PRODUCT);
+    });
+}
+
+#[test]
+fn control_data() {
+    with_device(|host, dev, class| {
+        enumerate(host, dev, class);
+
+        for len in [0, 7, 8, 9, 15, 16, 17] {
+            let data: Vec<u8> = (0..len as u8).collect();
+
+            host.control_out(
+                || poll(dev, class),
+                VENDOR_OUT,
+                test_class::REQ_WRITE_BUFFER,
+                0,
+                0,
+                &data,
+            )
+            .expect("control write");
+
+            let response = host
+                .control_in(
+                    || poll(dev, class),
+                    VENDOR_IN,
+                    test_class::REQ_READ_BUFFER,
+                    0,
+                    0,
+                    len as u16,
+                )
+                .expect("control read");
+
+            assert_eq!(response, data);
+        }
+
+        let response = host
+            .control_in(
+                || poll(dev, class),
+                VENDOR_IN,
+                test_class::REQ_READ_LONG_DATA,
+                0,
+                0,
+                test_class::LONG_DATA.len() as u16,
+            )
+            .expect("control read static");
+        assert_eq!(response, test_class::LONG_DATA);
+    });
+}
+
+#[test]
+fn control_error() {
+    with_device(|host, dev, class| {
+        enumerate(host, dev, class);
+
+        let res = host.control_out(
+            || poll(dev, class),
+            VENDOR_OUT,
+            test_class::REQ_UNKNOWN,
+            0,
+            0,
+            &[],
+        );
+        assert_eq!(res, Err(TransferError::Stall));
+
+        // The control pipe must recover on the next SETUP.
+        host.control_in(|| poll(dev, class), DEVICE_IN, Request::GET_STATUS, 0, 0, 2)
+            .expect("get status after stall");
+    });
+}
+
+#[test]
+fn bulk_loopback() {
+    with_device(|host, dev, class| {
+        enumerate(host, dev, class);
+
+        for len in [1, 2, 32, 63, 64, 65, 127, 128, 129] {
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+
+            host.transfer_out(|| poll(dev, class), 1, &data)
+                .expect("bulk write");
+
+            let response = host
+                .transfer_in(|| poll(dev, class), 1, len)
+                .expect("bulk read");
+
+            assert_eq!(response, data, "bulk loopback len {}", len);
+        }
+    });
+}
+
+#[test]
+fn endpoint_halt() {
+    with_device(|host, dev, class| {
+        enumerate(host, dev, class);
+
+        host.control_out(
+            || poll(dev, class),
+            ENDPOINT_OUT,
+            Request::SET_FEATURE,
+            Request::FEATURE_ENDPOINT_HALT,
+            0x81,
+            &[],
+        )
+        .expect("set halt");
+
+        assert_eq!(host.in_token(1), InResponse::Stall);
+
+        let status = host
+            .control_in(
+                || poll(dev, class),
+                ENDPOINT_IN,
+                Request::GET_STATUS,
+                0,
+                0x81,
+                2,
+            )
+            .expect("get endpoint status");
+        assert_eq!(status, [1, 0]);
+
+        host.control_out(
+            || poll(dev, class),
+            ENDPOINT_OUT,
+            Request::CLEAR_FEATURE,
+            Request::FEATURE_ENDPOINT_HALT,
+            0x81,
+            &[],
+        )
+        .expect("clear halt");
+
+        assert_eq!(host.in_token(1), InResponse::Nak);
+    });
+}
+
+#[test]
+fn suspend_resume() {
+    with_device(|host, dev, class| {
+        enumerate(host, dev, class);
+
+        host.suspend();
+        poll(dev, class);
+        assert_eq!(dev.state(), UsbDeviceState::Suspend);
+        assert!(host.is_device_suspended());
+
+        host.resume();
+        poll(dev, class);
+        assert_eq!(dev.state(), UsbDeviceState::Configured);
+        assert!(!host.is_device_suspended());
+    });
+}
+
+#[test]
+fn bus_reset() {
+    with_device(|host, dev, class| {
+        enumerate(host, dev, class);
+
+        host.reset();
+        poll(dev, class);
+        assert_eq!(dev.state(), UsbDeviceState::Default);
+        assert_eq!(host.address(), 0);
+    });
+}
+
+#[test]
+fn chapter9_conformance() {
+    with_device(|host, dev, class| {
+        let report = conformance::run(host, || poll(dev, class));
+
+        assert!(report.is_compliant(), "{}", report);
+        assert_eq!(dev.state(), UsbDeviceState::Configured);
+    });
+}
+
+#[test]
+fn control_error_propagation() {
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    // Too small to hold the product string descriptor.
+    let mut control_buffer = [0u8; 32];
+    let mut dev = UsbDeviceBuilder::new(
+        &alloc,
+        UsbVidPid(test_class::VID, test_class::PID),
+        &mut control_buffer,
+    )
+    .strings(&[StringDescriptors::default().product(test_class::PRODUCT)])
+    .unwrap()
+    .build()
+    .unwrap();
+
+    host.reset();
+    dev.poll(&mut []);
+
+    let mut errors = Vec::new();
+    let res = host.control_in(
+        || {
+            if let Err(err) = dev.try_poll(&mut []) {
+                errors.push(err);
+            }
+        },
+        DEVICE_IN,
+        Request::GET_DESCRIPTOR,
+        0x0302,
+        0x0409,
+        255,
+    );
+
+    assert_eq!(res, Err(TransferError::Stall));
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].error, UsbError::BufferOverflow);
+    assert_eq!(
+        errors[0].request.map(|req| req.request),
+        Some(Request::GET_DESCRIPTOR)
+    );
+}
+
+#[test]
+fn device_events() {
+    with_device(|host, dev, class| {
+        enumerate(host, dev, class);
+
+        let events: Vec<_> = dev.events().collect();
+        assert_eq!(
+            events,
+            [
+                UsbDeviceEvent::Reset,
+                UsbDeviceEvent::Addressed(5),
+                UsbDeviceEvent::Configured(CONFIGURATION_VALUE)
+            ]
+        );
+
+        // Selecting the same setting again is not a change.
+        for _ in 0..2 {
+            host.control_out(
+                || poll(dev, class),
+                INTERFACE_OUT,
+                Request::SET_INTERFACE,
+                1,
+                0,
+                &[],
+            )
+            .expect("set interface");
+        }
+
+        // Endpoints that were not allocated can't be halted.
+        for &request in [Request::SET_FEATURE, Request::CLEAR_FEATURE].iter() {
+            for &index in [0x008f, 0x0181].iter() {
+                assert_eq!(
+                    host.control_out(
+                        || poll(dev, class),
+                        ENDPOINT_OUT,
+                        request,
+                        Request::FEATURE_ENDPOINT_HALT,
+                        index,
+                        &[],
+                    ),
+                    Err(TransferError::Stall)
+                );
+            }
+
+            host.control_out(
+                || poll(dev, class),
+                ENDPOINT_OUT,
+                request,
+                Request::FEATURE_ENDPOINT_HALT,
+                0x81,
+                &[],
+            )
+            .expect("endpoint halt");
+        }
+
+        host.suspend();
+        poll(dev, class);
+        host.resume();
+        poll(dev, class);
+
+        let events: Vec<_> = dev.events().collect();
+        assert!(matches!(
+            events[..],
+            [
+                UsbDeviceEvent::AltSettingChanged { alt_setting: 1, .. },
+                UsbDeviceEvent::EndpointHalted(halted),
+                UsbDeviceEvent::EndpointCleared(cleared),
+                UsbDeviceEvent::Suspended,
+                UsbDeviceEvent::Resumed
+            ] if u8::from(halted) == 0x81 && u8::from(cleared) == 0x81
+        ));
+    });
+}
+
+#[test]
+fn multiple_configurations() {
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut class = TestClass::new(&alloc);
+    let mut control_buffer = [0u8; 256];
+    let mut dev = UsbDeviceBuilder::new(
+        &alloc,
+        UsbVidPid(test_class::VID, test_class::PID),
+        &mut control_buffer,
+    )
+    .strings(&[StringDescriptors::default().product(test_class::PRODUCT)])
+    .unwrap()
+    .configurations(&[
+        UsbConfiguration::new(1)
+            .name("Low power")
+            .max_power(100)
+            .unwrap(),
+        UsbConfiguration::new(2)
+            .name("Full function")
+            .self_powered(true)
+            .max_power(500)
+            .unwrap(),
+    ])
+    .unwrap()
+    .build()
+    .unwrap();
+
+    host.reset();
+    poll(&mut dev, &mut class);
+
+    let report = conformance::run(&host, || poll(&mut dev, &mut class));
+    assert!(report.is_compliant(), "{}", report);
+    assert_eq!(dev.configuration(), 1);
+
+    let config = host
+        .control_in(
+            || poll(&mut dev, &mut class),
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0201,
+            0,
+            9,
+        )
+        .expect("get second configuration descriptor");
+    assert_eq!(config[5], 2);
+    assert_eq!(config[7], 0xc0);
+    assert_eq!(config[8], 250);
+
+    let name = host
+        .control_in(
+            || poll(&mut dev, &mut class),
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0300 | u16::from(config[6]),
+            0x0409,
+            255,
+        )
+        .expect("get configuration string");
+    assert_eq!(name.len(), 2 + 2 * "Full function".len());
+
+    dev.events().for_each(drop);
+
+    host.control_out(
+        || poll(&mut dev, &mut class),
+        DEVICE_OUT,
+        Request::SET_CONFIGURATION,
+        2,
+        0,
+        &[],
+    )
+    .expect("set configuration 2");
+    assert_eq!(dev.configuration(), 2);
+    assert_eq!(
+        dev.events().collect::<Vec<_>>(),
+        [UsbDeviceEvent::Configured(2)]
+    );
+
+    let res = host.control_out(
+        || poll(&mut dev, &mut class),
+        DEVICE_OUT,
+        Request::SET_CONFIGURATION,
+        3,
+        0,
+        &[],
+    );
+    assert_eq!(res, Err(TransferError::Stall));
+    assert_eq!(dev.configuration(), 2);
+}
+
+#[test]
+fn configuration_classes() {
+    const CLASS_INTERFACE_IN: u8 = 0xa1;
+    const GET_STATE: u8 = 0x05;
+
+    // A class that answers a class request on its interface with a fixed state.
+    struct StateClass {
+        iface: InterfaceNumber,
+    }
+
+    impl<B: UsbBus> UsbClass<B> for StateClass {
+        fn get_configuration_descriptors(
+            &self,
+            writer: &mut DescriptorWriter,
+        ) -> usb_device::Result<()> {
+            writer.interface(self.iface, 0xfe, 0x01, 0x01)
+        }
+
+        fn control_in(&mut self, xfer: ControlIn<B>) {
+            let req = *xfer.request();
+
+            if req.request_type == control::RequestType::Class
+                && req.recipient == control::Recipient::Interface
+                && req.index == u8::from(self.iface) as u16
+                && req.request == GET_STATE
+            {
+                xfer.accept_with(&[2]).ok();
+            }
+        }
+    }
+
+    // Counts the events of a class without interfaces.
+    #[derive(Default)]
+    struct Counter {
+        polls: usize,
+        frames: usize,
+    }
+
+    impl<B: UsbBus> UsbClass<B> for Counter {
+        fn poll(&mut self) {
+            self.polls += 1;
+        }
+
+        fn sof(&mut self, _frame_number: u16) {
+            self.frames += 1;
+        }
+    }
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut state = StateClass {
+        iface: alloc.interface(),
+    };
+    let mut counter = Counter::default();
+    alloc.next_configuration();
+    let mut test = TestClass::new(&alloc);
+
+    // Both classes use interface 0.
+    assert_eq!(u8::from(state.iface), 0);
+
+    let mut control_buffer = [0u8; 256];
+    let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+        .configurations(&[
+            UsbConfiguration::new(1).classes(0..2),
+            UsbConfiguration::new(2).classes(2..3),
+        ])
+        .unwrap()
+        .build()
+        .unwrap();
+
+    host.reset();
+    dev.poll(&mut [&mut state, &mut counter, &mut test]);
+
+    let config = host
+        .control_in(
+            || {
+                dev.poll(&mut [&mut state, &mut counter, &mut test]);
+            },
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0200,
+            0,
+            255,
+        )
+        .expect("get first configuration descriptor");
+    assert_eq!(config.len(), 18);
+    assert_eq!(config[4], 1);
+    assert_eq!(&config[9..18], &[9, 0x04, 0, 0, 0, 0xfe, 0x01, 0x01, 0]);
+
+    let config = host
+        .control_in(
+            || {
+                dev.poll(&mut [&mut state, &mut counter, &mut test]);
+            },
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0201,
+            0,
+            255,
+        )
+        .expect("get second configuration descriptor");
+    assert_eq!(config[4], 1);
+    assert_eq!(&config[9..15], &[9, 0x04, 0, 0, 4, 0xff]);
+    assert!(!config.windows(2).any(|d| d == [0x04, 0xfe]));
+
+    for &value in [1u16, 2].iter() {
+        host.control_out(
+            || {
+                dev.poll(&mut [&mut state, &mut counter, &mut test]);
+            },
+            DEVICE_OUT,
+            Request::SET_CONFIGURATION,
+            value,
+            0,
+            &[],
+        )
+        .expect("set configuration");
+
+        // Class requests to interface 0 only reach the state class in its configuration.
+        let state_result = host.control_in(
+            || {
+                dev.poll(&mut [&mut state, &mut counter, &mut test]);
+            },
+            CLASS_INTERFACE_IN,
+            GET_STATE,
+            0,
+            0,
+            1,
+        );
+
+        // Only the test class has a second alternate setting on interface 0.
+        let alt_setting = host.control_out(
+            || {
+                dev.poll(&mut [&mut state, &mut counter, &mut test]);
+            },
+            INTERFACE_OUT,
+            Request::SET_INTERFACE,
+            1,
+            0,
+            &[],
+        );
+
+        if value == 1 {
+            assert_eq!(state_result, Ok(vec![2]));
+            assert_eq!(alt_setting, Err(TransferError::Stall));
+        } else {
+            assert_eq!(state_result, Err(TransferError::Stall));
+            assert_eq!(alt_setting, Ok(()));
+            assert_eq!(
+                host.control_in(
+                    || {
+                        dev.poll(&mut [&mut state, &mut counter, &mut test]);
+                    },
+                    INTERFACE_IN,
+                    Request::GET_INTERFACE,
+                    0,
+                    0,
+                    1
+                ),
+                Ok(vec![1])
+            );
+        }
+
+        // Only the classes of the active configuration are polled and see start-of-frame events.
+        let (polls, frames) = (counter.polls, counter.frames);
+        host.sof();
+        dev.poll(&mut [&mut state, &mut counter, &mut test]);
+        if value == 1 {
+            assert_eq!(counter.frames, frames + 1);
+        } else {
+            assert_eq!((counter.polls, counter.frames), (polls, frames));
+        }
+    }
+}
+
+#[test]
+fn invalid_configurations() {
+    let alloc = UsbBusAllocator::new(SimUsbBus::new());
+    let mut control_buffer = [0u8; 64];
+
+    let res = UsbDeviceBuilder::new(&alloc, UsbVidPid(0, 0), &mut control_buffer)
+        .configurations(&[UsbConfiguration::new(1), UsbConfiguration::new(1)]);
+    assert_eq!(res.err(), Some(BuilderError::InvalidConfigurationValue));
+}
+
+#[test]
+fn high_speed() {
+    let bus = SimUsbBus::new();
+    bus.set_max_speed(UsbSpeed::High);
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut class = TestClass::new(&alloc);
+    let mut control_buffer = [0u8; 256];
+    let mut dev = UsbDeviceBuilder::new(
+        &alloc,
+        UsbVidPid(test_class::VID, test_class::PID),
+        &mut control_buffer,
+    )
+    .strings(&[StringDescriptors::default().product(test_class::PRODUCT)])
+    .unwrap()
+    .max_packet_size_0(64)
+    .unwrap()
+    .build()
+    .unwrap();
+
+    host.reset();
+    poll(&mut dev, &mut class);
+    assert_eq!(dev.speed(), UsbSpeed::High);
+
+    let report = conformance::run(&host, || poll(&mut dev, &mut class));
+    assert!(report.is_compliant(), "{}", report);
+
+    let qualifier = host
+        .control_in(
+            || poll(&mut dev, &mut class),
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0600,
+            0,
+            10,
+        )
+        .expect("get device qualifier");
+    assert_eq!(qualifier[..2], [10, 6]);
+
+    host.set_speed(UsbSpeed::Full);
+    host.reset();
+    poll(&mut dev, &mut class);
+    assert_eq!(dev.speed(), UsbSpeed::Full);
+
+    let other_speed = host
+        .control_in(
+            || poll(&mut dev, &mut class),
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0700,
+            0,
+            9,
+        )
+        .expect("get other speed configuration");
+    assert_eq!(other_speed[1], 7);
+}
+
+#[test]
+fn test_mode() {
+    with_device(|host, dev, class| {
+        // Test modes only exist for high speed devices.
+        let res = host.control_out(
+            || poll(dev, class),
+            DEVICE_OUT,
+            Request::SET_FEATURE,
+            Request::FEATURE_TEST_MODE,
+            0x0400,
+            &[],
+        );
+        assert_eq!(res, Err(TransferError::Stall));
+    });
+
+    let bus = SimUsbBus::new();
+    bus.set_max_speed(UsbSpeed::High);
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut control_buffer = [0u8; 64];
+    let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0, 0), &mut control_buffer)
+        .max_packet_size_0(64)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    host.reset();
+    dev.poll(&mut []);
+
+    for index in [0x0600, 0x0401] {
+        let res = host.control_out(
+            || {
+                dev.poll(&mut []);
+            },
+            DEVICE_OUT,
+            Request::SET_FEATURE,
+            Request::FEATURE_TEST_MODE,
+            index,
+            &[],
+        );
+        assert_eq!(res, Err(TransferError::Stall), "wIndex {:#06x}", index);
+    }
+
+    host.control_out(
+        || {
+            dev.poll(&mut []);
+        },
+        DEVICE_OUT,
+        Request::SET_FEATURE,
+        Request::FEATURE_TEST_MODE,
+        0x0400,
+        &[],
+    )
+    .expect("set test mode");
+
+    assert_eq!(host.test_mode(), Some(TestMode::Packet));
+    assert_eq!(
+        dev.events().last(),
+        Some(UsbDeviceEvent::TestModeEntered(TestMode::Packet))
+    );
+}
+
+#[test]
+fn remote_wakeup() {
+    // Remote wakeup can't be enabled unless the configuration supports it.
+    with_device(|host, dev, class| {
+        enumerate(host, dev, class);
+
+        assert_eq!(
+            host.control_out(
+                || poll(dev, class),
+                DEVICE_OUT,
+                Request::SET_FEATURE,
+                Request::FEATURE_DEVICE_REMOTE_WAKEUP,
+                0,
+                &[],
+            ),
+            Err(TransferError::Stall)
+        );
+        assert!(!dev.remote_wakeup_enabled());
+    });
+
+    with_configured_device(
+        |builder| builder.supports_remote_wakeup(true),
+        |host, dev, class| {
+            enumerate(host, dev, class);
+
+            host.suspend();
+            poll(dev, class);
+            assert_eq!(dev.remote_wakeup(), Err(UsbError::InvalidState));
+
+            host.resume();
+            poll(dev, class);
+            host.control_out(
+                || poll(dev, class),
+                DEVICE_OUT,
+                Request::SET_FEATURE,
+                Request::FEATURE_DEVICE_REMOTE_WAKEUP,
+                0,
+                &[],
+            )
+            .expect("enable remote wakeup");
+            assert_eq!(dev.remote_wakeup(), Err(UsbError::InvalidState));
+
+            host.suspend();
+            poll(dev, class);
+            assert_eq!(dev.state(), UsbDeviceState::Suspend);
+            dev.events().for_each(drop);
+
+            dev.remote_wakeup().expect("remote wakeup");
+            assert_eq!(dev.state(), UsbDeviceState::Configured);
+            assert_eq!(host.remote_wakeups(), 1);
+            assert!(!host.is_device_suspended());
+            assert_eq!(dev.events().collect::<Vec<_>>(), [UsbDeviceEvent::Resumed]);
+
+            poll(dev, class);
+            assert_eq!(dev.state(), UsbDeviceState::Configured);
+        },
+    );
+
+    // Only the second configuration supports remote wakeup.
+    with_configured_device(
+        |builder| {
+            builder
+                .configurations(&[
+                    UsbConfiguration::new(1),
+                    UsbConfiguration::new(2).supports_remote_wakeup(true),
+                ])
+                .unwrap()
+        },
+        |host, dev, class| {
+            enumerate(host, dev, class);
+
+            let set_remote_wakeup = |dev: &mut Device, class: &mut Class| {
+                host.control_out(
+                    || poll(dev, class),
+                    DEVICE_OUT,
+                    Request::SET_FEATURE,
+                    Request::FEATURE_DEVICE_REMOTE_WAKEUP,
+                    0,
+                    &[],
+                )
+            };
+            let set_configuration = |dev: &mut Device, class: &mut Class, value: u16| {
+                host.control_out(
+                    || poll(dev, class),
+                    DEVICE_OUT,
+                    Request::SET_CONFIGURATION,
+                    value,
+                    0,
+                    &[],
+                )
+                .expect("set configuration");
+            };
+
+            assert_eq!(set_remote_wakeup(dev, class), Err(TransferError::Stall));
+
+            set_configuration(dev, class, 2);
+            assert_eq!(set_remote_wakeup(dev, class), Ok(()));
+            assert!(dev.remote_wakeup_enabled());
+
+            // Switching to a configuration without remote wakeup makes it unusable.
+            set_configuration(dev, class, 1);
+            host.suspend();
+            poll(dev, class);
+            assert_eq!(dev.remote_wakeup(), Err(UsbError::InvalidState));
+            assert_eq!(host.remote_wakeups(), 0);
+        },
+    );
+}
+
+#[test]
+fn start_of_frame() {
+    struct SofClass {
+        frames: Vec<u16>,
+        in_complete: usize,
+        polls: usize,
+    }
+
+    impl<B: UsbBus> UsbClass<B> for SofClass {
+        fn poll(&mut self) {
+            self.polls += 1;
+        }
+
+        fn sof(&mut self, frame_number: u16) {
+            self.frames.push(frame_number);
+        }
+
+        fn endpoint_in_complete(&mut self, _addr: EndpointAddress) {
+            self.in_complete += 1;
+        }
+    }
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut class = SofClass {
+        frames: Vec::new(),
+        in_complete: 0,
+        polls: 0,
+    };
+    let ep: EndpointIn<_> = alloc.isochronous(
+        IsochronousSynchronizationType::Asynchronous,
+        IsochronousUsageType::Data,
+        64,
+        1,
+    );
+    let index = u8::from(ep.address()).into();
+    let mut control_buffer = [0u8; 64];
+    let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0, 0), &mut control_buffer)
+        .build()
+        .unwrap();
+
+    host.reset();
+    dev.poll(&mut [&mut class]);
+
+    let res = host.control_in(
+        || {
+            dev.poll(&mut [&mut class]);
+        },
+        ENDPOINT_IN,
+        Request::SYNCH_FRAME,
+        0,
+        index,
+        2,
+    );
+    assert_eq!(res, Err(TransferError::Stall));
+
+    let polls = class.polls;
+    for _ in 0..3 {
+        host.sof();
+        assert!(dev.poll(&mut [&mut class]));
+    }
+    assert_eq!(class.frames, [0, 1, 2]);
+    assert_eq!(class.polls, polls + 3);
+
+    // A start-of-frame packet is reported together with other endpoint events.
+    ep.write(&[0x55; 8]).expect("write");
+    assert_eq!(
+        host.in_token(ep.address().index()),
+        InResponse::Data(vec![0x55; 8])
+    );
+    host.sof();
+    assert!(dev.poll(&mut [&mut class]));
+    assert_eq!(class.frames, [0, 1, 2, 3]);
+    assert_eq!(class.in_complete, 1);
+
+    let frame = host
+        .control_in(
+            || {
+                dev.poll(&mut [&mut class]);
+            },
+            ENDPOINT_IN,
+            Request::SYNCH_FRAME,
+            0,
+            index,
+            2,
+        )
+        .expect("synch frame");
+    assert_eq!(frame, [3, 0]);
+
+    // Only isochronous endpoints answer, and only to a well-formed request.
+    for &(value, index, length) in [(0, 0x00, 2), (0, 0x82, 2), (1, index, 2), (0, index, 4)].iter()
+    {
+        let res = host.control_in(
+            || {
+                dev.poll(&mut [&mut class]);
+            },
+            ENDPOINT_IN,
+            Request::SYNCH_FRAME,
+            value,
+            index,
+            length,
+        );
+        assert_eq!(res, Err(TransferError::Stall));
+    }
+}
+
+#[test]
+fn ms_os_20_descriptors() {
+    use usb_device::ms_os_20::{windows_version, CompatibleId, DescriptorSetWriter, MsOs20Class};
+
+    assert_eq!(
+        CompatibleId::new("WINUSB_LONG", ""),
+        Err(BuilderError::InvalidCompatibleId)
+    );
+    assert_eq!(
+        CompatibleId::new("WINUSB", "\u{e9}"),
+        Err(BuilderError::InvalidCompatibleId)
+    );
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut class = TestClass::new(&alloc);
+
+    let mut buf = [0u8; 256];
+    let mut writer = DescriptorSetWriter::new(&mut buf, windows_version::WIN81).unwrap();
+    writer.configuration_subset(0).unwrap();
+    writer
+        .compatible_id(CompatibleId::new("WINUSB", "").unwrap())
+        .unwrap();
+    writer
+        .device_interface_guids(&["{3b4a6f2e-2b5c-4a0e-8f5d-1c8e3d7a9b10}"])
+        .unwrap();
+    assert_eq!(writer.min_resume_time(11, 1), Err(UsbError::ParseError));
+    assert_eq!(writer.min_resume_time(0, 0), Err(UsbError::ParseError));
+    assert_eq!(writer.min_resume_time(0, 21), Err(UsbError::ParseError));
+    assert_eq!(
+        writer.write_with(0x10, |buf| Ok(buf.len() + 1)),
+        Err(UsbError::BufferOverflow)
+    );
+    let set = writer.finish();
+    let mut ms_os = MsOs20Class::new(set, 0x20);
+
+    // wTotalLength limits the descriptor set to 65535 bytes.
+    let mut large_buf = vec![0u8; 70000];
+    let mut large = DescriptorSetWriter::new(&mut large_buf, windows_version::WIN81).unwrap();
+    assert_eq!(large.write_with(0x10, |buf| Ok(buf.len())), Ok(()));
+    assert_eq!(large.position(), u16::MAX as usize);
+    assert_eq!(large.ccgp_device(), Err(UsbError::BufferOverflow));
+
+    let mut control_buffer = [0u8; 256];
+    let mut dev = UsbDeviceBuilder::new(
+        &alloc,
+        UsbVidPid(test_class::VID, test_class::PID),
+        &mut control_buffer,
+    )
+    .build()
+    .unwrap();
+
+    let mut poll = || {
+        if dev.poll(&mut [&mut ms_os, &mut class]) {
+            class.poll();
+        }
+    };
+
+    host.reset();
+    poll();
+
+    let bos = host
+        .control_in(
+            &mut poll,
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0f00,
+            0,
+            255,
+        )
+        .expect("get BOS descriptor");
+    let platform = bos
+        .windows(3)
+        .position(|w| w == [28, 16, 5])
+        .map(|i| &bos[i..i + 28])
+        .expect("platform capability");
+    assert_eq!(platform[4..20], ms_os_20::PLATFORM_CAPABILITY_UUID);
+    assert_eq!(
+        u16::from_le_bytes([platform[24], platform[25]]),
+        set.len() as u16
+    );
+    assert_eq!(platform[26], 0x20);
+
+    let response = host
+        .control_in(
+            &mut poll,
+            VENDOR_IN,
+            0x20,
+            0,
+            ms_os_20::DESCRIPTOR_INDEX,
+            set.len() as u16,
+        )
+        .expect("get descriptor set");
+    assert_eq!(response, set);
+
+    // Set header and configuration subset lengths
+    assert_eq!(u16::from_le_bytes([set[8], set[9]]), set.len() as u16);
+    assert_eq!(
+        u16::from_le_bytes([set[16], set[17]]),
+        set.len() as u16 - 10
+    );
+}
+
+#[test]
+fn ms_os_10_descriptors() {
+    use usb_device::ms_os_10::{CompatibleId, ExtendedProperty, MsOs10Descriptors};
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut class = TestClass::new(&alloc);
+
+    let vendor_interface = alloc.interface();
+
+    let compatible_ids = [CompatibleId::new(vendor_interface, "WINUSB", "").unwrap()];
+    let properties = [ExtendedProperty::device_interface_guid(
+        vendor_interface,
+        "{3b4a6f2e-2b5c-4a0e-8f5d-1c8e3d7a9b10}",
+    )];
+
+    let mut control_buffer = [0u8; 256];
+    let mut dev = UsbDeviceBuilder::new(
+        &alloc,
+        UsbVidPid(test_class::VID, test_class::PID),
+        &mut control_buffer,
+    )
+    .ms_os_10(
+        MsOs10Descriptors::new(0x21)
+            .compatible_ids(&compatible_ids)
+            .properties(&properties),
+    )
+    .build()
+    .unwrap();
+
+    let mut poll = || {
+        if dev.poll(&mut [&mut class]) {
+            class.poll();
+        }
+    };
+
+    host.reset();
+    poll();
+
+    // Windows asks for the OS string without a language ID.
+    let os_string = host
+        .control_in(
+            &mut poll,
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x03ee,
+            0,
+            255,
+        )
+        .expect("get OS string descriptor");
+    assert_eq!(os_string.len(), 18);
+    assert_eq!(&os_string[..4], &[18, 3, b'M', 0]);
+    assert_eq!(os_string[16], 0x21);
+
+    let compat_id = host
+        .control_in(
+            &mut poll,
+            VENDOR_IN,
+            0x21,
+            0,
+            ms_os_10::EXTENDED_COMPAT_ID_INDEX,
+            255,
+        )
+        .expect("get extended compat ID descriptor");
+    assert_eq!(compat_id.len(), 40);
+    assert_eq!(compat_id[..4], 40u32.to_le_bytes());
+    assert_eq!(compat_id[8], 1);
+    assert_eq!(compat_id[16], 1);
+    assert_eq!(&compat_id[18..26], b"WINUSB\0\0");
+
+    let properties = host
+        .control_in(
+            &mut poll,
+            VENDOR_INTERFACE_IN,
+            0x21,
+            1,
+            ms_os_10::EXTENDED_PROPERTIES_INDEX,
+            255,
+        )
+        .expect("get extended properties descriptor");
+    assert_eq!(properties[..4], (properties.len() as u32).to_le_bytes());
+    assert_eq!(properties[8..10], 1u16.to_le_bytes());
+
+    // There are no properties for other interfaces.
+    assert!(host
+        .control_in(
+            &mut poll,
+            VENDOR_INTERFACE_IN,
+            0x21,
+            0,
+            ms_os_10::EXTENDED_PROPERTIES_INDEX,
+            255,
+        )
+        .is_err());
+}
+
+#[test]
+fn webusb() {
+    use usb_device::webusb::{self, WebUsb};
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut class = TestClass::new(&alloc);
+
+    let mut control_buffer = [0u8; 256];
+    let mut dev = UsbDeviceBuilder::new(
+        &alloc,
+        UsbVidPid(test_class::VID, test_class::PID),
+        &mut control_buffer,
+    )
+    .webusb(
+        WebUsb::new(0x22)
+            .landing_page("https://example.com/device")
+            .unwrap(),
+    )
+    .build()
+    .unwrap();
+
+    let mut poll = || {
+        if dev.poll(&mut [&mut class]) {
+            class.poll();
+        }
+    };
+
+    host.reset();
+    poll();
+
+    let bos = host
+        .control_in(
+            &mut poll,
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0f00,
+            0,
+            255,
+        )
+        .expect("get BOS descriptor");
+    let platform = bos
+        .windows(3)
+        .position(|w| w == [24, 16, 5])
+        .map(|i| &bos[i..i + 24])
+        .expect("platform capability");
+    assert_eq!(platform[4..20], webusb::PLATFORM_CAPABILITY_UUID);
+    assert_eq!(platform[22], 0x22);
+    assert_eq!(platform[23], webusb::LANDING_PAGE_INDEX);
+
+    let url = host
+        .control_in(
+            &mut poll,
+            VENDOR_IN,
+            0x22,
+            webusb::LANDING_PAGE_INDEX.into(),
+            webusb::GET_URL,
+            255,
+        )
+        .expect("get landing page URL");
+    assert_eq!(url[..3], [21, webusb::URL_DESCRIPTOR_TYPE, 1]);
+    assert_eq!(&url[3..], b"example.com/device");
+
+    assert!(host
+        .control_in(&mut poll, VENDOR_IN, 0x22, 2, webusb::GET_URL, 255)
+        .is_err());
+}
+
+#[test]
+fn webusb_requires_usb210() {
+    use usb_device::webusb::WebUsb;
+
+    let bus = SimUsbBus::new();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut control_buffer = [0u8; 64];
+    let result = UsbDeviceBuilder::new(
+        &alloc,
+        UsbVidPid(test_class::VID, test_class::PID),
+        &mut control_buffer,
+    )
+    .usb_rev(UsbRev::Usb200)
+    .webusb(WebUsb::new(0x22))
+    .build();
+
+    assert_eq!(result.err(), Some(BuilderError::BosRequiresUsb210));
+}
+
+#[test]
+fn link_power_management() {
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut class = TestClass::new(&alloc);
+    let mut control_buffer = [0u8; 256];
+    let mut dev = UsbDeviceBuilder::new(
+        &alloc,
+        UsbVidPid(test_class::VID, test_class::PID),
+        &mut control_buffer,
+    )
+    .supports_remote_wakeup(true)
+    .lpm(Some(2), Some(6))
+    .unwrap()
+    .build()
+    .unwrap();
+
+    host.reset();
+    poll(&mut dev, &mut class);
+    enumerate(&host, &mut dev, &mut class);
+
+    let bos = host
+        .control_in(
+            || poll(&mut dev, &mut class),
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0f00,
+            0,
+            255,
+        )
+        .expect("get BOS descriptor");
+    // USB 2.0 Extension with LPM, BESL, baseline BESL 2 and deep BESL 6
+    assert_eq!(bos[5..12], [7, 16, 2, 0x1e, 0x62, 0, 0]);
+    dev.events().for_each(drop);
+
+    assert_eq!(host.lpm(4, true), Handshake::Ack);
+    poll(&mut dev, &mut class);
+    assert!(host.is_sleeping());
+    assert_eq!(dev.state(), UsbDeviceState::Configured);
+    assert_eq!(
+        dev.events().collect::<Vec<_>>(),
+        [UsbDeviceEvent::SleepEntered {
+            besl: 4,
+            remote_wakeup: true
+        }]
+    );
+
+    dev.remote_wakeup().expect("remote wakeup");
+    assert!(!host.is_sleeping());
+    assert_eq!(host.remote_wakeups(), 1);
+    assert_eq!(
+        dev.events().collect::<Vec<_>>(),
+        [UsbDeviceEvent::SleepExited]
+    );
+    poll(&mut dev, &mut class);
+
+    assert_eq!(host.lpm(4, false), Handshake::Ack);
+    poll(&mut dev, &mut class);
+    assert_eq!(dev.remote_wakeup(), Err(UsbError::InvalidState));
+
+    host.resume();
+    poll(&mut dev, &mut class);
+    assert_eq!(
+        dev.events().collect::<Vec<_>>(),
+        [
+            UsbDeviceEvent::SleepEntered {
+                besl: 4,
+                remote_wakeup: false
+            },
+            UsbDeviceEvent::SleepExited
+        ]
+    );
+
+    dev.set_lpm_accepted(false).expect("reject LPM");
+    assert_eq!(host.lpm(4, false), Handshake::Nyet);
+    assert!(!host.is_sleeping());
+}
+
+#[test]
+fn invalid_besl() {
+    let bus = SimUsbBus::new();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut control_buffer = [0u8; 64];
+    let builder = UsbDeviceBuilder::new(&alloc, UsbVidPid(0, 0), &mut control_buffer);
+
+    assert_eq!(
+        builder.lpm(Some(6), Some(2)).err(),
+        Some(BuilderError::InvalidBesl)
+    );
+}
+
+#[test]
+fn container_id() {
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut class = TestClass::new(&alloc);
+    let mut control_buffer = [0u8; 256];
+    let mut dev = UsbDeviceBuilder::new(
+        &alloc,
+        UsbVidPid(test_class::VID, test_class::PID),
+        &mut control_buffer,
+    )
+    .strings(&[StringDescriptors::default().serial_number(test_class::SERIAL_NUMBER)])
+    .unwrap()
+    .container_id(ContainerId::FromSerialNumber)
+    .build()
+    .unwrap();
+
+    host.reset();
+    poll(&mut dev, &mut class);
+
+    let bos = host
+        .control_in(
+            || poll(&mut dev, &mut class),
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0f00,
+            0,
+            255,
+        )
+        .expect("get BOS descriptor");
+    let container_id = bos
+        .windows(3)
+        .position(|w| w == [20, 16, 4])
+        .map(|i| &bos[i + 4..i + 20])
+        .expect("container ID capability");
+
+    // {93f96949-0562-5210-bcd9-2e4ed5f82606}
+    assert_eq!(
+        container_id,
+        [
+            0x49, 0x69, 0xf9, 0x93, 0x62, 0x05, 0x10, 0x52, 0xbc, 0xd9, 0x2e, 0x4e, 0xd5, 0xf8,
+            0x26, 0x06
+        ]
+    );
+}
+
+#[test]
+fn container_id_requires_serial_number() {
+    let bus = SimUsbBus::new();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut control_buffer = [0u8; 64];
+    let result = UsbDeviceBuilder::new(&alloc, UsbVidPid(0, 0), &mut control_buffer)
+        .container_id(ContainerId::FromSerialNumber)
+        .build();
+
+    assert_eq!(result.err(), Some(BuilderError::NoSerialNumber));
+}
+
+#[test]
+fn descriptor_validation() {
+    use usb_device::descriptor::parser::{self, Descriptor, ValidationError};
+
+    with_device(|host, dev, class| {
+        let config = host
+            .control_in(
+                || poll(dev, class),
+                DEVICE_IN,
+                Request::GET_DESCRIPTOR,
+                0x0200,
+                0,
+                1024,
+            )
+            .expect("get configuration descriptor");
+
+        let mut string_exists = |index: u8| {
+            host.control_in(
+                || poll(dev, class),
+                DEVICE_IN,
+                Request::GET_DESCRIPTOR,
+                0x0300 | u16::from(index),
+                0x0409,
+                255,
+            )
+            .is_ok()
+        };
+
+        assert_eq!(
+            parser::validate_configuration(&config, &mut string_exists),
+            Ok(())
+        );
+
+        let endpoints = parser::parse(&config)
+            .filter(|d| matches!(d, Ok(Descriptor::Endpoint(_))))
+            .count();
+        assert_eq!(endpoints, 5);
+    });
+
+    #[rustfmt::skip]
+    let config = [
+        9, 2, 32, 0, 1, 1, 0, 0x80, 50,
+        9, 4, 0, 0, 2, 0xff, 0, 0, 0,
+        7, 5, 0x81, 2, 64, 0, 0,
+        7, 5, 0x81, 2, 64, 0, 0,
+    ];
+    assert_eq!(
+        parser::validate_configuration(&config, |_| true),
+        Err(ValidationError::EndpointAddress {
+            interface: 0,
+            address: EndpointAddress::from(0x81),
+        })
+    );
+
+    #[rustfmt::skip]
+    let config = [
+        9, 2, 26, 0, 2, 1, 0, 0x80, 50,
+        8, 11, 0, 2, 0xff, 0, 0, 0,
+        9, 4, 0, 0, 0, 0xff, 0, 0, 0,
+    ];
+    assert_eq!(
+        parser::validate_configuration(&config, |_| true),
+        Err(ValidationError::InterfaceAssociation {
+            first_interface: 0,
+            interface_count: 2,
+        })
+    );
+
+    #[rustfmt::skip]
+    let config = [
+        9, 2, 18, 0, 1, 1, 4, 0x80, 50,
+        9, 4, 0, 0, 0, 0xff, 0, 0, 0,
+    ];
+    assert_eq!(
+        parser::validate_configuration(&config, |_| false),
+        Err(ValidationError::StringIndex(4))
+    );
+    assert_eq!(
+        parser::validate_configuration(&config[..17], |_| true),
+        Err(ValidationError::TotalLength {
+            total_length: 18,
+            actual: 17
+        })
+    );
+
+    #[rustfmt::skip]
+    let config = [
+        9, 2, 18, 0, 2, 1, 0, 0x80, 50,
+        9, 4, 1, 0, 0, 0xff, 0, 0, 0,
+    ];
+    assert_eq!(
+        parser::validate_configuration(&config, |_| true),
+        Err(ValidationError::NumInterfaces {
+            num_interfaces: 2,
+            actual: 1
+        })
+    );
+}
+
+#[test]
+fn static_configuration_descriptor() {
+    use usb_device::descriptor::parser;
+    use usb_device::descriptor::static_config::StaticConfiguration;
+
+    const CONFIGURATION: StaticConfiguration<128> = StaticConfiguration::new(1)
+        .max_power(100)
+        .iad(0, 2, 0xff, 0x00, 0x00, 0)
+        .interface(0, 0, 0xff, 0x00, 0x00, 0)
+        .endpoint(0x81, EndpointType::Interrupt, 8, 10)
+        .interface(1, 0, 0xff, 0x00, 0x00, 0)
+        .write(0x24, &[0x01, 0x02])
+        .interface(1, 1, 0xff, 0x00, 0x00, 0)
+        .endpoint(0x82, EndpointType::Bulk, 64, 0)
+        .endpoint(0x01, EndpointType::Bulk, 64, 0);
+
+    static CONFIGURATION_DESCRIPTOR: [u8; CONFIGURATION.total_length()] = CONFIGURATION.to_array();
+
+    struct StaticClass;
+
+    impl<B: UsbBus> UsbClass<B> for StaticClass {}
+
+    fn alloc_static<B: UsbBus>(alloc: &UsbBusAllocator<B>, all_endpoints: bool) {
+        alloc.interface();
+        alloc.interface();
+        let _: EndpointIn<B> = alloc.interrupt(8, 10);
+        let _: EndpointIn<B> = alloc.bulk(64);
+
+        if all_endpoints {
+            let _: EndpointOut<B> = alloc.bulk(64);
+        }
+    }
+
+    // Descriptors that don't match the device are rejected
+    let bus = SimUsbBus::new();
+    let alloc = UsbBusAllocator::new(bus);
+    alloc_static(&alloc, false);
+    let mut control_buffer = [0u8; 64];
+    let result = UsbDeviceBuilder::new(&alloc, UsbVidPid(0, 0), &mut control_buffer)
+        .static_configuration(&CONFIGURATION_DESCRIPTOR)
+        .build();
+    assert_eq!(result.err(), Some(BuilderError::InvalidStaticConfiguration));
+
+    let bus = SimUsbBus::new();
+    let alloc = UsbBusAllocator::new(bus);
+    alloc_static(&alloc, true);
+    let mut control_buffer = [0u8; 64];
+    let result = UsbDeviceBuilder::new(&alloc, UsbVidPid(0, 0), &mut control_buffer)
+        .self_powered(true)
+        .static_configuration(&CONFIGURATION_DESCRIPTOR)
+        .build();
+    assert_eq!(result.err(), Some(BuilderError::InvalidStaticConfiguration));
+
+    let bus = SimUsbBus::new();
+    let alloc = UsbBusAllocator::new(bus);
+    alloc_static(&alloc, true);
+    let mut control_buffer = [0u8; 64];
+    let result = UsbDeviceBuilder::new(&alloc, UsbVidPid(0, 0), &mut control_buffer)
+        .max_power(200)
+        .unwrap()
+        .static_configuration(&CONFIGURATION_DESCRIPTOR)
+        .build();
+    assert_eq!(result.err(), Some(BuilderError::InvalidStaticConfiguration));
+
+    static TRUNCATED: [u8; 20] = {
+        let mut array = [0; 20];
+        let mut i = 0;
+        while i < array.len() {
+            array[i] = CONFIGURATION_DESCRIPTOR[i];
+            i += 1;
+        }
+        array
+    };
+
+    let bus = SimUsbBus::new();
+    let alloc = UsbBusAllocator::new(bus);
+    alloc_static(&alloc, true);
+    let mut control_buffer = [0u8; 64];
+    let result = UsbDeviceBuilder::new(&alloc, UsbVidPid(0, 0), &mut control_buffer)
+        .static_configuration(&TRUNCATED)
+        .build();
+    assert_eq!(result.err(), Some(BuilderError::InvalidStaticConfiguration));
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+    alloc_static(&alloc, true);
+
+    let mut class = StaticClass;
+    let mut control_buffer = [0u8; 64];
+    let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0, 0), &mut control_buffer)
+        .static_configuration(&CONFIGURATION_DESCRIPTOR)
+        .build()
+        .unwrap();
+
+    host.reset();
+    dev.poll(&mut [&mut class]);
+
+    let config = host
+        .control_in(
+            || {
+                dev.poll(&mut [&mut class]);
+            },
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0200,
+            0,
+            255,
+        )
+        .expect("get configuration descriptor");
+
+    assert_eq!(config, CONFIGURATION.as_slice());
+    assert_eq!(config.len(), 9 + 8 + 9 + 7 + 9 + 4 + 9 + 7 + 7);
+    assert_eq!(config[4], 2); // bNumInterfaces
+    assert_eq!(config[8], 50); // bMaxPower
+    assert_eq!(parser::validate_configuration(&config, |_| false), Ok(()));
+
+    // The device can be configured with it
+    host.control_out(
+        || {
+            dev.poll(&mut [&mut class]);
+        },
+        DEVICE_OUT,
+        Request::SET_CONFIGURATION,
+        1,
+        0,
+        &[],
+    )
+    .expect("set configuration");
+    assert_eq!(dev.state(), UsbDeviceState::Configured);
+}
+
+#[test]
+fn hid_report_sizes() {
+    use usb_device::descriptor::hid::{
+        item_flags::*, usage_page, CollectionType, ItemType, ReportDescriptorError,
+        ReportDescriptorWriter, ReportSizes,
+    };
+
+    let mut buf = [0u8; 64];
+
+    // Unclosed collection
+    let mut w = ReportDescriptorWriter::new(&mut buf);
+    w.collection(CollectionType::Application).unwrap();
+    assert_eq!(w.finish().err(), Some(UsbError::InvalidState));
+
+    let mut w = ReportDescriptorWriter::new(&mut buf);
+    assert_eq!(w.end_collection(), Err(UsbError::InvalidState));
+    assert_eq!(w.report_id(0), Err(UsbError::ParseError));
+    assert_eq!(w.item(ItemType::Local, 0x10, 0), Err(UsbError::ParseError));
+
+    // Logical Maximum (1000) needs two bytes
+    w.logical_maximum(1000).unwrap();
+    w.logical_minimum(-1).unwrap();
+    // Designator Index (3), written as a generic item
+    w.item(ItemType::Local, 0x3, 3).unwrap();
+    assert_eq!(
+        w.finish().unwrap(),
+        &[0x26, 0xe8, 0x03, 0x15, 0xff, 0x39, 0x03]
+    );
+
+    let mut w = ReportDescriptorWriter::new(&mut buf);
+    w.usage_page(usage_page::VENDOR_DEFINED_START).unwrap();
+    w.usage(1).unwrap();
+    w.collection(CollectionType::Application).unwrap();
+    w.report_id(1).unwrap();
+    w.report_size(16).unwrap();
+    w.report_count(3).unwrap();
+    w.input(DATA | VARIABLE | ABSOLUTE).unwrap();
+    w.report_id(2).unwrap();
+    w.report_size(1).unwrap();
+    w.report_count(9).unwrap();
+    w.output(DATA | VARIABLE | ABSOLUTE).unwrap();
+    w.feature(DATA | VARIABLE | ABSOLUTE).unwrap();
+    w.end_collection().unwrap();
+    let descriptor = w.finish().unwrap();
+
+    let sizes = ReportSizes::parse(descriptor).unwrap();
+    assert!(sizes.uses_report_ids());
+    assert_eq!(sizes.get(1).map(|r| (r.input, r.output)), Some((6, 0)));
+    assert_eq!(sizes.get(2).map(|r| (r.output, r.feature)), Some((2, 2)));
+    assert_eq!(sizes.get(0), None);
+    assert_eq!(sizes.max_input_size(), 7);
+    assert_eq!(sizes.max_output_size(), 3);
+
+    assert_eq!(
+        ReportSizes::parse(&descriptor[..descriptor.len() - 1]).err(),
+        Some(ReportDescriptorError::UnbalancedCollection)
+    );
+    assert_eq!(
+        ReportSizes::parse(&descriptor[..descriptor.len() - 2]).err(),
+        Some(ReportDescriptorError::Truncated)
+    );
+}
+
+#[test]
+fn hid_boot_keyboard() {
+    use usb_device::descriptor::parser;
+    use usb_device::hid::{
+        hid_request, BootDevice, HidClass, HidProtocol, BOOT_KEYBOARD_REPORT_DESCRIPTOR,
+    };
+
+    const CLASS_INTERFACE_IN: u8 = 0xa1;
+    const CLASS_INTERFACE_OUT: u8 = 0x21;
+    const STANDARD_INTERFACE_IN: u8 = 0x81;
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut report_buf = [0u8; 16];
+    let mut keyboard = HidClass::new(&alloc, BOOT_KEYBOARD_REPORT_DESCRIPTOR, &mut report_buf, 10)
+        .unwrap()
+        .boot_device(BootDevice::Keyboard);
+
+    let mut control_buffer = [0u8; 128];
+    let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+        .build()
+        .unwrap();
+
+    let mut poll = || {
+        dev.poll(&mut [&mut keyboard]);
+    };
+
+    host.reset();
+    poll();
+
+    let config = host
+        .control_in(
+            &mut poll,
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0200,
+            0,
+            255,
+        )
+        .expect("get configuration descriptor");
+    assert_eq!(parser::validate_configuration(&config, |_| false), Ok(()));
+    // Interface class, sub class and protocol
+    assert_eq!(&config[9 + 5..9 + 8], &[0x03, 0x01, 0x01]);
+    // HID descriptor
+    assert_eq!(&config[18..27], &[9, 0x21, 0x11, 0x01, 0, 1, 0x22, 63, 0]);
+
+    let report_descriptor = host
+        .control_in(
+            &mut poll,
+            STANDARD_INTERFACE_IN,
+            Request::GET_DESCRIPTOR,
+            0x2200,
+            0,
+            255,
+        )
+        .expect("get report descriptor");
+    assert_eq!(report_descriptor, BOOT_KEYBOARD_REPORT_DESCRIPTOR);
+
+    host.control_out(&mut poll, DEVICE_OUT, Request::SET_CONFIGURATION, 1, 0, &[])
+        .expect("set configuration");
+
+    // Boot keyboards default to an idle rate of 500 ms.
+    let idle = host
+        .control_in(
+            &mut poll,
+            CLASS_INTERFACE_IN,
+            hid_request::GET_IDLE,
+            0,
+            0,
+            1,
+        )
+        .expect("get idle");
+    assert_eq!(idle, [125]);
+
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        hid_request::SET_IDLE,
+        0x0000,
+        0,
+        &[],
+    )
+    .expect("set idle");
+
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        hid_request::SET_PROTOCOL,
+        0,
+        0,
+        &[],
+    )
+    .expect("set boot protocol");
+    let protocol = host
+        .control_in(
+            &mut poll,
+            CLASS_INTERFACE_IN,
+            hid_request::GET_PROTOCOL,
+            0,
+            0,
+            1,
+        )
+        .expect("get protocol");
+    assert_eq!(protocol, [0]);
+
+    // Caps Lock LED
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        hid_request::SET_REPORT,
+        0x0200,
+        0,
+        &[0x02],
+    )
+    .expect("set output report");
+
+    assert_eq!(keyboard.protocol(), HidProtocol::Boot);
+    assert_eq!(keyboard.idle_ms(0), 0);
+
+    let mut buf = [0u8; 8];
+    assert_eq!(keyboard.read_output(&mut buf), Ok((0, 1)));
+    assert_eq!(buf[0], 0x02);
+    assert_eq!(keyboard.read_output(&mut buf), Err(UsbError::WouldBlock));
+
+    let key_a = [0, 0, 0x04, 0, 0, 0, 0, 0];
+    assert_eq!(keyboard.push_input(0, &key_a), Ok(8));
+    assert_eq!(host.in_token(1), InResponse::Data(key_a.to_vec()));
+
+    // Bus reset selects the report protocol again.
+    host.reset();
+    dev.poll(&mut [&mut keyboard]);
+    assert_eq!(keyboard.protocol(), HidProtocol::Report);
+}
+
+#[test]
+fn hid_report_ids() {
+    use usb_device::descriptor::hid::ReportDescriptorWriter;
+    use usb_device::descriptor::hid::{item_flags::*, usage_page, CollectionType};
+    use usb_device::hid::{hid_request, HidClass};
+
+    const CLASS_INTERFACE_IN: u8 = 0xa1;
+    const CLASS_INTERFACE_OUT: u8 = 0x21;
+
+    let mut descriptor_buf = [0u8; 64];
+    let mut w = ReportDescriptorWriter::new(&mut descriptor_buf);
+    w.usage_page(usage_page::VENDOR_DEFINED_START).unwrap();
+    w.usage(1).unwrap();
+    w.collection(CollectionType::Application).unwrap();
+    w.logical_minimum(0).unwrap();
+    w.logical_maximum(255).unwrap();
+    w.report_size(8).unwrap();
+    w.report_id(1).unwrap();
+    w.report_count(4).unwrap();
+    w.usage(2).unwrap();
+    w.input(DATA | VARIABLE | ABSOLUTE).unwrap();
+    w.usage(3).unwrap();
+    w.output(DATA | VARIABLE | ABSOLUTE).unwrap();
+    w.report_id(2).unwrap();
+    w.report_count(2).unwrap();
+    w.usage(4).unwrap();
+    w.feature(DATA | VARIABLE | ABSOLUTE).unwrap();
+    w.end_collection().unwrap();
+    let report_descriptor = w.finish().unwrap();
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut small_buf = [0u8; 8];
+    assert_eq!(
+        HidClass::new(&alloc, report_descriptor, &mut small_buf, 1).err(),
+        Some(UsbError::BufferOverflow)
+    );
+
+    let mut report_buf = [0u8; 10];
+    let mut hid = HidClass::new(&alloc, report_descriptor, &mut report_buf, 1).unwrap();
+
+    let mut control_buffer = [0u8; 128];
+    let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+        .build()
+        .unwrap();
+
+    host.reset();
+    dev.poll(&mut [&mut hid]);
+    host.control_out(
+        || {
+            dev.poll(&mut [&mut hid]);
+        },
+        DEVICE_OUT,
+        Request::SET_CONFIGURATION,
+        1,
+        0,
+        &[],
+    )
+    .expect("set configuration");
+
+    assert_eq!(hid.push_input(1, &[1, 2, 3]), Ok(5));
+    assert_eq!(host.in_token(1), InResponse::Data(vec![1, 1, 2, 3, 0]));
+    assert_eq!(hid.push_input(2, &[1]), Err(UsbError::InvalidState));
+    assert_eq!(hid.set_feature_report(2, &[7, 8]), Ok(()));
+
+    // Output report on the interrupt OUT endpoint
+    assert_eq!(host.out(1, &[1, 9, 8, 7, 6]), Handshake::Ack);
+    dev.poll(&mut [&mut hid]);
+    let mut buf = [0u8; 8];
+    assert_eq!(hid.read_output(&mut buf), Ok((1, 4)));
+    assert_eq!(&buf[..4], &[9, 8, 7, 6]);
+
+    // Unknown report IDs are rejected.
+    assert_eq!(host.out(1, &[3, 0]), Handshake::Ack);
+    dev.poll(&mut [&mut hid]);
+    assert_eq!(hid.read_output(&mut buf), Err(UsbError::ParseError));
+
+    let mut poll = || {
+        dev.poll(&mut [&mut hid]);
+    };
+
+    let input = host
+        .control_in(
+            &mut poll,
+            CLASS_INTERFACE_IN,
+            hid_request::GET_REPORT,
+            0x0101,
+            0,
+            64,
+        )
+        .expect("get input report");
+    assert_eq!(input, [1, 1, 2, 3, 0]);
+
+    let feature = host
+        .control_in(
+            &mut poll,
+            CLASS_INTERFACE_IN,
+            hid_request::GET_REPORT,
+            0x0302,
+            0,
+            64,
+        )
+        .expect("get feature report");
+    assert_eq!(feature, [2, 7, 8]);
+
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        hid_request::SET_REPORT,
+        0x0302,
+        0,
+        &[2, 5, 6],
+    )
+    .expect("set feature report");
+
+    assert_eq!(
+        host.control_in(
+            &mut poll,
+            CLASS_INTERFACE_IN,
+            hid_request::GET_REPORT,
+            0x0102,
+            0,
+            64
+        ),
+        Err(TransferError::Stall)
+    );
+    assert_eq!(
+        host.control_out(
+            &mut poll,
+            CLASS_INTERFACE_OUT,
+            hid_request::SET_REPORT,
+            0x0302,
+            0,
+            &[1, 5, 6],
+        ),
+        Err(TransferError::Stall)
+    );
+
+    // Only boot devices support the protocol requests.
+    assert_eq!(
+        host.control_in(
+            &mut poll,
+            CLASS_INTERFACE_IN,
+            hid_request::GET_PROTOCOL,
+            0,
+            0,
+            1
+        ),
+        Err(TransferError::Stall)
+    );
+
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        hid_request::SET_IDLE,
+        0x0202,
+        0,
+        &[],
+    )
+    .expect("set idle");
+
+    assert_eq!(hid.feature_report(2), Some(&[5, 6][..]));
+    assert_eq!(hid.idle_ms(2), 8);
+    assert_eq!(hid.idle_ms(1), 0);
+}
+
+#[test]
+fn cdc_acm() {
+    use usb_device::cdc::acm::{serial_state, CdcAcmClass, ParityType, StopBits};
+    use usb_device::cdc::cdc_request;
+    use usb_device::descriptor::parser;
+
+    const CLASS_INTERFACE_IN: u8 = 0xa1;
+    const CLASS_INTERFACE_OUT: u8 = 0x21;
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut small_buf = [0u8; 16];
+    let mut tx_buf = [0u8; 64];
+    assert_eq!(
+        CdcAcmClass::new(&alloc, 64, &mut small_buf, &mut tx_buf).err(),
+        Some(UsbError::BufferOverflow)
+    );
+
+    let (mut rx_buf, mut tx_buf) = ([0u8; 128], [0u8; 256]);
+    let mut serial = CdcAcmClass::new(&alloc, 64, &mut rx_buf, &mut tx_buf).unwrap();
+
+    let mut control_buffer = [0u8; 128];
+    let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+        .composite_with_iads()
+        .build()
+        .unwrap();
+
+    let mut poll = || {
+        dev.poll(&mut [&mut serial]);
+    };
+
+    host.reset();
+    poll();
+
+    let config = host
+        .control_in(
+            &mut poll,
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0200,
+            0,
+            255,
+        )
+        .expect("get configuration descriptor");
+    assert_eq!(parser::validate_configuration(&config, |_| false), Ok(()));
+    // Interface association descriptor
+    assert_eq!(&config[9..17], &[8, 0x0b, 0, 2, 0x02, 0x02, 0x00, 0]);
+
+    host.control_out(&mut poll, DEVICE_OUT, Request::SET_CONFIGURATION, 1, 0, &[])
+        .expect("set configuration");
+
+    // 115200 bits per second, 7 data bits, even parity and 2 stop bits
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        cdc_request::SET_LINE_CODING,
+        0,
+        0,
+        &[0x00, 0xc2, 0x01, 0x00, 2, 2, 7],
+    )
+    .expect("set line coding");
+    let line_coding = host
+        .control_in(
+            &mut poll,
+            CLASS_INTERFACE_IN,
+            cdc_request::GET_LINE_CODING,
+            0,
+            0,
+            7,
+        )
+        .expect("get line coding");
+    assert_eq!(line_coding, [0x00, 0xc2, 0x01, 0x00, 2, 2, 7]);
+
+    assert_eq!(
+        host.control_out(
+            &mut poll,
+            CLASS_INTERFACE_OUT,
+            cdc_request::SET_LINE_CODING,
+            0,
+            0,
+            &[0x00, 0xc2, 0x01, 0x00, 3, 0, 8],
+        ),
+        Err(TransferError::Stall)
+    );
+
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        cdc_request::SET_CONTROL_LINE_STATE,
+        0x0001,
+        0,
+        &[],
+    )
+    .expect("set control line state");
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        cdc_request::SEND_BREAK,
+        100,
+        0,
+        &[],
+    )
+    .expect("send break");
+
+    let line_coding = *serial.line_coding();
+    assert_eq!(line_coding.data_rate, 115200);
+    assert_eq!(line_coding.stop_bits, StopBits::Two);
+    assert_eq!(line_coding.parity_type, ParityType::Even);
+    assert_eq!(line_coding.data_bits, 7);
+    assert!(serial.dtr());
+    assert!(!serial.rts());
+    assert_eq!(serial.take_break(), Some(100));
+    assert_eq!(serial.take_break(), None);
+
+    // Two packets fill the receive buffer, the third one is held off until there is room.
+    for i in 0..3 {
+        assert_eq!(host.out(1, &[i; 64]), Handshake::Ack);
+        dev.poll(&mut [&mut serial]);
+    }
+    assert_eq!(serial.available(), 128);
+    assert_eq!(host.out(1, &[3; 64]), Handshake::Nak);
+
+    let mut buf = [0u8; 100];
+    assert_eq!(serial.read(&mut buf), Ok(100));
+    assert_eq!(serial.available(), 28 + 64);
+    assert_eq!(serial.read(&mut buf), Ok(92));
+    assert_eq!(&buf[28..92], &[2; 64][..]);
+    assert_eq!(serial.read(&mut buf), Err(UsbError::WouldBlock));
+
+    // A transfer that ends with a full packet is terminated with a zero-length packet.
+    let data: Vec<u8> = (0..128).collect();
+    assert_eq!(serial.write(&data), Ok(128));
+    assert_eq!(serial.flush(), Err(UsbError::WouldBlock));
+    let received = host
+        .transfer_in(
+            || {
+                dev.poll(&mut [&mut serial]);
+            },
+            2,
+            256,
+        )
+        .expect("read serial data");
+    assert_eq!(received, data);
+    assert_eq!(serial.flush(), Ok(()));
+
+    assert_eq!(
+        serial.set_serial_state(serial_state::DCD | serial_state::DSR),
+        Ok(())
+    );
+    assert_eq!(
+        host.in_token(1),
+        InResponse::Data(vec![0xa1, 0x20, 0, 0, 0, 0, 2, 0, 0x03, 0])
+    );
+}
+
+#[cfg(feature = "embedded-io")]
+#[test]
+fn cdc_acm_blocking() {
+    use embedded_io::{Read, Write};
+    use usb_device::cdc::acm::CdcAcmClass;
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let (mut rx_buf, mut tx_buf) = ([0u8; 128], [0u8; 128]);
+    let mut serial = CdcAcmClass::new(&alloc, 64, &mut rx_buf, &mut tx_buf).unwrap();
+
+    let mut control_buffer = [0u8; 128];
+    let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+        .build()
+        .unwrap();
+
+    host.reset();
+    dev.poll(&mut [&mut serial]);
+    host.control_out(
+        || {
+            dev.poll(&mut [&mut serial]);
+        },
+        DEVICE_OUT,
+        Request::SET_CONFIGURATION,
+        1,
+        0,
+        &[],
+    )
+    .expect("set configuration");
+
+    // The host only sends and receives data while the blocked calls poll the device.
+    let mut polls = 0;
+    let mut received = Vec::new();
+    let mut blocking = serial.blocking(|serial| {
+        polls += 1;
+        if polls == 3 {
+            assert_eq!(host.out(1, b"hello"), Handshake::Ack);
+        }
+
+        dev.poll(&mut [serial]);
+
+        if let InResponse::Data(data) = host.in_token(2) {
+            received.extend(data);
+        }
+    });
+
+    let mut buf = [0u8; 64];
+    assert_eq!(blocking.read(&mut buf), Ok(5));
+    assert_eq!(&buf[..5], b"hello");
+
+    blocking.write_all(b"world").unwrap();
+    blocking.flush().unwrap();
+
+    assert_eq!(polls, 5);
+    assert_eq!(received, b"world");
+}
+
+#[test]
+fn cdc_ncm() {
+    use usb_device::cdc::cdc_request;
+    use usb_device::cdc::ncm::{CdcNcmClass, NDP16_SIGNATURE, NTH16_SIGNATURE};
+    use usb_device::cdc::EthernetFunction;
+    use usb_device::descriptor::parser;
+
+    const CLASS_INTERFACE_IN: u8 = 0xa1;
+    const CLASS_INTERFACE_OUT: u8 = 0x21;
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let (mut rx_buf, mut tx_buf) = ([0u8; 2048], [0u8; 2048]);
+    let mac_address = [0x02, 0x00, 0x00, 0xab, 0xcd, 0xef];
+    let mut ncm = CdcNcmClass::new(&alloc, mac_address, 64, &mut rx_buf, &mut tx_buf).unwrap();
+
+    let mut control_buffer = [0u8; 128];
+    let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+        .composite_with_iads()
+        .build()
+        .unwrap();
+
+    let mut poll = || {
+        dev.poll(&mut [&mut ncm]);
+    };
+
+    host.reset();
+    poll();
+
+    let config = host
+        .control_in(
+            &mut poll,
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0200,
+            0,
+            255,
+        )
+        .expect("get configuration descriptor");
+    assert_eq!(parser::validate_configuration(&config, |_| true), Ok(()));
+
+    // Ethernet networking functional descriptor
+    assert_eq!(&config[36..39], &[13, 0x24, 0x0f]);
+    let mac_string = host
+        .control_in(
+            &mut poll,
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0300 | u16::from(config[39]),
+            0x0409,
+            255,
+        )
+        .expect("get MAC address string");
+    let mac_string: Vec<u8> = mac_string[2..].iter().step_by(2).copied().collect();
+    assert_eq!(mac_string, b"020000ABCDEF");
+
+    host.control_out(&mut poll, DEVICE_OUT, Request::SET_CONFIGURATION, 1, 0, &[])
+        .expect("set configuration");
+
+    let params = host
+        .control_in(
+            &mut poll,
+            CLASS_INTERFACE_IN,
+            cdc_request::GET_NTB_PARAMETERS,
+            0,
+            0,
+            28,
+        )
+        .expect("get NTB parameters");
+    assert_eq!(&params[..4], &[28, 0, 0x01, 0x00]);
+    assert_eq!(&params[4..8], &2048u32.to_le_bytes());
+    assert_eq!(&params[16..20], &2048u32.to_le_bytes());
+
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        cdc_request::SET_ETHERNET_PACKET_FILTER,
+        0x000c,
+        0,
+        &[],
+    )
+    .expect("set packet filter");
+
+    // Activate the data interface
+    host.control_out(&mut poll, INTERFACE_OUT, Request::SET_INTERFACE, 1, 1, &[])
+        .expect("set interface");
+
+    let speed = match host.in_token(1) {
+        InResponse::Data(data) => data,
+        other => panic!("expected speed notification, got {:?}", other),
+    };
+    assert_eq!(&speed[..2], &[0xa1, 0x2a]);
+    poll();
+    assert_eq!(
+        host.in_token(1),
+        InResponse::Data(vec![0xa1, 0x00, 0, 0, 0, 0, 0, 0])
+    );
+    poll();
+
+    ncm.set_connected(true);
+    assert_eq!(
+        host.in_token(1),
+        InResponse::Data(vec![0xa1, 0x00, 1, 0, 0, 0, 0, 0])
+    );
+    assert_eq!(ncm.packet_filter(), 0x000c);
+
+    // An NTB with two datagrams, with the NDP after them
+    let frame_a = [0xaa; 60];
+    let frame_b = [0xbb; 100];
+    let mut ntb = Vec::new();
+    ntb.extend_from_slice(&NTH16_SIGNATURE.to_le_bytes());
+    ntb.extend_from_slice(&12u16.to_le_bytes());
+    ntb.extend_from_slice(&0u16.to_le_bytes());
+    ntb.extend_from_slice(&(12u16 + 60 + 100 + 20).to_le_bytes());
+    ntb.extend_from_slice(&(12u16 + 60 + 100).to_le_bytes());
+    ntb.extend_from_slice(&frame_a);
+    ntb.extend_from_slice(&frame_b);
+    ntb.extend_from_slice(&NDP16_SIGNATURE.to_le_bytes());
+    ntb.extend_from_slice(&20u16.to_le_bytes());
+    ntb.extend_from_slice(&0u16.to_le_bytes());
+    for (index, len) in [(12u16, 60u16), (72, 100), (0, 0)].iter() {
+        ntb.extend_from_slice(&index.to_le_bytes());
+        ntb.extend_from_slice(&len.to_le_bytes());
+    }
+
+    host.transfer_out(
+        || {
+            dev.poll(&mut [&mut ncm]);
+        },
+        1,
+        &ntb,
+    )
+    .expect("send NTB");
+
+    let mut frame = [0u8; 1514];
+    assert!(ncm.can_receive());
+    assert_eq!(ncm.read_frame(&mut frame), Ok(60));
+    assert_eq!(&frame[..60], &frame_a[..]);
+    assert_eq!(
+        ncm.receive_frame(|frame| frame.to_vec()),
+        Ok(frame_b.to_vec())
+    );
+    assert_eq!(ncm.read_frame(&mut frame), Err(UsbError::WouldBlock));
+
+    // Frames are sent in NTBs of their own.
+    let sent: Vec<u8> = (0..100).collect();
+    assert_eq!(ncm.write_frame(&sent), Ok(()));
+    assert!(!ncm.can_transmit());
+    assert_eq!(ncm.write_frame(&sent), Err(UsbError::WouldBlock));
+
+    let ntb = host
+        .transfer_in(
+            || {
+                dev.poll(&mut [&mut ncm]);
+            },
+            2,
+            2048,
+        )
+        .expect("receive NTB");
+    assert_eq!(ntb.len(), 28 + 100);
+    assert_eq!(&ntb[0..4], &NTH16_SIGNATURE.to_le_bytes());
+    assert_eq!(&ntb[8..10], &128u16.to_le_bytes());
+    assert_eq!(&ntb[12..16], &NDP16_SIGNATURE.to_le_bytes());
+    assert_eq!(&ntb[20..24], &[28, 0, 100, 0]);
+    assert_eq!(&ntb[28..], &sent[..]);
+    assert!(ncm.can_transmit());
+
+    // Deactivating the data interface stops the traffic.
+    host.control_out(
+        || {
+            dev.poll(&mut [&mut ncm]);
+        },
+        INTERFACE_OUT,
+        Request::SET_INTERFACE,
+        0,
+        1,
+        &[],
+    )
+    .expect("set interface");
+    assert!(!ncm.is_active());
+    assert_eq!(ncm.write_frame(&sent), Err(UsbError::InvalidState));
+}
+
+#[test]
+fn cdc_ecm() {
+    use usb_device::cdc::cdc_request;
+    use usb_device::cdc::ecm::CdcEcmClass;
+    use usb_device::cdc::EthernetFunction;
+    use usb_device::descriptor::parser;
+
+    const CLASS_INTERFACE_OUT: u8 = 0x21;
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let (mut rx_buf, mut tx_buf) = ([0u8; 1514], [0u8; 1514]);
+    let mac_address = [0x02, 0x00, 0x00, 0xab, 0xcd, 0xef];
+    let mut ecm = CdcEcmClass::new(&alloc, mac_address, 64, &mut rx_buf, &mut tx_buf).unwrap();
+
+    let mut control_buffer = [0u8; 128];
+    let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+        .composite_with_iads()
+        .build()
+        .unwrap();
+
+    let mut poll = || {
+        dev.poll(&mut [&mut ecm]);
+    };
+
+    host.reset();
+    poll();
+
+    let config = host
+        .control_in(
+            &mut poll,
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0200,
+            0,
+            255,
+        )
+        .expect("get configuration descriptor");
+    assert_eq!(parser::validate_configuration(&config, |_| true), Ok(()));
+
+    // Communication interface with the ECM subclass
+    assert_eq!(&config[17..23], &[9, 0x04, 0, 0, 1, 0x02]);
+    assert_eq!(config[23], 0x06);
+
+    // Ethernet networking functional descriptor
+    assert_eq!(&config[36..39], &[13, 0x24, 0x0f]);
+    let mac_string = host
+        .control_in(
+            &mut poll,
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0300 | u16::from(config[39]),
+            0x0409,
+            255,
+        )
+        .expect("get MAC address string");
+    let mac_string: Vec<u8> = mac_string[2..].iter().step_by(2).copied().collect();
+    assert_eq!(mac_string, b"020000ABCDEF");
+
+    host.control_out(&mut poll, DEVICE_OUT, Request::SET_CONFIGURATION, 1, 0, &[])
+        .expect("set configuration");
+
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        cdc_request::SET_ETHERNET_PACKET_FILTER,
+        0x000e,
+        0,
+        &[],
+    )
+    .expect("set packet filter");
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        cdc_request::SET_ETHERNET_MULTICAST_FILTERS,
+        0,
+        0,
+        &[0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb],
+    )
+    .expect("set multicast filters");
+    assert_eq!(
+        host.control_in(
+            &mut poll,
+            0xa1,
+            cdc_request::GET_ETHERNET_STATISTIC,
+            1,
+            0,
+            4
+        ),
+        Err(TransferError::Stall)
+    );
+
+    host.control_out(&mut poll, INTERFACE_OUT, Request::SET_INTERFACE, 1, 1, &[])
+        .expect("set interface");
+
+    let speed = match host.in_token(1) {
+        InResponse::Data(data) => data,
+        other => panic!("expected speed notification, got {:?}", other),
+    };
+    assert_eq!(&speed[..2], &[0xa1, 0x2a]);
+    poll();
+    assert_eq!(
+        host.in_token(1),
+        InResponse::Data(vec![0xa1, 0x00, 0, 0, 0, 0, 0, 0])
+    );
+    poll();
+
+    assert!(ecm.is_active());
+    assert_eq!(ecm.packet_filter(), 0x000e);
+
+    // A frame that ends with a full packet is terminated with a zero-length packet.
+    let received: Vec<u8> = (0..128).collect();
+    host.transfer_out(
+        || {
+            dev.poll(&mut [&mut ecm]);
+        },
+        1,
+        &received,
+    )
+    .expect("send frame");
+
+    let mut frame = [0u8; 1514];
+    assert!(ecm.can_receive());
+    assert_eq!(ecm.read_frame(&mut frame), Ok(128));
+    assert_eq!(&frame[..128], &received[..]);
+    assert_eq!(ecm.read_frame(&mut frame), Err(UsbError::WouldBlock));
+
+    let sent: Vec<u8> = (0..64).collect();
+    assert_eq!(ecm.write_frame(&sent), Ok(()));
+    assert_eq!(ecm.write_frame(&sent), Err(UsbError::WouldBlock));
+
+    let frame = host
+        .transfer_in(
+            || {
+                dev.poll(&mut [&mut ecm]);
+            },
+            2,
+            1514,
+        )
+        .expect("receive frame");
+    assert_eq!(frame, sent);
+    assert!(ecm.can_transmit());
+    assert_eq!(ecm.write_frame(&[0; 1515]), Err(UsbError::BufferOverflow));
+}
+
+#[test]
+fn rndis() {
+    use usb_device::cdc::cdc_request;
+    use usb_device::cdc::rndis::{message, oid, status, RndisClass};
+    use usb_device::cdc::EthernetFunction;
+    use usb_device::descriptor::parser;
+
+    const CLASS_INTERFACE_IN: u8 = 0xa1;
+    const CLASS_INTERFACE_OUT: u8 = 0x21;
+
+    fn u32_at(buf: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([
+            buf[offset],
+            buf[offset + 1],
+            buf[offset + 2],
+            buf[offset + 3],
+        ])
+    }
+
+    fn msg(fields: &[u32]) -> Vec<u8> {
+        let mut msg: Vec<u8> = fields.iter().flat_map(|f| f.to_le_bytes()).collect();
+        let len = msg.len() as u32;
+        msg[4..8].copy_from_slice(&len.to_le_bytes());
+        msg
+    }
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let (mut rx_buf, mut tx_buf) = ([0u8; 1600], [0u8; 1600]);
+    let mac_address = [0x02, 0x00, 0x00, 0xab, 0xcd, 0xef];
+    let mut rndis = RndisClass::new(&alloc, mac_address, 64, &mut rx_buf, &mut tx_buf).unwrap();
+
+    let mut control_buffer = [0u8; 256];
+    let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+        .composite_with_iads()
+        .build()
+        .unwrap();
+
+    let mut poll = || {
+        dev.poll(&mut [&mut rndis]);
+    };
+
+    host.reset();
+    poll();
+
+    let config = host
+        .control_in(
+            &mut poll,
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0200,
+            0,
+            255,
+        )
+        .expect("get configuration descriptor");
+    assert_eq!(parser::validate_configuration(&config, |_| true), Ok(()));
+
+    // Interface association and communication interface with the RNDIS class codes
+    assert_eq!(&config[9..17], &[8, 0x0b, 0, 2, 0xe0, 0x01, 0x03, 0]);
+    assert_eq!(&config[21..26], &[1, 0xe0, 0x01, 0x03, 0]);
+
+    host.control_out(&mut poll, DEVICE_OUT, Request::SET_CONFIGURATION, 1, 0, &[])
+        .expect("set configuration");
+
+    // Sends a control message and reads the response after the notification.
+    let mut command = |command: &[u32]| {
+        host.control_out(
+            || {
+                dev.poll(&mut [&mut rndis]);
+            },
+            CLASS_INTERFACE_OUT,
+            cdc_request::SEND_ENCAPSULATED_COMMAND,
+            0,
+            0,
+            &msg(command),
+        )
+        .expect("send encapsulated command");
+
+        assert_eq!(
+            host.in_token(1),
+            InResponse::Data(vec![1, 0, 0, 0, 0, 0, 0, 0])
+        );
+        dev.poll(&mut [&mut rndis]);
+
+        host.control_in(
+            || {
+                dev.poll(&mut [&mut rndis]);
+            },
+            CLASS_INTERFACE_IN,
+            cdc_request::GET_ENCAPSULATED_RESPONSE,
+            0,
+            0,
+            256,
+        )
+        .expect("get encapsulated response")
+    };
+
+    let response = command(&[message::INITIALIZE, 0, 1, 1, 0, 0x4000]);
+    assert_eq!(response.len(), 52);
+    assert_eq!(u32_at(&response, 0), 0x8000_0002);
+    assert_eq!(u32_at(&response, 8), 1);
+    assert_eq!(u32_at(&response, 12), status::SUCCESS);
+    assert_eq!(u32_at(&response, 32), 1);
+    assert_eq!(u32_at(&response, 36), 1600);
+
+    let response = command(&[message::QUERY, 0, 2, oid::GEN_SUPPORTED_LIST, 0, 0, 0]);
+    assert_eq!(u32_at(&response, 0), 0x8000_0004);
+    assert_eq!(u32_at(&response, 12), status::SUCCESS);
+    assert_eq!(u32_at(&response, 20), 16);
+    let count = u32_at(&response, 16) as usize / 4;
+    assert_eq!(response.len(), 24 + count * 4);
+    assert_eq!(u32_at(&response, 24), oid::GEN_SUPPORTED_LIST);
+
+    let response = command(&[message::QUERY, 0, 3, oid::CURRENT_ADDRESS_802_3, 0, 0, 0]);
+    assert_eq!(&response[24..], &mac_address);
+
+    let response = command(&[message::QUERY, 0, 4, oid::GEN_MEDIA_CONNECT_STATUS, 0, 0, 0]);
+    assert_eq!(u32_at(&response, 24), 1);
+
+    let response = command(&[message::QUERY, 0, 5, 0x0001_0108, 0, 0, 0]);
+    assert_eq!(response.len(), 24);
+    assert_eq!(u32_at(&response, 12), status::NOT_SUPPORTED);
+
+    // Setting a packet filter activates the function.
+    let response = command(&[
+        message::SET,
+        0,
+        6,
+        oid::GEN_CURRENT_PACKET_FILTER,
+        4,
+        20,
+        0,
+        0x0000_000b,
+    ]);
+    assert_eq!(response.len(), 16);
+    assert_eq!(u32_at(&response, 0), 0x8000_0005);
+    assert_eq!(u32_at(&response, 12), status::SUCCESS);
+
+    let response = command(&[message::KEEPALIVE, 0, 7]);
+    assert_eq!(&response[..12], &[8, 0, 0, 0x80, 16, 0, 0, 0, 7, 0, 0, 0]);
+
+    assert!(rndis.is_active());
+    assert_eq!(rndis.packet_filter(), 0x0000_000b);
+
+    // Connecting the link queues a status indication.
+    rndis.set_connected(true);
+    assert_eq!(
+        host.in_token(1),
+        InResponse::Data(vec![1, 0, 0, 0, 0, 0, 0, 0])
+    );
+    let indication = host
+        .control_in(
+            || {
+                dev.poll(&mut [&mut rndis]);
+            },
+            CLASS_INTERFACE_IN,
+            cdc_request::GET_ENCAPSULATED_RESPONSE,
+            0,
+            0,
+            256,
+        )
+        .expect("get status indication");
+    assert_eq!(indication.len(), 20);
+    assert_eq!(u32_at(&indication, 0), message::INDICATE_STATUS);
+    assert_eq!(u32_at(&indication, 8), status::MEDIA_CONNECT);
+
+    // Two packet messages in one transfer
+    let frame_a = [0xaa; 60];
+    let frame_b = [0xbb; 100];
+    let mut transfer = Vec::new();
+    for frame in [&frame_a[..], &frame_b[..]].iter() {
+        let mut packet = msg(&[
+            message::PACKET,
+            0,
+            36,
+            frame.len() as u32,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ]);
+        packet.extend_from_slice(frame);
+        let len = packet.len() as u32;
+        packet[4..8].copy_from_slice(&len.to_le_bytes());
+        transfer.extend_from_slice(&packet);
+    }
+
+    host.transfer_out(
+        || {
+            dev.poll(&mut [&mut rndis]);
+        },
+        1,
+        &transfer,
+    )
+    .expect("send packet messages");
+
+    let mut frame = [0u8; 1514];
+    assert_eq!(rndis.read_frame(&mut frame), Ok(60));
+    assert_eq!(&frame[..60], &frame_a[..]);
+    assert_eq!(rndis.read_frame(&mut frame), Ok(100));
+    assert_eq!(&frame[..100], &frame_b[..]);
+    assert_eq!(rndis.read_frame(&mut frame), Err(UsbError::WouldBlock));
+
+    let sent: Vec<u8> = (0..100).collect();
+    assert_eq!(rndis.write_frame(&sent), Ok(()));
+    assert_eq!(rndis.write_frame(&sent), Err(UsbError::WouldBlock));
+
+    let packet = host
+        .transfer_in(
+            || {
+                dev.poll(&mut [&mut rndis]);
+            },
+            2,
+            1600,
+        )
+        .expect("receive packet message");
+    assert_eq!(packet.len(), 44 + 100);
+    assert_eq!(u32_at(&packet, 0), message::PACKET);
+    assert_eq!(u32_at(&packet, 4), 144);
+    assert_eq!(u32_at(&packet, 8), 36);
+    assert_eq!(u32_at(&packet, 12), 100);
+    assert_eq!(&packet[44..], &sent[..]);
+
+    // Halting the function stops the traffic.
+    host.control_out(
+        || {
+            dev.poll(&mut [&mut rndis]);
+        },
+        CLASS_INTERFACE_OUT,
+        cdc_request::SEND_ENCAPSULATED_COMMAND,
+        0,
+        0,
+        &msg(&[message::HALT, 0, 8]),
+    )
+    .expect("send halt");
+    assert!(!rndis.is_active());
+    assert_eq!(rndis.write_frame(&sent), Err(UsbError::InvalidState));
+}
+
+#[test]
+fn msc() {
+    use usb_device::msc::{
+        msc_request, scsi_command, BlockDevice, BlockDeviceError, MscClass, CBW_SIGNATURE,
+        CSW_SIGNATURE,
+    };
+
+    const CLASS_INTERFACE_IN: u8 = 0xa1;
+    const CLASS_INTERFACE_OUT: u8 = 0x21;
+
+    struct RamDisk(Vec<u8>);
+
+    impl BlockDevice for RamDisk {
+        fn block_size(&self) -> usize {
+            512
+        }
+
+        fn block_count(&self) -> u64 {
+            (self.0.len() / 512) as u64
+        }
+
+        fn read_block(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockDeviceError> {
+            let start = lba as usize * 512;
+            buf.copy_from_slice(&self.0[start..start + 512]);
+            Ok(())
+        }
+
+        fn write_block(&mut self, lba: u64, data: &[u8]) -> Result<(), BlockDeviceError> {
+            let start = lba as usize * 512;
+            self.0[start..start + 512].copy_from_slice(data);
+            Ok(())
+        }
+    }
+
+    fn cbw(tag: u32, length: u32, direction_in: bool, cb: &[u8]) -> Vec<u8> {
+        let mut cbw = Vec::new();
+        cbw.extend_from_slice(&CBW_SIGNATURE.to_le_bytes());
+        cbw.extend_from_slice(&tag.to_le_bytes());
+        cbw.extend_from_slice(&length.to_le_bytes());
+        cbw.push(if direction_in { 0x80 } else { 0x00 });
+        cbw.push(0);
+        cbw.push(cb.len() as u8);
+        cbw.extend_from_slice(cb);
+        cbw.resize(31, 0);
+        cbw
+    }
+
+    fn csw(tag: u32, residue: u32, status: u8) -> Vec<u8> {
+        let mut csw = Vec::new();
+        csw.extend_from_slice(&CSW_SIGNATURE.to_le_bytes());
+        csw.extend_from_slice(&tag.to_le_bytes());
+        csw.extend_from_slice(&residue.to_le_bytes());
+        csw.push(status);
+        csw
+    }
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut buf = [0u8; 512];
+
+    // Blocks must be whole packets.
+    assert!(matches!(
+        MscClass::new(&alloc, 24, RamDisk(vec![0; 16 * 512]), &mut buf),
+        Err(UsbError::Unsupported)
+    ));
+
+    let mut msc = MscClass::new(&alloc, 64, RamDisk(vec![0; 16 * 512]), &mut buf)
+        .unwrap()
+        .inquiry("usb-dev", "Test disk", "1.0");
+
+    let mut control_buffer = [0u8; 64];
+    let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+        .build()
+        .unwrap();
+
+    let mut poll = || {
+        dev.poll(&mut [&mut msc]);
+    };
+
+    host.reset();
+    poll();
+
+    let config = host
+        .control_in(
+            &mut poll,
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0200,
+            0,
+            255,
+        )
+        .expect("get configuration descriptor");
+    assert_eq!(&config[9..18], &[9, 0x04, 0, 0, 2, 0x08, 0x06, 0x50, 0]);
+
+    host.control_out(&mut poll, DEVICE_OUT, Request::SET_CONFIGURATION, 1, 0, &[])
+        .expect("set configuration");
+
+    assert_eq!(
+        host.control_in(
+            &mut poll,
+            CLASS_INTERFACE_IN,
+            msc_request::GET_MAX_LUN,
+            0,
+            0,
+            1
+        ),
+        Ok(vec![0])
+    );
+
+    host.transfer_out(
+        &mut poll,
+        1,
+        &cbw(1, 36, true, &[scsi_command::INQUIRY, 0, 0, 0, 36, 0]),
+    )
+    .expect("send INQUIRY");
+    let inquiry = host.transfer_in(&mut poll, 1, 36).expect("receive INQUIRY");
+    assert_eq!(&inquiry[..5], &[0x00, 0x80, 0x04, 0x02, 31]);
+    assert_eq!(&inquiry[8..36], b"usb-dev Test disk       1.0 ");
+    assert_eq!(host.transfer_in(&mut poll, 1, 13), Ok(csw(1, 0, 0)));
+
+    let mut read_capacity = [0u8; 10];
+    read_capacity[0] = scsi_command::READ_CAPACITY_10;
+    host.transfer_out(&mut poll, 1, &cbw(2, 8, true, &read_capacity))
+        .expect("send READ CAPACITY(10)");
+    assert_eq!(
+        host.transfer_in(&mut poll, 1, 8),
+        Ok(vec![0, 0, 0, 15, 0, 0, 2, 0])
+    );
+    assert_eq!(host.transfer_in(&mut poll, 1, 13), Ok(csw(2, 0, 0)));
+
+    // Write two blocks and read them back
+    let data: Vec<u8> = (0..1024).map(|i| (i % 251) as u8).collect();
+    let write = [scsi_command::WRITE_10, 0, 0, 0, 0, 2, 0, 0, 2, 0];
+    host.transfer_out(&mut poll, 1, &cbw(3, 1024, false, &write))
+        .expect("send WRITE(10)");
+    // Bulk-Only Transport data stages don't end with a zero-length packet.
+    for packet in data.chunks(64) {
+        assert_eq!(host.out(1, packet), Handshake::Ack);
+        poll();
+    }
+    assert_eq!(host.transfer_in(&mut poll, 1, 13), Ok(csw(3, 0, 0)));
+
+    let read = [scsi_command::READ_10, 0, 0, 0, 0, 2, 0, 0, 2, 0];
+    host.transfer_out(&mut poll, 1, &cbw(4, 1024, true, &read))
+        .expect("send READ(10)");
+    assert_eq!(host.transfer_in(&mut poll, 1, 1024), Ok(data.clone()));
+    assert_eq!(host.transfer_in(&mut poll, 1, 13), Ok(csw(4, 0, 0)));
+
+    // A short response ends the data stage with a short packet.
+    host.transfer_out(
+        &mut poll,
+        1,
+        &cbw(
+            5,
+            192,
+            true,
+            &[scsi_command::MODE_SENSE_6, 0, 0x3f, 0, 192, 0],
+        ),
+    )
+    .expect("send MODE SENSE(6)");
+    assert_eq!(host.transfer_in(&mut poll, 1, 192), Ok(vec![3, 0, 0, 0]));
+    assert_eq!(host.transfer_in(&mut poll, 1, 13), Ok(csw(5, 188, 0)));
+
+    // Unsupported commands fail and set the sense data.
+    host.transfer_out(&mut poll, 1, &cbw(6, 0, false, &[0xff, 0, 0, 0, 0, 0]))
+        .expect("send unsupported command");
+    assert_eq!(host.transfer_in(&mut poll, 1, 13), Ok(csw(6, 0, 1)));
+
+    host.transfer_out(
+        &mut poll,
+        1,
+        &cbw(7, 18, true, &[scsi_command::REQUEST_SENSE, 0, 0, 0, 18, 0]),
+    )
+    .expect("send REQUEST SENSE");
+    let sense = host.transfer_in(&mut poll, 1, 18).expect("receive sense");
+    assert_eq!((sense[0], sense[2], sense[12]), (0x70, 0x05, 0x20));
+    assert_eq!(host.transfer_in(&mut poll, 1, 13), Ok(csw(7, 0, 0)));
+
+    // Reading past the end fails without a data stage, so the IN endpoint is halted until the
+    // host clears it.
+    let read = [scsi_command::READ_10, 0, 0, 0, 0, 15, 0, 0, 2, 0];
+    host.transfer_out(&mut poll, 1, &cbw(8, 1024, true, &read))
+        .expect("send READ(10)");
+    assert_eq!(
+        host.transfer_in(&mut poll, 1, 1024),
+        Err(TransferError::Stall)
+    );
+    host.control_out(
+        &mut poll,
+        ENDPOINT_OUT,
+        Request::CLEAR_FEATURE,
+        Request::FEATURE_ENDPOINT_HALT,
+        0x81,
+        &[],
+    )
+    .expect("clear halt");
+    assert_eq!(host.transfer_in(&mut poll, 1, 13), Ok(csw(8, 1024, 1)));
+
+    // A host that sends more data than the command takes is stopped by halting the OUT endpoint.
+    let write = [scsi_command::WRITE_10, 0, 0, 0, 0, 4, 0, 0, 1, 0];
+    host.transfer_out(&mut poll, 1, &cbw(9, 1024, false, &write))
+        .expect("send WRITE(10)");
+    for packet in data[..512].chunks(64) {
+        assert_eq!(host.out(1, packet), Handshake::Ack);
+        poll();
+    }
+    assert_eq!(host.out(1, &data[512..576]), Handshake::Stall);
+    assert_eq!(host.transfer_in(&mut poll, 1, 13), Ok(csw(9, 512, 0)));
+    host.control_out(
+        &mut poll,
+        ENDPOINT_OUT,
+        Request::CLEAR_FEATURE,
+        Request::FEATURE_ENDPOINT_HALT,
+        0x01,
+        &[],
+    )
+    .expect("clear halt");
+
+    // An invalid CBW halts both endpoints until the host resets the function.
+    host.transfer_out(&mut poll, 1, &[0x55; 31])
+        .expect("send invalid CBW");
+    for ep in [0x01, 0x81].iter() {
+        host.control_out(
+            &mut poll,
+            ENDPOINT_OUT,
+            Request::CLEAR_FEATURE,
+            Request::FEATURE_ENDPOINT_HALT,
+            *ep,
+            &[],
+        )
+        .expect("clear halt");
+    }
+    assert_eq!(host.in_token(1), InResponse::Stall);
+
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        msc_request::BULK_ONLY_MASS_STORAGE_RESET,
+        0,
+        0,
+        &[],
+    )
+    .expect("reset");
+    for ep in [0x01, 0x81].iter() {
+        host.control_out(
+            &mut poll,
+            ENDPOINT_OUT,
+            Request::CLEAR_FEATURE,
+            Request::FEATURE_ENDPOINT_HALT,
+            *ep,
+            &[],
+        )
+        .expect("clear halt");
+    }
+
+    host.transfer_out(
+        &mut poll,
+        1,
+        &cbw(
+            10,
+            0,
+            false,
+            &[scsi_command::TEST_UNIT_READY, 0, 0, 0, 0, 0],
+        ),
+    )
+    .expect("send TEST UNIT READY");
+    assert_eq!(host.transfer_in(&mut poll, 1, 13), Ok(csw(10, 0, 0)));
+
+    assert_eq!(&msc.device().0[1024..2048], &data[..]);
+}
+
+#[test]
+fn dfu_runtime() {
+    use std::cell::Cell;
+    use usb_device::dfu::{attributes, dfu_request, DfuRuntimeClass, DfuState};
+
+    const CLASS_INTERFACE_IN: u8 = 0xa1;
+    const CLASS_INTERFACE_OUT: u8 = 0x21;
+
+    for &will_detach in [false, true].iter() {
+        let detached = Cell::new(0);
+
+        let bus = SimUsbBus::new();
+        let host = bus.host();
+        let alloc = UsbBusAllocator::new(bus);
+
+        let mut attrs = attributes::CAN_DNLOAD;
+        if will_detach {
+            attrs |= attributes::WILL_DETACH;
+        }
+        let mut dfu = DfuRuntimeClass::new(&alloc, attrs, 500, 1024, || {
+            detached.set(detached.get() + 1)
+        });
+
+        let mut control_buffer = [0u8; 64];
+        let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+            .build()
+            .unwrap();
+
+        host.reset();
+        dev.poll(&mut [&mut dfu]);
+
+        let config = host
+            .control_in(
+                || {
+                    dev.poll(&mut [&mut dfu]);
+                },
+                DEVICE_IN,
+                Request::GET_DESCRIPTOR,
+                0x0200,
+                0,
+                255,
+            )
+            .expect("get configuration descriptor");
+        assert_eq!(&config[9..18], &[9, 0x04, 0, 0, 0, 0xfe, 0x01, 0x01, 0]);
+        assert_eq!(
+            &config[18..27],
+            &[9, 0x21, attrs, 0xf4, 0x01, 0x00, 0x04, 0x10, 0x01]
+        );
+
+        host.control_out(
+            || {
+                dev.poll(&mut [&mut dfu]);
+            },
+            DEVICE_OUT,
+            Request::SET_CONFIGURATION,
+            1,
+            0,
+            &[],
+        )
+        .expect("set configuration");
+
+        assert_eq!(
+            host.control_in(
+                || {
+                    dev.poll(&mut [&mut dfu]);
+                },
+                CLASS_INTERFACE_IN,
+                dfu_request::GETSTATUS,
+                0,
+                0,
+                6
+            ),
+            Ok(vec![0, 0, 0, 0, DfuState::AppIdle as u8, 0])
+        );
+
+        // Downloading requires DFU mode.
+        assert_eq!(
+            host.control_out(
+                || {
+                    dev.poll(&mut [&mut dfu]);
+                },
+                CLASS_INTERFACE_OUT,
+                dfu_request::DNLOAD,
+                0,
+                0,
+                &[0; 4]
+            ),
+            Err(TransferError::Stall)
+        );
+
+        host.control_out(
+            || {
+                dev.poll(&mut [&mut dfu]);
+            },
+            CLASS_INTERFACE_OUT,
+            dfu_request::DETACH,
+            500,
+            0,
+            &[],
+        )
+        .expect("detach");
+
+        if will_detach {
+            assert_eq!(detached.get(), 1);
+            assert!(!dfu.is_detach_requested());
+
+            // The hook has already run, so a bus reset must not run it again.
+            host.reset();
+            dev.poll(&mut [&mut dfu]);
+            assert_eq!(detached.get(), 1);
+        } else {
+            assert!(dfu.is_detach_requested());
+            assert_eq!(detached.get(), 0);
+            assert_eq!(
+                host.control_in(
+                    || {
+                        dev.poll(&mut [&mut dfu]);
+                    },
+                    CLASS_INTERFACE_IN,
+                    dfu_request::GETSTATE,
+                    0,
+                    0,
+                    1
+                ),
+                Ok(vec![DfuState::AppDetach as u8])
+            );
+
+            // Completing a request that isn't DETACH must not run the hook.
+            host.control_out(
+                || {
+                    dev.poll(&mut [&mut dfu]);
+                },
+                DEVICE_OUT,
+                Request::SET_CONFIGURATION,
+                1,
+                0,
+                &[],
+            )
+            .expect("set configuration");
+            assert_eq!(detached.get(), 0);
+
+            host.reset();
+            dev.poll(&mut [&mut dfu]);
+            assert_eq!(detached.get(), 1);
+        }
+    }
+}
+
+struct DfuRam {
+    data: Vec<u8>,
+    erased: Vec<u32>,
+    manifested: bool,
+}
+
+impl usb_device::dfu::DfuMemory for DfuRam {
+    fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<usize, usb_device::dfu::DfuError> {
+        let start = (address as usize).min(self.data.len());
+        let count = buf.len().min(self.data.len() - start);
+        buf[..count].copy_from_slice(&self.data[start..start + count]);
+        Ok(count)
+    }
+
+    fn program(&mut self, address: u32, data: &[u8]) -> Result<(), usb_device::dfu::DfuError> {
+        let start = address as usize;
+        self.data
+            .get_mut(start..start + data.len())
+            .ok_or(usb_device::dfu::DfuError::Address)?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), usb_device::dfu::DfuError> {
+        self.erased.push(address);
+        Ok(())
+    }
+
+    fn manifest(&mut self) -> Result<(), usb_device::dfu::DfuError> {
+        self.manifested = true;
+        Ok(())
+    }
+}
+
+fn dfu_ram(len: usize) -> DfuRam {
+    DfuRam {
+        data: vec![0xff; len],
+        erased: Vec::new(),
+        manifested: false,
+    }
+}
+
+#[test]
+fn dfu() {
+    use usb_device::dfu::{dfu_request, DfuClass, DfuError, DfuState};
+
+    const CLASS_INTERFACE_IN: u8 = 0xa1;
+    const CLASS_INTERFACE_OUT: u8 = 0x21;
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut buf = [0u8; 128];
+    let mut dfu = DfuClass::new(&alloc, dfu_ram(300), &mut buf).unwrap();
+
+    let mut control_buffer = [0u8; 128];
+    let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+        .build()
+        .unwrap();
+
+    let mut poll = || {
+        dev.poll(&mut [&mut dfu]);
+    };
+
+    host.reset();
+    poll();
+
+    let config = host
+        .control_in(
+            &mut poll,
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0200,
+            0,
+            255,
+        )
+        .expect("get configuration descriptor");
+    assert_eq!(&config[9..18], &[9, 0x04, 0, 0, 0, 0xfe, 0x01, 0x02, 0]);
+    assert_eq!(
+        &config[18..27],
+        &[9, 0x21, 0x07, 0xe8, 0x03, 128, 0x00, 0x10, 0x01]
+    );
+
+    host.control_out(&mut poll, DEVICE_OUT, Request::SET_CONFIGURATION, 1, 0, &[])
+        .expect("set configuration");
+
+    let get_status = |poll: &mut dyn FnMut()| {
+        host.control_in(poll, CLASS_INTERFACE_IN, dfu_request::GETSTATUS, 0, 0, 6)
+            .expect("get status")
+    };
+
+    // Download three blocks, the last one short.
+    let image: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    for (block, chunk) in image.chunks(128).enumerate() {
+        host.control_out(
+            &mut poll,
+            CLASS_INTERFACE_OUT,
+            dfu_request::DNLOAD,
+            block as u16,
+            0,
+            chunk,
+        )
+        .expect("download block");
+
+        assert_eq!(
+            get_status(&mut poll),
+            vec![0, 5, 0, 0, DfuState::DnBusy as u8, 0]
+        );
+        assert_eq!(
+            get_status(&mut poll),
+            vec![0, 0, 0, 0, DfuState::DnloadIdle as u8, 0]
+        );
+    }
+
+    // The end of the download starts manifestation.
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        dfu_request::DNLOAD,
+        3,
+        0,
+        &[],
+    )
+    .expect("end download");
+    assert_eq!(
+        get_status(&mut poll),
+        vec![0, 1, 0, 0, DfuState::Manifest as u8, 0]
+    );
+    assert_eq!(
+        get_status(&mut poll),
+        vec![0, 0, 0, 0, DfuState::DfuIdle as u8, 0]
+    );
+
+    // Upload the image back, ending with a short block.
+    let mut uploaded = Vec::new();
+    for block in 0..3 {
+        uploaded.extend(
+            host.control_in(
+                &mut poll,
+                CLASS_INTERFACE_IN,
+                dfu_request::UPLOAD,
+                block,
+                0,
+                128,
+            )
+            .expect("upload block"),
+        );
+    }
+    assert_eq!(uploaded, image);
+    assert_eq!(
+        get_status(&mut poll),
+        vec![0, 0, 0, 0, DfuState::DfuIdle as u8, 0]
+    );
+
+    // Writing past the end of the memory fails in DNBUSY.
+    for block in 0..3 {
+        host.control_out(
+            &mut poll,
+            CLASS_INTERFACE_OUT,
+            dfu_request::DNLOAD,
+            block,
+            0,
+            &[0; 128],
+        )
+        .expect("download block");
+        get_status(&mut poll);
+        get_status(&mut poll);
+    }
+    assert_eq!(
+        get_status(&mut poll),
+        vec![DfuError::Address as u8, 0, 0, 0, DfuState::Error as u8, 0]
+    );
+
+    // Requests other than status requests are stalled in dfuERROR.
+    assert_eq!(
+        host.control_out(
+            &mut poll,
+            CLASS_INTERFACE_OUT,
+            dfu_request::ABORT,
+            0,
+            0,
+            &[]
+        ),
+        Err(TransferError::Stall)
+    );
+    assert_eq!(
+        get_status(&mut poll),
+        vec![
+            DfuError::StalledPacket as u8,
+            0,
+            0,
+            0,
+            DfuState::Error as u8,
+            0
+        ]
+    );
+
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        dfu_request::CLRSTATUS,
+        0,
+        0,
+        &[],
+    )
+    .expect("clear status");
+    assert_eq!(
+        host.control_in(
+            &mut poll,
+            CLASS_INTERFACE_IN,
+            dfu_request::GETSTATE,
+            0,
+            0,
+            1
+        ),
+        Ok(vec![DfuState::DfuIdle as u8])
+    );
+
+    assert!(dfu.memory().manifested);
+}
+
+#[test]
+fn dfuse() {
+    use usb_device::dfu::{dfu_request, dfuse_command, DfuClass, DfuState};
+
+    const CLASS_INTERFACE_IN: u8 = 0xa1;
+    const CLASS_INTERFACE_OUT: u8 = 0x21;
+    const LAYOUT: &str = "@Internal Flash  /0x00000000/04*064 g";
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut buf = [0u8; 64];
+    let mut dfu = DfuClass::new_dfuse(&alloc, dfu_ram(256), &mut buf, LAYOUT).unwrap();
+
+    let mut control_buffer = [0u8; 128];
+    let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x0483, 0xdf11), &mut control_buffer)
+        .build()
+        .unwrap();
+
+    let mut poll = || {
+        dev.poll(&mut [&mut dfu]);
+    };
+
+    host.reset();
+    poll();
+
+    let config = host
+        .control_in(
+            &mut poll,
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0200,
+            0,
+            255,
+        )
+        .expect("get configuration descriptor");
+    let string_index = config[17];
+    assert_ne!(string_index, 0);
+    assert_eq!(&config[25..27], &[0x1a, 0x01]);
+
+    let string = host
+        .control_in(
+            &mut poll,
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0300 | u16::from(string_index),
+            0x0409,
+            255,
+        )
+        .expect("get memory layout");
+    let layout: Vec<u8> = string[2..].iter().step_by(2).copied().collect();
+    assert_eq!(layout, LAYOUT.as_bytes());
+
+    host.control_out(&mut poll, DEVICE_OUT, Request::SET_CONFIGURATION, 1, 0, &[])
+        .expect("set configuration");
+
+    let command = |poll: &mut dyn FnMut(), block: u16, data: &[u8]| {
+        host.control_out(
+            &mut *poll,
+            CLASS_INTERFACE_OUT,
+            dfu_request::DNLOAD,
+            block,
+            0,
+            data,
+        )
+        .expect("download");
+        let busy = host
+            .control_in(
+                &mut *poll,
+                CLASS_INTERFACE_IN,
+                dfu_request::GETSTATUS,
+                0,
+                0,
+                6,
+            )
+            .expect("get status");
+        assert_eq!(busy[4], DfuState::DnBusy as u8);
+        let idle = host
+            .control_in(
+                &mut *poll,
+                CLASS_INTERFACE_IN,
+                dfu_request::GETSTATUS,
+                0,
+                0,
+                6,
+            )
+            .expect("get status");
+        assert_eq!(idle, vec![0, 0, 0, 0, DfuState::DnloadIdle as u8, 0]);
+    };
+
+    assert_eq!(
+        host.control_in(&mut poll, CLASS_INTERFACE_IN, dfu_request::UPLOAD, 0, 0, 64),
+        Ok(vec![
+            dfuse_command::GET_COMMANDS,
+            dfuse_command::SET_ADDRESS_POINTER,
+            dfuse_command::ERASE
+        ])
+    );
+
+    command(&mut poll, 0, &[dfuse_command::ERASE, 0x40, 0, 0, 0]);
+    command(
+        &mut poll,
+        0,
+        &[dfuse_command::SET_ADDRESS_POINTER, 0x40, 0, 0, 0],
+    );
+    command(&mut poll, 2, &[0x11; 64]);
+    command(&mut poll, 3, &[0x22; 64]);
+
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        dfu_request::ABORT,
+        0,
+        0,
+        &[],
+    )
+    .expect("abort");
+
+    assert_eq!(
+        host.control_in(&mut poll, CLASS_INTERFACE_IN, dfu_request::UPLOAD, 3, 0, 64),
+        Ok(vec![0x22; 64])
+    );
+
+    // Block 1 is reserved.
+    assert_eq!(
+        host.control_out(
+            &mut poll,
+            CLASS_INTERFACE_OUT,
+            dfu_request::DNLOAD,
+            1,
+            0,
+            &[0; 4]
+        ),
+        Err(TransferError::Stall)
+    );
+
+    let memory = dfu.memory();
+    assert_eq!(memory.erased, vec![0x40]);
+    assert_eq!(&memory.data[..0x40], &[0xff; 0x40][..]);
+    assert_eq!(&memory.data[0x40..0x80], &[0x11; 64][..]);
+    assert_eq!(&memory.data[0x80..0xc0], &[0x22; 64][..]);
+}
+
+#[test]
+fn audio_playback() {
+    use usb_device::audio::{
+        audio_request, entity, feature_unit_control, terminal_type, AudioClass, AudioVersion,
+        StreamConfig, SAMPLING_FREQ_CONTROL,
+    };
+
+    const CLASS_INTERFACE_IN: u8 = 0xa1;
+    const CLASS_INTERFACE_OUT: u8 = 0x21;
+    const CLASS_ENDPOINT_IN: u8 = 0xa2;
+    const CLASS_ENDPOINT_OUT: u8 = 0x22;
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let config = StreamConfig::new(terminal_type::SPEAKER, 2, 2, 16, &[48000, 44100]);
+    let mut speaker = AudioClass::playback(&alloc, AudioVersion::Uac1, config).unwrap();
+
+    let mut control_buffer = [0u8; 256];
+    let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+        .build()
+        .unwrap();
+
+    let mut poll = || {
+        dev.poll(&mut [&mut speaker]);
+    };
+
+    host.reset();
+    poll();
+
+    let config = host
+        .control_in(
+            &mut poll,
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0200,
+            0,
+            255,
+        )
+        .expect("get configuration descriptor");
+    assert_eq!(&config[9..18], &[9, 0x04, 0, 0, 0, 0x01, 0x01, 0x00, 0]);
+    // The AudioControl header, with the length of the whole topology
+    assert_eq!(&config[18..27], &[9, 0x24, 0x01, 0x00, 0x01, 40, 0, 1, 1]);
+    // The feature unit, with mute and volume on the master channel
+    assert_eq!(&config[39..49], &[10, 0x24, 0x06, 2, 1, 1, 0x03, 0, 0, 0]);
+
+    // Streaming alternate setting 1, its format and its endpoints
+    let alt = &config[67..];
+    assert_eq!(&alt[..9], &[9, 0x04, 1, 1, 2, 0x01, 0x02, 0x00, 0]);
+    assert_eq!(
+        &alt[16..30],
+        &[14, 0x24, 0x02, 0x01, 2, 2, 16, 2, 0x80, 0xbb, 0x00, 0x44, 0xac, 0x00]
+    );
+    let data_ep = &alt[30..39];
+    assert_eq!(data_ep[..4], [9, 0x05, 0x01, 0x05]);
+    assert_eq!(u16::from_le_bytes([data_ep[4], data_ep[5]]), 49 * 4);
+    let feedback_address = data_ep[8];
+    assert_eq!(&alt[39..46], &[7, 0x25, 0x01, 0x01, 0, 0, 0]);
+    assert_eq!(
+        &alt[46..55],
+        &[9, 0x05, feedback_address, 0x11, 3, 0, 1, 5, 0]
+    );
+    let feedback_ep = usize::from(feedback_address & 0x7f);
+
+    host.control_out(&mut poll, DEVICE_OUT, Request::SET_CONFIGURATION, 1, 0, &[])
+        .expect("set configuration");
+
+    // Sample rate
+    let sampling_freq = u16::from(SAMPLING_FREQ_CONTROL) << 8;
+    assert_eq!(
+        host.control_in(
+            &mut poll,
+            CLASS_ENDPOINT_IN,
+            audio_request::GET_CUR,
+            sampling_freq,
+            0x01,
+            3
+        ),
+        Ok(vec![0x80, 0xbb, 0x00])
+    );
+    host.control_out(
+        &mut poll,
+        CLASS_ENDPOINT_OUT,
+        audio_request::SET_CUR,
+        sampling_freq,
+        0x01,
+        &[0x44, 0xac, 0x00],
+    )
+    .expect("set sample rate");
+    assert_eq!(
+        host.control_out(
+            &mut poll,
+            CLASS_ENDPOINT_OUT,
+            audio_request::SET_CUR,
+            sampling_freq,
+            0x01,
+            &[0x00, 0x7d, 0x00]
+        ),
+        Err(TransferError::Stall)
+    );
+
+    // Volume and mute
+    let feature_unit = u16::from(entity::FEATURE_UNIT) << 8;
+    let volume = u16::from(feature_unit_control::VOLUME) << 8;
+    let mute = u16::from(feature_unit_control::MUTE) << 8;
+    for &(request, value) in [
+        (audio_request::GET_MIN, -25600i16),
+        (audio_request::GET_MAX, 0),
+        (audio_request::GET_RES, 256),
+        (audio_request::GET_CUR, 0),
+    ]
+    .iter()
+    {
+        assert_eq!(
+            host.control_in(
+                &mut poll,
+                CLASS_INTERFACE_IN,
+                request,
+                volume,
+                feature_unit,
+                2
+            ),
+            Ok(value.to_le_bytes().to_vec())
+        );
+    }
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        audio_request::SET_CUR,
+        volume,
+        feature_unit,
+        &(-2560i16).to_le_bytes(),
+    )
+    .expect("set volume");
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        audio_request::SET_CUR,
+        mute,
+        feature_unit,
+        &[1],
+    )
+    .expect("set mute");
+    // Only the master channel has controls.
+    assert_eq!(
+        host.control_in(
+            &mut poll,
+            CLASS_INTERFACE_IN,
+            audio_request::GET_CUR,
+            volume | 1,
+            feature_unit,
+            2
+        ),
+        Err(TransferError::Stall)
+    );
+
+    host.control_out(&mut poll, INTERFACE_OUT, Request::SET_INTERFACE, 1, 1, &[])
+        .expect("start streaming");
+
+    // The feedback starts at the nominal rate of 44.1 samples per frame.
+    let nominal = (44100u32 << 14) / 1000;
+    assert_eq!(
+        host.in_token(feedback_ep),
+        InResponse::Data(nominal.to_le_bytes()[..3].to_vec())
+    );
+    poll();
+
+    assert_eq!(host.out(1, &[0x5a; 176]), Handshake::Ack);
+    poll();
+
+    let mut packet = [0u8; 196];
+    assert_eq!(speaker.read(&mut packet), Ok(176));
+    assert!(speaker.is_streaming());
+    assert_eq!(speaker.sample_rate(), 44100);
+    assert_eq!(speaker.volume(), -2560);
+    assert!(speaker.is_muted());
+
+    // The codec plays slightly slower than the host sends.
+    for _ in 0..32 {
+        host.sof();
+        speaker.count_samples(44);
+        dev.poll(&mut [&mut speaker]);
+    }
+    assert_eq!(speaker.feedback(), 44 << 14);
+
+    let mut poll = || {
+        dev.poll(&mut [&mut speaker]);
+    };
+    assert_eq!(
+        host.in_token(feedback_ep),
+        InResponse::Data(nominal.to_le_bytes()[..3].to_vec())
+    );
+    poll();
+    assert_eq!(
+        host.in_token(feedback_ep),
+        InResponse::Data((44u32 << 14).to_le_bytes()[..3].to_vec())
+    );
+
+    host.control_out(&mut poll, INTERFACE_OUT, Request::SET_INTERFACE, 0, 1, &[])
+        .expect("stop streaming");
+    assert_eq!(speaker.read(&mut packet), Err(UsbError::InvalidState));
+}
+
+#[test]
+fn audio_capture() {
+    use usb_device::audio::{
+        audio_request, clock_source_control, entity, feature_unit_control, terminal_type,
+        AudioClass, AudioVersion, StreamConfig,
+    };
+
+    const CLASS_INTERFACE_IN: u8 = 0xa1;
+    const CLASS_INTERFACE_OUT: u8 = 0x21;
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let config = StreamConfig::new(terminal_type::MICROPHONE, 1, 3, 24, &[48000, 96000])
+        .volume_range(-60 * 256, 12 * 256, 128);
+    let mut microphone = AudioClass::capture(&alloc, AudioVersion::Uac2, config).unwrap();
+
+    let mut control_buffer = [0u8; 256];
+    let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+        .composite_with_iads()
+        .build()
+        .unwrap();
+
+    let mut poll = || {
+        dev.poll(&mut [&mut microphone]);
+    };
+
+    host.reset();
+    poll();
+
+    let config = host
+        .control_in(
+            &mut poll,
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0200,
+            0,
+            255,
+        )
+        .expect("get configuration descriptor");
+    assert_eq!(&config[9..17], &[8, 0x0b, 0, 2, 0x01, 0x00, 0x20, 0]);
+    assert_eq!(&config[17..26], &[9, 0x04, 0, 0, 0, 0x01, 0x01, 0x20, 0]);
+    assert_eq!(
+        &config[26..35],
+        &[9, 0x24, 0x01, 0x00, 0x02, 0xff, 60, 0, 0]
+    );
+    // A programmable clock source
+    assert_eq!(&config[35..43], &[8, 0x24, 0x0a, 4, 0x03, 0x07, 0, 0]);
+
+    host.control_out(&mut poll, DEVICE_OUT, Request::SET_CONFIGURATION, 1, 0, &[])
+        .expect("set configuration");
+
+    let clock_source = u16::from(entity::CLOCK_SOURCE) << 8;
+    let sam_freq = u16::from(clock_source_control::SAM_FREQ) << 8;
+    let mut ranges = vec![2, 0];
+    for rate in [48000u32, 96000].iter() {
+        ranges.extend_from_slice(&rate.to_le_bytes());
+        ranges.extend_from_slice(&rate.to_le_bytes());
+        ranges.extend_from_slice(&[0; 4]);
+    }
+    assert_eq!(
+        host.control_in(
+            &mut poll,
+            CLASS_INTERFACE_IN,
+            audio_request::RANGE,
+            sam_freq,
+            clock_source,
+            255
+        ),
+        Ok(ranges)
+    );
+    // Hosts first read the number of subranges alone.
+    assert_eq!(
+        host.control_in(
+            &mut poll,
+            CLASS_INTERFACE_IN,
+            audio_request::RANGE,
+            sam_freq,
+            clock_source,
+            2
+        ),
+        Ok(vec![2, 0])
+    );
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        audio_request::CUR,
+        sam_freq,
+        clock_source,
+        &96000u32.to_le_bytes(),
+    )
+    .expect("set sample rate");
+    assert_eq!(
+        host.control_in(
+            &mut poll,
+            CLASS_INTERFACE_IN,
+            audio_request::CUR,
+            sam_freq,
+            clock_source,
+            4
+        ),
+        Ok(96000u32.to_le_bytes().to_vec())
+    );
+    assert_eq!(
+        host.control_in(
+            &mut poll,
+            CLASS_INTERFACE_IN,
+            audio_request::CUR,
+            u16::from(clock_source_control::CLOCK_VALID) << 8,
+            clock_source,
+            1
+        ),
+        Ok(vec![1])
+    );
+
+    let feature_unit = u16::from(entity::FEATURE_UNIT) << 8;
+    let volume = u16::from(feature_unit_control::VOLUME) << 8;
+    let mut range = vec![1, 0];
+    for value in [-60 * 256i16, 12 * 256, 128].iter() {
+        range.extend_from_slice(&value.to_le_bytes());
+    }
+    assert_eq!(
+        host.control_in(
+            &mut poll,
+            CLASS_INTERFACE_IN,
+            audio_request::RANGE,
+            volume,
+            feature_unit,
+            8
+        ),
+        Ok(range)
+    );
+    // Volumes outside the range are clamped.
+    host.control_out(
+        &mut poll,
+        CLASS_INTERFACE_OUT,
+        audio_request::CUR,
+        volume,
+        feature_unit,
+        &(20 * 256i16).to_le_bytes(),
+    )
+    .expect("set volume");
+    assert_eq!(
+        host.control_in(
+            &mut poll,
+            CLASS_INTERFACE_IN,
+            audio_request::CUR,
+            volume,
+            feature_unit,
+            2
+        ),
+        Ok((12 * 256i16).to_le_bytes().to_vec())
+    );
+
+    host.control_out(&mut poll, INTERFACE_OUT, Request::SET_INTERFACE, 1, 1, &[])
+        .expect("start streaming");
+
+    assert_eq!(microphone.write(&[0x11; 288]), Ok(288));
+    assert_eq!(host.in_token(1), InResponse::Data(vec![0x11; 288]));
+
+    assert_eq!(microphone.sample_rate(), 96000);
+
+    let report = conformance::run(&host, || {
+        dev.poll(&mut [&mut microphone]);
+    });
+    assert!(report.is_compliant(), "{}", report);
+}
+
+#[test]
+fn midi() {
+    use usb_device::midi::{code_index, EventPacket, MidiClass, SysExPackets};
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    let mut midi = MidiClass::new(&alloc, 1, 1, 64)
+        .unwrap()
+        .external_jacks(true);
+
+    let mut control_buffer = [0u8; 128];
+    let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+        .build()
+        .unwrap();
+
+    let mut poll = || {
+        dev.poll(&mut [&mut midi]);
+    };
+
+    host.reset();
+    poll();
+
+    let config = host
+        .control_in(
+            &mut poll,
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0200,
+            0,
+            255,
+        )
+        .expect("get configuration descriptor");
+    assert_eq!(config.len(), 101);
+    assert_eq!(&config[18..27], &[9, 0x24, 0x01, 0x00, 0x01, 9, 0, 1, 1]);
+    assert_eq!(&config[27..36], &[9, 0x04, 1, 0, 2, 0x01, 0x03, 0x00, 0]);
+    // The MIDIStreaming header, with the length of the jacks and endpoints
+    assert_eq!(&config[36..43], &[7, 0x24, 0x01, 0x00, 0x01, 65, 0]);
+    // The cable from the host: embedded IN jack 1 to external OUT jack 2
+    assert_eq!(&config[43..49], &[6, 0x24, 0x02, 0x01, 1, 0]);
+    assert_eq!(&config[49..58], &[9, 0x24, 0x03, 0x02, 2, 1, 1, 1, 0]);
+    // The cable to the host: external IN jack 4 to embedded OUT jack 3
+    assert_eq!(&config[58..64], &[6, 0x24, 0x02, 0x02, 4, 0]);
+    assert_eq!(&config[64..73], &[9, 0x24, 0x03, 0x01, 3, 1, 4, 1, 0]);
+    // Bulk endpoints with the embedded jacks they carry
+    assert_eq!(&config[73..82], &[9, 0x05, 0x01, 0x02, 64, 0, 0, 0, 0]);
+    assert_eq!(&config[82..87], &[5, 0x25, 0x01, 1, 1]);
+    assert_eq!(&config[87..96], &[9, 0x05, 0x81, 0x02, 64, 0, 0, 0, 0]);
+    assert_eq!(&config[96..101], &[5, 0x25, 0x01, 1, 3]);
+
+    host.control_out(&mut poll, DEVICE_OUT, Request::SET_CONFIGURATION, 1, 0, &[])
+        .expect("set configuration");
+
+    // A note on and the end of a System Exclusive message, followed by padding
+    host.transfer_out(
+        || {
+            dev.poll(&mut [&mut midi]);
+        },
+        1,
+        &[0x09, 0x90, 60, 100, 0x06, 0x01, 0xf7, 0, 0, 0, 0, 0],
+    )
+    .expect("send events");
+
+    let mut buf = [0u8; 64];
+    let len = midi.read(&mut buf).expect("read events");
+    let packets: Vec<EventPacket> = EventPacket::parse(&buf[..len]).collect();
+    assert_eq!(packets.len(), 2);
+    assert_eq!(packets[0].code_index_number(), code_index::NOTE_ON);
+    assert_eq!(packets[0].midi_bytes(), &[0x90, 60, 100]);
+    assert!(!packets[0].is_sysex());
+    assert!(packets[1].is_sysex());
+    assert_eq!(packets[1].midi_bytes(), &[0x01, 0xf7]);
+
+    assert_eq!(
+        EventPacket::from_message(1, &[0xc3, 5]).map(|p| *p.as_bytes()),
+        Ok([0x1c, 0xc3, 5, 0])
+    );
+    assert_eq!(
+        EventPacket::from_message(0, &[0xf8]).map(|p| *p.as_bytes()),
+        Ok([0x0f, 0xf8, 0, 0])
+    );
+    assert_eq!(
+        EventPacket::from_message(0, &[0x90, 60]),
+        Err(UsbError::ParseError)
+    );
+    assert_eq!(
+        EventPacket::from_message(16, &[0x90, 60, 0]),
+        Err(UsbError::ParseError)
+    );
+    assert!(SysExPackets::new(0, &[0xf0, 0x01]).is_err());
+
+    let mut events = Vec::new();
+    for packet in SysExPackets::new(0, &[0xf0, 1, 2, 3, 4, 5, 0xf7]).unwrap() {
+        events.extend_from_slice(packet.as_bytes());
+    }
+    assert_eq!(
+        events,
+        vec![0x04, 0xf0, 1, 2, 0x04, 3, 4, 5, 0x05, 0xf7, 0, 0]
+    );
+    assert_eq!(midi.write(&events), Ok(12));
+    assert_eq!(host.in_token(1), InResponse::Data(events));
+    dev.poll(&mut [&mut midi]);
+
+    midi.send(EventPacket::from_message(0, &[0x80, 60, 0]).unwrap())
+        .expect("send note off");
+    assert_eq!(host.in_token(1), InResponse::Data(vec![0x08, 0x80, 60, 0]));
+    dev.poll(&mut [&mut midi]);
+
+    let report = conformance::run(&host, || {
+        dev.poll(&mut [&mut midi]);
+    });
+    assert!(report.is_compliant(), "{}", report);
+}
+
+#[test]
+fn midi_embedded_jacks() {
+    use usb_device::midi::{MidiClass, MAX_CABLES};
+
+    let bus = SimUsbBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+
+    assert!(matches!(
+        MidiClass::new(&alloc, 0, 0, 64),
+        Err(UsbError::Unsupported)
+    ));
+    assert!(matches!(
+        MidiClass::new(&alloc, 255, 1, 64),
+        Err(UsbError::Unsupported)
+    ));
+    assert!(matches!(
+        MidiClass::new(&alloc, 1, MAX_CABLES + 1, 64),
+        Err(UsbError::Unsupported)
+    ));
+
+    let mut midi = MidiClass::new(&alloc, 1, 1, 64)
+        .unwrap()
+        .external_jacks(false);
+
+    let mut control_buffer = [0u8; 128];
+    let mut dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x1234, 0x5678), &mut control_buffer)
+        .build()
+        .unwrap();
+
+    let mut poll = || {
+        dev.poll(&mut [&mut midi]);
+    };
+
+    host.reset();
+    poll();
+
+    let config = host
+        .control_in(
+            &mut poll,
+            DEVICE_IN,
+            Request::GET_DESCRIPTOR,
+            0x0200,
+            0,
+            255,
+        )
+        .expect("get configuration descriptor");
+    assert_eq!(config.len(), 84);
+    // wTotalLength covers the header, the jacks and the endpoints.
+    assert_eq!(&config[36..43], &[7, 0x24, 0x01, 0x00, 0x01, 48, 0]);
+    // Embedded IN jack 1, and embedded OUT jack 2 without input pins
+    assert_eq!(&config[43..49], &[6, 0x24, 0x02, 0x01, 1, 0]);
+    assert_eq!(&config[49..56], &[7, 0x24, 0x03, 0x01, 2, 0, 0]);
+    assert_eq!(&config[56..65], &[9, 0x05, 0x01, 0x02, 64, 0, 0, 0, 0]);
+    assert_eq!(&config[65..70], &[5, 0x25, 0x01, 1, 1]);
+    assert_eq!(&config[70..79], &[9, 0x05, 0x81, 0x02, 64, 0, 0, 0, 0]);
+    assert_eq!(&config[79..84], &[5, 0x25, 0x01, 1, 2]);
+}